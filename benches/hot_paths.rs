@@ -0,0 +1,146 @@
+// Times the solver's hot paths one at a time, so that an optimization can
+// be measured without a full run.  Run with `cargo bench --bench hot_paths`,
+// optionally with a filter:  `cargo bench --bench hot_paths -- try_place`.
+//
+// This uses a small harness of its own rather than criterion, so that the
+// benchmarks build without any extra dependencies.  Each benchmark runs for
+// a short warmup, then reports the fastest of several samples.
+extern crate nmbr9;
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use nmbr9::bag::Bag;
+use nmbr9::piece::{MAX_EDGE_LENGTH, MAX_ROTATIONS, Overlap};
+use nmbr9::results::Results;
+use nmbr9::rng::Rng;
+use nmbr9::rules::Rules;
+use nmbr9::state::State;
+use nmbr9::strategy::{Greedy, Strategy};
+use nmbr9::tables::Tables;
+
+const SAMPLES: usize = 5;
+const SAMPLE_TIME: Duration = Duration::from_millis(200);
+
+// Runs f (which does `per_call` operations) repeatedly, printing the
+// fastest sample's time per operation
+fn bench<F: FnMut() -> u64>(filter: &Option<String>, name: &str,
+                            per_call: usize, mut f: F) {
+    if filter.as_ref().map(|s| !name.contains(s.as_str())).unwrap_or(false) {
+        return;
+    }
+    let start = Instant::now();
+    let mut calls = 0;
+    while start.elapsed() < SAMPLE_TIME {
+        black_box(f());
+        calls += 1;
+    }
+
+    let mut best = Duration::MAX;
+    for _ in 0..SAMPLES {
+        let start = Instant::now();
+        for _ in 0..calls {
+            black_box(f());
+        }
+        best = best.min(start.elapsed() / calls);
+    }
+    let per_op = best.as_secs_f64() * 1e9 / per_call as f64;
+    println!("{:<24} {:>12.1} ns/op", name, per_op);
+}
+
+// The arrangements made by greedily placing tiles 9, 8, ... 0 in turn,
+// so that later ones have more pieces and layers
+fn states(rules: Rules) -> Vec<State> {
+    let mut greedy = Greedy::new(rules);
+    let mut out = Vec::new();
+    let mut state = State::with_adjacency(rules.adjacency);
+    for tile in (0..10).rev() {
+        let p = greedy.choose(&state, tile).unwrap();
+        state = state.try_place(p.id(), p.x, p.y)
+            .unwrap();
+        out.push(state.clone());
+    }
+    out
+}
+
+fn main() {
+    let filter = std::env::args().skip(1).find(|a| !a.starts_with('-'));
+    let rules = Rules::default();
+    let tables = Tables::get(rules.adjacency, rules.mirrored);
+    let states = states(rules);
+
+    let mut bag = Bag::from_usize(0);
+    for i in 0..10 {
+        bag = bag.add(i * MAX_ROTATIONS).add(i * MAX_ROTATIONS);
+    }
+
+    // Every position around each state, as the worker's scan tries them
+    let mut tries = Vec::new();
+    for (i, s) in states.iter().enumerate() {
+        let (w, h) = s.size();
+        for b in bag.into_iter() {
+            for x in -MAX_EDGE_LENGTH..=w + MAX_EDGE_LENGTH {
+                for y in -MAX_EDGE_LENGTH..=h + MAX_EDGE_LENGTH {
+                    tries.push((i, b, x, y));
+                }
+            }
+        }
+    }
+    println!("{} states, {} placements", states.len(), tries.len());
+    bench(&filter, "try_place", tries.len(), || {
+        tries.iter()
+            .filter(|&&(i, b, x, y)| states[i].try_place(b, x, y).is_some())
+            .count() as u64
+    });
+
+    bench(&filter, "bag_iter", 1, || {
+        bag.into_iter().map(|b| b as u64).sum()
+    });
+
+    // Lookups between each candidate tile and the pieces already placed
+    let last = states.last().unwrap();
+    let lookups: Vec<(usize, i32, i32)> = bag.into_iter()
+        .flat_map(|b| (-MAX_EDGE_LENGTH..=MAX_EDGE_LENGTH)
+            .flat_map(move |x| (-MAX_EDGE_LENGTH..=MAX_EDGE_LENGTH)
+                .map(move |y| (b, x, y))))
+        .collect();
+    bench(&filter, "table_check", lookups.len() * last.pieces.len(), || {
+        let mut n = 0;
+        for &(b, x, y) in &lookups {
+            let t = tables.at(b);
+            for p in last.pieces.iter() {
+                if let Overlap::None = t.check(x + p.x, y + p.y, p) {
+                    n += 1;
+                }
+            }
+        }
+        n
+    });
+
+    // Seen-sets key every node by its canonical form
+    bench(&filter, "canonical", states.len(), || {
+        states.iter().filter(|s| s.canonical() != s.pack()).count() as u64
+    });
+
+    let results = Results::new(rules);
+    let rest = bag.take(0).take(MAX_ROTATIONS).take(2 * MAX_ROTATIONS);
+    bench(&filter, "upper_score_bound", states.len(), || {
+        states.iter()
+            .map(|s| results.upper_score_bound(&rest, s) as u64)
+            .sum()
+    });
+
+    // Score and subset lookups for every bag, in a scattered order (as the
+    // search's are), so that the table's size shows up as cache misses
+    let mut bags = results.bags();
+    let mut rng = Rng::new(1);
+    for i in (1..bags.len()).rev() {
+        bags.swap(i, rng.below(i + 1));
+    }
+    bench(&filter, "score_lookup", bags.len(), || {
+        bags.iter()
+            .map(|b| results.score(b).unwrap_or(0) as u64 +
+                     results.upper_subset_score(b) as u64)
+            .sum()
+    });
+}
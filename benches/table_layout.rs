@@ -0,0 +1,97 @@
+// Compares the table index layouts on lookup traces from real searches.
+// Run with `cargo bench --bench table_layout`.
+extern crate nmbr9;
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use nmbr9::bag::Bag;
+use nmbr9::piece::{MAX_EDGE_LENGTH, MAX_ROTATIONS};
+use nmbr9::state::State;
+use nmbr9::tables::{IndexLayout, TABLE_SIZE};
+
+// A table lookup:  candidate piece, offset, and the placed piece's id
+type Lookup = (usize, i32, i32, usize);
+
+// Collects states reached by a breadth-first search of the given bag
+fn states(bag: &Bag, count: usize) -> Vec<State> {
+    let mut out = Vec::new();
+    let mut todo = VecDeque::new();
+    todo.push_back((bag.clone(), State::new().try_place(0, 0, 0).unwrap()));
+    while let Some((bag, state)) = todo.pop_front() {
+        if out.len() >= count {
+            break;
+        }
+        let size = state.size();
+        for b in bag.into_iter() {
+            for x in -MAX_EDGE_LENGTH..=size.0 + MAX_EDGE_LENGTH {
+                for y in -MAX_EDGE_LENGTH..=size.1 + MAX_EDGE_LENGTH {
+                    if todo.len() >= count {
+                        continue;
+                    }
+                    if let Some(s) = state.try_place(b, x, y) {
+                        todo.push_back((bag.take(b), s));
+                    }
+                }
+            }
+        }
+        out.push(state);
+    }
+    out
+}
+
+// Records the lookups that the worker's scan makes against each state,
+// in the order that it makes them
+fn trace(states: &[State], bag: &Bag) -> Vec<Lookup> {
+    let mut out = Vec::new();
+    for state in states {
+        let size = state.size();
+        for b in bag.into_iter() {
+            for x in -MAX_EDGE_LENGTH..=size.0 + MAX_EDGE_LENGTH {
+                for y in -MAX_EDGE_LENGTH..=size.1 + MAX_EDGE_LENGTH {
+                    for p in state.pieces.iter() {
+                        let (dx, dy) = (x - p.x, y - p.y);
+                        if dx.abs() <= MAX_EDGE_LENGTH &&
+                           dy.abs() <= MAX_EDGE_LENGTH
+                        {
+                            out.push((b, dx, dy, p.id()));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+fn time(layout: IndexLayout, data: &[u8], trace: &[Lookup]) -> (Duration, u64) {
+    let start = Instant::now();
+    let mut sum = 0u64;
+    for _ in 0..50 {
+        for &(t, x, y, id) in trace {
+            sum += data[t * TABLE_SIZE + layout.index(x, y, id)] as u64;
+        }
+    }
+    (start.elapsed(), sum)
+}
+
+fn main() {
+    let mut bag = Bag::from_usize(0);
+    for i in 0..10 {
+        bag = bag.add(i * MAX_ROTATIONS).add(i * MAX_ROTATIONS + 1);
+    }
+    let states = states(&bag.take(0), 50);
+    let trace = trace(&states, &bag);
+    println!("{} lookups from {} states", trace.len(), states.len());
+
+    // Fill in pseudo-random table contents, so that nothing is optimized out
+    let data: Vec<u8> = (0..TABLE_SIZE * bag.into_iter().max().unwrap() + TABLE_SIZE)
+        .map(|i| ((i * 2654435761) >> 7) as u8)
+        .collect();
+
+    for &layout in &[IndexLayout::XMajor, IndexLayout::YMajor,
+                     IndexLayout::XMajor, IndexLayout::YMajor] {
+        let (t, sum) = time(layout, &data, &trace);
+        println!("{:?}: {:?} (checksum {})", layout, t, sum);
+    }
+}
@@ -0,0 +1,5 @@
+// The shared library for the C API and Python bindings, which are part of
+// the main crate.  Linking it in brings along their exported symbols.
+extern crate nmbr9;
+
+pub use nmbr9::ffi::*;
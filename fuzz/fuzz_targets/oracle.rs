@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+
+use nmbr9::piece::{PIECES, shape_of};
+use nmbr9::state::State;
+
+// A deliberately naive model of the placement rules, working on
+// individual grid cells rather than the precomputed overlap tables.
+pub struct Grid {
+    // Maps (x, y, z) to the index (in state.pieces) of the occupying piece
+    cells: HashMap<(i32, i32, usize), usize>,
+    layers: usize,
+}
+
+pub fn cells(id: usize, x: i32, y: i32) -> Vec<(i32, i32)> {
+    shape_of(&PIECES, id)
+        .pts.iter().map(|&(px, py)| (px + x, py + y)).collect()
+}
+
+impl Grid {
+    pub fn new(state: &State) -> Grid {
+        let mut out = Grid { cells: HashMap::new(), layers: state.layers() };
+        for (i, p) in state.pieces.iter().enumerate() {
+            let id = p.id();
+            for (x, y) in cells(id, p.x, p.y) {
+                let prev = out.cells.insert((x, y, p.z), i);
+                assert!(prev.is_none(), "Pieces overlap at {:?}", (x, y, p.z));
+            }
+        }
+        out
+    }
+
+    fn height(&self, x: i32, y: i32) -> usize {
+        let mut z = 0;
+        while self.cells.contains_key(&(x, y, z)) {
+            z += 1;
+        }
+        z
+    }
+
+    // Returns the layer on which the given piece would land, or None
+    // if the placement is illegal.
+    pub fn place(&self, state: &State, id: usize, x: i32, y: i32) -> Option<usize> {
+        if state.is_empty() {
+            return Some(0);
+        }
+
+        let pts = cells(id, x, y);
+
+        // Every cell must be at the same height
+        let z = self.height(pts[0].0, pts[0].1);
+        if pts.iter().any(|&(x, y)| self.height(x, y) != z) {
+            return None;
+        }
+
+        // Pieces above the ground must bridge at least two pieces
+        if z > 0 {
+            let mut below: Vec<usize> = pts.iter()
+                .map(|&(x, y)| self.cells[&(x, y, z - 1)])
+                .collect();
+            below.sort();
+            below.dedup();
+            if below.len() < 2 {
+                return None;
+            }
+        }
+
+        // Pieces on an existing layer must touch a piece on that layer
+        if z <= self.layers {
+            let touching = pts.iter().any(|&(x, y)|
+                [(0, 1), (0, -1), (1, 0), (-1, 0)].iter().any(|&(dx, dy)|
+                    self.cells.contains_key(&(x + dx, y + dy, z))));
+            if !touching {
+                return None;
+            }
+        }
+        Some(z)
+    }
+}
@@ -0,0 +1,47 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate nmbr9;
+
+use nmbr9::piece::{Overlap, Adjacency};
+use nmbr9::piece::{PIECES, MIRRORED_ID, PIECE_ID_COUNT, shape_of};
+use nmbr9::state::Placed;
+use nmbr9::tables::Tables;
+
+// Compares a lookup in the precomputed overlap tables against
+// a direct check of the two pieces' bitmaps, including offsets
+// beyond the edge of the tables, with or without the mirrored pieces.
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 5 {
+        return;
+    }
+    let mirrored = data[4] & 2 != 0;
+    let count = if mirrored { PIECE_ID_COUNT } else { MIRRORED_ID };
+    let piece = data[0] as usize % count;
+    let placed = data[1] as usize % count;
+    let x = (data[2] as i8 as i32) % 10;
+    let y = (data[3] as i8 as i32) % 10;
+    let adjacency = if data[4] & 1 == 0 {
+        Adjacency::Orthogonal
+    } else {
+        Adjacency::Diagonal
+    };
+
+    let tables = Tables::get(adjacency, mirrored);
+    let p = Placed::new(placed, 0, 0, 0);
+    let result = tables.at(piece).check(x, y, &p);
+
+    let base = shape_of(&PIECES, p.id());
+    let other = shape_of(&PIECES, piece);
+    let expected = base.check(&other, x, y, adjacency);
+
+    // Neighbors are left out of the tables and found with masks instead
+    match (result, expected) {
+        (Overlap::Partial(i), Overlap::_Partial(b)) =>
+            assert_eq!(tables.bitmap(i), b),
+        (Overlap::None, Overlap::Neighbor) =>
+            assert!(tables.touches(piece, x, y, &[p])),
+        (Overlap::None, Overlap::None) =>
+            assert!(!tables.touches(piece, x, y, &[p])),
+        (a, b) => assert_eq!(a, b),
+    }
+});
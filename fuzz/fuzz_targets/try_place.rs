@@ -0,0 +1,64 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate nmbr9;
+
+mod oracle;
+
+use nmbr9::piece::{UNIQUE_PIECE_COUNT, MAX_ROTATIONS, MAX_EDGE_LENGTH};
+use nmbr9::piece::PIECE_VALUES;
+use nmbr9::state::State;
+use nmbr9::bitboard::Bitboards;
+
+// Each move is three bytes: piece id and rotation, then x and y offsets
+// (spanning a margin around the current footprint, so that positions which
+// land just outside the overlap tables' range are exercised).
+fuzz_target!(|data: &[u8]| {
+    if data.is_empty() {
+        return;
+    }
+
+    // The first piece can go anywhere, so we only pick its rotation
+    let first = data[0] as usize % (UNIQUE_PIECE_COUNT * MAX_ROTATIONS);
+    let mut state = State::new().try_place(first, 0, 0).unwrap();
+
+    for m in data[1..].chunks(3) {
+        if m.len() < 3 || state.pieces.is_full() {
+            break;
+        }
+        let id = m[0] as usize % (UNIQUE_PIECE_COUNT * MAX_ROTATIONS);
+        let (w, h) = state.size();
+        let margin = MAX_EDGE_LENGTH + 2;
+        let x = (m[1] as i32) % (w + 2 * margin) - margin;
+        let y = (m[2] as i32) % (h + 2 * margin) - margin;
+
+        let grid = oracle::Grid::new(&state);
+        let expected = grid.place(&state, id, x, y);
+
+        // The worker's whole-row check must agree, wherever it's used
+        if x >= -MAX_EDGE_LENGTH {
+            let row = Bitboards::new(&state).row(id, y, x, x);
+            assert_eq!(row != 0, expected.is_some(),
+                       "Bitboards disagree at {}, {}", x, y);
+        }
+
+        match state.try_place(id, x, y) {
+            None => assert_eq!(expected, None,
+                               "Rejected legal placement {} at {}, {}",
+                               id, x, y),
+            Some(next) => {
+                let z = expected.expect("Accepted illegal placement");
+                assert_eq!(next.pieces.len(), state.pieces.len() + 1);
+                let value = PIECE_VALUES[id / MAX_ROTATIONS];
+                assert_eq!(next.score(), state.score() + value * z as isize);
+
+                let recomputed: isize = next.pieces.iter()
+                    .map(|p| PIECE_VALUES[p.index()] * p.z as isize).sum();
+                assert_eq!(next.score(), recomputed);
+
+                // Rebuilding the grid checks that nothing overlaps
+                oracle::Grid::new(&next);
+                state = next;
+            }
+        }
+    }
+});
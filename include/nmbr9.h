@@ -0,0 +1,57 @@
+/*  C API for the NMBR 9 placement rules (see src/ffi.rs)
+ *
+ *  Link against the shared library built by `cargo build --release` in
+ *  cdylib/ (cdylib/target/release/libnmbr9.so, .dylib, or nmbr9.dll).
+ *
+ *  States are opaque handles, created by nmbr9_state_new or
+ *  nmbr9_state_clone and released with nmbr9_state_free.  Every function
+ *  which takes a handle expects a live one (not null, not yet freed).
+ */
+#ifndef NMBR9_H
+#define NMBR9_H
+
+#include <stdbool.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct nmbr9_state nmbr9_state;
+
+/*  A placed tile, as read back by nmbr9_state_piece.  x and y are the
+ *  corner of the tile's bounding box, and z is its layer (0 is the table). */
+typedef struct {
+    uint32_t tile;
+    uint32_t rotation;
+    int32_t x;
+    int32_t y;
+    uint32_t z;
+} Nmbr9Piece;
+
+/*  Returns an empty table.  If diagonal is true, tiles touching at a
+ *  corner count as neighbors (a common house rule). */
+nmbr9_state* nmbr9_state_new(bool diagonal);
+nmbr9_state* nmbr9_state_clone(const nmbr9_state* state);
+void nmbr9_state_free(nmbr9_state* state);
+
+/*  Places a tile (0-9), turned by rotation quarter turns, with its
+ *  bounding box's corner at x, y.  Returns false (leaving the state
+ *  unchanged) if the placement is illegal.  After a placement the
+ *  arrangement is shifted so that its smallest x and y are 0, so positions
+ *  should be read back with nmbr9_state_piece. */
+bool nmbr9_state_place(nmbr9_state* state, uint32_t tile, uint32_t rotation,
+                       int32_t x, int32_t y);
+
+int64_t nmbr9_state_score(const nmbr9_state* state);
+uint32_t nmbr9_state_piece_count(const nmbr9_state* state);
+
+/*  Reads the i'th placed tile (ordered from the top layer down) into out,
+ *  returning false if there aren't that many tiles */
+bool nmbr9_state_piece(const nmbr9_state* state, uint32_t i, Nmbr9Piece* out);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
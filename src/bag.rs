@@ -1,6 +1,12 @@
-use piece::{UNIQUE_PIECE_COUNT, MAX_ROTATIONS};
+use std::cmp::{max, min, Ordering, Reverse};
+use std::fmt;
+use std::str::FromStr;
 
-#[derive(Clone, Debug)]
+use piece::{UNIQUE_PIECE_COUNT, MAX_COPIES, MAX_ROTATIONS, PIECE_VALUES, Values, pieces};
+use piece::{PIECE_ID_COUNT, tile};
+use error::{self, Error};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Bag {
     data: [usize; UNIQUE_PIECE_COUNT],
 }
@@ -10,27 +16,93 @@ impl Bag {
         Bag { data: [0; UNIQUE_PIECE_COUNT] }
     }
 
-    // Interprets an integer as a ternary number
-    // that tells us how many of each piece we put
+    // Interprets an integer as a number in base MAX_COPIES + 1 (ternary,
+    // for the standard game) that tells us how many of each piece we put
     // into the bag.
-    pub fn from_usize(mut p: usize) -> Bag {
+    pub fn from_usize(p: usize) -> Bag {
+        Bag::from_index(p, MAX_COPIES)
+    }
+
+    pub fn as_usize(&self) -> usize {
+        self.index(MAX_COPIES)
+    }
+
+    // As from_usize, but for a game with some other number of copies of
+    // each piece (so the integer is interpreted in base copies + 1)
+    pub fn from_index(mut p: usize, copies: usize) -> Bag {
         let mut out = Bag::new();
         for i in 0..UNIQUE_PIECE_COUNT {
-            out.data[i] = p % 3;
-            p /= 3;
+            out.data[i] = p % (copies + 1);
+            p /= copies + 1;
         }
         return out;
     }
 
-    pub fn as_usize(&self) -> usize {
+    pub fn index(&self, copies: usize) -> usize {
         let mut p = 0;
         for i in (0..UNIQUE_PIECE_COUNT).rev() {
-            p *= 3;
+            debug_assert!(self.data[i] <= copies);
+            p *= copies + 1;
             p += self.data[i];
         }
         return p;
     }
 
+    // Returns the count of each tile as one digit per tile, so that
+    // 0120000000 is a 1 and two 2s
+    pub fn digits(&self) -> String {
+        self.data.iter().map(|d| d.to_string()).collect()
+    }
+
+    // Parses the output of digits(), returning None if it's malformed or
+    // has more than the given number of copies of any tile
+    pub fn from_digits(s: &str, copies: usize) -> Option<Bag> {
+        if s.len() != UNIQUE_PIECE_COUNT {
+            return None;
+        }
+        let mut out = Bag::new();
+        for (i, c) in s.chars().enumerate() {
+            out.data[i] = c.to_digit(10).filter(|&d| d as usize <= copies)?
+                as usize;
+        }
+        return Some(out);
+    }
+
+    // Parses a bag either in the form of digits() or as a list of its
+    // tiles, e.g. 9,9,8,7,3 (so a bag of ten tiles must use commas).  An
+    // empty string is the empty bag.
+    pub fn parse(s: &str, copies: usize) -> Result<Bag, String> {
+        let s = s.trim();
+        if s.len() == UNIQUE_PIECE_COUNT && !s.contains(',') {
+            return Bag::from_digits(s, copies).ok_or_else(|| {
+                format!("Invalid bag '{}' (expected one digit from 0 to {} \
+                         per tile)", s, copies)
+            });
+        }
+        let mut out = Bag::new();
+        for t in s.split(',').map(|t| t.trim()).filter(|t| !t.is_empty()) {
+            let tile = t.parse::<usize>().ok()
+                .filter(|&t| t < UNIQUE_PIECE_COUNT)
+                .ok_or_else(|| format!("Invalid tile '{}' in bag '{}'", t, s))?;
+            if out.data[tile] == copies {
+                return Err(format!("Too many {}s in bag '{}' (at most {})",
+                                   tile, s, copies));
+            }
+            out.data[tile] += 1;
+        }
+        return Ok(out);
+    }
+
+    // Returns the tiles from a full set (with the given number of copies
+    // of each piece) that aren't in this bag
+    pub fn complement(&self, copies: usize) -> Bag {
+        let mut out = Bag::new();
+        for i in 0..UNIQUE_PIECE_COUNT {
+            out.data[i] = copies - self.data[i];
+        }
+        return out;
+    }
+
     pub fn len(&self) -> usize {
         self.data.iter().sum()
     }
@@ -39,17 +111,58 @@ impl Bag {
         self.len() == 0
     }
 
+    // Returns how many copies of the given piece (0-9) are in the bag,
+    // i.e. the corresponding ternary digit
+    pub fn digit(&self, index: usize) -> usize {
+        self.data[index]
+    }
+
+    // Adds a piece (given as a piece id, i.e. tile * MAX_ROTATIONS +
+    // rotation, or a mirrored id after those) to the bag.  This is the
+    // search's fast path, so the bag having room is only checked in debug
+    // builds; use try_add for ids which come from outside the library.
+    pub fn add(&self, id: usize) -> Bag {
+        let mut out = self.clone();
+        let index = tile(id);
+        debug_assert!(out.data[index] < MAX_COPIES,
+                      "Attempted to add too many copies of a piece");
+        out.data[index] += 1;
+        return out;
+    }
+
+    // Removes a piece from the bag, which must hold it (checked only in
+    // debug builds, as with add; see try_take)
     pub fn take(&self, id: usize) -> Bag {
         let mut out = self.clone();
-        let index = id / MAX_ROTATIONS;
-        if out.data[index] == 0 {
-            panic!("Attempted to remove non-existent piece");
-        } else {
-            out.data[index] -= 1;
-        }
+        let index = tile(id);
+        debug_assert!(out.data[index] > 0,
+                      "Attempted to remove non-existent piece");
+        out.data[index] -= 1;
         return out;
     }
 
+    // Checked version of add
+    pub fn try_add(&self, id: usize) -> error::Result<Bag> {
+        let index = tile(id);
+        if id >= PIECE_ID_COUNT {
+            return Err(Error::InvalidPieceId(id));
+        } else if self.data[index] == MAX_COPIES {
+            return Err(Error::TooManyCopies(index));
+        }
+        return Ok(self.add(id));
+    }
+
+    // Checked version of take
+    pub fn try_take(&self, id: usize) -> error::Result<Bag> {
+        let index = tile(id);
+        if id >= PIECE_ID_COUNT {
+            return Err(Error::InvalidPieceId(id));
+        } else if self.data[index] == 0 {
+            return Err(Error::MissingTile(index));
+        }
+        return Ok(self.take(id));
+    }
+
     pub fn contains(&self, other: &Bag) -> bool {
         for i in 0..UNIQUE_PIECE_COUNT {
             if other.data[i] > self.data[i] {
@@ -59,30 +172,149 @@ impl Bag {
         return true;
     }
 
+    // Returns the total number of cells covered by the tiles in the bag
+    pub fn area(&self) -> usize {
+        let areas = &pieces().areas;
+        let mut a = 0;
+        for i in 0..UNIQUE_PIECE_COUNT {
+            a += self.data[i] * areas[i];
+        }
+        return a;
+    }
+
     // Returns the flat score of all the tiles in the bag
     // (i.e. the sum of their face values)
-    pub fn score_flat(&self) -> usize {
+    pub fn score_flat(&self) -> isize {
+        self.score_flat_with(&PIECE_VALUES)
+    }
+
+    // As score_flat, but with a custom value for each piece
+    pub fn score_flat_with(&self, values: &Values) -> isize {
         let mut s = 0;
         for i in 0..UNIQUE_PIECE_COUNT {
-            s += self.data[i] * i;
+            s += self.data[i] as isize * values[i];
         }
         return s;
     }
 
-    // Returns an upper bound on score if the tiles are stacked
-    // This has the conservative assumption that each layer is a mere
-    // two tiles (the minimum to stack onto).
-    pub fn score_stacked(&self) -> usize {
+    // Returns an upper bound on score if the tiles are stacked.  This is
+    // the tighter of two bounds:  one where each layer is a mere two tiles
+    // (the minimum to stack onto), and one from the tiles' areas (see
+    // stacked_by_area).
+    pub fn score_stacked(&self) -> isize {
+        self.score_stacked_by(&PIECE_VALUES, |z| z)
+    }
+
+    // As score_stacked, but with a custom value for each piece
+    // and a custom multiplier for each level.  Negative tiles are best
+    // buried on the ground, so they contribute nothing to the bound.
+    // The multiplier must never shrink as levels rise, or neither bound
+    // would hold.
+    pub fn score_stacked_by<F>(&self, values: &Values, weight: F) -> isize
+        where F: Fn(usize) -> usize
+    {
+        // The most valuable pieces go on top
+        let mut order: Vec<usize> = (0..UNIQUE_PIECE_COUNT).collect();
+        order.sort_by_key(|&p| Reverse(values[p]));
+
         let mut remaining = self.len();
         let mut score = 0;
-        for p in (0..UNIQUE_PIECE_COUNT).rev() {
+        for p in order {
             for _ in 0..self.data[p] {
-                score += p * (((remaining + 1) / 2) - 1);
+                let w = weight(remaining.div_ceil(2) - 1) as isize;
+                score += max(values[p], 0) * w;
                 remaining -= 1;
             }
         }
         debug_assert!(remaining == 0);
-        return score;
+        return min(score, self.stacked_by_area(values, &weight));
+    }
+
+    // Each layer covers no more cells than the one below it, so in a stack
+    // of n layers, the top k cover at most k/n of the bag's area.  Filling
+    // n equal layers from the top with the tiles worth the most per cell
+    // (splitting tiles between layers where needed) beats any real stack
+    // with n layers, and the tallest stack has as many layers as the count
+    // bound allows.  Scores are kept as multiples of 1/(lcm * n), so that
+    // the sums are exact.
+    fn stacked_by_area<F>(&self, values: &Values, weight: &F) -> isize
+        where F: Fn(usize) -> usize
+    {
+        let areas = &pieces().areas;
+        let mut order: Vec<usize> = (0..UNIQUE_PIECE_COUNT)
+            .filter(|&p| self.data[p] > 0 && values[p] > 0)
+            .collect();
+        if order.iter().any(|&p| areas[p] == 0) {
+            return isize::MAX;
+        }
+        order.sort_by_key(|&p| Reverse(Ratio(values[p], areas[p])));
+        let lcm = order.iter().fold(1, |l, &p| l / gcd(l, areas[p]) * areas[p]);
+
+        let mut best = 0;
+        for top in 0..self.len().div_ceil(2) {
+            // Each cell is split into one unit per layer, so that each
+            // layer holds as many units as the bag has cells
+            let layers = top + 1;
+            let mut z = top;
+            let mut left = self.area();
+            let mut score = 0;
+            for &p in &order {
+                let per_unit = values[p] * (lcm / areas[p]) as isize;
+                let mut units = self.data[p] * areas[p] * layers;
+                while units > 0 {
+                    let used = min(units, left);
+                    score += per_unit * (used * weight(z)) as isize;
+                    units -= used;
+                    left -= used;
+                    if left == 0 && z > 0 {
+                        z -= 1;
+                        left = self.area();
+                    }
+                }
+            }
+            best = max(best, score / (lcm * layers) as isize);
+        }
+        return best;
+    }
+}
+
+// A tile's value per cell, as a fraction which compares exactly
+#[derive(Copy, Clone, PartialEq, Eq)]
+struct Ratio(isize, usize);
+
+impl PartialOrd for Ratio {
+    fn partial_cmp(&self, other: &Ratio) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Ratio {
+    fn cmp(&self, other: &Ratio) -> Ordering {
+        (self.0 * other.1 as isize).cmp(&(other.0 * self.1 as isize))
+    }
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+// Bags are written as a list of their tiles, largest first, e.g. 9,9,8,7,3
+impl fmt::Display for Bag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let tiles: Vec<String> = (0..UNIQUE_PIECE_COUNT).rev()
+            .flat_map(|i| std::iter::repeat_n(i.to_string(), self.data[i]))
+            .collect();
+        write!(f, "{}", tiles.join(","))
+    }
+}
+
+// Parses either form accepted by Bag::parse, with up to MAX_COPIES of
+// each tile
+impl FromStr for Bag {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Bag, String> {
+        Bag::parse(s, MAX_COPIES)
     }
 }
 
@@ -115,8 +347,9 @@ impl<'a> Iterator for BagIterator<'a> {
 
         let out = self.i * MAX_ROTATIONS + self.r;
 
+        // Symmetric tiles (the 0 and 8) only yield their distinct rotations
         self.r += 1;
-        if self.r == MAX_ROTATIONS
+        if self.r == pieces().distinct_rotations[self.i]
         {
             self.r = 0;
             self.i += 1;
@@ -182,6 +415,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(debug_assertions)]
     #[should_panic]
     fn bad_take() /* Hi Twitter! */ {
         let b = Bag::from_usize(1);
@@ -189,6 +423,49 @@ mod tests {
         assert_eq!(b.len(), 0);
     }
 
+    #[test]
+    fn complement() {
+        let b = Bag::from_usize(0).complement(2);
+        assert_eq!(b.len(), 2 * UNIQUE_PIECE_COUNT);
+        assert_eq!(b.complement(2).as_usize(), 0);
+
+        let b = Bag::from_usize(5).complement(2);
+        assert_eq!(b.digit(0), 0);
+        assert_eq!(b.digit(1), 1);
+        assert_eq!(b.digit(2), 2);
+    }
+
+    #[test]
+    fn add() {
+        let b = Bag::from_usize(0).add(3).add(1);
+        assert_eq!(b.digit(0), 2);
+        assert_eq!(b.as_usize(), 2);
+        let b = b.take(1).add(4);
+        assert_eq!(b.digit(0), 1);
+        assert_eq!(b.digit(1), 1);
+        assert_eq!(b.as_usize(), 4);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic]
+    fn bad_add() {
+        let b = Bag::from_usize(2);
+        b.add(0);
+    }
+
+    #[test]
+    fn try_add_take() {
+        let b = Bag::from_usize(2);
+        assert_eq!(b.try_add(0), Err(Error::TooManyCopies(0)));
+        assert_eq!(b.try_add(80), Err(Error::InvalidPieceId(80)));
+        assert_eq!(b.try_take(83), Err(Error::InvalidPieceId(83)));
+        assert_eq!(b.try_add(44), b.try_add(4));
+        assert_eq!(b.try_take(4), Err(Error::MissingTile(1)));
+        assert_eq!(b.try_take(3), Ok(Bag::from_usize(1)));
+        assert_eq!(b.try_take(3).unwrap().try_add(5), Ok(Bag::from_usize(4)));
+    }
+
     #[test]
     fn iter() {
         let b = Bag::from_usize(0);
@@ -199,16 +476,12 @@ mod tests {
         let mut i = b.into_iter();
         assert_eq!(i.next(), Some(0));
         assert_eq!(i.next(), Some(1));
-        assert_eq!(i.next(), Some(2));
-        assert_eq!(i.next(), Some(3));
         assert_eq!(i.next(), None);
 
         let b = Bag::from_usize(2);
         let mut i = b.into_iter();
         assert_eq!(i.next(), Some(0));
         assert_eq!(i.next(), Some(1));
-        assert_eq!(i.next(), Some(2));
-        assert_eq!(i.next(), Some(3));
         assert_eq!(i.next(), None);
 
         let b = Bag::from_usize(3);
@@ -218,16 +491,69 @@ mod tests {
         assert_eq!(i.next(), Some(6));
         assert_eq!(i.next(), Some(7));
         assert_eq!(i.next(), None);
+
+        let b = Bag::from_usize(0).add(32);
+        assert_eq!(b.into_iter().collect::<Vec<_>>(), vec![32, 33]);
     }
 
     #[test]
     fn as_usize() {
-        for i in 0..3_usize.pow(10) {
+        for i in 0..(MAX_COPIES + 1).pow(UNIQUE_PIECE_COUNT as u32) {
             let b = Bag::from_usize(i);
             assert_eq!(b.as_usize(), i);
         }
     }
 
+    #[test]
+    fn area() {
+        assert_eq!(Bag::from_usize(0).area(), 0);
+        assert_eq!(Bag::from_usize(1).area(), 10);
+        assert_eq!(Bag::from_usize(2).area(), 20);
+        assert_eq!(Bag::from_usize(3).area(), 5);
+        assert_eq!(Bag::from_usize(4).area(), 15);
+    }
+
+    #[test]
+    fn index() {
+        for i in 0..2_usize.pow(UNIQUE_PIECE_COUNT as u32) {
+            let b = Bag::from_index(i, 1);
+            assert!(b.len() <= UNIQUE_PIECE_COUNT);
+            assert_eq!(b.index(1), i);
+        }
+        assert_eq!(Bag::from_index(5, 1).as_usize(), 10);
+    }
+
+    #[test]
+    fn digits() {
+        let b = Bag::from_usize(0).add(4).add(8).add(9).add(36);
+        assert_eq!(b.digits(), "0120000001");
+        assert_eq!(Bag::from_digits("0120000001", 2), Some(b));
+        assert_eq!(Bag::from_digits("0120000001", 1), None);
+        assert_eq!(Bag::from_digits("012000000", 2), None);
+        assert_eq!(Bag::from_digits("012000000x", 2), None);
+    }
+
+    #[test]
+    fn parse() {
+        let b = Bag::from_usize(0).add(4).add(8).add(9).add(36);
+        assert_eq!(b.to_string(), "9,2,2,1");
+        assert_eq!("9,2,2,1".parse(), Ok(b.clone()));
+        assert_eq!(" 1, 2,9 ,2 ".parse(), Ok(b.clone()));
+        assert_eq!("0120000001".parse(), Ok(b.clone()));
+        assert!(Bag::parse(&b.to_string(), 1).is_err());
+        assert_eq!("".parse(), Ok(Bag::from_usize(0)));
+        assert_eq!(Bag::from_usize(0).to_string(), "");
+
+        assert!("1,2,x".parse::<Bag>().unwrap_err().contains("Invalid tile 'x'"));
+        assert!("10".parse::<Bag>().is_err());
+        assert!("2,2,2".parse::<Bag>().unwrap_err().contains("Too many 2s"));
+        assert!("0120000003".parse::<Bag>().is_err());
+
+        // Ten tiles in a list aren't mistaken for digits
+        let full = "9,8,7,6,5,4,3,2,1,0".parse::<Bag>().unwrap();
+        assert_eq!(full.digits(), "1111111111");
+    }
+
     #[test]
     fn score_flat() {
         assert_eq!(Bag::from_usize(0).score_flat(), 0);
@@ -254,5 +580,12 @@ mod tests {
         assert_eq!(Bag::from_usize(7).score_stacked(), 1);
         assert_eq!(Bag::from_usize(8).score_stacked(), 2);
         assert_eq!(Bag::from_usize(9).score_stacked(), 0);
+
+        // Counting tiles alone would put both 9s on level 1, but two 1s
+        // only cover as many cells as one 9, so at most three quarters of
+        // the 9s' cells can be above the ground
+        let bag = Bag::from_digits("0200000002", 2).unwrap();
+        assert_eq!(bag.score_stacked(), 13);
+        assert_eq!(bag.score_stacked_by(&PIECE_VALUES, |z| z * z), 13);
     }
 }
@@ -0,0 +1,336 @@
+use std::borrow::Borrow;
+
+use piece::MAX_EDGE_LENGTH;
+use state::{Placed, State};
+use tables::Tables;
+
+// Each row of a layer is packed into a u128, with bit c holding the cell
+// at x = c - PAD.  The padding keeps every cell that a candidate (or its
+// neighbors) could touch at a non-negative row and column.
+const PAD: i32 = MAX_EDGE_LENGTH + 1;
+
+// A placed piece's rows, already shifted into its layer's columns, along
+// with the y position of its first row
+struct Shape {
+    y: i32,
+    rows: [u128; 4],
+}
+
+struct Layer {
+    occupied: Vec<u128>,
+    pieces: Vec<Shape>,
+}
+
+// Per-layer bitboards for a state, used to check a whole row of candidate
+// positions at once rather than calling try_place at every (x, y)
+pub struct Bitboards {
+    tables: &'static Tables,
+    offsets: &'static [(i32, i32)],
+    layers: Vec<Layer>,
+
+    // Pieces can't land on layers at or above this one (see
+    // State::with_max_layers)
+    max_layers: usize,
+}
+
+// Returns the cells of a packed 4x4 bitmap
+fn cells(bmp: u16) -> impl Iterator<Item=(i32, i32)> {
+    (0..16).filter(move |i| bmp & (1 << i) != 0)
+           .map(|i| (3 - (i % 4), i / 4))
+}
+
+// Shifts a row so that bit c holds what was at column c + dx
+fn shift(row: u128, dx: i32) -> u128 {
+    if dx >= 0 { row >> dx } else { row << -dx }
+}
+
+impl Layer {
+    fn row(&self, r: i32) -> u128 {
+        if r < 0 {
+            return 0;
+        }
+        return self.occupied.get(r as usize).cloned().unwrap_or(0);
+    }
+}
+
+impl Bitboards {
+    pub fn new(state: &State) -> Bitboards {
+        let tables = Tables::get(state.adjacency(), state.mirrored());
+        let (w, h) = state.size();
+        assert!(w + 2 * PAD + 4 <= 128, "State is too wide for bitboards");
+
+        // An empty state has no layers, so that the first piece can go
+        // anywhere
+        let count = if state.is_empty() { 0 } else { state.layers() + 1 };
+        let mut layers: Vec<Layer> = (0..count)
+            .map(|_| Layer {
+                occupied: vec![0; (h + 2 * PAD + 4) as usize],
+                pieces: Vec::new(),
+            })
+            .collect();
+        for p in state.pieces.iter() {
+            let bmp = tables.bitmap(p.id());
+            let mut rows = [0; 4];
+            for (x, y) in cells(bmp) {
+                rows[y as usize] |= 1 << (p.x + x + PAD);
+            }
+            let layer = &mut layers[p.z];
+            for y in 0..4 {
+                layer.occupied[(p.y + y + PAD) as usize] |= rows[y as usize];
+            }
+            layer.pieces.push(Shape { y: p.y, rows: rows });
+        }
+
+        return Bitboards {
+            tables: tables,
+            offsets: state.adjacency().offsets(),
+            layers: layers,
+            max_layers: state.max_layers().unwrap_or(usize::MAX),
+        };
+    }
+
+    // Returns the legal placements of a piece onto the state that these
+    // bitboards were built from, reusing them for each piece tried
+    pub fn placements<'a>(&'a self, state: &'a State, piece: usize)
+        -> Placements<'a, &'a Bitboards>
+    {
+        Placements::new(state, self, piece)
+    }
+
+    // Returns a mask of the positions from xmin to xmax (inclusive) where
+    // try_place would accept one of the original pieces at the given y,
+    // with bit i set if it would be accepted at xmin + i.
+    pub fn row(&self, piece: usize, y: i32, xmin: i32, xmax: i32) -> u128 {
+        debug_assert!(xmax >= xmin);
+        debug_assert!(xmin + PAD >= 1);
+        debug_assert!(xmax - xmin < 128);
+
+        let bmp = self.tables.bitmap(piece);
+        let width = xmax - xmin + 1;
+        let range = if width == 128 { !0 } else { (1 << width) - 1 };
+
+        // Positions that are still scanning down through the layers, and
+        // whether each one has a neighbor on the layer above.  As in
+        // try_place, nothing is needed above the top layer.
+        let mut pending = range;
+        let mut above = range;
+        let mut out = 0;
+
+        for (z, layer) in self.layers.iter().enumerate().rev() {
+            // Bit i of each mask is for the candidate at xmin + i
+            let col = xmin + PAD;
+            let r = y + PAD;
+
+            let mut hit = 0;
+            let mut cover = range;
+            let mut neighbor = 0;
+            for (cx, cy) in cells(bmp) {
+                let o = shift(layer.row(r + cy), col + cx);
+                hit |= o;
+                cover &= o;
+                for &(nx, ny) in self.offsets {
+                    neighbor |= shift(layer.row(r + cy + ny), col + cx + nx);
+                }
+            }
+
+            // Landing on a single piece isn't allowed
+            let mut single = 0;
+            for p in layer.pieces.iter().filter(|p| (p.y - y).abs() < 4) {
+                let mut m = range;
+                for (cx, cy) in cells(bmp) {
+                    let dy = y + cy - p.y;
+                    m &= if dy >= 0 && dy < 4 {
+                        shift(p.rows[dy as usize], col + cx)
+                    } else {
+                        0
+                    };
+                }
+                single |= m;
+            }
+
+            // Landing fully on several pieces also needs a neighbor on the
+            // layer above (and room under the cap); anything else that
+            // overlaps is rejected.
+            if z + 1 < self.max_layers {
+                out |= pending & cover & !single & above;
+            }
+            pending &= !hit;
+            above = neighbor;
+        }
+
+        // Whatever is left lands on the table, beside a piece there
+        return out | (pending & above);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Iterates over every legal placement of one piece (with its rotation)
+// onto a state, a row at a time, yielding the placement (in the state's
+// coordinates) along with the state that it makes.  The bitboards are
+// either owned (from State::placements) or shared between the pieces
+// being tried on one state (from Bitboards::placements).
+pub struct Placements<'a, B> {
+    state: &'a State,
+    boards: B,
+    piece: usize,
+
+    // Total of the pieces' layers before placing, from which the new
+    // piece's layer is found
+    height: usize,
+
+    margin: i32,
+    size: (i32, i32),
+    y: i32,
+    legal: u128,
+}
+
+impl<'a, B: Borrow<Bitboards>> Placements<'a, B> {
+    pub fn new(state: &'a State, boards: B, piece: usize) -> Placements<'a, B> {
+        // An empty state only needs its first piece at the origin
+        let margin = if state.is_empty() { 0 } else { MAX_EDGE_LENGTH };
+        Placements {
+            state: state,
+            boards: boards,
+            piece: piece,
+            height: state.pieces.iter().map(|p| p.z).sum(),
+            margin: margin,
+            size: state.size(),
+            y: -margin - 1,
+            legal: 0,
+        }
+    }
+}
+
+impl<'a, B: Borrow<Bitboards>> Iterator for Placements<'a, B> {
+    type Item = (Placed, State);
+
+    fn next(&mut self) -> Option<(Placed, State)> {
+        // Check a whole row at once, then only build states for the
+        // positions where the piece can actually go
+        while self.legal == 0 {
+            if self.y >= self.size.1 + self.margin {
+                return None;
+            }
+            self.y += 1;
+            self.legal = self.boards.borrow().row(
+                self.piece, self.y, -self.margin, self.size.0 + self.margin);
+        }
+        let x = -self.margin + self.legal.trailing_zeros() as i32;
+        self.legal &= self.legal - 1;
+
+        let s = self.state.try_place(self.piece, x, self.y)
+            .expect("Bitboards disagree with try_place");
+        // The new piece's layer is however much higher the pieces are,
+        // in total
+        let z = s.pieces.iter().map(|p| p.z).sum::<usize>() - self.height;
+        return Some((Placed::new(self.piece, x, self.y, z), s));
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use piece::{Adjacency, MIRRORED_ID, PIECE_ID_COUNT};
+
+    // Checks every row against try_place, for every piece that the state
+    // allows (the original 40, or those and the mirrored ones)
+    fn check(state: &State) {
+        let boards = Bitboards::new(state);
+        let (w, h) = state.size();
+        let m = MAX_EDGE_LENGTH;
+        let count = if state.mirrored() { PIECE_ID_COUNT } else { MIRRORED_ID };
+        for piece in 0..count {
+            for y in -m..=h + m {
+                let row = boards.row(piece, y, -m, w + m);
+                for x in -m..=w + m {
+                    assert_eq!(row & (1 << (x + m)) != 0,
+                               state.try_place(piece, x, y).is_some(),
+                               "piece {} at {}, {}", piece, x, y);
+                }
+            }
+
+            // The placements are the positions that try_place accepts,
+            // on the layer that the piece lands on
+            let mut expected = Vec::new();
+            for y in -m..=h + m {
+                for x in -m..=w + m {
+                    if let Some(s) = state.try_place(piece, x, y) {
+                        expected.push((x, y, s));
+                    }
+                }
+            }
+            if state.is_empty() {
+                expected.retain(|&(x, y, _)| x == 0 && y == 0);
+            }
+            let found: Vec<_> = boards.placements(state, piece).collect();
+            assert_eq!(found.len(), expected.len(), "piece {}", piece);
+            for (p, s) in found {
+                assert!(expected.iter()
+                        .any(|e| (e.0, e.1, &e.2) == (p.x, p.y, &s)));
+                assert_eq!(p.id(), piece);
+                assert!(s.pieces.iter().any(|q| q.z == p.z &&
+                                                q.index() == p.index()));
+            }
+        }
+    }
+
+    // Builds up a few layers by placing each piece wherever it lands
+    // highest (keeping things compact otherwise), checking the bitboards at
+    // every step.  If the state allows mirrored pieces, every other piece
+    // is mirrored.
+    fn stack(mut state: State) -> State {
+        check(&state);
+        for (i, &piece) in [0, 4, 8, 1, 20, 37, 13, 26, 30, 9, 0, 4, 12, 16, 24,
+                            33, 5, 28].iter().enumerate() {
+            let piece = if state.mirrored() && i % 3 == 2 {
+                piece + MIRRORED_ID
+            } else {
+                piece
+            };
+            let (w, h) = state.size();
+            let m = MAX_EDGE_LENGTH;
+            state = (-m..=w + m)
+                .flat_map(|x| (-m..=h + m).map(move |y| (x, y)))
+                .filter_map(|(x, y)| state.try_place(piece, x, y))
+                .max_by_key(|s| {
+                    let (w, h) = s.size();
+                    (s.layers(), -(w + h))
+                })
+                .unwrap();
+            check(&state);
+        }
+        assert!(state.layers() >= 2);
+        return state;
+    }
+
+    #[test]
+    fn row() {
+        stack(State::new());
+    }
+
+    #[test]
+    fn row_diagonal() {
+        stack(State::with_adjacency(Adjacency::Diagonal));
+    }
+
+    #[test]
+    fn row_mirrored() {
+        let state = stack(State::new().with_mirrored(true));
+        assert!(state.pieces.iter().any(|p| p.mirrored()));
+    }
+
+    #[test]
+    fn row_capped() {
+        // Under a cap, pieces can still land below it but not on it
+        let state = stack(State::new());
+        for n in 1..=state.layers() + 1 {
+            let capped = state.clone().with_max_layers(Some(n));
+            check(&capped);
+            assert!(Bitboards::new(&capped).placements(&capped, 0)
+                    .all(|(p, _)| p.z < n));
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use image::Image;
+
+use bag::Bag;
+use piece::UNIQUE_PIECE_COUNT;
+use state::State;
+use theme::theme;
+
+// Sized for link previews on most social media sites (16:9)
+pub const WIDTH: usize = 1200;
+pub const HEIGHT: usize = 675;
+
+// Headline text, with everything else in the theme's label color
+const FOREGROUND: [u8; 3] = [240, 240, 240];
+
+// Lists a bag's tiles, separated by spaces
+pub fn tiles(bag: &Bag) -> String {
+    let tiles: Vec<String> = (0..UNIQUE_PIECE_COUNT)
+        .flat_map(|i| (0..bag.digit(i)).map(move |_| i.to_string()))
+        .collect();
+    tiles.join(" ")
+}
+
+// Renders a "score card" for a solution:  its layers, the bag that it
+// was built from, its score, and the version of the solver that found it
+pub fn score_card(bag: &Bag, score: isize, state: &State) -> Image {
+    let mut img = Image::new(WIDTH, HEIGHT, theme().background);
+    let margin = 40;
+
+    img.text("NMBR 9", margin, margin, 6, FOREGROUND);
+    let s = format!("SCORE {}", score);
+    img.text(&s, WIDTH - margin - Image::text_width(&s, 6), margin, 6,
+             FOREGROUND);
+
+    img.draw_layers(state, margin, 120, WIDTH - 2 * margin, 440,
+                    theme().label);
+
+    // Large bags (with extra copies) get smaller text, to stay on the card
+    let b = format!("BAG {}", tiles(bag));
+    let scale = if Image::text_width(&b, 3) > 800 { 2 } else { 3 };
+    img.text(&b, margin, HEIGHT - 75, scale, FOREGROUND);
+
+    let v = format!("NMBR9 V{}", env!("CARGO_PKG_VERSION"));
+    img.text(&v, WIDTH - margin - Image::text_width(&v, 2), HEIGHT - 68, 2,
+             theme().label);
+    return img;
+}
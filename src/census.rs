@@ -0,0 +1,249 @@
+// Counts the distinct legal arrangements which use every tile in a bag,
+// rather than looking for the best one.  Arrangements only count once if
+// they differ by where they sit on the table (as states are normalized),
+// and optionally if they differ by how the whole table is turned.  Their
+// scores can also be tallied, or sampled from random play for bags with
+// too many arrangements to walk.  Sampled scores can be summarized, as a
+// baseline for what random play scores compared to the best arrangement.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use bag::Bag;
+use bitboard::Bitboards;
+use deck;
+use piece::{MAX_ROTATIONS, UNIQUE_PIECE_COUNT, pieces};
+use rules::Rules;
+use rng::Rng;
+use sim;
+use state::{Packed, State};
+use strategy::{Random, Strategy};
+
+pub struct Census {
+    rules: Rules,
+
+    // Count arrangements which are turned copies of each other once
+    turned: bool,
+
+    // Partial arrangements which have already been expanded
+    seen: HashSet<Packed>,
+
+    // Complete arrangements which have been found, with their scores
+    found: HashMap<Packed, isize>,
+}
+
+impl Census {
+    pub fn new(rules: Rules) -> Census {
+        Census { rules: rules, turned: false, seen: HashSet::new(),
+                 found: HashMap::new() }
+    }
+
+    // Counts arrangements up to turning the table, as well as translation
+    pub fn up_to_rotation(mut self) -> Census {
+        self.turned = true;
+        self
+    }
+
+    // Returns the number of distinct arrangements of the whole bag
+    pub fn count(&mut self, bag: &Bag) -> usize {
+        self.seen.clear();
+        self.found.clear();
+        self.search(bag, State::with_rules(&self.rules));
+        return self.found.len();
+    }
+
+    // Returns how many of the last count's arrangements reach each score
+    pub fn histogram(&self) -> BTreeMap<isize, usize> {
+        let mut out = BTreeMap::new();
+        for score in self.found.values() {
+            *out.entry(*score).or_insert(0) += 1;
+        }
+        return out;
+    }
+
+    // Plays the given number of random games, placing a random tile from
+    // the bag at a random legal position each turn, and returns how many
+    // ended with each score.  The games only depend on the seed.
+    pub fn sample(&self, bag: &Bag, runs: usize, seed: u64)
+        -> BTreeMap<isize, usize>
+    {
+        let mut out = BTreeMap::new();
+        for run in 0..runs {
+            let mut bag = bag.clone();
+            let mut state = State::with_rules(&self.rules);
+            let mut rng = Rng::keyed(vec![seed, run as u64]);
+            while !bag.is_empty() {
+                let mut next = self.moves(&bag, &state);
+                if next.is_empty() {
+                    break;
+                }
+                let (b, s) = next.swap_remove(rng.below(next.len()));
+                bag = bag.take(b);
+                state = s;
+            }
+            if bag.is_empty() {
+                *out.entry(self.rules.score(&state)).or_insert(0) += 1;
+            }
+        }
+        return out;
+    }
+
+    // Plays the given number of games dealt from a shuffled deck, placing
+    // each tile as it's drawn at a random legal position, and returns how
+    // many ended with each score.  The games only depend on the seed.
+    pub fn sample_deck(&self, bag: &Bag, runs: usize, seed: u64)
+        -> BTreeMap<isize, usize>
+    {
+        let mut out = BTreeMap::new();
+        for run in 0..runs {
+            let order = deck::shuffle(bag, seed.wrapping_add(run as u64));
+            let player = Rng::keyed(vec![seed, run as u64]).next_u64();
+            let mut players: [Box<dyn Strategy + Send>; 1] =
+                [Box::new(Random::new(self.rules, player))];
+            let states = sim::play(&mut players, &order, &self.rules);
+            *out.entry(self.rules.score(&states[0])).or_insert(0) += 1;
+        }
+        return out;
+    }
+
+    // Number of partial arrangements expanded by the last count
+    pub fn expanded(&self) -> usize {
+        self.seen.len()
+    }
+
+    fn key(&self, state: &State) -> Packed {
+        if self.turned { state.canonical() } else { state.pack() }
+    }
+
+    fn search(&mut self, bag: &Bag, state: State) {
+        if bag.is_empty() {
+            let key = self.key(&state);
+            self.found.insert(key, self.rules.score(&state));
+            return;
+        }
+        if !self.seen.insert(self.key(&state)) {
+            return;
+        }
+        for (b, s) in self.moves(bag, &state) {
+            self.search(&bag.take(b), s);
+        }
+    }
+
+    // Returns every legal placement of a tile from the bag
+    fn moves(&self, bag: &Bag, state: &State) -> Vec<(usize, State)> {
+        // Up to rotation, the first tile can always be turned to its
+        // first rotation (mirrored or not), as in Worker::moves
+        let first = self.turned && state.is_empty();
+        let boards = Bitboards::new(state);
+        let mut out = Vec::new();
+        for b in (0..UNIQUE_PIECE_COUNT)
+            .filter(|&t| bag.digit(t) > 0)
+            .flat_map(|t| pieces().orientations(t, state.mirrored()))
+            .filter(|b| !first || b % MAX_ROTATIONS == 0)
+        {
+            out.extend(boards.placements(state, b).map(|(_, s)| (b, s)));
+        }
+        return out;
+    }
+}
+
+// Statistics of a set of scores, e.g. from Census::sample
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Summary {
+    pub games: usize,
+    pub mean: f64,
+    pub min: isize,
+    pub p10: isize,
+    pub median: isize,
+    pub p90: isize,
+    pub max: isize,
+}
+
+// Summarizes how many games reached each score, or returns None if there
+// weren't any.  Percentiles are the lowest score which at least that
+// fraction of games reached or fell below.
+pub fn summarize(counts: &BTreeMap<isize, usize>) -> Option<Summary> {
+    let games: usize = counts.values().sum();
+    if games == 0 {
+        return None;
+    }
+    let total: isize = counts.iter().map(|(s, n)| s * *n as isize).sum();
+    let percentile = |q: f64| {
+        let rank = ((q * games as f64).ceil() as usize).max(1);
+        let mut seen = 0;
+        for (s, n) in counts {
+            seen += n;
+            if seen >= rank {
+                return *s;
+            }
+        }
+        unreachable!()
+    };
+    return Some(Summary {
+        games: games,
+        mean: total as f64 / games as f64,
+        min: *counts.keys().next().unwrap(),
+        p10: percentile(0.1),
+        median: percentile(0.5),
+        p90: percentile(0.9),
+        max: *counts.keys().next_back().unwrap(),
+    });
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count() {
+        let rules = Rules::default();
+        let bag = Bag::from_digits("1000000000", 2).unwrap();
+
+        // The 0 looks the same turned halfway around
+        assert_eq!(Census::new(rules).count(&bag), 2);
+        assert_eq!(Census::new(rules).up_to_rotation().count(&bag), 1);
+        assert_eq!(Census::new(rules).count(&Bag::from_usize(0)), 1);
+
+        // Every turned copy of an arrangement is also counted, so there
+        // are up to four times as many without the symmetry
+        let bag = Bag::from_digits("1100000000", 2).unwrap();
+        let all = Census::new(rules).count(&bag);
+        let turned = Census::new(rules).up_to_rotation().count(&bag);
+        assert!(turned > 0);
+        assert!(all > turned && all <= turned * MAX_ROTATIONS);
+    }
+
+    #[test]
+    fn histogram() {
+        // Two 0s and a 1 score a point when the 1 is laid across the 0s
+        let rules = Rules::default();
+        let bag = Bag::from_digits("2100000000", 2).unwrap();
+        let mut census = Census::new(rules);
+        let n = census.count(&bag);
+        let h = census.histogram();
+        assert_eq!(h.values().sum::<usize>(), n);
+        assert_eq!(h.keys().cloned().collect::<Vec<_>>(), vec![0, 1]);
+
+        let s = census.sample(&bag, 50, 1);
+        assert_eq!(s.values().sum::<usize>(), 50);
+        assert!(s.keys().all(|k| h.contains_key(k)));
+        assert_eq!(s, census.sample(&bag, 50, 1));
+
+        let d = census.sample_deck(&bag, 50, 1);
+        assert_eq!(d.values().sum::<usize>(), 50);
+        assert!(d.keys().all(|k| h.contains_key(k)));
+        assert_eq!(d, census.sample_deck(&bag, 50, 1));
+    }
+
+    #[test]
+    fn summarize() {
+        let counts: BTreeMap<isize, usize> =
+            [(0, 5), (2, 3), (7, 2)].iter().cloned().collect();
+        let s = super::summarize(&counts).unwrap();
+        assert_eq!(s.games, 10);
+        assert!((s.mean - 2.0).abs() < 1e-9);
+        assert_eq!((s.min, s.p10, s.median, s.p90, s.max), (0, 0, 0, 7, 7));
+        assert_eq!(super::summarize(&BTreeMap::new()), None);
+    }
+}
@@ -0,0 +1,193 @@
+// Generates shell completion scripts from a table of command-line flags
+
+// What sort of argument a flag takes
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Arg {
+    None,
+    // Free-form text or numbers, which can't usefully be completed
+    Text,
+    // One of a fixed set of words, separated by spaces
+    Words(&'static str),
+    File,
+    Dir,
+}
+
+pub struct Flag {
+    pub name: &'static str,
+    pub arg: Arg,
+    pub help: &'static str,
+}
+
+pub const SHELLS: &str = "bash zsh fish";
+
+// Returns the completion script for the named shell, or None if it
+// isn't one of SHELLS.  Commands are subcommands which may be given as
+// the first argument, and their arg is for the positional argument that
+// follows them.
+pub fn script(shell: &str, bin: &str, commands: &[Flag], flags: &[Flag])
+    -> Option<String>
+{
+    match shell {
+        "bash" => Some(bash(bin, commands, flags)),
+        "zsh" => Some(zsh(bin, commands, flags)),
+        "fish" => Some(fish(bin, commands, flags)),
+        _ => None,
+    }
+}
+
+// Flags, as a space-separated list of their names
+fn names(flags: &[Flag]) -> String {
+    let names: Vec<&str> = flags.iter().map(|f| f.name).collect();
+    names.join(" ")
+}
+
+fn bash_reply(arg: Arg) -> Option<String> {
+    match arg {
+        Arg::None => None,
+        Arg::Text => Some("return".to_string()),
+        Arg::Words(w) =>
+            Some(format!("COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return", w)),
+        Arg::File => Some("COMPREPLY=($(compgen -f -- \"$cur\")); return".to_string()),
+        Arg::Dir => Some("COMPREPLY=($(compgen -d -- \"$cur\")); return".to_string()),
+    }
+}
+
+fn bash(bin: &str, commands: &[Flag], flags: &[Flag]) -> String {
+    let mut cases = String::new();
+    for f in flags {
+        if let Some(reply) = bash_reply(f.arg) {
+            cases += &format!("        {}) {};;\n", f.name, reply);
+        }
+    }
+    let mut positional = String::new();
+    for c in commands {
+        if let Some(reply) = bash_reply(c.arg) {
+            positional += &format!("            {}) {};;\n", c.name, reply);
+        }
+    }
+    let func = format!("_{}", bin.replace('-', "_"));
+    format!("\
+{func}() {{
+    local cur prev
+    cur=\"${{COMP_WORDS[COMP_CWORD]}}\"
+    prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"
+    if [ \"$COMP_CWORD\" -eq 2 ]; then
+        case \"$prev\" in
+{positional}        esac
+    fi
+    case \"$prev\" in
+{cases}    esac
+    local words=\"{flags}\"
+    [ \"$COMP_CWORD\" -eq 1 ] && words=\"{commands} $words\"
+    COMPREPLY=($(compgen -W \"$words\" -- \"$cur\"))
+}}
+complete -F {func} {bin}
+", func = func, positional = positional, cases = cases,
+       flags = names(flags), commands = names(commands), bin = bin)
+}
+
+fn zsh_action(arg: Arg) -> String {
+    match arg {
+        Arg::None => String::new(),
+        Arg::Text => ": : ".to_string(),
+        Arg::Words(w) => format!(": :({})", w),
+        Arg::File => ": :_files".to_string(),
+        Arg::Dir => ": :_files -/".to_string(),
+    }
+}
+
+fn zsh(bin: &str, commands: &[Flag], flags: &[Flag]) -> String {
+    let mut specs = String::new();
+    for f in flags {
+        specs += &format!("    '{}[{}]{}' \\\n", f.name,
+                          f.help.replace('\'', "'\\''"), zsh_action(f.arg));
+    }
+    let mut positional = String::new();
+    for c in commands.iter().filter(|c| c.arg != Arg::None) {
+        positional += &format!("    {}) _arguments '1:command:({})' '2{}' ;;\n",
+                               c.name, c.name, zsh_action(c.arg));
+    }
+    format!("\
+#compdef {bin}
+case \"$words[2]\" in
+{positional}    *) _arguments \\
+{specs}        '1::command:({commands})' ;;
+esac
+", bin = bin, positional = positional, specs = specs,
+       commands = names(commands))
+}
+
+fn fish(bin: &str, commands: &[Flag], flags: &[Flag]) -> String {
+    let mut out = format!("complete -c {} -f\n", bin);
+    for c in commands {
+        out += &format!("complete -c {} -n __fish_use_subcommand -a {} -d '{}'\n",
+                        bin, c.name, c.help.replace('\'', "\\'"));
+        let arg = match c.arg {
+            Arg::None | Arg::Text => continue,
+            Arg::Words(w) => format!("-a '{}'", w),
+            Arg::File => "-F".to_string(),
+            Arg::Dir => "-a '(__fish_complete_directories)'".to_string(),
+        };
+        out += &format!("complete -c {} -n '__fish_seen_subcommand_from {}' {}\n",
+                        bin, c.name, arg);
+    }
+    for f in flags {
+        let arg = match f.arg {
+            Arg::None => String::new(),
+            Arg::Text => " -x".to_string(),
+            Arg::Words(w) => format!(" -x -a '{}'", w),
+            Arg::File => " -r -F".to_string(),
+            Arg::Dir => " -x -a '(__fish_complete_directories)'".to_string(),
+        };
+        out += &format!("complete -c {} -l {}{} -d '{}'\n", bin,
+                        f.name.trim_start_matches("--"), arg,
+                        f.help.replace('\'', "\\'"));
+    }
+    return out;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FLAGS: &[Flag] = &[
+        Flag { name: "--single-copy", arg: Arg::None, help: "One of each" },
+        Flag { name: "--order", arg: Arg::Words("index area"), help: "Order" },
+        Flag { name: "--trace", arg: Arg::File, help: "Trace file" },
+    ];
+
+    const COMMANDS: &[Flag] = &[
+        Flag { name: "completions", arg: Arg::Words(SHELLS), help: "Completions" },
+        Flag { name: "replay", arg: Arg::File, help: "Replay" },
+        Flag { name: "bench", arg: Arg::None, help: "Benchmark" },
+    ];
+
+    #[test]
+    fn script() {
+        let script = |shell| super::script(shell, "nmbr9", COMMANDS, FLAGS);
+        let bash = script("bash").unwrap();
+        assert!(bash.contains("--order) COMPREPLY=($(compgen -W \"index area\""));
+        assert!(bash.contains("--trace) COMPREPLY=($(compgen -f"));
+        assert!(!bash.contains("--single-copy)"));
+        assert!(bash.contains("\"--single-copy --order --trace\""));
+        assert!(bash.ends_with("complete -F _nmbr9 nmbr9\n"));
+
+        assert!(bash.contains("completions) COMPREPLY=($(compgen -W \"bash zsh fish\""));
+        assert!(bash.contains("words=\"completions replay bench $words\""));
+
+        let zsh = script("zsh").unwrap();
+        assert!(zsh.starts_with("#compdef nmbr9\n"));
+        assert!(zsh.contains("'--order[Order]: :(index area)'"));
+
+        assert!(zsh.contains("replay) _arguments '1:command:(replay)' '2: :_files'"));
+
+        let fish = script("fish").unwrap();
+        assert!(fish.contains("complete -c nmbr9 -l trace -r -F -d 'Trace file'"));
+
+        assert!(fish.contains("-n '__fish_seen_subcommand_from replay' -F"));
+
+        assert!(script("tcsh").is_none());
+    }
+}
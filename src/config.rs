@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+
+use toml;
+
+use completions::{Flag, Arg};
+
+// Flags can be set in three places, which take precedence in this order:
+// the command line, then NMBR9_* environment variables, then a config
+// file (from --config, or else NMBR9_CONFIG).  Arguments from each are
+// parsed in the reverse order, so that later ones override earlier ones.
+// (Switches have no "off" form, so once one is on, it stays on.)
+pub const ENV_PREFIX: &str = "NMBR9_";
+
+// Returns the environment variable for a flag, e.g. NMBR9_MAX_LAYERS
+pub fn env_name(flag: &Flag) -> String {
+    format!("{}{}", ENV_PREFIX,
+            flag.name.trim_start_matches("--").replace('-', "_")
+                .to_uppercase())
+}
+
+// Converts a TOML config file into command-line arguments.  Each key is
+// a flag's name without the leading dashes:  true turns a switch on (and
+// false leaves it off), arrays are joined with commas, and anything else
+// is passed as the flag's argument.  For example,
+//
+//   max-layers = 3
+//   values = [0, 1, 2, 3, 4, 5, 6, 7, 8, -9]
+//   single-copy = true
+//   trace = "trace.json"
+//
+// Arguments come out in the order of the flags table, not the file, so
+// that flags which depend on others (like --joker on --values) see them.
+pub fn to_args(text: &str, flags: &[Flag]) -> Result<Vec<String>, String> {
+    let table: toml::Table = text.parse().map_err(|e| format!("{}", e))?;
+    for key in table.keys() {
+        if !flags.iter().any(|f| f.name.trim_start_matches("--") == key) {
+            return Err(format!("Unknown key '{}'", key));
+        }
+    }
+
+    let mut out = Vec::new();
+    for f in flags {
+        let value = match table.get(f.name.trim_start_matches("--")) {
+            Some(v) => v,
+            None => continue,
+        };
+        let arg = match *value {
+            toml::Value::Boolean(false) => continue,
+            toml::Value::Boolean(true) => None,
+            toml::Value::String(ref s) => Some(s.clone()),
+            toml::Value::Integer(i) => Some(i.to_string()),
+            toml::Value::Float(x) => Some(x.to_string()),
+            toml::Value::Array(ref a) => {
+                let items: Result<Vec<String>, String> = a.iter()
+                    .map(|v| match *v {
+                        toml::Value::String(ref s) => Ok(s.clone()),
+                        toml::Value::Integer(i) => Ok(i.to_string()),
+                        _ => Err(format!("Invalid item in '{}'", f.name)),
+                    })
+                    .collect();
+                Some(items?.join(","))
+            },
+            _ => return Err(format!("Invalid value for '{}'", f.name)),
+        };
+        out.push(f.name.to_string());
+        out.extend(arg);
+    }
+    return Ok(out);
+}
+
+// Converts NMBR9_* environment variables into command-line arguments.
+// Switches take 1, true, or yes to turn them on (and 0, false, no, or an
+// empty string to leave them off); other flags take their argument as-is.
+// NMBR9_CONFIG is skipped, since the caller reads the file itself.
+pub fn env_args<I>(vars: I, flags: &[Flag]) -> Result<Vec<String>, String>
+    where I: IntoIterator<Item=(String, String)>
+{
+    let mut set = HashMap::new();
+    for (k, v) in vars {
+        if !k.starts_with(ENV_PREFIX) || k == format!("{}CONFIG", ENV_PREFIX) {
+            continue;
+        }
+        if !flags.iter().any(|f| env_name(f) == k) {
+            return Err(format!("Unknown variable {}", k));
+        }
+        set.insert(k, v);
+    }
+
+    let mut out = Vec::new();
+    for f in flags {
+        let var = env_name(f);
+        let value = match set.get(&var) {
+            Some(v) => v,
+            None => continue,
+        };
+        if f.arg == Arg::None {
+            match value.to_lowercase().as_str() {
+                "1" | "true" | "yes" => out.push(f.name.to_string()),
+                "0" | "false" | "no" | "" => (),
+                _ => return Err(format!("{} must be true or false", var)),
+            }
+        } else {
+            out.push(f.name.to_string());
+            out.push(value.clone());
+        }
+    }
+    return Ok(out);
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FLAGS: &[Flag] = &[
+        Flag { name: "--max-layers", arg: Arg::Text, help: "" },
+        Flag { name: "--single-copy", arg: Arg::None, help: "" },
+        Flag { name: "--numa", arg: Arg::None, help: "" },
+        Flag { name: "--values", arg: Arg::Text, help: "" },
+        Flag { name: "--trace", arg: Arg::File, help: "" },
+    ];
+
+    #[test]
+    fn to_args() {
+        let args = super::to_args("trace = \"t.json\"\n\
+                                   values = [0, 1, -2]\n\
+                                   numa = false\n\
+                                   single-copy = true\n\
+                                   max-layers = 3\n", FLAGS).unwrap();
+        assert_eq!(args, ["--max-layers", "3", "--single-copy",
+                          "--values", "0,1,-2", "--trace", "t.json"]);
+
+        assert!(super::to_args("layers = 3", FLAGS).is_err());
+        assert!(super::to_args("trace = {}", FLAGS).is_err());
+        assert!(super::to_args("max-layers = ", FLAGS).is_err());
+    }
+
+    #[test]
+    fn env_args() {
+        let vars = |v: &[(&str, &str)]| v.iter()
+            .map(|&(k, v)| (k.to_string(), v.to_string()))
+            .collect::<Vec<_>>();
+        let args = super::env_args(vars(&[
+            ("HOME", "/root"),
+            ("NMBR9_TRACE", "t.json"),
+            ("NMBR9_NUMA", "0"),
+            ("NMBR9_SINGLE_COPY", "true"),
+            ("NMBR9_CONFIG", "nmbr9.toml"),
+            ("NMBR9_MAX_LAYERS", "3")]), FLAGS).unwrap();
+        assert_eq!(args, ["--max-layers", "3", "--single-copy",
+                          "--trace", "t.json"]);
+
+        assert!(super::env_args(vars(&[("NMBR9_LAYERS", "3")]),
+                                FLAGS).is_err());
+        assert!(super::env_args(vars(&[("NMBR9_NUMA", "maybe")]),
+                                FLAGS).is_err());
+    }
+}
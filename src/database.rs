@@ -0,0 +1,240 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use bag::Bag;
+use rules::Rules;
+use state::State;
+
+// One finished search of a bag, as recorded in the database
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub bag: Bag,
+    pub score: isize,
+
+    // Whether the score is exact, rather than a lower bound from a search
+    // which ran out of time or hit a limit
+    pub exact: bool,
+
+    pub nodes: usize,
+    pub elapsed: Duration,
+
+    // The best arrangement found, in State::layout's format
+    pub layout: String,
+}
+
+impl Record {
+    pub fn new(bag: &Bag, score: isize, exact: bool, nodes: usize,
+               elapsed: Duration, state: &State) -> Record {
+        Record {
+            bag: bag.clone(),
+            score: score,
+            exact: exact,
+            nodes: nodes,
+            elapsed: elapsed,
+            layout: state.layout(),
+        }
+    }
+
+    // Writes the record as a line of tab-separated fields:  the bag's
+    // digits, its score, "exact" or "lower", the nodes expanded, the
+    // seconds taken, and the best arrangement (which may be empty)
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "{}\t{}\t{}\t{}\t{:.3}\t{}", self.bag.digits(),
+                 self.score, if self.exact { "exact" } else { "lower" },
+                 self.nodes, self.elapsed.as_secs_f64(), self.layout)
+    }
+
+    fn parse(line: &str, copies: usize) -> Option<Record> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 6 {
+            return None;
+        }
+        let exact = match fields[2] {
+            "exact" => true,
+            "lower" => false,
+            _ => return None,
+        };
+        let secs: f64 = fields[4].parse().ok()?;
+        if !secs.is_finite() || secs < 0.0 {
+            return None;
+        }
+        return Some(Record {
+            bag: Bag::from_digits(fields[0], copies)?,
+            score: fields[1].parse().ok()?,
+            exact: exact,
+            nodes: fields[3].parse().ok()?,
+            elapsed: Duration::from_secs_f64(secs),
+            layout: fields[5].to_string(),
+        });
+    }
+}
+
+// An append-only file of every search that a solver finishes, so that a
+// long run's scores, layouts and costs can be looked up while it's going
+// (or after it crashed).  The file starts with a comment line naming the
+// rules, so that runs under different rules can't be mixed in one file.
+pub struct Database {
+    file: Mutex<File>,
+}
+
+// The version of the database format, named in its header.  Databases
+// without it are from before version 2, whose search never scored
+// arrangements that use every tile, so they're refused rather than
+// trusted (see results::VERSION).
+const VERSION: u32 = 2;
+
+fn header(rules: &Rules) -> String {
+    format!("# nmbr9 database v{} for {:?}", VERSION, rules)
+}
+
+// Explains why a database with the given first line can't be used
+fn mismatch(first: &str) -> String {
+    if first.starts_with(&format!("# nmbr9 database v{} ", VERSION)) {
+        "The database was written under different rules".to_string()
+    } else {
+        "The database was written by an older version of nmbr9, whose \
+         scores may be too low".to_string()
+    }
+}
+
+impl Database {
+    // Opens a database for appending, creating it if it doesn't exist
+    pub fn open(path: &Path, rules: &Rules) -> Result<Database, String> {
+        let mut file = OpenOptions::new().read(true).append(true).create(true)
+            .open(path).map_err(|e| e.to_string())?;
+        let mut first = String::new();
+        BufReader::new(&mut file).read_line(&mut first)
+            .map_err(|e| e.to_string())?;
+        if first.is_empty() {
+            writeln!(file, "{}", header(rules)).map_err(|e| e.to_string())?;
+        } else if first.trim_end() != header(rules) {
+            return Err(mismatch(&first));
+        }
+        return Ok(Database { file: Mutex::new(file) });
+    }
+
+    // Appends one record.  Each is written with a single call, so records
+    // from different threads don't interleave.
+    pub fn append(&self, record: &Record) -> io::Result<()> {
+        let mut line = Vec::new();
+        record.write(&mut line)?;
+        self.file.lock().unwrap().write_all(&line)
+    }
+}
+
+// Reads every record from a database, in the order they were written
+pub fn read<R: BufRead>(input: R, rules: &Rules) -> Result<Vec<Record>, String> {
+    let mut out = Vec::new();
+    for (n, line) in input.lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        if n == 0 && line != header(rules) {
+            return Err(mismatch(&line));
+        }
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        out.push(Record::parse(&line, rules.copies)
+            .ok_or_else(|| format!("Invalid record on line {}", n + 1))?);
+    }
+    return Ok(out);
+}
+
+// Every record of one bag, oldest first
+pub fn show<'a>(records: &'a [Record], bag: &Bag) -> Vec<&'a Record> {
+    records.iter().filter(|r| r.bag == *bag).collect()
+}
+
+// The best record of each bag, highest-scoring first, keeping at most n.
+// Exact scores beat lower bounds of the same value, and later records
+// beat earlier ones.
+pub fn top(records: &[Record], n: usize) -> Vec<&Record> {
+    let mut best: Vec<&Record> = Vec::new();
+    for r in records {
+        match best.iter_mut().find(|b| b.bag == r.bag) {
+            Some(b) => if (r.score, r.exact) >= (b.score, b.exact) {
+                *b = r;
+            },
+            None => best.push(r),
+        }
+    }
+    best.sort_by_key(|r| (-r.score, !r.exact));
+    best.truncate(n);
+    return best;
+}
+
+pub fn read_file(path: &Path, rules: &Rules) -> Result<Vec<Record>, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    read(BufReader::new(file), rules)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    fn record(digits: &str, score: isize, exact: bool) -> Record {
+        Record {
+            bag: Bag::from_digits(digits, 2).unwrap(),
+            score: score,
+            exact: exact,
+            nodes: 10,
+            elapsed: Duration::from_millis(1500),
+            layout: String::new(),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = std::env::temp_dir()
+            .join(format!("nmbr9-database-{}", process::id()));
+        let _ = std::fs::remove_file(&path);
+        let rules = Rules::default();
+        let records = vec![record("0000000002", 5, false),
+                           record("0000000002", 8, true),
+                           record("1000000000", 0, true)];
+        {
+            let db = Database::open(&path, &rules).unwrap();
+            db.append(&records[0]).unwrap();
+        }
+        {
+            // Reopening appends rather than truncating
+            let db = Database::open(&path, &rules).unwrap();
+            db.append(&records[1]).unwrap();
+            db.append(&records[2]).unwrap();
+        }
+        assert_eq!(read_file(&path, &rules).unwrap(), records);
+
+        let other = Rules { copies: 1, ..rules };
+        assert!(Database::open(&path, &other).is_err());
+        assert!(read_file(&path, &other).is_err());
+
+        // Databases from before the format was versioned are refused
+        let text = std::fs::read_to_string(&path).unwrap()
+            .replacen(&format!(" v{} ", VERSION), " ", 1);
+        std::fs::write(&path, text).unwrap();
+        match Database::open(&path, &rules) {
+            Err(e) => assert!(e.contains("older version"), "{}", e),
+            Ok(_) => panic!("Opened an unversioned database"),
+        }
+        assert!(read_file(&path, &rules).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn queries() {
+        let records = vec![record("0000000002", 8, false),
+                           record("0000000001", 3, true),
+                           record("0000000002", 8, true),
+                           record("0000000011", 9, false)];
+        let bag = Bag::from_digits("0000000002", 2).unwrap();
+        assert_eq!(show(&records, &bag), vec![&records[0], &records[2]]);
+        assert_eq!(top(&records, 10),
+                   vec![&records[3], &records[2], &records[1]]);
+        assert_eq!(top(&records, 1), vec![&records[3]]);
+    }
+}
@@ -0,0 +1,79 @@
+// The real game's deck of cards, which decides the order that tiles are
+// placed in (see Worker::with_draw_order)
+
+use bag::Bag;
+use piece::{UNIQUE_PIECE_COUNT, MAX_ROTATIONS};
+use rng::Rng;
+
+// Returns a bag's tiles (0-9), in ascending order
+pub fn tiles(bag: &Bag) -> Vec<usize> {
+    (0..UNIQUE_PIECE_COUNT)
+        .flat_map(|i| (0..bag.digit(i)).map(move |_| i))
+        .collect()
+}
+
+// Returns the bag holding the given tiles, or None if there are more than
+// the given number of copies of any tile
+pub fn bag(order: &[usize], copies: usize) -> Option<Bag> {
+    let mut out = Bag::from_usize(0);
+    for &t in order {
+        if t >= UNIQUE_PIECE_COUNT || out.digit(t) >= copies {
+            return None;
+        }
+        out = out.add(t * MAX_ROTATIONS);
+    }
+    return Some(out);
+}
+
+// Parses a draw order written as one digit per card, e.g. 9012 for a 9,
+// then a 0, a 1, and a 2
+pub fn parse(s: &str, copies: usize) -> Option<Vec<usize>> {
+    let order = s.chars()
+        .map(|c| c.to_digit(10).map(|d| d as usize))
+        .collect::<Option<Vec<usize>>>()?;
+    bag(&order, copies)?;
+    return Some(order);
+}
+
+// Shuffles a bag's tiles into a draw order.  The order only depends on the
+// seed, so a shuffle can be repeated.
+pub fn shuffle(bag: &Bag, seed: u64) -> Vec<usize> {
+    let mut order = tiles(bag);
+    let mut rng = Rng::new(seed);
+    for i in (1..order.len()).rev() {
+        order.swap(i, rng.below(i + 1));
+    }
+    return order;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(super::parse("9012", 2), Some(vec![9, 0, 1, 2]));
+        assert_eq!(super::parse("901x", 2), None);
+        assert_eq!(super::parse("999", 2), None);
+        assert_eq!(super::parse("99", 1), None);
+        assert_eq!(bag(&[9, 0, 9], 2),
+                   Some(Bag::from_usize(0).add(0).add(36).add(36)));
+    }
+
+    #[test]
+    fn shuffle() {
+        let full = Bag::from_usize(0).complement(2);
+        let a = super::shuffle(&full, 1);
+        assert_eq!(a.len(), 20);
+        assert_eq!(bag(&a, 2), Some(full.clone()));
+        assert_ne!(a, tiles(&full));
+        assert_eq!(a, super::shuffle(&full, 1));
+        assert_ne!(a, super::shuffle(&full, 2));
+
+        // Shuffles are fixed by the seed alone, whatever the toolchain
+        assert_eq!(a, vec![4, 0, 6, 5, 0, 1, 7, 9, 2, 2,
+                           4, 1, 3, 6, 9, 8, 3, 8, 7, 5]);
+    }
+}
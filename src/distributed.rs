@@ -0,0 +1,472 @@
+// Solving across several machines:  a coordinator hands out bags over TCP
+// and collects their scores, and workers solve them.  Bags are handed out
+// in batches by number of pieces (as in Solver::sweep_bags), so that every
+// bag's subsets are finished before it's searched.
+//
+// Messages are lines of text.  A worker starts with
+//
+//   hello # nmbr9 results v4 for scoring=linear ... pieces=...
+//
+// (the header of a results file), which the coordinator answers with "ok"
+// if its rules match, or "error ..." otherwise.  The worker then repeats
+//
+//   ready                  asks for a bag.  The coordinator replies with
+//                          "bound LINE" for each bag finished since the
+//                          worker last asked, then "solve DIGITS", "wait"
+//                          (try again shortly), or "done"
+//   result LINE            reports a finished bag, answered with "ok"
+//
+// where each LINE is a line of a results file (see Results::write).
+// Workers keep the scores that they're sent, which bound their searches.
+//
+// If a worker disconnects, its bag goes back in the queue.  Workers
+// reconnect after connection errors, and resend a result which the
+// coordinator didn't acknowledge.
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Mutex, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use bag::Bag;
+use piece::UNIQUE_PIECE_COUNT;
+use progress::{Snapshot, Tracker};
+use results::{self, Results};
+use rules::Rules;
+use solver::Solver;
+
+// How long an idle worker waits before asking for work again, which is
+// also how often the coordinator checks whether it's finished
+const WAIT: Duration = Duration::from_secs(1);
+
+// How many times in a row a worker tries to reconnect, and how long it
+// waits between tries
+const RECONNECTS: usize = 60;
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+enum Next {
+    Solve(Bag),
+    Wait,
+    Done,
+}
+
+struct Schedule {
+    // Batches which haven't started, with the smallest bags last
+    batches: Vec<Vec<Bag>>,
+    // Bags in the current batch which haven't been handed out
+    pending: VecDeque<Bag>,
+    // Bags which have been handed out, with the connection solving each
+    running: Vec<(usize, Bag)>,
+    // How far the current batch has got
+    progress: Tracker,
+}
+
+impl Schedule {
+    fn next(&mut self, conn: usize) -> Next {
+        loop {
+            if let Some(b) = self.pending.pop_front() {
+                self.running.push((conn, b.clone()));
+                return Next::Solve(b);
+            } else if !self.running.is_empty() {
+                return Next::Wait;
+            }
+            match self.batches.pop() {
+                Some(batch) => {
+                    status!("============================================================");
+                    status!("BEGINNING {}-PIECE COMBINATIONS ({} to do)",
+                            batch[0].len(), batch.len());
+                    self.progress.begin(batch[0].len(), batch.len());
+                    self.pending = batch.into_iter().collect();
+                },
+                None => return Next::Done,
+            }
+        }
+    }
+
+    // Marks a bag in the current batch as finished, returning false if
+    // it isn't one (e.g. a result which was sent twice)
+    fn finish(&mut self, bag: &Bag) -> bool {
+        if let Some(i) = self.running.iter().position(|r| r.1 == *bag) {
+            self.running.remove(i);
+        } else if let Some(i) = self.pending.iter().position(|b| b == bag) {
+            self.pending.remove(i);
+        } else {
+            return false;
+        }
+        // Workers don't report their node counts
+        self.progress.solved(0);
+        return true;
+    }
+
+    // Puts a connection's bags back at the front of the queue
+    fn requeue(&mut self, conn: usize) {
+        while let Some(i) = self.running.iter().position(|r| r.0 == conn) {
+            let (_, b) = self.running.remove(i);
+            self.pending.push_front(b);
+        }
+    }
+
+    fn finished(&self) -> bool {
+        self.batches.is_empty() && self.pending.is_empty() &&
+            self.running.is_empty()
+    }
+}
+
+pub struct Coordinator {
+    results: RwLock<Results>,
+    schedule: Mutex<Schedule>,
+    // Bags in the order that they were finished, so that each worker can
+    // be sent the scores which it hasn't seen
+    log: RwLock<Vec<Bag>>,
+    connections: AtomicUsize,
+}
+
+impl Coordinator {
+    // Plans to solve the given bags, along with every subset that their
+    // searches need
+    pub fn new(rules: Rules, targets: &[Bag]) -> Coordinator {
+        let results = Results::new(rules);
+        let needed: Vec<Bag> = results.bags().into_iter()
+            .filter(|b| targets.iter().any(|t| t.contains(b)))
+            .collect();
+        let batches = (0..=(rules.copies * UNIQUE_PIECE_COUNT)).rev()
+            .map(|n| needed.iter().filter(|b| b.len() == n).cloned()
+                 .collect::<Vec<Bag>>())
+            .filter(|batch| !batch.is_empty())
+            .collect();
+        let schedule = Schedule { batches: batches, pending: VecDeque::new(),
+                                  running: Vec::new(),
+                                  progress: Tracker::new() };
+        Coordinator { results: RwLock::new(results),
+                      schedule: Mutex::new(schedule),
+                      log: RwLock::new(Vec::new()),
+                      connections: AtomicUsize::new(0) }
+    }
+
+    pub fn results(&self) -> &RwLock<Results> {
+        &self.results
+    }
+
+    // Returns how far the current batch has got
+    pub fn progress(&self) -> Snapshot {
+        self.schedule.lock().unwrap().progress.snapshot()
+    }
+
+    fn finished(&self) -> bool {
+        self.schedule.lock().unwrap().finished()
+    }
+
+    // Serves workers until every bag is finished
+    pub fn serve(&self, listener: TcpListener) -> io::Result<()> {
+        listener.set_nonblocking(true)?;
+        thread::scope(|scope| {
+            while !self.finished() {
+                let (stream, addr) = match listener.accept() {
+                    Ok(a) => a,
+                    Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                        thread::sleep(Duration::from_millis(50));
+                        continue;
+                    },
+                    Err(e) => return Err(e),
+                };
+                let conn = self.connections.fetch_add(1, Ordering::Relaxed);
+                status!("Worker {} connected from {}", conn, addr);
+                scope.spawn(move || {
+                    if let Err(e) = self.handle(conn, stream) {
+                        warning!("Lost worker {}: {}", conn, e);
+                    }
+                    self.schedule.lock().unwrap().requeue(conn);
+                });
+            }
+            Ok(())
+        })
+    }
+
+    // Reads a line from a worker, returning false once the connection
+    // closes (or every bag is finished, so there's nothing left to say)
+    fn read_line<R: BufRead>(&self, reader: &mut R, line: &mut String)
+        -> io::Result<bool>
+    {
+        line.clear();
+        loop {
+            match reader.read_line(line) {
+                Ok(0) => return Ok(false),
+                Ok(_) => return Ok(line.ends_with('\n')),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock ||
+                              e.kind() == ErrorKind::TimedOut => {
+                    if self.finished() {
+                        return Ok(false);
+                    }
+                },
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn handle(&self, conn: usize, stream: TcpStream) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(WAIT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut out = stream;
+
+        let rules = self.results.read().unwrap().rules();
+        let mut header = Vec::new();
+        self.results.read().unwrap().write_header(&mut header)?;
+        let header = String::from_utf8(header).unwrap();
+
+        let mut greeted = false;
+        let mut sent = 0;
+        let mut line = String::new();
+        while self.read_line(&mut reader, &mut line)? {
+            let (command, args) = match line.find(' ') {
+                Some(i) => (&line[..i], &line[i + 1..]),
+                None => (line.trim_end(), ""),
+            };
+            match command {
+                "hello" if results::header_matches(args, &rules) => {
+                    greeted = true;
+                    writeln!(out, "ok")?;
+                },
+                "hello" => {
+                    writeln!(out, "error rules or pieces don't match:  {}",
+                             header.trim_end())?;
+                    return Ok(());
+                },
+                _ if !greeted => {
+                    writeln!(out, "error expected hello")?;
+                    return Ok(());
+                },
+                "ready" => {
+                    // Results are logged while the schedule is locked (see
+                    // result()), so once the next bag is picked, the log
+                    // has every score that its search needs
+                    let next = self.schedule.lock().unwrap().next(conn);
+                    {
+                        let log = self.log.read().unwrap();
+                        let results = self.results.read().unwrap();
+                        for b in &log[sent..] {
+                            out.write_all(b"bound ")?;
+                            results.write_bag(&mut out, b)?;
+                        }
+                        sent = log.len();
+                    }
+                    match next {
+                        Next::Solve(b) => writeln!(out, "solve {}", b.digits())?,
+                        Next::Wait => writeln!(out, "wait")?,
+                        Next::Done => writeln!(out, "done")?,
+                    }
+                },
+                "result" => self.result(conn, args, &mut out)?,
+                _ => writeln!(out, "error unknown command")?,
+            }
+            out.flush()?;
+        }
+        return Ok(());
+    }
+
+    // Records a bag's result, which must be in the current batch
+    fn result<W: Write>(&self, conn: usize, line: &str, out: &mut W)
+        -> io::Result<()>
+    {
+        let rules = self.results.read().unwrap().rules();
+        let bag = line.split('\t').next()
+            .and_then(|d| Bag::from_digits(d, rules.copies));
+        let bag = match bag {
+            Some(b) => b,
+            None => return writeln!(out, "error invalid result"),
+        };
+
+        // The schedule stays locked until the result is logged, so that
+        // the next batch can't start without it
+        let mut schedule = self.schedule.lock().unwrap();
+        if schedule.finish(&bag) {
+            let loaded = self.results.write().unwrap().read(line.as_bytes());
+            if let Err(e) = loaded {
+                schedule.pending.push_back(bag);
+                return writeln!(out, "error {}", e);
+            }
+            self.log.write().unwrap().push(bag.clone());
+            status!("Worker {} finished {}", conn, line.trim_end());
+        }
+        writeln!(out, "ok")
+    }
+}
+
+// Reads a reply from the coordinator, treating an error reply (or the
+// connection closing) as an error
+fn reply<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(ErrorKind::UnexpectedEof,
+                                  "The coordinator hung up"));
+    }
+    if let Some(e) = line.strip_prefix("error ") {
+        return Err(io::Error::new(ErrorKind::InvalidData,
+                                  e.trim_end().to_string()));
+    }
+    return Ok(line);
+}
+
+// Solves bags for the coordinator at the given address until it says that
+// they're all done, returning the number of bags solved.  Connection
+// errors are retried; if the coordinator rejects the worker (e.g. because
+// the rules don't match), the error is returned.
+pub fn work(addr: &str, solver: &Solver) -> io::Result<usize> {
+    let mut unsent = None;
+    let mut solved = 0;
+    let mut failures = 0;
+    loop {
+        match session(addr, solver, &mut unsent, &mut solved, &mut failures) {
+            Ok(()) => return Ok(solved),
+            Err(ref e) if e.kind() == ErrorKind::InvalidData =>
+                return Err(io::Error::new(e.kind(), e.to_string())),
+            Err(e) => {
+                failures += 1;
+                if failures > RECONNECTS {
+                    return Err(e);
+                }
+                warning!("Lost the coordinator ({}), reconnecting in {:?}",
+                          e, RECONNECT_DELAY);
+                thread::sleep(RECONNECT_DELAY);
+            },
+        }
+    }
+}
+
+// Runs one connection to the coordinator, keeping a result which hasn't
+// been acknowledged in unsent
+fn session(addr: &str, solver: &Solver, unsent: &mut Option<String>,
+           solved: &mut usize, failures: &mut usize) -> io::Result<()>
+{
+    let stream = TcpStream::connect(addr)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut out = stream;
+
+    out.write_all(b"hello ")?;
+    solver.results().read().unwrap().write_header(&mut out)?;
+    reply(&mut reader)?;
+    *failures = 0;
+
+    loop {
+        if let Some(line) = unsent.clone() {
+            write!(out, "result {}", line)?;
+            reply(&mut reader)?;
+            *unsent = None;
+            *solved += 1;
+        }
+        writeln!(out, "ready")?;
+        loop {
+            let line = reply(&mut reader)?;
+            let (command, args) = match line.find(' ') {
+                Some(i) => (&line[..i], line[i + 1..].trim_end()),
+                None => (line.trim_end(), ""),
+            };
+            match command {
+                "bound" => {
+                    solver.results().write().unwrap().read(args.as_bytes())
+                        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+                },
+                "solve" => {
+                    let copies = solver.results().read().unwrap().rules().copies;
+                    let bag = Bag::from_digits(args, copies)
+                        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData,
+                                                      "Invalid bag"))?;
+                    solver.solve(&bag);
+                    let mut line = Vec::new();
+                    solver.results().read().unwrap().write_bag(&mut line, &bag)?;
+                    if line.is_empty() {
+                        line = format!("{}\t0\tlower\n", bag.digits()).into_bytes();
+                    }
+                    *unsent = Some(String::from_utf8(line).unwrap());
+                    break;
+                },
+                "wait" => {
+                    thread::sleep(WAIT);
+                    break;
+                },
+                "done" => return Ok(()),
+                _ => return Err(io::Error::new(ErrorKind::InvalidData,
+                                               format!("Unexpected {}", line.trim_end()))),
+            }
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schedule() {
+        let bag = Bag::from_digits("1100000000", 2).unwrap();
+        let c = Coordinator::new(Rules::default(), &[bag]);
+        let mut s = c.schedule.lock().unwrap();
+        assert_eq!(s.batches.len(), 3);
+
+        // A lost worker's bag goes to the next one
+        let first = match s.next(0) { Next::Solve(b) => b, _ => panic!() };
+        assert!(first.is_empty());
+        assert!(matches!(s.next(0), Next::Wait));
+        s.requeue(0);
+        assert!(matches!(s.next(1), Next::Solve(ref b) if *b == first));
+
+        // The next batch doesn't start until this one's finished
+        assert!(s.finish(&first));
+        assert!(!s.finish(&first));
+        assert!(matches!(s.next(1), Next::Solve(ref b) if b.len() == 1));
+        assert!(!s.finished());
+        let p = s.progress.snapshot();
+        assert_eq!((p.pieces, p.done, p.total), (1, 0, 2));
+    }
+
+    #[test]
+    fn solve() {
+        let bag = Bag::from_digits("2100000000", 2).unwrap();
+        let rules = Rules::default();
+        let local = Solver::new(rules);
+        local.sweep_bags(std::slice::from_ref(&bag));
+
+        let coordinator = Coordinator::new(rules, std::slice::from_ref(&bag));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let solved = thread::scope(|scope| {
+            scope.spawn(|| coordinator.serve(listener).unwrap());
+            let workers: Vec<_> = (0..2).map(|_| scope.spawn(|| {
+                work(&addr, &Solver::new(rules)).unwrap()
+            })).collect();
+            workers.into_iter().map(|w| w.join().unwrap()).sum::<usize>()
+        });
+
+        // Every subset of 0, 0, 1 (including the empty bag)
+        assert_eq!(solved, 6);
+        let results = coordinator.results().read().unwrap();
+        let local = local.results().read().unwrap();
+        for b in results.bags().into_iter().filter(|b| bag.contains(b)) {
+            assert!(results.score(&b).is_some());
+            assert_eq!(results.score(&b), local.score(&b));
+        }
+    }
+
+    #[test]
+    fn rules() {
+        let bag = Bag::from_digits("1000000000", 2).unwrap();
+        let coordinator = Coordinator::new(Rules::default(), &[bag]);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let single = Rules { copies: 1, ..Rules::default() };
+        thread::scope(|scope| {
+            scope.spawn(|| coordinator.serve(listener).unwrap());
+            let err = work(&addr, &Solver::new(single)).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+            assert!(err.to_string().starts_with("rules or pieces don't match"));
+
+            // Let the coordinator finish, so that the scope can end
+            work(&addr, &Solver::new(Rules::default())).unwrap();
+        });
+    }
+}
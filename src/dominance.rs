@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::mem::{size_of, size_of_val};
+
+use bag::Bag;
+use footprint::Footprint;
+use piece::{UNIQUE_PIECE_COUNT, MAX_COPIES};
+use state::State;
+
+// Checks whether every piece in a lies within a single piece in b, given
+// the owners of the same cells.  Then anything resting on two of b's
+// pieces also rests on (at least) two of a's.
+fn refines(a: &[u8], b: &[u8]) -> bool {
+    let mut within = [u8::MAX; UNIQUE_PIECE_COUNT * MAX_COPIES];
+    for (&i, &j) in a.iter().zip(b.iter()) {
+        if within[i as usize] == u8::MAX {
+            within[i as usize] = j;
+        } else if within[i as usize] != j {
+            return false;
+        }
+    }
+    return true;
+}
+
+type Entry = (isize, Vec<u8>);
+
+// Records searched states by their remaining bag and footprint, so that
+// states which can never beat one of them are skipped.
+//
+// A state A dominates B if they have the same tiles left to place, cover
+// exactly the same cells on every layer, A's pieces split each layer at
+// least as finely as B's, and A scores at least as much.  Whether a tile
+// can be placed only depends on the cells covered on each layer and on how
+// many pieces it rests on, so every way of finishing B also finishes A,
+// with the same tiles on the same layers.
+//
+// Since both states hold the same tiles (and so cover the same area), one
+// covering a superset of the other's cells on every layer means that they
+// cover the same cells; a looser test wouldn't be sound, since extra cells
+// on a layer block placements there.
+//
+// As with PlacementCache, the table is cleared when it passes its budget.
+// Forgetting states only means that fewer are skipped:  a state is only
+// skipped if one which dominates it was searched, and clearing never adds
+// entries.
+pub struct Dominance {
+    // Keyed by the bag left to place (as Bag::as_usize) and the cells,
+    // holding the score and owners of each undominated state
+    table: HashMap<(usize, Vec<u32>), Vec<Entry>>,
+
+    // Rough memory use of the table, its most recent peak, and its budget
+    bytes: usize,
+    peak: usize,
+    limit: usize,
+}
+
+// Rough size of a table entry, for keeping within the budget.  Each key and
+// state also pays for a heap allocation, counted as 64 bytes as in
+// PlacementCache.
+fn key_bytes(cells: &[u32]) -> usize {
+    size_of::<(usize, Vec<u32>)>() + size_of::<Vec<Entry>>() +
+        size_of_val(cells) + 64
+}
+
+fn entry_bytes(owners: &[u8]) -> usize {
+    size_of::<Entry>() + owners.len() + 64
+}
+
+impl Dominance {
+    // Builds a table which uses roughly the given number of bytes
+    pub fn new(limit: usize) -> Dominance {
+        Dominance { table: HashMap::new(), bytes: 0, peak: 0, limit: limit }
+    }
+
+    // Returns true if the state (with the given bag left to place and
+    // score) is dominated by one which was already recorded.  Otherwise,
+    // records it, forgetting any states that it dominates.
+    pub fn visit(&mut self, bag: &Bag, state: &State, score: isize) -> bool {
+        let Footprint { cells, owners } = Footprint::new(state);
+        let key = (bag.as_usize(), cells);
+        if let Some(entries) = self.table.get_mut(&key) {
+            if entries.iter().any(|e| e.0 >= score && refines(&e.1, &owners)) {
+                return true;
+            }
+            let before = entries.len();
+            entries.retain(|e| !(score >= e.0 && refines(&owners, &e.1)));
+            self.bytes -= (before - entries.len()) * entry_bytes(&owners);
+        }
+
+        let mut bytes = entry_bytes(&owners);
+        if !self.table.contains_key(&key) {
+            bytes += key_bytes(&key.1);
+        }
+        if self.bytes + bytes > self.limit {
+            self.table.clear();
+            self.bytes = 0;
+            bytes = entry_bytes(&owners) + key_bytes(&key.1);
+        }
+        self.bytes += bytes;
+        self.peak = self.peak.max(self.bytes);
+        self.table.entry(key).or_default().push((score, owners));
+        return false;
+    }
+
+    // Returns the number of states recorded
+    pub fn len(&self) -> usize {
+        self.table.values().map(|v| v.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+
+    // Returns the most memory that the table has used, in bytes
+    pub fn peak_bytes(&self) -> usize {
+        self.peak
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refines() {
+        assert!(super::refines(&[0, 0, 1, 1], &[2, 2, 2, 2]));
+        assert!(super::refines(&[0, 1], &[0, 1]));
+        assert!(!super::refines(&[0, 0, 1], &[0, 1, 1]));
+    }
+
+    #[test]
+    fn visit() {
+        let bag = Bag::from_usize(4);
+        let a = State::new().try_place(0, 0, 0).unwrap()
+            .try_place(0, 3, 0).unwrap();
+        let b = State::new().try_place(0, 0, 0).unwrap()
+            .try_place(0, 0, 4).unwrap();
+
+        let mut d = Dominance::new(1 << 20);
+        assert!(!d.visit(&bag, &a, 0));
+        assert!(d.visit(&bag, &a, 0));
+        assert!(!d.visit(&bag, &b, 0), "different cells");
+        assert!(!d.visit(&bag.add(4), &a, 0), "different bag");
+        assert_eq!(d.len(), 3);
+
+        // A higher score replaces the one that it dominates
+        assert!(!d.visit(&bag, &a, 1));
+        assert!(d.visit(&bag, &a, 1));
+        assert_eq!(d.len(), 3);
+        assert!(d.peak_bytes() > 0);
+    }
+
+    #[test]
+    fn limit() {
+        // A table which can't hold two states forgets the first, so it's
+        // no longer skipped
+        let bag = Bag::from_usize(4);
+        let a = State::new().try_place(0, 0, 0).unwrap()
+            .try_place(0, 3, 0).unwrap();
+        let b = State::new().try_place(0, 0, 0).unwrap()
+            .try_place(0, 0, 4).unwrap();
+
+        let mut d = Dominance::new(0);
+        assert!(!d.visit(&bag, &a, 0));
+        assert!(!d.visit(&bag, &b, 0));
+        assert_eq!(d.len(), 1);
+        assert!(!d.visit(&bag, &a, 0));
+        assert_eq!(d.len(), 1);
+    }
+}
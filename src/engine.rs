@@ -0,0 +1,253 @@
+// A line protocol for driving the hint search from another program (such
+// as a GUI), in the style of chess engines' UCI.  Each command is one line
+// on stdin, and the replies are lines on stdout:
+//
+//   nmbr9                  replies "id name nmbr9", then "nmbr9ok"
+//   isready                replies "readyok"
+//   newgame                clears the position
+//   position [MOVES]       sets the moves played so far, in notation (see
+//                          the game module) separated by semicolons
+//   remaining DIGITS       sets the tiles still in the deck, not counting
+//                          the next one drawn, as one digit per tile.
+//                          Otherwise, it's every tile not on the table.
+//   go TILE [movetime MS]  finds the best placement for the drawn tile,
+//                          within the time budget (1 second by default)
+//   quit                   stops the engine
+//
+// While searching, each pass of the lookahead is reported as
+//
+//   info depth 1 score 12.50 nodes 4810 time 35 pv 7@3,2 r1 z0
+//
+// and the search ends with "bestmove 7@3,2 r1 z0", in the position's
+// frame (the same one as the moves).  If the engine was given another
+// strategy, it's asked instead, and there are no info lines.  Problems with a command are
+// reported as "info string error ...", and unknown commands are ignored.
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::time::Duration;
+
+use bag::Bag;
+use game::{self, Move};
+use hint::{Hint, Info};
+use piece::UNIQUE_PIECE_COUNT;
+use rules::Rules;
+use state::{Placed, State};
+use strategy::Strategy;
+
+pub struct Engine {
+    rules: Rules,
+    moves: Vec<Move>,
+    remaining: Option<Bag>,
+
+    // If present, chooses placements instead of the hint search
+    strategy: Option<Box<dyn Strategy>>,
+}
+
+// Converts a placement in a state's frame to the game's frame, where the
+// state's corner is at the given position
+pub(crate) fn to_move(p: &Placed, corner: (i32, i32)) -> Move {
+    Move { tile: p.index(), rotation: p.orientation(), x: p.x + corner.0,
+           y: p.y + corner.1, z: Some(p.z) }
+}
+
+impl Engine {
+    pub fn new(rules: Rules) -> Engine {
+        Engine { rules: rules, moves: Vec::new(), remaining: None,
+                 strategy: None }
+    }
+
+    pub fn with_strategy(mut self, strategy: Box<dyn Strategy>) -> Engine {
+        self.strategy = Some(strategy);
+        self
+    }
+
+    // Handles one line of input, writing replies to out.  Returns false
+    // once the engine should stop.
+    pub fn handle<W: Write>(&mut self, line: &str, out: &mut W)
+        -> io::Result<bool>
+    {
+        let line = line.trim();
+        let (command, args) = match line.find(char::is_whitespace) {
+            Some(i) => (&line[..i], line[i..].trim()),
+            None => (line, ""),
+        };
+        let error = match command {
+            "nmbr9" => {
+                writeln!(out, "id name nmbr9")?;
+                writeln!(out, "nmbr9ok")?;
+                None
+            },
+            "isready" => {
+                writeln!(out, "readyok")?;
+                None
+            },
+            "newgame" => {
+                self.moves.clear();
+                self.remaining = None;
+                None
+            },
+            "position" => self.position(args).err(),
+            "remaining" => match Bag::from_digits(args, self.rules.copies) {
+                Some(b) => {
+                    self.remaining = Some(b);
+                    None
+                },
+                None => Some(format!("invalid tiles '{}'", args)),
+            },
+            "go" => self.go(args, out)?.err(),
+            "quit" => return Ok(false),
+            _ => None,
+        };
+        if let Some(e) = error {
+            writeln!(out, "info string error {}", e)?;
+        }
+        out.flush()?;
+        return Ok(true);
+    }
+
+    fn position(&mut self, args: &str) -> Result<(), String> {
+        let moves = game::parse_text(&args.replace(';', "\n"))?;
+        let (_, err) = game::steps(&moves, &self.rules);
+        if let Some((i, e)) = err {
+            return Err(format!("move {} ({}) is illegal: {}", i + 1,
+                               moves[i], e));
+        }
+        self.moves = moves;
+        return Ok(());
+    }
+
+    // Returns the current arrangement, and the game's position of its
+    // corner
+    fn state(&self) -> (State, (i32, i32)) {
+        let (states, _) = game::steps(&self.moves, &self.rules);
+        let state = states.last().cloned()
+            .unwrap_or_else(|| State::with_rules(&self.rules));
+        let corner = (self.moves.iter().map(|m| m.x).min().unwrap_or(0),
+                      self.moves.iter().map(|m| m.y).min().unwrap_or(0));
+        return (state, corner);
+    }
+
+    fn go<W: Write>(&mut self, args: &str, out: &mut W)
+        -> io::Result<Result<(), String>>
+    {
+        let words: Vec<&str> = args.split_whitespace().collect();
+        let tile = match words.first().and_then(|t| t.parse().ok()) {
+            Some(t) if t < UNIQUE_PIECE_COUNT => t,
+            _ => return Ok(Err("go needs a tile (0-9)".to_string())),
+        };
+        let budget = match &words[1..] {
+            [] => Duration::from_secs(1),
+            ["movetime", ms] => match ms.parse() {
+                Ok(ms) => Duration::from_millis(ms),
+                Err(_) => return Ok(Err(format!("invalid movetime '{}'", ms))),
+            },
+            _ => return Ok(Err(format!("invalid go '{}'", args))),
+        };
+        let (state, corner) = self.state();
+        if let Some(ref r) = self.remaining {
+            let on_table = state.pieces.iter()
+                .filter(|p| p.index() == tile).count();
+            if r.digit(tile) + on_table >= self.rules.copies {
+                return Ok(Err(format!("there are no more copies of tile {}",
+                                      tile)));
+            }
+        }
+
+        if let Some(ref mut s) = self.strategy {
+            return match s.choose(&state, tile) {
+                Some(p) => {
+                    writeln!(out, "bestmove {}", to_move(&p, corner))?;
+                    Ok(Ok(()))
+                },
+                None => Ok(Err(format!("{} can't place tile {}", s.name(), tile))),
+            };
+        }
+
+        // Info lines are written as the search goes, but a failed write
+        // can't stop the search, so it's saved for afterwards
+        let shared = RefCell::new((out, Ok(())));
+        let best = {
+            let info = |i: &Info| {
+                let mut lock = shared.borrow_mut();
+                let (ref mut out, ref mut written) = *lock;
+                if written.is_ok() {
+                    *written = writeln!(out, "info depth {} score {:.2} \
+                                              nodes {} time {} pv {}",
+                                        i.depth, i.value, i.nodes,
+                                        i.elapsed.as_millis(),
+                                        to_move(&i.best, corner))
+                        .and_then(|_| out.flush());
+                }
+            };
+            let mut hint = Hint::new(self.rules).with_info(Box::new(info));
+            if let Some(ref r) = self.remaining {
+                hint = hint.with_remaining(r.clone());
+            }
+            hint.run(&state, tile, budget)
+        };
+        let (out, written) = shared.into_inner();
+        written?;
+        writeln!(out, "bestmove {}", to_move(&best, corner))?;
+        return Ok(Ok(()));
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use strategy::Greedy;
+
+    // Runs the engine on some lines of input, returning its output
+    fn run(input: &str) -> String {
+        run_with(Engine::new(Rules::default()), input)
+    }
+
+    fn run_with(mut engine: Engine, input: &str) -> String {
+        let mut out = Vec::new();
+        for line in input.lines() {
+            if !engine.handle(line, &mut out).unwrap() {
+                break;
+            }
+        }
+        return String::from_utf8(out).unwrap();
+    }
+
+    #[test]
+    fn handshake() {
+        assert_eq!(run("nmbr9\nisready\nbogus\nquit\nisready\n"),
+                   "id name nmbr9\nnmbr9ok\nreadyok\n");
+    }
+
+    #[test]
+    fn go() {
+        // Two 0s, in a frame where the first isn't at the origin; the 1
+        // bridges them
+        let out = run("position 0@5,5; 0@8,5\nremaining 0000000000\n\
+                       go 1 movetime 5000\n");
+        let lines: Vec<&str> = out.lines().collect();
+        assert!(lines[0].starts_with("info depth 0 score 1.00 nodes "),
+                "{}", out);
+        assert_eq!(lines.last(), Some(&"bestmove 1@7,5 r0 z1"));
+    }
+
+    #[test]
+    fn strategy() {
+        let engine = Engine::new(Rules::default())
+            .with_strategy(Box::new(Greedy::new(Rules::default())));
+        assert_eq!(run_with(engine, "position 0@5,5; 0@8,5\ngo 1\n"),
+                   "bestmove 1@7,5 r0 z1\n");
+    }
+
+    #[test]
+    fn errors() {
+        let out = run("position 0@0,0; 0@9,9\ngo\ngo 1 movetime x\n\
+                       remaining 99\nremaining 2000000000\ngo 0\n");
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 5, "{}", out);
+        assert!(lines[0].starts_with("info string error move 2 "));
+        assert!(lines.iter().all(|l| l.starts_with("info string error ")));
+    }
+}
@@ -0,0 +1,75 @@
+// Errors from the checked versions of the library's operations, for
+// callers which pass along input that hasn't been validated (such as the C
+// API, the Python module and the HTTP server).  The search uses unchecked
+// versions, such as Bag::take and State::try_place, whose preconditions
+// are only asserted in debug builds.
+
+use std::error;
+use std::fmt;
+use std::result;
+
+use piece::{MAX_COPIES, UNIQUE_PIECE_COUNT, MAX_ROTATIONS, MAX_ORIENTATIONS};
+use piece::PIECE_ID_COUNT;
+
+// The furthest from the origin that a placement can be, in either
+// direction.  Every legal placement onto a normalized state is well inside
+// this, and the state it makes still fits in a packed placement's
+// coordinates (see Placed::pack).
+pub const MAX_COORDINATE: i32 = 255;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    // A tile outside 0-9, or a rotation outside 0-3 (or 0-7, where
+    // mirrored pieces are allowed)
+    InvalidTile(usize),
+    InvalidRotation(usize),
+
+    // A piece id past every tile's orientations (see PIECE_ID_COUNT)
+    InvalidPieceId(usize),
+
+    // A position too far from the origin to be placed (see MAX_COORDINATE)
+    OutOfRange(i32, i32),
+
+    // A tile taken from a bag which doesn't hold it
+    MissingTile(usize),
+
+    // A tile added to a bag or a table which already holds every copy
+    TooManyCopies(usize),
+
+    // A tile placed on a table which already holds every tile in the game
+    Full,
+
+    // A placement which breaks the rules for where tiles can go
+    Illegal,
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InvalidTile(t) =>
+                write!(f, "there's no tile {} (tiles are 0-{})", t,
+                       UNIQUE_PIECE_COUNT - 1),
+            Error::InvalidRotation(r) =>
+                write!(f, "rotation {} isn't 0-{} quarter turns (or {}-{} \
+                           for a mirrored tile, where those are allowed)",
+                       r, MAX_ROTATIONS - 1, MAX_ROTATIONS,
+                       MAX_ORIENTATIONS - 1),
+            Error::InvalidPieceId(id) =>
+                write!(f, "there's no piece id {} (ids are 0-{})", id,
+                       PIECE_ID_COUNT - 1),
+            Error::OutOfRange(x, y) =>
+                write!(f, "position {},{} is out of range", x, y),
+            Error::MissingTile(t) =>
+                write!(f, "there's no tile {} to take", t),
+            Error::TooManyCopies(t) =>
+                write!(f, "there are at most {} copies of tile {}",
+                       MAX_COPIES, t),
+            Error::Full => write!(f, "every tile has been placed"),
+            Error::Illegal => write!(f, "the tile can't go there"),
+        }
+    }
+}
+
+impl error::Error for Error {}
@@ -0,0 +1,218 @@
+use std::cmp::max;
+use std::mem::size_of;
+
+use bag::Bag;
+use results::Results;
+use tuning::{Tuning, entry_bytes};
+
+// The result of a short solve of one sampled bag
+#[derive(Copy, Clone, Debug)]
+pub struct Sample {
+    pub pieces: usize,
+    pub seconds: f64,
+    pub nodes: usize,
+
+    // False if the solve ran out of its budget, in which case the time
+    // and node count are only lower bounds
+    pub complete: bool,
+}
+
+// Predicted cost of every bag with a particular number of pieces
+#[derive(Copy, Clone, Debug)]
+pub struct Row {
+    pub pieces: usize,
+    pub bags: usize,
+    pub seconds: f64,
+    pub bytes: usize,
+
+    // True if this row comes from the fitted model rather than from
+    // complete samples
+    pub extrapolated: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Estimate {
+    pub rows: Vec<Row>,
+    pub seconds: f64,
+    pub peak_bytes: usize,
+
+    // Sizes with neither samples nor a fitted model, which are left out
+    // of the totals rather than counted as free
+    pub unknown: Vec<usize>,
+}
+
+// Picks up to `per_count` bags of each size, spread evenly through the
+// bags of that size
+pub fn stratify(bags: &[Bag], per_count: usize) -> Vec<Bag> {
+    let most = bags.iter().map(|b| b.len()).max().unwrap_or(0);
+    let mut out = Vec::new();
+    for n in 0..=most {
+        let of_size: Vec<&Bag> = bags.iter().filter(|b| b.len() == n).collect();
+        let k = per_count.min(of_size.len());
+        for i in 0..k {
+            out.push(of_size[i * of_size.len() / k].clone());
+        }
+    }
+    return out;
+}
+
+// Workers need a lower bound for every proper subset of their bag.  When
+// sampling, most subsets haven't been solved, so this gives them a lower
+// bound of 0 (which any bag can reach, by laying every tile on the table).
+pub fn prepare(results: &mut Results, bag: &Bag) {
+    for b in results.bags() {
+        if b.len() < bag.len() && bag.contains(&b) &&
+           results.lower_bound(&b).is_none()
+        {
+            results.write_lower_bound(&b, 0);
+        }
+    }
+}
+
+// Fits ln(y) = a + b * x by least squares, returning (a, b)
+fn fit(points: &[(f64, f64)]) -> Option<(f64, f64)> {
+    let pts: Vec<(f64, f64)> = points.iter().filter(|p| p.1 > 0.0)
+        .map(|&(x, y)| (x, y.ln())).collect();
+    if pts.len() < 2 {
+        return None;
+    }
+    let n = pts.len() as f64;
+    let sx: f64 = pts.iter().map(|p| p.0).sum();
+    let sy: f64 = pts.iter().map(|p| p.1).sum();
+    let sxx: f64 = pts.iter().map(|p| p.0 * p.0).sum();
+    let sxy: f64 = pts.iter().map(|p| p.0 * p.1).sum();
+    let d = n * sxx - sx * sx;
+    if d == 0.0 {
+        return None;
+    }
+    let b = (n * sxy - sx * sy) / d;
+    return Some(((sy - b * sx) / n, b));
+}
+
+// Predicts the cost of a full sweep from samples.  `counts[n]` is the
+// number of bags with n pieces.  Sizes whose samples all finished use
+// their mean time and node count; the rest use an exponential fit in
+// the number of pieces (or their samples' lower bounds, if larger).
+// Samples must each have run on one thread, since the total is divided
+// between the threads here.  Peak memory assumes that every thread is
+// working on one of the largest bags at once, with the results table
+// alongside.
+pub fn estimate(samples: &[Sample], counts: &[usize], threads: usize,
+                results: &Results) -> Estimate {
+    let mean = |n: usize, f: &dyn Fn(&Sample) -> f64| {
+        let s: Vec<f64> = samples.iter().filter(|s| s.pieces == n)
+            .map(f).collect();
+        if s.is_empty() { None } else { Some(s.iter().sum::<f64>() / s.len() as f64) }
+    };
+    let complete = |n: usize| samples.iter().any(|s| s.pieces == n) &&
+        samples.iter().filter(|s| s.pieces == n).all(|s| s.complete);
+
+    let known: Vec<usize> = (0..counts.len()).filter(|&n| complete(n)).collect();
+    let time_fit = fit(&known.iter()
+        .map(|&n| (n as f64, mean(n, &|s| s.seconds).unwrap()))
+        .collect::<Vec<_>>());
+    let node_fit = fit(&known.iter()
+        .map(|&n| (n as f64, mean(n, &|s| s.nodes as f64).unwrap()))
+        .collect::<Vec<_>>());
+    // The larger of the model and the samples' lower bound, whichever
+    // of them are known
+    let model = |f: Option<(f64, f64)>, n: usize, lower: Option<f64>| {
+        let m = f.map(|(a, b)| (a + b * n as f64).exp());
+        match (m, lower) {
+            (Some(m), Some(lower)) => Some(m.max(lower)),
+            (m, lower) => m.or(lower),
+        }
+    };
+
+    let mut rows = Vec::new();
+    let mut unknown = Vec::new();
+    for (n, &bags) in counts.iter().enumerate() {
+        if bags == 0 {
+            continue;
+        }
+        let (seconds, nodes, extrapolated) = if complete(n) {
+            (mean(n, &|s| s.seconds).unwrap(),
+             mean(n, &|s| s.nodes as f64).unwrap(), false)
+        } else {
+            let seconds = model(time_fit, n, mean(n, &|s| s.seconds));
+            let nodes = model(node_fit, n, mean(n, &|s| s.nodes as f64));
+            match (seconds, nodes) {
+                (Some(seconds), Some(nodes)) => (seconds, nodes, true),
+                _ => {
+                    unknown.push(n);
+                    continue;
+                },
+            }
+        };
+
+        // Seen-sets stop growing at their limit, when they switch to a
+        // fixed-size Bloom filter
+        let tuning = Tuning::for_len(n);
+        let bytes = ((nodes * entry_bytes(tuning.dedup) as f64) as usize)
+            .min(tuning.seen_limit);
+        rows.push(Row { pieces: n, bags: bags, seconds: seconds, bytes: bytes,
+                        extrapolated: extrapolated });
+    }
+
+    let total: f64 = rows.iter().map(|r| r.seconds * r.bags as f64).sum();
+    let largest = rows.iter().map(|r| r.bytes).fold(0, max);
+    let table = results.bags().len() * 3 * size_of::<i32>();
+    return Estimate {
+        seconds: total / threads.max(1) as f64,
+        peak_bytes: largest * threads + table,
+        rows: rows,
+        unknown: unknown,
+    };
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rules::Rules;
+
+    #[test]
+    fn stratify() {
+        let results = Results::new(Rules::default());
+        let bags = super::stratify(&results.bags(), 2);
+        for n in 0..=20 {
+            let count = bags.iter().filter(|b| b.len() == n).count();
+            assert_eq!(count, if n == 0 || n == 20 { 1 } else { 2 });
+        }
+    }
+
+    #[test]
+    fn fit() {
+        let pts: Vec<(f64, f64)> = (0..5)
+            .map(|x| (x as f64, 3.0 * (0.5 * x as f64).exp())).collect();
+        let (a, b) = super::fit(&pts).unwrap();
+        assert!((a - 3.0f64.ln()).abs() < 1e-9);
+        assert!((b - 0.5).abs() < 1e-9);
+        assert!(super::fit(&pts[..1]).is_none());
+    }
+
+    #[test]
+    fn estimate() {
+        let results = Results::new(Rules::default());
+        let sample = |n, s, c| Sample { pieces: n, seconds: s, nodes: 10 * n,
+                                        complete: c };
+        let samples = [sample(1, 1.0, true), sample(2, 2.0, true),
+                       sample(3, 4.0, true), sample(4, 1.0, false)];
+        let e = super::estimate(&samples, &[0, 10, 10, 10, 10], 2, &results);
+        assert_eq!(e.rows.len(), 4);
+        assert!(!e.rows[2].extrapolated);
+        assert!(e.rows[3].extrapolated);
+        assert!((e.rows[3].seconds - 8.0).abs() < 1e-6);
+        assert!((e.seconds - (10.0 + 20.0 + 40.0 + 80.0) / 2.0).abs() < 1e-4);
+        assert!(e.unknown.is_empty());
+
+        // Without a model, a size with no samples is left out rather than
+        // predicted to take no time, but timed-out samples still count
+        let samples = [sample(1, 1.0, true), sample(3, 4.0, false)];
+        let e = super::estimate(&samples, &[0, 10, 10, 10], 1, &results);
+        assert_eq!(e.rows.len(), 2);
+        assert_eq!(e.unknown, vec![2]);
+        assert!((e.seconds - (10.0 + 40.0)).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,244 @@
+// Expected scores when tiles are drawn from a shuffled deck, and placed
+// one at a time without knowing what comes next.  The player picks the
+// best placement for each tile (a max node), then the deck picks the next
+// tile (a chance node, weighted by how many copies of each tile remain).
+
+use std::collections::{HashMap, HashSet};
+
+use bag::Bag;
+use bitboard::Bitboards;
+use piece::{MAX_ROTATIONS, UNIQUE_PIECE_COUNT, pieces};
+use rules::Rules;
+use rng::Rng;
+use state::{Placed, State};
+use strategy::Strategy;
+
+pub struct Expectimax {
+    rules: Rules,
+
+    // Expected final score from a state, with the given tiles still in
+    // the deck (keyed by the bag's usize representation)
+    memo: HashMap<(State, usize), f64>,
+
+    // If present, chance nodes average over this many draws (picked using
+    // the seed) rather than over every tile left in the deck
+    samples: Option<(usize, u64)>,
+}
+
+impl Expectimax {
+    pub fn new(rules: Rules) -> Expectimax {
+        Expectimax { rules: rules, memo: HashMap::new(), samples: None }
+    }
+
+    // Estimates each chance node from a few sampled draws.  This makes the
+    // result approximate, but is needed for anything near a full deck.
+    pub fn with_samples(mut self, samples: usize, seed: u64) -> Expectimax {
+        assert!(samples > 0);
+        self.samples = Some((samples, seed));
+        self.memo.clear();
+        self
+    }
+
+    // Number of (state, deck) pairs which have been evaluated
+    pub fn memo_len(&self) -> usize {
+        self.memo.len()
+    }
+
+    // Returns the expected score of a perfect player who draws every tile
+    // in the bag, in a random order
+    pub fn expected(&mut self, bag: &Bag) -> f64 {
+        let state = State::with_rules(&self.rules);
+        return self.value(&state, bag);
+    }
+
+    // Returns the expected final score from the given state, with the
+    // tiles in the bag still to be drawn
+    pub fn value(&mut self, state: &State, bag: &Bag) -> f64 {
+        if bag.is_empty() {
+            return self.rules.score(state) as f64;
+        }
+        let key = (state.clone(), bag.as_usize());
+        if let Some(v) = self.memo.get(&key) {
+            return *v;
+        }
+
+        let mut total = 0.0;
+        for (tile, weight) in self.draws(state, bag) {
+            total += weight * self.best(state, bag, tile)
+                .map(|(v, _)| v)
+                .unwrap_or(0.0);
+        }
+        self.memo.insert(key, total);
+        return total;
+    }
+
+    // Returns the best placement of a drawn tile (which must be in the
+    // bag), along with its expected final score, or None if the tile
+    // can't be placed anywhere
+    pub fn best(&mut self, state: &State, bag: &Bag, tile: usize)
+        -> Option<(f64, State)>
+    {
+        let rest = bag.take(tile * MAX_ROTATIONS);
+        let mut best: Option<(f64, State)> = None;
+        for (_, s) in placements(state, tile, &self.rules) {
+            let v = self.value(&s, &rest);
+            if best.as_ref().map(|b| v > b.0).unwrap_or(true) {
+                best = Some((v, s));
+            }
+        }
+        return best;
+    }
+
+    // Returns the expected score of a player who places each tile as the
+    // strategy chooses, drawing every tile in the bag in a random order.
+    // Each arrangement and deck is only played out once, so a strategy
+    // which isn't deterministic is sampled rather than averaged.
+    pub fn evaluate(&self, strategy: &mut dyn Strategy, bag: &Bag) -> f64 {
+        let state = State::with_rules(&self.rules);
+        return self.played(strategy, &mut HashMap::new(), &state, bag);
+    }
+
+    fn played(&self, strategy: &mut dyn Strategy,
+              memo: &mut HashMap<(State, usize), f64>,
+              state: &State, bag: &Bag) -> f64
+    {
+        if bag.is_empty() {
+            return self.rules.score(state) as f64;
+        }
+        let key = (state.clone(), bag.as_usize());
+        if let Some(v) = memo.get(&key) {
+            return *v;
+        }
+
+        let mut total = 0.0;
+        for (tile, weight) in self.draws(state, bag) {
+            let rest = bag.take(tile * MAX_ROTATIONS);
+            let next = match strategy.choose(state, tile) {
+                Some(p) => state
+                    .try_place(p.id(), p.x, p.y)
+                    .unwrap_or_else(|| panic!("{} chose an illegal placement {}",
+                                              strategy.name(), p)),
+                None => state.clone(),
+            };
+            total += weight * self.played(strategy, memo, &next, &rest);
+        }
+        memo.insert(key, total);
+        return total;
+    }
+
+    // Returns the tiles which the deck could deal next, with the chance
+    // of each (which sum to 1)
+    fn draws(&self, state: &State, bag: &Bag) -> Vec<(usize, f64)> {
+        let n = bag.len() as f64;
+        let all = (0..UNIQUE_PIECE_COUNT)
+            .filter(|&t| bag.digit(t) > 0)
+            .map(|t| (t, bag.digit(t) as f64 / n));
+        let (samples, seed) = match self.samples {
+            Some(s) => s,
+            None => return all.collect(),
+        };
+
+        // Each draw picks one of the bag's tiles uniformly, so tiles with
+        // more copies come up more often
+        let tiles: Vec<usize> = all.flat_map(|(t, _)| {
+            (0..bag.digit(t)).map(move |_| t)
+        }).collect();
+        let mut counts = HashMap::new();
+        let mut rng = Rng::for_state(&[seed, bag.as_usize() as u64], state);
+        for _ in 0..samples {
+            let t = tiles[rng.below(tiles.len())];
+            *counts.entry(t).or_insert(0) += 1;
+        }
+        let mut out: Vec<(usize, f64)> = counts.into_iter()
+            .map(|(t, c)| (t, c as f64 / samples as f64))
+            .collect();
+        out.sort_by_key(|&(t, _)| t);
+        return out;
+    }
+}
+
+// Returns every distinct state made by placing the given tile (0-9), along
+// with the placement that made it, in the original state's coordinates
+pub(crate) fn placements(state: &State, tile: usize, rules: &Rules)
+    -> Vec<(Placed, State)>
+{
+    // Any arrangement can be turned so that its first tile is unrotated
+    // (mirrored or not), so those are the only first placements to try
+    let first = state.is_empty();
+    let boards = Bitboards::new(state);
+    let mut seen = HashSet::new();
+    let mut out = Vec::new();
+    for b in pieces().orientations(tile, state.mirrored())
+        .filter(|b| !first || b % MAX_ROTATIONS == 0)
+    {
+        for (p, s) in boards.placements(state, b) {
+            if rules.max_layers.map(|n| s.layers() >= n).unwrap_or(false) {
+                continue;
+            }
+            if seen.insert(s.clone()) {
+                out.push((p, s));
+            }
+        }
+    }
+    return out;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use strategy::{Greedy, Random};
+
+    #[test]
+    fn certain() {
+        // With one kind of tile, every shuffle is the same, and there's
+        // no way to stack a lone tile
+        let mut e = Expectimax::new(Rules::default());
+        let bag = Bag::from_usize(0).add(36);
+        assert_eq!(e.expected(&bag), 0.0);
+        assert_eq!(e.expected(&Bag::from_usize(0)), 0.0);
+    }
+
+    #[test]
+    fn expected() {
+        // Two 0s then a 1 can score 1 (the 1 bridges both 0s), but the 1
+        // can't go on top if it's drawn before either 0
+        let mut e = Expectimax::new(Rules::default());
+        let bag = Bag::from_usize(0).add(0).add(0).add(4);
+        let v = e.expected(&bag);
+        assert!(v > 0.0 && v < 1.0, "{}", v);
+        assert!((v - 1.0 / 3.0).abs() < 1e-9, "{}", v);
+        assert!(e.memo_len() > 0);
+
+        // After one 0, it's a coin flip whether the other 0 comes next;
+        // if it does, it's placed so that the 1 can bridge both
+        let one = State::new().try_place(0, 0, 0).unwrap();
+        let rest = Bag::from_usize(0).add(0).add(4);
+        assert!((e.value(&one, &rest) - 0.5).abs() < 1e-9);
+        let (v, two) = e.best(&one, &rest, 0).unwrap();
+        assert_eq!(v, 1.0);
+        assert_eq!(two.pieces.len(), 2);
+
+        // Sampling gives an estimate in the same range, and is repeatable
+        let mut s = Expectimax::new(Rules::default()).with_samples(4, 1);
+        let a = s.expected(&bag);
+        assert!(a >= 0.0 && a <= 1.0, "{}", a);
+        let mut s = Expectimax::new(Rules::default()).with_samples(4, 1);
+        assert_eq!(a, s.expected(&bag));
+    }
+
+    #[test]
+    fn evaluate() {
+        // Greedy play bridges the 0s whenever the 1 comes last, which is
+        // as well as a perfect player can do
+        let rules = Rules::default();
+        let bag = Bag::from_usize(0).add(0).add(0).add(4);
+        let e = Expectimax::new(rules);
+        let v = e.evaluate(&mut Greedy::new(rules), &bag);
+        assert!((v - 1.0 / 3.0).abs() < 1e-9, "{}", v);
+
+        let r = e.evaluate(&mut Random::new(rules, 1), &bag);
+        assert!(r >= 0.0 && r <= v, "{}", r);
+    }
+}
@@ -0,0 +1,128 @@
+// A C API for the placement rules, so that other programs (such as GUIs)
+// can check moves and score arrangements without reimplementing them.  The
+// declarations are in include/nmbr9.h.
+//
+// States are opaque handles, created by nmbr9_state_new or
+// nmbr9_state_clone and released with nmbr9_state_free.  Every function
+// which takes a handle expects a live one (not null, not yet freed).
+
+// Safety requirements are given once, above, rather than per function
+#![allow(clippy::missing_safety_doc)]
+
+use piece::Adjacency;
+use state::State;
+
+// Returns an empty table.  If diagonal is true, tiles touching at a corner
+// count as neighbors (a common house rule).
+#[no_mangle]
+pub extern "C" fn nmbr9_state_new(diagonal: bool) -> *mut State {
+    let adjacency = if diagonal {
+        Adjacency::Diagonal
+    } else {
+        Adjacency::Orthogonal
+    };
+    Box::into_raw(Box::new(State::with_adjacency(adjacency)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn nmbr9_state_clone(state: *const State) -> *mut State {
+    Box::into_raw(Box::new((&*state).clone()))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn nmbr9_state_free(state: *mut State) {
+    if !state.is_null() {
+        drop(Box::from_raw(state));
+    }
+}
+
+// Places a tile (0-9), turned by rotation quarter turns, with its bounding
+// box's corner at x, y.  Returns false (leaving the state unchanged) if the
+// placement is illegal or its arguments are out of range.  After a placement
+// the arrangement is shifted so that its smallest x and y are 0, so
+// positions should be read back with nmbr9_state_piece.
+#[no_mangle]
+pub unsafe extern "C" fn nmbr9_state_place(state: *mut State, tile: u32,
+                                           rotation: u32, x: i32, y: i32)
+    -> bool
+{
+    match (&*state).place(tile as usize, rotation as usize, x, y) {
+        Ok(next) => {
+            *state = next;
+            true
+        },
+        Err(_) => false,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn nmbr9_state_score(state: *const State) -> i64 {
+    (&*state).score() as i64
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn nmbr9_state_piece_count(state: *const State) -> u32 {
+    (&*state).pieces.len() as u32
+}
+
+// A placed tile, as read back by nmbr9_state_piece
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Nmbr9Piece {
+    pub tile: u32,
+    pub rotation: u32,
+    pub x: i32,
+    pub y: i32,
+    pub z: u32,
+}
+
+// Reads the i'th placed tile (ordered from the top layer down) into out,
+// returning false if there aren't that many tiles
+#[no_mangle]
+pub unsafe extern "C" fn nmbr9_state_piece(state: *const State, i: u32,
+                                           out: *mut Nmbr9Piece) -> bool
+{
+    match (&*state).pieces.get(i as usize) {
+        Some(p) => {
+            *out = Nmbr9Piece { tile: p.index() as u32, rotation: p.orientation() as u32,
+                                x: p.x, y: p.y, z: p.z as u32 };
+            true
+        },
+        None => false,
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn place() {
+        unsafe {
+            let s = nmbr9_state_new(false);
+            assert!(nmbr9_state_place(s, 0, 0, 0, 0));
+            assert!(nmbr9_state_place(s, 0, 0, 3, 0));
+            assert!(!nmbr9_state_place(s, 0, 0, 9, 0));
+            assert!(!nmbr9_state_place(s, 10, 0, 0, 0));
+            assert!(!nmbr9_state_place(s, 1, 4, 2, 0));
+            assert_eq!(nmbr9_state_piece_count(s), 2);
+
+            // Placing on a copy leaves the original alone
+            let t = nmbr9_state_clone(s);
+            assert!(nmbr9_state_place(t, 1, 0, 2, 0));
+            assert_eq!(nmbr9_state_score(t), 1);
+            assert_eq!(nmbr9_state_score(s), 0);
+
+            let mut p = Nmbr9Piece::default();
+            assert!(nmbr9_state_piece(t, 0, &mut p));
+            assert_eq!(p, Nmbr9Piece { tile: 1, rotation: 0, x: 2, y: 0, z: 1 });
+            assert!(!nmbr9_state_piece(t, 3, &mut p));
+
+            nmbr9_state_free(s);
+            nmbr9_state_free(t);
+            nmbr9_state_free(std::ptr::null_mut());
+        }
+    }
+}
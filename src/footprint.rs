@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+use std::mem::size_of_val;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use piece::{UNIQUE_PIECE_COUNT, MAX_COPIES, pieces};
+use state::{Placed, State};
+use tables::Tables;
+
+// A state's covered cells on every layer, packed as z << 16 | x << 8 | y
+// and sorted, along with the index (in State::pieces) of the piece which
+// covers each cell.  States are normalized, so two states which cover the
+// same cells have the same footprint.
+//
+// Whether a tile can be placed only depends on the cells covered on each
+// layer and on which piece covers each of them (since a tile can't rest
+// on a single piece), so states with the same footprint and owners allow
+// the same placements.
+pub struct Footprint {
+    pub cells: Vec<u32>,
+    pub owners: Vec<u8>,
+}
+
+impl Footprint {
+    pub fn new(state: &State) -> Footprint {
+        let tables = Tables::get(state.adjacency(), state.mirrored());
+        let mut cells = Vec::new();
+        for (i, p) in state.pieces.iter().enumerate() {
+            let bmp = tables.bitmap(p.id());
+            for b in (0..16).filter(|b| bmp & (1 << b) != 0) {
+                let (x, y) = (p.x + 3 - b % 4, p.y + b / 4);
+                cells.push(((p.z as u32) << 16 | (x as u32) << 8 | y as u32,
+                            i as u8));
+            }
+        }
+        cells.sort_unstable();
+        Footprint { cells: cells.iter().map(|c| c.0).collect(),
+                    owners: cells.iter().map(|c| c.1).collect() }
+    }
+
+    // Returns the cells with their owners, numbered in the order that
+    // they're first seen, so that states which allow the same placements
+    // have the same key whatever their tiles are
+    pub fn key(&self) -> Vec<u32> {
+        let mut labels = [u8::MAX; UNIQUE_PIECE_COUNT * MAX_COPIES];
+        let mut next = 0;
+        self.cells.iter().zip(self.owners.iter()).map(|(&c, &o)| {
+            let label = &mut labels[o as usize];
+            if *label == u8::MAX {
+                *label = next;
+                next += 1;
+            }
+            c << 8 | *label as u32
+        }).collect()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Number of separately-locked parts of a PlacementCache or SupportTable,
+// as for SharedSeen
+const SHARDS: usize = 64;
+
+// Picks the shard for a footprint key, with FNV-1a, which is plenty to
+// spread keys across shards
+fn shard_index(key: &[u32]) -> usize {
+    let h = key.iter().fold(0xcbf29ce484222325u64, |h, &c|
+        (h ^ c as u64).wrapping_mul(0x100000001b3));
+    (h >> 58) as usize % SHARDS
+}
+
+// Placements found so far, by footprint key (see Footprint::key) and then
+// by piece (with its rotation)
+#[derive(Default)]
+struct Shard {
+    found: HashMap<Vec<u32>, HashMap<usize, Arc<[Placed]>>>,
+    bytes: usize,
+}
+
+// The legal placements of each piece onto each footprint that the search
+// has reached, shared by every worker in a sweep.  Different bags reach the
+// same arrangements over and over, so finding their placements becomes a
+// lookup.  Each shard is cleared when it passes its share of the memory
+// budget.  The placements depend on the neighbor rules, so a cache should
+// only be used under one set of rules.
+pub struct PlacementCache {
+    shards: Vec<Mutex<Shard>>,
+    limit: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl PlacementCache {
+    // Builds a cache which uses roughly the given number of bytes
+    pub fn new(bytes: usize) -> PlacementCache {
+        PlacementCache {
+            shards: (0..SHARDS).map(|_| Mutex::new(Shard::default())).collect(),
+            limit: bytes / SHARDS,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    fn shard(&self, key: &[u32]) -> &Mutex<Shard> {
+        &self.shards[shard_index(key)]
+    }
+
+    pub fn get(&self, key: &[u32], piece: usize) -> Option<Arc<[Placed]>> {
+        let found = self.shard(key).lock().unwrap().found.get(key)
+            .and_then(|f| f.get(&piece).cloned());
+        let counter = if found.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        return found;
+    }
+
+    pub fn insert(&self, key: &[u32], piece: usize, placed: Vec<Placed>) {
+        let mut shard = self.shard(key).lock().unwrap();
+        let entry = size_of_val(key) + 64;
+        let mut bytes = size_of_val(&placed[..]) + 64;
+        if !shard.found.contains_key(key) {
+            bytes += entry;
+        }
+        if shard.bytes + bytes > self.limit {
+            shard.found.clear();
+            shard.bytes = entry;
+        }
+        shard.bytes += bytes;
+        shard.found.entry(key.to_vec()).or_default()
+            .insert(piece, placed.into());
+    }
+
+    // Returns how many lookups found their placements, and how many didn't
+    pub fn stats(&self) -> (u64, u64) {
+        (self.hits.load(Ordering::Relaxed), self.misses.load(Ordering::Relaxed))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Masks of the tiles which can rest on each top layer found so far, by
+// the layer's footprint key
+#[derive(Default)]
+struct SupportShard {
+    found: HashMap<Vec<u32>, u16>,
+    bytes: usize,
+}
+
+// Which tiles can rest on each top layer that the search has reached,
+// shared by every worker in a sweep.  A tile lands above the top layer
+// only if it lies entirely on that layer's cells and spans at least two of
+// its pieces, so this only depends on the top layer's footprint (with its
+// owners), whatever is beneath it.  When a bag is down to its last tile,
+// a tile which can't rest on the top layer can be no higher than that
+// layer, which usually rules out the high-value tiles that the search
+// saves for last.  As with PlacementCache, the table is cleared when it
+// passes its memory budget, and should only be used under one set of
+// rules.
+pub struct SupportTable {
+    shards: Vec<Mutex<SupportShard>>,
+    limit: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl SupportTable {
+    pub fn new(bytes: usize) -> SupportTable {
+        SupportTable {
+            shards: (0..SHARDS).map(|_| Mutex::new(SupportShard::default()))
+                .collect(),
+            limit: bytes / SHARDS,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    // Returns a mask with bit t set if tile t can be placed on the state's
+    // top layer, in some orientation
+    pub fn supported(&self, state: &State) -> u16 {
+        let top = state.top_layer();
+        let key = Footprint::new(&top).key();
+
+        let shard = &self.shards[shard_index(&key)];
+        if let Some(&mask) = shard.lock().unwrap().found.get(&key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return mask;
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        let mut mask = 0;
+        for t in 0..UNIQUE_PIECE_COUNT {
+            if pieces().orientations(t, top.mirrored())
+                .any(|b| top.placements(b).any(|p| p.0.z == 1))
+            {
+                mask |= 1 << t;
+            }
+        }
+
+        let mut shard = shard.lock().unwrap();
+        let bytes = size_of_val(&key[..]) + 64;
+        if shard.bytes + bytes > self.limit {
+            shard.found.clear();
+            shard.bytes = 0;
+        }
+        shard.bytes += bytes;
+        shard.found.insert(key, mask);
+        return mask;
+    }
+
+    // Returns how many lookups found their top layer, and how many didn't
+    pub fn stats(&self) -> (u64, u64) {
+        (self.hits.load(Ordering::Relaxed), self.misses.load(Ordering::Relaxed))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use piece::Adjacency;
+
+    #[test]
+    fn key() {
+        // The same cells, whichever side the second piece was placed on
+        let a = State::new().try_place(0, 0, 0).unwrap()
+            .try_place(0, 3, 0).unwrap();
+        let b = State::new().try_place(0, 0, 0).unwrap()
+            .try_place(0, -3, 0).unwrap();
+        assert_eq!(Footprint::new(&a).key(), Footprint::new(&b).key());
+
+        let f = Footprint::new(&a);
+        assert_eq!(f.cells.len(), 2 * Footprint::new(
+            &State::new().try_place(0, 0, 0).unwrap()).cells.len());
+        assert!(f.key().iter().any(|&k| k & 0xff == 1));
+        assert!(f.key().iter().all(|&k| k & 0xff < 2));
+    }
+
+    #[test]
+    fn cache() {
+        let state = State::new().try_place(0, 0, 0).unwrap();
+        let key = Footprint::new(&state).key();
+        let found: Vec<Placed> = state.placements(4).map(|p| p.0).collect();
+
+        let cache = PlacementCache::new(1 << 20);
+        assert_eq!(cache.get(&key, 4), None);
+        cache.insert(&key, 4, found.clone());
+        assert_eq!(cache.get(&key, 4).as_deref(), Some(&found[..]));
+        assert_eq!(cache.get(&key, 5), None);
+        assert_eq!(cache.stats(), (1, 2));
+
+        // Past its budget, the cache forgets what it found
+        let tiny = PlacementCache::new(0);
+        tiny.insert(&key, 4, found.clone());
+        tiny.insert(&key, 5, found);
+        assert_eq!(tiny.get(&key, 4), None);
+        assert!(tiny.get(&key, 5).is_some());
+    }
+
+    #[test]
+    fn support() {
+        // Nothing can rest on a single tile, but a 1 can rest on two 0s
+        let table = SupportTable::new(1 << 20);
+        let one = State::new().try_place(0, 0, 0).unwrap();
+        assert_eq!(table.supported(&one), 0);
+        let two = one.try_place(0, 3, 0).unwrap();
+        let mask = table.supported(&two);
+        assert!(mask & 1 << 1 != 0);
+        assert!(mask & 1 << 8 == 0);
+
+        // Only the top layer matters, so the 1 on top of them can't hold
+        // anything, and the same layer elsewhere is found in the table
+        assert_eq!(table.supported(&two.try_place(4, 2, 0).unwrap()), 0);
+        assert_eq!(table.stats(), (0, 3));
+        table.supported(&State::new().try_place(0, 0, 0).unwrap()
+                        .try_place(0, -3, 0).unwrap());
+        assert_eq!(table.stats(), (1, 3));
+
+        // A 5 can rest on two 2s, which keep their places relative to each
+        // other when the top layer is taken on its own
+        let twos = State::from_layout("8,3,0,0;10,0,0,0", Adjacency::Orthogonal)
+            .unwrap();
+        assert!(table.supported(&twos) & 1 << 5 != 0);
+    }
+}
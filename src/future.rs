@@ -0,0 +1,179 @@
+// An async-friendly way to solve one bag, for frontends which run several
+// bounded solves at once and need to cancel them cleanly (such as an HTTP
+// server or a daemon).  Each solve runs a Worker on a thread of its own,
+// and the returned future is ready once the worker finishes.  The future
+// only uses std's Waker, so it can be awaited on any executor, and there's
+// no async runtime to depend on.
+//
+// Solves share one results table, which bounds their searches and which
+// their scores are written to (exact scores, or lower bounds if they're
+// cut short), so later solves go faster.  Subsets which haven't been
+// solved fall back to looser bounds, so a table loaded from a results
+// file makes a big difference.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bag::Bag;
+use results::Results;
+use state::State;
+use worker::{CancelToken, Worker};
+
+// Limits on one solve, besides its cancellation token
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SolveOptions {
+    pub timeout: Option<Duration>,
+    pub node_limit: Option<usize>,
+}
+
+#[derive(Clone, Debug)]
+pub enum Solution {
+    // The bag's best score, and an arrangement which reaches it
+    Exact(isize, State),
+
+    // The best score found before the timeout or node limit, which is only
+    // a lower bound on the bag's best score
+    Partial(isize, State),
+
+    // The token was cancelled before the search finished
+    Cancelled,
+}
+
+struct Shared {
+    solution: Option<Solution>,
+    waker: Option<Waker>,
+}
+
+// The future returned by solve
+pub struct SolveFuture(Arc<Mutex<Shared>>);
+
+impl Future for SolveFuture {
+    type Output = Solution;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Solution> {
+        let mut shared = self.0.lock().unwrap();
+        match shared.solution.take() {
+            Some(s) => Poll::Ready(s),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            },
+        }
+    }
+}
+
+// Starts solving the bag on a new thread, returning a future for its
+// solution.  Cancelling the token stops the search at its next node;
+// dropping the future doesn't, so keep the token to stop a solve which is
+// no longer wanted.
+pub fn solve(results: Arc<RwLock<Results>>, bag: Bag, options: SolveOptions,
+             cancel: CancelToken) -> SolveFuture {
+    let shared = Arc::new(Mutex::new(Shared { solution: None, waker: None }));
+    let out = SolveFuture(shared.clone());
+    thread::spawn(move || {
+        let mut worker = Worker::new(bag, &results).with_cancel(cancel);
+        if let Some(timeout) = options.timeout {
+            worker = worker.with_deadline(Instant::now() + timeout);
+        }
+        if let Some(nodes) = options.node_limit {
+            worker = worker.with_node_limit(nodes);
+        }
+        let solution = match worker.run() {
+            Some((score, state)) if worker.timed_out() || worker.limited() =>
+                Solution::Partial(score, state),
+            Some((score, state)) => Solution::Exact(score, state),
+            None => Solution::Cancelled,
+        };
+
+        let mut shared = shared.lock().unwrap();
+        shared.solution = Some(solution);
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    });
+    return out;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::task::Wake;
+    use piece::MAX_ROTATIONS;
+    use rules::Rules;
+    use solver::Solver;
+
+    // Wakes a thread which is blocked on a future
+    struct Unpark(thread::Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    // A minimal executor, which polls the future until it's ready
+    fn block_on<F: Future>(f: F) -> F::Output {
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut f = std::pin::pin!(f);
+        loop {
+            if let Poll::Ready(out) = f.as_mut().poll(&mut cx) {
+                return out;
+            }
+            thread::park();
+        }
+    }
+
+    fn bag(tiles: &[usize]) -> Bag {
+        tiles.iter().fold(Bag::from_usize(0), |b, &t| b.add(t * MAX_ROTATIONS))
+    }
+
+    #[test]
+    fn solve() {
+        let bag = bag(&[0, 1, 4, 8, 9]);
+        let solver = Solver::new(Rules::default());
+        solver.sweep_bags(std::slice::from_ref(&bag));
+        let expected = solver.results().read().unwrap().score(&bag).unwrap();
+
+        // Two solves at once, each with its own token
+        let results = Arc::new(RwLock::new(Results::new(Rules::default())));
+        let a = super::solve(results.clone(), bag.clone(),
+                             SolveOptions::default(), CancelToken::new());
+        let b = super::solve(results.clone(), bag.take(0),
+                             SolveOptions::default(), CancelToken::new());
+        match block_on(a) {
+            Solution::Exact(score, state) => {
+                assert_eq!(score, expected);
+                assert_eq!(Rules::default().score(&state), score);
+            },
+            s => panic!("Expected an exact solution, got {:?}", s),
+        }
+        assert!(matches!(block_on(b), Solution::Exact(..)));
+        assert_eq!(results.read().unwrap().score(&bag), Some(expected));
+    }
+
+    #[test]
+    fn cancel() {
+        let results = Arc::new(RwLock::new(Results::new(Rules::default())));
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let bag = bag(&[0, 1, 4, 8, 9]);
+        let f = super::solve(results.clone(), bag.clone(),
+                             SolveOptions::default(), cancel);
+        assert!(matches!(block_on(f), Solution::Cancelled));
+        assert_eq!(results.read().unwrap().score(&bag), None);
+
+        // A node limit gives a lower bound, rather than the bag's score
+        let options = SolveOptions { node_limit: Some(1), ..Default::default() };
+        let f = super::solve(results.clone(), bag.clone(), options,
+                             CancelToken::new());
+        assert!(matches!(block_on(f), Solution::Partial(..)));
+        assert_eq!(results.read().unwrap().score(&bag), None);
+    }
+}
@@ -0,0 +1,372 @@
+// A game as a list of moves, in the order that they were played, which can
+// be replayed to check that each one was legal
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use serde_json::{self, Value};
+
+use piece::{MAX_ORIENTATIONS, MAX_ROTATIONS, UNIQUE_PIECE_COUNT};
+use error::Error;
+use rules::Rules;
+use state::{parse_notation, State};
+
+// One tile placed on the table.  Positions are in a fixed frame for the
+// whole game (unlike a State, which shifts so that its corner is at 0, 0).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Move {
+    pub tile: usize,
+    // Quarter turns
+    pub rotation: usize,
+    pub x: i32,
+    pub y: i32,
+    // If present, the layer that the tile is claimed to land on
+    pub z: Option<usize>,
+}
+
+// Moves use the same notation as Placed, e.g. 7@3,2 r1 z0, but the layer
+// may be left out
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}@{},{} r{}", self.tile, self.x, self.y, self.rotation)?;
+        match self.z {
+            Some(z) => write!(f, " z{}", z),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FromStr for Move {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Move, String> {
+        let (tile, x, y, rotation, z) = parse_notation(s)?;
+        return Ok(Move { tile: tile, rotation: rotation, x: x, y: y, z: z });
+    }
+}
+
+// Writes a game in notation, one move per line
+pub fn to_text(moves: &[Move]) -> String {
+    moves.iter().map(|m| format!("{}\n", m)).collect()
+}
+
+// Parses a game written as one move per line, either in notation (see
+// to_text) or as the tile, its x and y position, its rotation, and
+// optionally its layer, separated by spaces.  Moves in notation may also
+// be separated by semicolons.  Blank lines and lines starting with '#'
+// are ignored.
+pub fn parse_text(s: &str) -> Result<Vec<Move>, String> {
+    let mut out = Vec::new();
+    for (n, line) in s.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+        if line.contains('@') {
+            for m in line.split(';').filter(|m| !m.trim().is_empty()) {
+                out.push(m.parse().map_err(|e| format!("{} on line {}",
+                                                       e, n + 1))?);
+            }
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let m = if fields.len() == 4 || fields.len() == 5 {
+            (|| Some(Move {
+                tile: fields[0].parse().ok()?,
+                x: fields[1].parse().ok()?,
+                y: fields[2].parse().ok()?,
+                rotation: fields[3].parse().ok()?,
+                z: match fields.get(4) {
+                    Some(z) => Some(z.parse().ok()?),
+                    None => None,
+                },
+            }))()
+        } else {
+            None
+        };
+        out.push(m.ok_or_else(|| format!("Invalid move on line {}", n + 1))?);
+    }
+    return Ok(out);
+}
+
+// Reads one entry of a "placements" list
+fn json_move(p: &Value, i: usize) -> Result<Move, String> {
+    let invalid = |key| format!("Invalid \"{}\" in placement {}", key, i + 1);
+    let field = |key| p.get(key).and_then(Value::as_i64)
+        .and_then(|v| i32::try_from(v).ok())
+        .ok_or_else(|| invalid(key));
+    let index = |key| p.get(key).and_then(Value::as_u64)
+        .and_then(|v| usize::try_from(v).ok())
+        .ok_or_else(|| invalid(key));
+    return Ok(Move {
+        tile: index("tile")?,
+        rotation: index("rotation")?,
+        x: field("x")?,
+        y: field("y")?,
+        z: match p.get("z") {
+            None | Some(&Value::Null) => None,
+            Some(_) => Some(index("z")?),
+        },
+    });
+}
+
+// Parses the placements in a JSON document written by the json module:
+// either one bag (as from json::bag), or an export whose "bags" list holds
+// exactly one bag, since there'd be no telling which of several to read.
+// Other fields are ignored.
+pub fn parse_json(s: &str) -> Result<Vec<Move>, String> {
+    let doc: Value = serde_json::from_str(s)
+        .map_err(|e| format!("Invalid JSON: {}", e))?;
+    let bag = match doc.get("bags") {
+        Some(bags) => match bags.as_array().map(|b| &b[..]) {
+            Some([bag]) => bag,
+            Some(bags) => return Err(format!(
+                "{} bags in JSON, rather than one", bags.len())),
+            None => return Err("Invalid \"bags\"".to_string()),
+        },
+        None => &doc,
+    };
+    let list = match bag.get("placements") {
+        None => return Err("No \"placements\" in JSON".to_string()),
+        Some(&Value::Null) => return Err("Arrangement isn't known".to_string()),
+        Some(list) => list.as_array().ok_or("Invalid \"placements\"")?,
+    };
+    return list.iter().enumerate().map(|(i, p)| json_move(p, i)).collect();
+}
+
+// Places each move in turn, returning the final arrangement, or the index
+// of the first illegal move and why it's illegal.
+//
+// If in_order is false, the moves are an arrangement rather than a game
+// (such as the placements in a JSON file, which go from the top layer
+// down), so they're placed in whatever order works, starting from the
+// ground.  The index is then of the first move which couldn't be placed.
+pub fn replay(moves: &[Move], rules: &Rules, in_order: bool)
+    -> Result<State, (usize, String)>
+{
+    let mut state = State::with_rules(rules);
+
+    // The game's position of the state's corner
+    let mut offset = (0, 0);
+
+    let mut todo: Vec<(usize, Move)> = moves.iter().cloned().enumerate()
+        .collect();
+    if !in_order {
+        todo.sort_by_key(|&(_, m)| m.z);
+    }
+    while !todo.is_empty() {
+        let count = if in_order { 1 } else { todo.len() };
+        let mut found = None;
+        let mut error = None;
+        for (j, &(i, m)) in todo.iter().enumerate().take(count) {
+            match place(&state, offset, &m, rules) {
+                Ok(next) => {
+                    found = Some((j, next));
+                    break;
+                },
+                Err(e) => if error.is_none() { error = Some((i, e)) },
+            }
+        }
+        match found {
+            Some((j, next)) => {
+                let m = todo.remove(j).1;
+                offset = shift(&state, offset, &m);
+                state = next;
+            },
+            None => return Err(error.unwrap()),
+        }
+    }
+    return Ok(state);
+}
+
+// Places each move in order, returning the arrangement after each one,
+// along with the index of the first illegal move and why it's illegal
+// (if there is one)
+pub fn steps(moves: &[Move], rules: &Rules)
+    -> (Vec<State>, Option<(usize, String)>)
+{
+    let mut out: Vec<State> = Vec::new();
+    let mut offset = (0, 0);
+    for (i, m) in moves.iter().enumerate() {
+        let state = out.last().cloned()
+            .unwrap_or_else(|| State::with_rules(rules));
+        match place(&state, offset, m, rules) {
+            Ok(next) => {
+                offset = shift(&state, offset, m);
+                out.push(next);
+            },
+            Err(e) => return (out, Some((i, e))),
+        }
+    }
+    return (out, None);
+}
+
+// Returns the game's position of the state's corner, after placing a move
+fn shift(state: &State, offset: (i32, i32), m: &Move) -> (i32, i32) {
+    if state.is_empty() {
+        (m.x, m.y)
+    } else {
+        (offset.0.min(m.x), offset.1.min(m.y))
+    }
+}
+
+// Places a single move, given the game's position of the state's corner
+fn place(state: &State, offset: (i32, i32), m: &Move, rules: &Rules)
+    -> Result<State, String>
+{
+    if m.tile >= UNIQUE_PIECE_COUNT {
+        return Err(format!("there's no tile {}", m.tile));
+    } else if m.rotation >= if rules.mirrored { MAX_ORIENTATIONS }
+                             else { MAX_ROTATIONS } {
+        return Err(format!("rotation {} isn't a quarter turn{}", m.rotation,
+                           if rules.mirrored { " (mirrored or not)" }
+                           else { "" }));
+    }
+    let copies = state.pieces.iter().filter(|p| p.index() == m.tile).count();
+    if copies >= rules.copies {
+        return Err(format!("there are only {} copies of tile {}",
+                           rules.copies, m.tile));
+    }
+
+    let (x, y) = (m.x.saturating_sub(offset.0), m.y.saturating_sub(offset.1));
+    let next = match state.place(m.tile, m.rotation, x, y) {
+        Ok(next) => next,
+        // The state enforces the rules' layer cap, so check whether the
+        // tile was only rejected for going above it
+        Err(Error::Illegal) => return Err(
+            match state.clone().with_max_layers(None)
+                       .place(m.tile, m.rotation, x, y) {
+                Ok(s) => format!("layer {} is above the limit", s.layers()),
+                Err(e) => e.to_string(),
+            }),
+        Err(e) => return Err(e.to_string()),
+    };
+
+    // The tile's layer is however much higher the pieces are, in total
+    let height = |s: &State| s.pieces.iter().map(|p| p.z).sum::<usize>();
+    let z = height(&next) - height(state);
+    if let Some(claimed) = m.z {
+        if claimed != z {
+            return Err(format!("the tile lands on layer {}, not {}",
+                               z, claimed));
+        }
+    }
+    return Ok(next);
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use json;
+    use bag::Bag;
+
+    #[test]
+    fn text() {
+        let moves = parse_text("# Two 0s, bridged by a 1\n\
+                                0 0 0 0\n\n0 3 0 0 0\n1 2 0 0 1\n").unwrap();
+        assert_eq!(moves.len(), 3);
+        assert_eq!(moves[2], Move { tile: 1, rotation: 0, x: 2, y: 0,
+                                    z: Some(1) });
+        assert_eq!(replay(&moves, &Rules::default(), true).unwrap().score(), 1);
+
+        assert!(parse_text("0 0 0").is_err());
+        assert!(parse_text("0 0 0 x").is_err());
+    }
+
+    #[test]
+    fn notation() {
+        let game = "# Two 0s, bridged by a 1\n0@0,0 r0 z0\n0@3,0; 1@2,0 z1\n";
+        let moves = parse_text(game).unwrap();
+        assert_eq!(moves.len(), 3);
+        assert_eq!(moves[1], Move { tile: 0, rotation: 0, x: 3, y: 0, z: None });
+        assert_eq!(to_text(&moves), "0@0,0 r0 z0\n0@3,0 r0\n1@2,0 r0 z1\n");
+        assert_eq!(parse_text(&to_text(&moves)), Ok(moves.clone()));
+
+        let (states, err) = steps(&moves, &Rules::default());
+        assert!(err.is_none());
+        let scores: Vec<isize> = states.iter().map(|s| s.score()).collect();
+        assert_eq!(scores, vec![0, 0, 1]);
+
+        // Steps stop at the first illegal move
+        let (states, err) = steps(&parse_text("0@0,0\n1@9,9").unwrap(),
+                                  &Rules::default());
+        assert_eq!(states.len(), 1);
+        assert_eq!(err.unwrap().0, 1);
+
+        assert!(parse_text("0@0,0 r0 z0\n0@3").unwrap_err().contains("line 2"));
+    }
+
+    #[test]
+    fn illegal() {
+        let rules = Rules::default();
+        let mv = |tile, x, y, z| Move { tile: tile, rotation: 0, x: x, y: y,
+                                        z: z };
+
+        // Negative positions are fine, since they're in the game's frame
+        let game = vec![mv(0, 0, 0, None), mv(0, -3, 0, None),
+                        mv(1, -1, 0, Some(1))];
+        assert_eq!(replay(&game, &rules, true).unwrap().score(), 1);
+
+        // Not touching
+        let err = replay(&[mv(0, 0, 0, None), mv(0, 9, 0, None)], &rules, true);
+        assert_eq!(err.unwrap_err().0, 1);
+
+        // Wrong layer
+        let err = replay(&[mv(0, 0, 0, None), mv(0, 3, 0, Some(1))],
+                         &rules, true);
+        assert_eq!(err.unwrap_err().0, 1);
+
+        // Too many copies
+        let err = replay(&[mv(9, 0, 0, None), mv(9, 3, 0, None),
+                           mv(9, 6, 0, None)], &rules, true);
+        assert_eq!(err.unwrap_err().0, 2);
+
+        // Out of order, which is only legal as an arrangement
+        let game = vec![mv(1, 2, 0, Some(1)), mv(0, 0, 0, None),
+                        mv(0, 3, 0, None)];
+        assert_eq!(replay(&game, &rules, true).unwrap_err().0, 0);
+        assert_eq!(replay(&game, &rules, false).unwrap().score(), 1);
+
+        // Above the layer cap
+        let flat = Rules { max_layers: Some(1), ..rules };
+        let game = vec![mv(0, 0, 0, None), mv(0, -3, 0, None),
+                        mv(1, -1, 0, Some(1))];
+        assert_eq!(replay(&game, &flat, true).unwrap_err(),
+                   (2, "layer 1 is above the limit".to_string()));
+    }
+
+    #[test]
+    fn json() {
+        // A JSON export round-trips through replay as an arrangement
+        let state = State::new().try_place(0, 0, 0).unwrap()
+            .try_place(0, 3, 0).unwrap()
+            .try_place(4, 2, 0).unwrap();
+        let bag = Bag::from_usize(0).add(0).add(0).add(4);
+        let text = json::bag(&bag, 1, Some(&state));
+        let moves = parse_json(&text).unwrap();
+        assert_eq!(moves.len(), 3);
+        assert_eq!(moves[0].z, Some(1));
+        let replayed = replay(&moves, &Rules::default(), false).unwrap();
+        assert_eq!(replayed, state);
+
+        // A one-bag export reads the same, but with several bags, there's
+        // no telling which one to read
+        let export = format!("{{\"version\": 1, \"bags\": [{}]}}", text);
+        assert_eq!(parse_json(&export).unwrap(), moves);
+        let two = format!("{{\"version\": 1, \"bags\": [{}, {}]}}", text, text);
+        assert!(parse_json(&two).is_err());
+
+        assert!(parse_json("{\"bags\": []}").is_err());
+        assert!(parse_json("{\"placements\": null}").is_err());
+        assert!(parse_json("{\"placements\": [{\"tile\": 1}]}").is_err());
+        assert!(parse_json("{\"placements\": [{\"tile\": -1, \"rotation\": 0, \
+                            \"x\": 0, \"y\": 0}]}").is_err());
+
+        // Anything after the document is an error, not ignored
+        assert!(parse_json(&format!("{} {}", text, text)).is_err());
+        assert!(parse_json(&text[..text.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,229 @@
+// Suggests where to place a tile during a real game, where the rest of
+// the deck is still face down
+
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+use bag::Bag;
+use expectimax::placements;
+use piece::{MAX_ROTATIONS, UNIQUE_PIECE_COUNT};
+use results::Results;
+use rules::Rules;
+use state::{Placed, State};
+
+// Returns the best placement for the tile (0-9) which was just drawn,
+// thinking for about the given budget, under the default rules
+pub fn hint(state: &State, piece: usize, budget: Duration) -> Placed {
+    Hint::new(Rules::default()).run(state, piece, budget)
+}
+
+// Called with the search's progress
+pub type InfoFn<'a> = Box<dyn Fn(&Info) + 'a>;
+
+pub struct Hint<'a> {
+    rules: Rules,
+
+    // If present, solved bags tighten the bound on what the rest of the
+    // deck can add to an arrangement
+    results: Option<&'a Results>,
+
+    // If present, the tiles still in the deck (not counting the one which
+    // was drawn).  Otherwise, it's every tile not yet on the table.
+    remaining: Option<Bag>,
+
+    // If present, called as each pass of the lookahead finishes
+    info: Option<InfoFn<'a>>,
+
+    // Number of arrangements evaluated so far
+    nodes: Cell<u64>,
+}
+
+// Progress of a hint's search, after a pass of the lookahead
+#[derive(Copy, Clone, Debug)]
+pub struct Info {
+    // Number of draws looked ahead
+    pub depth: usize,
+    // Expected value of the best placement so far
+    pub value: f64,
+    pub best: Placed,
+    // Number of arrangements evaluated, including earlier passes
+    pub nodes: u64,
+    pub elapsed: Duration,
+}
+
+impl<'a> Hint<'a> {
+    pub fn new(rules: Rules) -> Hint<'a> {
+        Hint { rules: rules, results: None, remaining: None, info: None,
+               nodes: Cell::new(0) }
+    }
+
+    pub fn with_remaining(mut self, remaining: Bag) -> Hint<'a> {
+        self.remaining = Some(remaining);
+        self
+    }
+
+    pub fn with_info(mut self, info: InfoFn<'a>) -> Hint<'a> {
+        self.info = Some(info);
+        self
+    }
+
+    pub fn with_results(mut self, results: &'a Results) -> Hint<'a> {
+        assert_eq!(results.rules(), self.rules);
+        self.results = Some(results);
+        self
+    }
+
+    // Returns the best placement for the tile (0-9) which was just drawn,
+    // in the state's coordinates.
+    //
+    // Each placement is scored by its expected value a few draws ahead,
+    // assuming that the tiles not yet on the table are still in the deck.
+    // Once the lookahead stops, an arrangement is valued at its score
+    // plus the Results bound on what the rest of the deck could add.  The
+    // lookahead deepens one draw at a time until the budget runs out; the
+    // first pass (with no lookahead) always runs to completion.
+    pub fn run(&self, state: &State, piece: usize, budget: Duration)
+        -> Placed
+    {
+        let start = Instant::now();
+        let deadline = start + budget;
+        let fallback;
+        let results = match self.results {
+            Some(r) => r,
+            None => {
+                fallback = Results::new(self.rules);
+                &fallback
+            },
+        };
+
+        // Everything which hasn't been drawn yet
+        let rest = self.remaining.clone()
+            .unwrap_or_else(|| unseen(state, piece, &self.rules));
+
+        let moves = placements(state, piece, &self.rules);
+        let mut best = None;
+        for depth in 0..=rest.len() {
+            let mut found: Option<(f64, Placed)> = None;
+            for &(p, ref s) in &moves {
+                let v = match self.value(results, s, &rest, depth, deadline) {
+                    Some(v) => v,
+                    None => break,
+                };
+                if found.map(|f| v > f.0).unwrap_or(true) {
+                    found = Some((v, p));
+                }
+            }
+            // Only a finished pass is used, except for the first
+            match found {
+                Some(f) if best.is_none() || Instant::now() < deadline => {
+                    best = Some(f.1);
+                    if let Some(ref info) = self.info {
+                        info(&Info { depth: depth, value: f.0, best: f.1,
+                                     nodes: self.nodes.get(),
+                                     elapsed: start.elapsed() });
+                    }
+                },
+                _ => break,
+            }
+        }
+        return best.expect("Tiles can always be placed on the table");
+    }
+
+    // Returns the expected value of a state, looking the given number of
+    // draws ahead, or None if the deadline passed
+    fn value(&self, results: &Results, state: &State, rest: &Bag,
+             depth: usize, deadline: Instant) -> Option<f64>
+    {
+        self.nodes.set(self.nodes.get() + 1);
+        if depth == 0 || rest.is_empty() {
+            let bound = results.upper_score_bound(rest, state);
+            return Some((self.rules.score(state) + bound) as f64);
+        }
+        if Instant::now() >= deadline {
+            return None;
+        }
+
+        let mut total = 0.0;
+        for t in (0..UNIQUE_PIECE_COUNT).filter(|&t| rest.digit(t) > 0) {
+            let next = rest.take(t * MAX_ROTATIONS);
+            let mut best: Option<f64> = None;
+            for (_, s) in placements(state, t, &self.rules) {
+                let v = self.value(results, &s, &next, depth - 1, deadline)?;
+                best = Some(best.map(|b| b.max(v)).unwrap_or(v));
+            }
+            let chance = rest.digit(t) as f64 / rest.len() as f64;
+            total += chance * best.unwrap_or(0.0);
+        }
+        return Some(total);
+    }
+}
+
+// Returns every tile which is neither on the table nor the one which was
+// just drawn, which is what's left in the deck if nothing was discarded
+pub(crate) fn unseen(state: &State, piece: usize, rules: &Rules) -> Bag {
+    let mut rest = Bag::from_usize(0);
+    for t in 0..UNIQUE_PIECE_COUNT {
+        let used = state.pieces.iter()
+            .filter(|p| p.index() == t).count()
+            + if t == piece { 1 } else { 0 };
+        for _ in used..rules.copies {
+            rest = rest.add(t * MAX_ROTATIONS);
+        }
+    }
+    return rest;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stack() {
+        // Two 0s side by side, which a 1 can bridge
+        let state = State::new().try_place(0, 0, 0).unwrap()
+            .try_place(0, 3, 0).unwrap();
+        let p = hint(&state, 1, Duration::from_millis(0));
+        assert_eq!(p.index(), 1);
+        assert_eq!(p.z, 1);
+        let placed = state.try_place(p.id(),
+                                     p.x, p.y).unwrap();
+        assert_eq!(placed.score(), 1);
+
+        // The first tile goes at the origin
+        let p = hint(&State::new(), 9, Duration::from_millis(0));
+        assert_eq!((p.index(), p.x, p.y, p.z), (9, 0, 0, 0));
+    }
+
+    #[test]
+    fn info() {
+        use std::cell::RefCell;
+
+        // Only a 1 is left, so one pass of lookahead sees the whole game
+        let state = State::new().try_place(0, 0, 0).unwrap();
+        let passes = RefCell::new(Vec::new());
+        let p = Hint::new(Rules::default())
+            .with_remaining(Bag::from_usize(0).add(4))
+            .with_info(Box::new(|i: &Info| passes.borrow_mut().push(*i)))
+            .run(&state, 0, Duration::from_secs(60));
+        let passes = passes.into_inner();
+        assert_eq!(passes.len(), 2);
+        assert_eq!(passes[1].depth, 1);
+        assert_eq!(passes[1].value, 1.0);
+        assert_eq!(passes[1].best, p);
+        assert!(passes[1].nodes > passes[0].nodes);
+    }
+
+    #[test]
+    fn budget() {
+        // A longer budget still finishes promptly, with a legal placement
+        let state = State::new().try_place(0, 0, 0).unwrap();
+        let start = Instant::now();
+        let p = Hint::new(Rules::default())
+            .run(&state, 2, Duration::from_millis(100));
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(state.try_place(p.id(),
+                                p.x, p.y).is_some());
+    }
+}
@@ -0,0 +1,172 @@
+// Results as a single HTML page, for exploring solved arrangements in a
+// browser without a server.  The page embeds the bags in the same format
+// as the json module, along with each tile's cells, and draws the chosen
+// bag's arrangement with inline JS:  each layer can be hidden to see the
+// ones under it, and hovering over a tile shows where it was placed.
+
+use std::io::{self, Write};
+
+use json;
+use piece::{MAX_ORIENTATIONS, UNIQUE_PIECE_COUNT, piece_id, pieces};
+use results::Results;
+use theme::theme;
+
+const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>nmbr9</title>
+<style>
+body { background: #181820; color: #c8c8d8; font-family: monospace; margin: 20px; }
+label { margin-right: 12px; }
+#info { height: 1.5em; margin: 8px 0; }
+rect { stroke: #181820; stroke-width: 1; }
+g.piece:hover rect { stroke: #ffffff; }
+</style>
+</head>
+<body>
+<div>
+<label>Bag <select id="bag"></select></label>
+<span id="score"></span>
+</div>
+<div id="layers"></div>
+<div id="info"></div>
+<svg id="view"></svg>
+<script>
+const DATA = @DATA@;
+const CELL = 24;
+const svg = document.getElementById("view");
+const select = document.getElementById("bag");
+
+function cells(p) {
+  return DATA.shapes[p.tile][p.rotation].map(c => [p.x + c[0], p.y + c[1]]);
+}
+
+function draw() {
+  const b = DATA.bags[select.value];
+  document.getElementById("score").textContent =
+    "score " + b.score + ", " + b.placements.length + " tiles";
+  const all = b.placements.flatMap(cells);
+  const w = Math.max(0, ...all.map(c => c[0] + 1));
+  const h = Math.max(0, ...all.map(c => c[1] + 1));
+  svg.setAttribute("width", w * CELL);
+  svg.setAttribute("height", h * CELL);
+
+  const boxes = document.getElementById("layers");
+  boxes.innerHTML = "";
+  for (let z = 0; z < b.layers; z++) {
+    const label = document.createElement("label");
+    label.innerHTML = '<input type="checkbox" checked data-z="' + z +
+                      '"> layer ' + z;
+    label.firstChild.onchange = redraw;
+    boxes.appendChild(label);
+  }
+
+  function redraw() {
+    const shown = new Set([...boxes.querySelectorAll("input:checked")]
+      .map(i => +i.dataset.z));
+    svg.innerHTML = "";
+    // Bottom layer first, so that upper layers are drawn over it.  x is
+    // mirrored to match the terminal's drawing.
+    const order = b.placements.slice().sort((a, c) => a.z - c.z);
+    for (const p of order.filter(p => shown.has(p.z))) {
+      const g = document.createElementNS(svg.namespaceURI, "g");
+      g.setAttribute("class", "piece");
+      const text = "tile " + p.tile + ", rotation " + p.rotation + ", at " +
+                   p.x + "," + p.y + " on layer " + p.z;
+      g.onmouseover = () => document.getElementById("info").textContent = text;
+      for (const c of cells(p)) {
+        const r = document.createElementNS(svg.namespaceURI, "rect");
+        r.setAttribute("x", (w - c[0] - 1) * CELL);
+        r.setAttribute("y", c[1] * CELL);
+        r.setAttribute("width", CELL);
+        r.setAttribute("height", CELL);
+        r.setAttribute("fill", DATA.colors[p.tile]);
+        g.appendChild(r);
+      }
+      svg.appendChild(g);
+    }
+  }
+  redraw();
+}
+
+DATA.bags.forEach((b, i) => {
+  const o = document.createElement("option");
+  o.value = i;
+  o.textContent = b.bag + " (" + b.score + ")";
+  select.appendChild(o);
+});
+select.onchange = draw;
+if (DATA.bags.length) {
+  draw();
+}
+</script>
+</body>
+</html>
+"#;
+
+// Writes a page with every bag whose best arrangement is known, biggest
+// bags first
+pub fn write<W: Write>(out: &mut W, results: &Results) -> io::Result<()> {
+    let mut bags = Vec::new();
+    for b in results.bags().iter().rev() {
+        match (results.score(b), results.state(b)) {
+            (Some(score), Some(state)) if !state.is_empty() =>
+                bags.push(json::bag(b, score, Some(state))),
+            _ => (),
+        }
+    }
+
+    // Each tile's cells in each orientation (with the mirrored ones after
+    // the rotations), as [x, y] pairs
+    let mut shapes = Vec::new();
+    for t in 0..UNIQUE_PIECE_COUNT {
+        let rotations: Vec<String> = (0..MAX_ORIENTATIONS).map(|r| {
+            let pts: Vec<String> = pieces().shape(piece_id(t, r))
+                .pts.iter()
+                .map(|&(x, y)| format!("[{}, {}]", x, y))
+                .collect();
+            format!("[{}]", pts.join(", "))
+        }).collect();
+        shapes.push(format!("[{}]", rotations.join(", ")));
+    }
+    let colors: Vec<String> = (0..UNIQUE_PIECE_COUNT)
+        .map(|t| format!("\"{}\"", theme().hex(t)))
+        .collect();
+
+    let data = format!("{{\"shapes\": [{}],\n \"colors\": [{}],\n \"bags\": [\n  {}]}}",
+                       shapes.join(", "), colors.join(", "),
+                       bags.join(",\n  "));
+    out.write_all(PAGE.replace("@DATA@", &data).as_bytes())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bag::Bag;
+    use rules::Rules;
+    use state::State;
+
+    #[test]
+    fn page() {
+        let mut results = Results::new(Rules::default());
+        let bag = Bag::from_digits("2100000000", 2).unwrap();
+        let state = State::new().try_place(0, 0, 0).unwrap()
+            .try_place(0, 3, 0).unwrap()
+            .try_place(4, 2, 0).unwrap();
+        results.write_score(&bag, 1, Some(&state));
+
+        let mut out = Vec::new();
+        write(&mut out, &results).unwrap();
+        let page = String::from_utf8(out).unwrap();
+        assert!(page.starts_with("<!DOCTYPE html>"));
+        assert!(!page.contains("@DATA@"));
+        assert!(page.contains("{\"bag\": \"2100000000\", \"tiles\": [0, 0, 1], \
+                               \"score\": 1, \"layers\": 2"));
+
+        // Only bags with known arrangements are shown
+        assert_eq!(page.matches("{\"bag\": ").count(), 1);
+    }
+}
@@ -0,0 +1,342 @@
+// A small HTTP API, so that web front-ends and bots can use the solver
+// without linking it.  Every reply is a JSON object, and errors are
+// {"error": "..."} with a 4xx status.
+//
+//   POST /hint?tile=T[&movetime=MS][&remaining=DIGITS]
+//                          finds the best placement for the drawn tile, as
+//                          with the engine's go (see the engine module).
+//                          The body is the game so far, as moves in
+//                          notation or JSON (see the game module), and may
+//                          be empty.  The reply is the move in the game's
+//                          frame:  {"tile": 7, "rotation": 1, "x": 3,
+//                          "y": 2, "z": 0, "move": "7@3,2 r1 z0"}
+//   POST /verify           replays the moves in the body, replying
+//                          {"legal": true, "moves": N, "score": S}, or
+//                          {"legal": false, "move": I, "error": "..."}
+//                          for the first illegal move (counting from 1)
+//   GET /results/BAG       looks up a bag (as digits, or a list of tiles)
+//                          in the results, replying with its entry in the
+//                          json module's format, or 404 if it isn't solved
+//
+// Each connection is answered once, then closed.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use serde_json;
+
+use bag::Bag;
+use engine::to_move;
+use game::{self, Move};
+use hint::Hint;
+use json;
+use piece::UNIQUE_PIECE_COUNT;
+use results::Results;
+use rules::Rules;
+
+// The longest that a hint may think for, so that one request can't tie up
+// a thread indefinitely, and the budget if the request doesn't give one
+const MAX_BUDGET: Duration = Duration::from_secs(10);
+const DEFAULT_BUDGET: Duration = Duration::from_secs(1);
+
+// Requests with larger bodies are refused
+const MAX_BODY: usize = 1 << 20;
+
+// How long a connection may take to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Server {
+    rules: Rules,
+
+    // Solved bags, which answer /results and tighten the hints' bounds
+    results: Results,
+}
+
+// Quotes a string for JSON
+fn quote(s: &str) -> String {
+    serde_json::to_string(s).unwrap()
+}
+
+fn error(status: u16, message: &str) -> (u16, String) {
+    (status, format!("{{\"error\": {}}}", quote(message)))
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        _ => "Error",
+    }
+}
+
+// Parses a game from a request body:  JSON placements (which are an
+// arrangement, so they're placed in whatever order works), or moves in
+// notation (which are placed in order)
+fn parse_moves(body: &str) -> Result<(Vec<Move>, bool), String> {
+    if body.trim_start().starts_with('{') {
+        game::parse_json(body).map(|m| (m, false))
+    } else {
+        game::parse_text(body).map(|m| (m, true))
+    }
+}
+
+impl Server {
+    pub fn new(results: Results) -> Server {
+        Server { rules: results.rules(), results: results }
+    }
+
+    // Answers one request, returning the status and the reply's body
+    pub fn handle(&self, method: &str, target: &str, body: &str)
+        -> (u16, String)
+    {
+        let (path, query) = match target.find('?') {
+            Some(i) => (&target[..i], &target[i + 1..]),
+            None => (target, ""),
+        };
+        match (method, path) {
+            ("POST", "/hint") => self.hint(query, body),
+            ("POST", "/verify") => self.verify(body),
+            ("GET", p) if p.starts_with("/results/") =>
+                self.lookup(&p["/results/".len()..]),
+            (_, "/hint") | (_, "/verify") =>
+                error(405, &format!("{} needs POST", path)),
+            (_, p) if p.starts_with("/results/") =>
+                error(405, "/results needs GET"),
+            _ => error(404, &format!("no such path {}", path)),
+        }
+    }
+
+    fn hint(&self, query: &str, body: &str) -> (u16, String) {
+        let mut tile = None;
+        let mut budget = DEFAULT_BUDGET;
+        let mut remaining = None;
+        for pair in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = match pair.find('=') {
+                Some(i) => (&pair[..i], &pair[i + 1..]),
+                None => (pair, ""),
+            };
+            match key {
+                "tile" => match value.parse() {
+                    Ok(t) if t < UNIQUE_PIECE_COUNT => tile = Some(t),
+                    _ => return error(400, &format!("invalid tile '{}'", value)),
+                },
+                "movetime" => match value.parse() {
+                    Ok(ms) => budget = Duration::from_millis(ms).min(MAX_BUDGET),
+                    Err(_) => return error(400, &format!("invalid movetime '{}'", value)),
+                },
+                "remaining" => match Bag::from_digits(value, self.rules.copies) {
+                    Some(b) => remaining = Some(b),
+                    None => return error(400, &format!("invalid tiles '{}'", value)),
+                },
+                _ => return error(400, &format!("unknown parameter '{}'", key)),
+            }
+        }
+        let tile = match tile {
+            Some(t) => t,
+            None => return error(400, "hint needs a tile (0-9)"),
+        };
+        let (moves, in_order) = match parse_moves(body) {
+            Ok(m) => m,
+            Err(e) => return error(400, &e),
+        };
+        let state = match game::replay(&moves, &self.rules, in_order) {
+            Ok(s) => s,
+            Err((i, e)) => return error(400, &format!(
+                "move {} ({}) is illegal: {}", i + 1, moves[i], e)),
+        };
+        if let Some(ref r) = remaining {
+            let on_table = state.pieces.iter()
+                .filter(|p| p.index() == tile).count();
+            if r.digit(tile) + on_table >= self.rules.copies {
+                return error(400, &format!(
+                    "there are no more copies of tile {}", tile));
+            }
+        }
+
+        let corner = (moves.iter().map(|m| m.x).min().unwrap_or(0),
+                      moves.iter().map(|m| m.y).min().unwrap_or(0));
+        let mut hint = Hint::new(self.rules).with_results(&self.results);
+        if let Some(r) = remaining {
+            hint = hint.with_remaining(r);
+        }
+        let m = to_move(&hint.run(&state, tile, budget), corner);
+        (200, format!("{{\"tile\": {}, \"rotation\": {}, \"x\": {}, \
+                       \"y\": {}, \"z\": {}, \"move\": {}}}",
+                      m.tile, m.rotation, m.x, m.y, m.z.unwrap_or(0),
+                      quote(&m.to_string())))
+    }
+
+    fn verify(&self, body: &str) -> (u16, String) {
+        let (moves, in_order) = match parse_moves(body) {
+            Ok(m) => m,
+            Err(e) => return error(400, &e),
+        };
+        match game::replay(&moves, &self.rules, in_order) {
+            Ok(state) => (200, format!(
+                "{{\"legal\": true, \"moves\": {}, \"score\": {}}}",
+                moves.len(), self.rules.score(&state))),
+            Err((i, e)) => (200, format!(
+                "{{\"legal\": false, \"move\": {}, \"error\": {}}}",
+                i + 1, quote(&format!("{} is illegal: {}", moves[i], e)))),
+        }
+    }
+
+    fn lookup(&self, bag: &str) -> (u16, String) {
+        let bag = match Bag::parse(&bag.replace("%2C", ","), self.rules.copies) {
+            Ok(b) => b,
+            Err(e) => return error(400, &e),
+        };
+        match self.results.score(&bag) {
+            Some(score) => (200, json::bag(&bag, score,
+                                           self.results.state(&bag))),
+            None => error(404, &format!("{} isn't solved", bag.digits())),
+        }
+    }
+
+    // Answers requests until the listener fails, one thread per connection
+    pub fn serve(&self, listener: TcpListener) -> io::Result<()> {
+        thread::scope(|scope| {
+            loop {
+                let (stream, addr) = listener.accept()?;
+                scope.spawn(move || {
+                    if let Err(e) = self.respond(stream) {
+                        warning!("Lost connection from {}: {}", addr, e);
+                    }
+                });
+            }
+        })
+    }
+
+    // Reads one request from the stream and writes its reply
+    fn respond(&self, stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut out = stream;
+
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (method, target) = match words[..] {
+            [m, t, _] => (m.to_string(), t.to_string()),
+            _ => return reply(&mut out, error(400, "invalid request line")),
+        };
+
+        // Only the body's length matters among the headers
+        let mut length = 0;
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                break;
+            }
+            if let Some(i) = line.find(':') {
+                if line[..i].trim().eq_ignore_ascii_case("content-length") {
+                    match line[i + 1..].trim().parse() {
+                        Ok(n) => length = n,
+                        Err(_) => return reply(&mut out,
+                            error(400, "invalid Content-Length")),
+                    }
+                }
+            }
+        }
+        if length > MAX_BODY {
+            return reply(&mut out, error(413, "request body is too large"));
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+        let body = match String::from_utf8(body) {
+            Ok(b) => b,
+            Err(_) => return reply(&mut out, error(400, "body isn't UTF-8")),
+        };
+        debug!("{} {}", method, target);
+        reply(&mut out, self.handle(&method, &target, &body))
+    }
+}
+
+fn reply<W: Write>(out: &mut W, (status, body): (u16, String))
+    -> io::Result<()>
+{
+    write!(out, "HTTP/1.1 {} {}\r\n\
+                 Content-Type: application/json\r\n\
+                 Content-Length: {}\r\n\
+                 Access-Control-Allow-Origin: *\r\n\
+                 Connection: close\r\n\r\n{}",
+           status, reason(status), body.len(), body)?;
+    out.flush()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server() -> Server {
+        let mut results = Results::new(Rules::default());
+        let bag = Bag::from_usize(0).add(0).add(0).add(4);
+        results.write_score(&bag, 1, None);
+        Server::new(results)
+    }
+
+    #[test]
+    fn hint() {
+        // With nothing left in the deck, the 1 bridges two 0s, in the
+        // game's frame
+        let (status, body) = server().handle(
+            "POST", "/hint?tile=1&movetime=5000&remaining=0000000000",
+            "0@5,5; 0@8,5\n");
+        assert_eq!(status, 200, "{}", body);
+        assert!(body.ends_with("\"move\": \"1@7,5 r0 z1\"}"), "{}", body);
+
+        let (status, _) = server().handle("POST", "/hint", "");
+        assert_eq!(status, 400);
+        let (status, _) = server().handle("POST", "/hint?tile=1",
+                                          "0@0,0; 0@9,9\n");
+        assert_eq!(status, 400);
+    }
+
+    #[test]
+    fn verify() {
+        let s = server();
+        assert_eq!(s.handle("POST", "/verify", "0@0,0\n0@3,0\n1@2,0 r0 z1\n"),
+                   (200, "{\"legal\": true, \"moves\": 3, \"score\": 1}".to_string()));
+        let (status, body) = s.handle("POST", "/verify", "0@0,0\n0@9,9\n");
+        assert_eq!(status, 200);
+        assert!(body.starts_with("{\"legal\": false, \"move\": 2, "), "{}", body);
+        assert_eq!(s.handle("GET", "/verify", "").0, 405);
+    }
+
+    #[test]
+    fn lookup() {
+        let s = server();
+        let (status, body) = s.handle("GET", "/results/0,0,1", "");
+        assert_eq!(status, 200);
+        assert_eq!(body, json::bag(&Bag::from_usize(0).add(0).add(0).add(4),
+                                   1, None));
+        assert_eq!(s.handle("GET", "/results/2000000000", "").0, 404);
+        assert_eq!(s.handle("GET", "/results/x", "").0, 400);
+        assert_eq!(s.handle("GET", "/bogus", "").0, 404);
+    }
+
+    #[test]
+    fn serve() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let s = server();
+        thread::spawn(move || s.serve(listener));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let body = "0@0,0\n";
+        write!(stream, "POST /verify HTTP/1.1\r\nHost: localhost\r\n\
+                        Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).unwrap();
+        assert!(reply.starts_with("HTTP/1.1 200 OK\r\n"), "{}", reply);
+        assert!(reply.ends_with("\r\n\r\n{\"legal\": true, \"moves\": 1, \"score\": 0}"),
+                "{}", reply);
+    }
+}
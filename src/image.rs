@@ -0,0 +1,272 @@
+use std::io::{self, Write};
+
+use png;
+
+use piece::{UNIQUE_PIECE_COUNT, pieces};
+use state::State;
+use theme::theme;
+
+pub type Rgb = [u8; 3];
+
+// Sizes for images of a single arrangement, whose colors come from the
+// theme
+const CELL: usize = 24;
+const MARGIN: usize = 20;
+
+// RGB equivalents of the terminal colors in PIECE_COLORS, for the default
+// theme
+pub const PIECE_RGB: [Rgb; UNIQUE_PIECE_COUNT] = [
+    [229, 229, 229],
+    [205, 205, 0],
+    [241, 76, 76],
+    [245, 245, 67],
+    [13, 188, 121],
+    [17, 168, 205],
+    [36, 114, 200],
+    [188, 63, 188],
+    [214, 112, 214],
+    [205, 49, 49],
+];
+
+// A 5x7 bitmap font, one byte per row, covering the characters that
+// rendered images need.  Lowercase letters are drawn as uppercase, and
+// anything missing is drawn as a space.
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        '0' => [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e],
+        '1' => [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        '2' => [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f],
+        '3' => [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e],
+        '4' => [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02],
+        '5' => [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e],
+        '6' => [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e],
+        '7' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e],
+        '9' => [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c],
+        'A' => [0x0e, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11],
+        'B' => [0x1e, 0x11, 0x11, 0x1e, 0x11, 0x11, 0x1e],
+        'C' => [0x0e, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0e],
+        'D' => [0x1e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1e],
+        'E' => [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x1f],
+        'F' => [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x10],
+        'G' => [0x0e, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0f],
+        'H' => [0x11, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11],
+        'I' => [0x0e, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1f],
+        'M' => [0x11, 0x1b, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e],
+        'P' => [0x1e, 0x11, 0x11, 0x1e, 0x10, 0x10, 0x10],
+        'R' => [0x1e, 0x11, 0x11, 0x1e, 0x14, 0x12, 0x11],
+        'S' => [0x0f, 0x10, 0x10, 0x0e, 0x01, 0x01, 0x1e],
+        'T' => [0x1f, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0a, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0a],
+        'X' => [0x11, 0x11, 0x0a, 0x04, 0x0a, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x0a, 0x04, 0x04, 0x04, 0x04],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c],
+        ':' => [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x0c, 0x00],
+        '-' => [0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00],
+        '/' => [0x01, 0x01, 0x02, 0x04, 0x08, 0x10, 0x10],
+        _ => [0; 7],
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// A simple RGB raster, for rendering solutions to PNG files
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<Rgb>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, background: Rgb) -> Image {
+        Image { width: width, height: height,
+                pixels: vec![background; width * height] }
+    }
+
+    pub fn at(&self, x: usize, y: usize) -> Rgb {
+        self.pixels[x + y * self.width]
+    }
+
+    // Fills a rectangle, clipped to the image
+    pub fn fill(&mut self, x: usize, y: usize, w: usize, h: usize, c: Rgb) {
+        for j in y..(y + h).min(self.height) {
+            for i in x..(x + w).min(self.width) {
+                self.pixels[i + j * self.width] = c;
+            }
+        }
+    }
+
+    // Returns the width in pixels of a string drawn at the given scale
+    pub fn text_width(s: &str, scale: usize) -> usize {
+        (s.chars().count() * 6).saturating_sub(1) * scale
+    }
+
+    // Draws a string with its top-left corner at the given position
+    pub fn text(&mut self, s: &str, x: usize, y: usize, scale: usize, c: Rgb) {
+        for (i, ch) in s.chars().enumerate() {
+            let g = glyph(ch);
+            for (row, bits) in g.iter().enumerate() {
+                for col in 0..5 {
+                    if bits & (0x10 >> col) != 0 {
+                        self.fill(x + (i * 6 + col) * scale, y + row * scale,
+                                  scale, scale, c);
+                    }
+                }
+            }
+        }
+    }
+
+    // Draws each layer of a state side by side, bottom layer first, in the
+    // given rectangle.  Pieces are drawn in their terminal colors, with a
+    // gap between neighboring pieces so that their outlines are visible.
+    pub fn draw_layers(&mut self, state: &State, x: usize, y: usize,
+                       w: usize, h: usize, label: Rgb) {
+        if state.is_empty() {
+            return;
+        }
+        let (sw, sh) = state.size();
+        let (sw, sh) = (sw as usize, sh as usize);
+        let layers = state.layers() + 1;
+
+        // Leave room for a label above each layer
+        let gap = 20;
+        let label_height = 24;
+        let panel = (w - gap * (layers - 1)) / layers;
+        let cell = (panel / sw).min((h - label_height) / sh).max(1);
+
+        for z in 0..layers {
+            let px = x + z * (panel + gap) + (panel - cell * sw) / 2;
+            self.text(&format!("LAYER {}", z), px, y, 2, label);
+            self.draw_layer(state, z, px, y + label_height, cell);
+        }
+    }
+
+    // Draws one layer of a state with its top-left corner at the given
+    // position, with each grid cell taking up cell x cell pixels.  The
+    // layer is drawn in the state's full footprint, so that layers drawn
+    // separately line up.
+    pub fn draw_layer(&mut self, state: &State, z: usize, px: usize,
+                      py: usize, cell: usize) {
+        let (sw, sh) = state.size();
+        let (sw, sh) = (sw as usize, sh as usize);
+        self.fill(px, py, cell * sw, cell * sh, [40, 40, 52]);
+
+        // Map each cell on this layer to the piece covering it, mirrored
+        // in x to match State::pretty_print
+        let mut grid = vec![None; sw * sh];
+        for (n, p) in state.layer(z).iter().enumerate() {
+            let shape = pieces().shape(p.id());
+            for (cx, cy) in shape.pts {
+                let gx = sw - (p.x + cx) as usize - 1;
+                let gy = (p.y + cy) as usize;
+                grid[gx + gy * sw] = Some((n, p.index()));
+            }
+        }
+
+        let inset = (cell / 10).max(1);
+        for gy in 0..sh {
+            for gx in 0..sw {
+                let (n, index) = match grid[gx + gy * sw] {
+                    Some(c) => c,
+                    None => continue,
+                };
+                let same = |dx: isize, dy: isize| {
+                    let (nx, ny) = (gx as isize + dx, gy as isize + dy);
+                    nx >= 0 && ny >= 0 && (nx as usize) < sw &&
+                        (ny as usize) < sh &&
+                        grid[nx as usize + ny as usize * sw]
+                            .map(|c| c.0) == Some(n)
+                };
+                let left = if same(-1, 0) { 0 } else { inset };
+                let right = if same(1, 0) { 0 } else { inset };
+                let top = if same(0, -1) { 0 } else { inset };
+                let bottom = if same(0, 1) { 0 } else { inset };
+                self.fill(px + gx * cell + left, py + gy * cell + top,
+                          cell - left - right, cell - top - bottom,
+                          theme().rgb[index]);
+            }
+        }
+    }
+
+    // Draws a state's layers side by side, bottom layer first, sized to fit
+    pub fn of_state(state: &State) -> Image {
+        let (sw, sh) = state.size();
+        let layers = if state.is_empty() { 0 } else { state.layers() + 1 };
+        let panels = layers * sw as usize * CELL + layers.saturating_sub(1) * 20;
+        let height = 24 + sh as usize * CELL;
+        let mut img = Image::new(panels + 2 * MARGIN, height + 2 * MARGIN,
+                                 theme().background);
+        img.draw_layers(state, MARGIN, MARGIN, panels, height, theme().label);
+        return img;
+    }
+
+    // Draws one layer of a state, in the state's full footprint
+    pub fn of_layer(state: &State, z: usize) -> Image {
+        let (sw, sh) = state.size();
+        let mut img = Image::new(sw as usize * CELL + 2 * MARGIN,
+                                 24 + sh as usize * CELL + 2 * MARGIN,
+                                 theme().background);
+        img.text(&format!("LAYER {}", z), MARGIN, MARGIN, 2, theme().label);
+        img.draw_layer(state, z, MARGIN, MARGIN + 24, CELL);
+        return img;
+    }
+
+    pub fn write_png<W: Write>(&self, out: W) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(out, self.width as u32,
+                                            self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let data: Vec<u8> = self.pixels.iter().flat_map(|p| p.iter())
+            .cloned().collect();
+        encoder.write_header()
+            .and_then(|mut w| w.write_image_data(&data))
+            .map_err(io::Error::other)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draw() {
+        let state = State::new().try_place(0, 0, 0).unwrap()
+            .try_place(0, 3, 0).unwrap()
+            .try_place(4, 2, 0).unwrap();
+        let mut img = Image::new(400, 200, [0, 0, 0]);
+        img.draw_layers(&state, 0, 0, 400, 200, [255, 255, 255]);
+
+        // Both layers have some of their pieces' colors in them
+        let count = |c: Rgb, xs: ::std::ops::Range<usize>| xs
+            .flat_map(|x| (0..200).map(move |y| (x, y)))
+            .filter(|&(x, y)| img.at(x, y) == c)
+            .count();
+        assert!(count(PIECE_RGB[0], 0..190) > 0);
+        assert_eq!(count(PIECE_RGB[1], 0..190), 0);
+        assert!(count(PIECE_RGB[1], 210..400) > 0);
+
+        // Images of the whole state fit every layer, and images of one
+        // layer only have that layer's pieces
+        let img = Image::of_state(&state);
+        let (sw, sh) = state.size();
+        assert_eq!((img.width, img.height),
+                   (2 * (sw as usize * 24) + 20 + 40, sh as usize * 24 + 64));
+        assert_eq!(Image::of_state(&State::new()).width, 40);
+        let top = Image::of_layer(&state, 1);
+        assert!(top.pixels.contains(&PIECE_RGB[1]));
+        assert!(!top.pixels.contains(&PIECE_RGB[0]));
+
+        let mut png = Vec::new();
+        img.write_png(&mut png).unwrap();
+        assert_eq!(&png[1..4], b"PNG");
+        assert_eq!(Image::text_width("SCORE", 2), 58);
+    }
+}
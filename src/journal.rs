@@ -0,0 +1,540 @@
+use std::io::{self, Read, Write};
+
+use bag::Bag;
+use piece::{Adjacency, MAX_COPIES, UNIQUE_PIECE_COUNT};
+use rules::Rules;
+use scoring::Scoring;
+use state::State;
+use tuning::{Dedup, MoveOrder, PieceOrder, Tuning};
+
+// A journal is a compact record of every step of one solve, which can be
+// replayed later to reproduce the search exactly.  The file starts with
+// MAGIC and a header of varints (see Header::write), then has one tag
+// byte per step, followed by a zigzag varint if the step has a value.
+// Seeded searches break ties with rng::Rng, so VERSION changes with it.
+const MAGIC: &[u8] = b"NMBR9J";
+const VERSION: u64 = 6;
+
+// One step of the search.  Inputs are values that the search read from
+// outside (the results table or the clock), which are taken from the
+// journal when replaying; the others are checked against it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Event {
+    // Input:  the initial best score, from the bag's subsets
+    Initial(isize),
+    // Input:  an upper bound on the score reachable from a state
+    Bound(isize),
+    // Input:  the deadline had passed when a state was reached
+    Deadline,
+
+    // The state had already been searched
+    Seen,
+    // The state beat the best score
+    Improved(isize),
+    // The state was expanded into this many moves
+    Expanded(usize),
+}
+
+impl Event {
+    fn tag(&self) -> u8 {
+        match *self {
+            Event::Initial(_) => 0,
+            Event::Bound(_) => 1,
+            Event::Deadline => 2,
+            Event::Seen => 3,
+            Event::Improved(_) => 4,
+            Event::Expanded(_) => 5,
+        }
+    }
+
+    pub fn is_input(&self) -> bool {
+        self.tag() <= 2
+    }
+
+    // Steps which are stored as a bare tag, without a value
+    fn value_free(&self) -> bool {
+        matches!(*self, Event::Deadline | Event::Seen)
+    }
+
+    pub fn value(&self) -> isize {
+        match *self {
+            Event::Initial(v) | Event::Bound(v) | Event::Improved(v) => v,
+            Event::Expanded(n) => n as isize,
+            Event::Deadline | Event::Seen => 0,
+        }
+    }
+
+    fn from_parts(tag: u8, value: isize) -> Option<Event> {
+        match tag {
+            0 => Some(Event::Initial(value)),
+            1 => Some(Event::Bound(value)),
+            2 => Some(Event::Deadline),
+            3 => Some(Event::Seen),
+            4 => Some(Event::Improved(value)),
+            5 => Some(Event::Expanded(value as usize)),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) fn write_varint<W: Write>(out: &mut W, mut v: u64) -> io::Result<()> {
+    loop {
+        let b = (v & 0x7f) as u8;
+        v >>= 7;
+        if v == 0 {
+            return out.write_all(&[b]);
+        }
+        out.write_all(&[b | 0x80])?;
+    }
+}
+
+// Reads a varint from the front of a slice, advancing it
+pub(crate) fn read_varint(data: &mut &[u8]) -> io::Result<u64> {
+    let mut v = 0;
+    for shift in (0..64).step_by(7) {
+        let (&b, rest) = data.split_first().ok_or_else(
+            || io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated journal"))?;
+        *data = rest;
+        v |= ((b & 0x7f) as u64) << shift;
+        if b & 0x80 == 0 {
+            return Ok(v);
+        }
+    }
+    return Err(invalid("Varint is too long"));
+}
+
+pub(crate) fn zigzag(v: isize) -> u64 {
+    ((v << 1) ^ (v >> 63)) as u64
+}
+
+pub(crate) fn unzigzag(v: u64) -> isize {
+    ((v >> 1) as isize) ^ -((v & 1) as isize)
+}
+
+pub(crate) fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Everything needed to set up the same search again
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Header {
+    pub bag: Bag,
+    pub rules: Rules,
+    pub tuning: Tuning,
+    pub seed: Option<u64>,
+}
+
+impl Header {
+    fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let r = &self.rules;
+        let t = &self.tuning;
+        let mut fields = vec![
+            VERSION,
+            r.copies as u64,
+            self.bag.index(r.copies) as u64,
+            match r.scoring {
+                Scoring::Linear => 0,
+                Scoring::Squared => 1,
+                Scoring::TopBonus => 2,
+            },
+            match r.adjacency {
+                Adjacency::Orthogonal => 0,
+                Adjacency::Diagonal => 1,
+            },
+            r.max_layers.map(|n| n as u64 + 1).unwrap_or(0),
+            match t.dedup {
+                Dedup::Exact => 0,
+                Dedup::Hashed => 1,
+                Dedup::Bloom => 2,
+                Dedup::Evicting => 3,
+            },
+            t.seen_limit as u64,
+            t.evict as u64,
+            match t.order {
+                PieceOrder::Index => 0,
+                PieceOrder::LargestArea => 1,
+                PieceOrder::HighestValue => 2,
+            },
+            self.seed.map(|s| s + 1).unwrap_or(0),
+        ];
+        fields.extend(r.values.iter().map(|&v| zigzag(v)));
+        fields.push(r.mirrored as u64);
+
+        out.write_all(MAGIC)?;
+        for f in fields {
+            write_varint(out, f)?;
+        }
+        Ok(())
+    }
+
+    fn read(data: &mut &[u8]) -> io::Result<Header> {
+        if !data.starts_with(MAGIC) {
+            return Err(invalid("Not a journal"));
+        }
+        *data = &data[MAGIC.len()..];
+        if read_varint(data)? != VERSION {
+            return Err(invalid("Unsupported journal version"));
+        }
+        let mut f = || read_varint(data);
+        let copies = f()? as usize;
+        let bag = f()? as usize;
+        let scoring = match f()? {
+            0 => Scoring::Linear,
+            1 => Scoring::Squared,
+            2 => Scoring::TopBonus,
+            _ => return Err(invalid("Unknown scoring")),
+        };
+        let adjacency = match f()? {
+            0 => Adjacency::Orthogonal,
+            1 => Adjacency::Diagonal,
+            _ => return Err(invalid("Unknown adjacency")),
+        };
+        let max_layers = match f()? {
+            0 => None,
+            n => Some(n as usize - 1),
+        };
+        let dedup = match f()? {
+            0 => Dedup::Exact,
+            1 => Dedup::Hashed,
+            2 => Dedup::Bloom,
+            3 => Dedup::Evicting,
+            _ => return Err(invalid("Unknown dedup")),
+        };
+        let seen_limit = f()? as usize;
+        let evict = f()? != 0;
+        let order = match f()? {
+            0 => PieceOrder::Index,
+            1 => PieceOrder::LargestArea,
+            2 => PieceOrder::HighestValue,
+            _ => return Err(invalid("Unknown piece order")),
+        };
+        let seed = match f()? {
+            0 => None,
+            s => Some(s - 1),
+        };
+        let mut values = [0; UNIQUE_PIECE_COUNT];
+        for v in values.iter_mut() {
+            *v = unzigzag(f()?);
+        }
+        let mirrored = match f()? {
+            0 => false,
+            1 => true,
+            _ => return Err(invalid("Unknown mirroring")),
+        };
+        if copies == 0 || copies > MAX_COPIES ||
+           bag >= (copies + 1).pow(UNIQUE_PIECE_COUNT as u32)
+        {
+            return Err(invalid("Invalid bag"));
+        }
+
+        let rules = Rules { scoring: scoring, adjacency: adjacency,
+                            max_layers: max_layers, copies: copies,
+                            values: values, mirrored: mirrored };
+        let tuning = Tuning { dedup: dedup, split_depth: 0,
+                              seen_limit: seen_limit, evict: evict,
+                              order: order, moves: MoveOrder::Score,
+                              shared_seen: false, dominance: false };
+        Ok(Header { bag: Bag::from_index(bag, copies), rules: rules,
+                    tuning: tuning, seed: seed })
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+enum Mode {
+    Record(Box<dyn Write + Send>),
+
+    // The rest of the journal's steps, still to be replayed
+    Replay(Vec<u8>, usize),
+}
+
+pub struct Journal {
+    header: Header,
+    mode: Mode,
+
+    // Number of steps recorded or replayed so far
+    steps: u64,
+
+    // If present, the step at which to stop replaying
+    stop: Option<u64>,
+
+    // The first error from writing a recorded step.  The search goes on
+    // without the journal, and the error is returned by flush().
+    error: Option<io::Error>,
+}
+
+impl Journal {
+    // Starts recording a journal, writing its header right away
+    pub fn record(mut out: Box<dyn Write + Send>, header: Header)
+        -> io::Result<Journal>
+    {
+        header.write(&mut out)?;
+        Ok(Journal { header: header, mode: Mode::Record(out), steps: 0,
+                     stop: None, error: None })
+    }
+
+    // Loads a journal to be replayed
+    pub fn replay<R: Read>(mut input: R) -> io::Result<Journal> {
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        let mut rest = &data[..];
+        let header = Header::read(&mut rest)?;
+        let offset = data.len() - rest.len();
+        Ok(Journal { header: header, mode: Mode::Replay(data, offset),
+                     steps: 0, stop: None, error: None })
+    }
+
+    // Stops replaying after the given number of steps
+    pub fn with_stop(mut self, step: u64) -> Journal {
+        self.stop = Some(step);
+        self
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn replaying(&self) -> bool {
+        match self.mode {
+            Mode::Record(_) => false,
+            Mode::Replay(..) => true,
+        }
+    }
+
+    // Returns true once a replay has reached its stopping step
+    pub fn stopped(&self) -> bool {
+        self.stop.map(|s| self.steps >= s).unwrap_or(false)
+    }
+
+    // Returns true if a replay has used up every recorded step
+    pub fn finished(&self) -> bool {
+        match self.mode {
+            Mode::Record(_) => false,
+            Mode::Replay(ref data, pos) => pos >= data.len(),
+        }
+    }
+
+    // Returns the next recorded step without consuming it
+    fn peek(&self) -> io::Result<Option<(Event, usize)>> {
+        let (data, pos) = match self.mode {
+            Mode::Replay(ref data, pos) => (data, pos),
+            Mode::Record(_) => return Ok(None),
+        };
+        let mut rest = &data[pos..];
+        let tag = match rest.split_first() {
+            Some((&t, r)) => { rest = r; t },
+            None => return Ok(None),
+        };
+        let value = if Event::from_parts(tag, 0).map(|e| e.value_free())
+                           .unwrap_or(false) {
+            0
+        } else {
+            unzigzag(read_varint(&mut rest)?)
+        };
+        let e = Event::from_parts(tag, value)
+            .ok_or_else(|| invalid("Unknown journal step"))?;
+        Ok(Some((e, data.len() - rest.len())))
+    }
+
+    // Records a step, or when replaying, checks it against the next
+    // recorded step.  Inputs are replaced by their recorded values; any
+    // other difference means that the replay has diverged, which panics
+    // with the state that it diverged at.  Once a replay has stopped,
+    // steps are passed through unchanged.
+    pub fn step(&mut self, event: Event, state: &State) -> Event {
+        if self.stopped() {
+            return event;
+        }
+        self.steps += 1;
+        let recorded = match self.mode {
+            Mode::Record(ref mut out) => {
+                if self.error.is_none() {
+                    let written = out.write_all(&[event.tag()])
+                        .and_then(|_| if event.value_free() {
+                            Ok(())
+                        } else {
+                            write_varint(out, zigzag(event.value()))
+                        });
+                    self.error = written.err();
+                }
+                return event;
+            }
+            Mode::Replay(..) => self.peek().expect("Corrupt journal"),
+        };
+        let diverged = match recorded {
+            Some((r, _)) if event.is_input() => r.tag() != event.tag(),
+            Some((r, _)) => r != event,
+            None => true,
+        };
+        if diverged {
+            panic!("Replay diverged at step {}: expected {:?}, got {:?} at\n{:?}",
+                   self.steps, recorded.map(|r| r.0), event, state);
+        }
+        let (r, next) = recorded.unwrap();
+        if let Mode::Replay(_, ref mut pos) = self.mode {
+            *pos = next;
+        }
+        return r;
+    }
+
+    // Records whether the deadline had passed, or when replaying, returns
+    // whether it had passed at this point in the recorded search
+    pub fn deadline(&mut self, late: bool, state: &State) -> bool {
+        if self.stopped() {
+            return false;
+        } else if self.replaying() {
+            let next = self.peek().expect("Corrupt journal");
+            if next.map(|n| n.0) != Some(Event::Deadline) {
+                return false;
+            }
+        } else if !late {
+            return false;
+        }
+        self.step(Event::Deadline, state);
+        return true;
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        match self.mode {
+            Mode::Record(ref mut out) => out.flush(),
+            Mode::Replay(..) => Ok(()),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    // A writer whose output can be read back after the journal has it
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn header() -> Header {
+        let rules = Rules { max_layers: Some(3), mirrored: true,
+                            ..Rules::default() }.with_joker(0);
+        Header {
+            bag: Bag::from_usize(0).add(0).add(36),
+            rules: rules,
+            tuning: Tuning::for_len(2),
+            seed: Some(7),
+        }
+    }
+
+    #[test]
+    fn varint() {
+        for &v in &[0, 1, -1, 63, -64, 1000, isize::MAX, isize::MIN] {
+            let mut out = Vec::new();
+            write_varint(&mut out, zigzag(v)).unwrap();
+            let mut data = &out[..];
+            assert_eq!(unzigzag(read_varint(&mut data).unwrap()), v);
+            assert!(data.is_empty());
+        }
+    }
+
+    #[test]
+    fn replay() {
+        let state = State::new();
+        let steps = [Event::Initial(3), Event::Expanded(12), Event::Seen,
+                     Event::Bound(-5), Event::Improved(9), Event::Deadline];
+        let out = Shared::default();
+        let mut j = Journal::record(Box::new(out.clone()), header()).unwrap();
+        for &e in steps.iter() {
+            assert_eq!(j.step(e, &state), e);
+        }
+        assert_eq!(j.steps(), steps.len() as u64);
+
+        let data = out.0.lock().unwrap().clone();
+        let mut j = Journal::replay(&data[..]).unwrap();
+        assert_eq!(j.header(), &header());
+        assert!(j.replaying());
+
+        // Inputs come from the journal
+        assert_eq!(j.step(Event::Initial(0), &state), Event::Initial(3));
+        assert_eq!(j.step(Event::Expanded(12), &state), Event::Expanded(12));
+        assert!(!j.deadline(true, &state));
+        assert_eq!(j.step(Event::Seen, &state), Event::Seen);
+        assert_eq!(j.step(Event::Bound(100), &state), Event::Bound(-5));
+        assert_eq!(j.step(Event::Improved(9), &state), Event::Improved(9));
+        assert!(!j.finished());
+        assert!(j.deadline(false, &state));
+        assert!(j.finished());
+
+        let mut j = Journal::replay(&data[..]).unwrap().with_stop(2);
+        j.step(Event::Initial(0), &state);
+        assert!(!j.stopped());
+        j.step(Event::Expanded(12), &state);
+        assert!(j.stopped());
+        assert_eq!(j.step(Event::Bound(1), &state), Event::Bound(1));
+        assert_eq!(j.steps(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "Replay diverged at step 2")]
+    fn diverged() {
+        let state = State::new();
+        let out = Shared::default();
+        let mut j = Journal::record(Box::new(out.clone()), header()).unwrap();
+        j.step(Event::Initial(3), &state);
+        j.step(Event::Expanded(12), &state);
+
+        let data = out.0.lock().unwrap().clone();
+        let mut j = Journal::replay(&data[..]).unwrap();
+        j.step(Event::Initial(0), &state);
+        j.step(Event::Expanded(11), &state);
+    }
+
+    #[test]
+    fn write_error() {
+        // A writer which fails, as when the disk fills up
+        struct Full;
+        impl Write for Full {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::other("disk full"))
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        // The header goes to a working writer, so the failure is in a step,
+        // which is passed through, with the error reported by flush()
+        let mut j = Journal::record(Box::new(Vec::new()), header()).unwrap();
+        j.mode = Mode::Record(Box::new(Full));
+        let state = State::new();
+        assert_eq!(j.step(Event::Expanded(1), &state), Event::Expanded(1));
+        assert_eq!(j.step(Event::Seen, &state), Event::Seen);
+        assert_eq!(j.flush().unwrap_err().to_string(), "disk full");
+        assert!(j.flush().is_ok());
+    }
+
+    #[test]
+    fn corrupt() {
+        assert!(Journal::replay(&b"NMBR9X"[..]).is_err());
+        let mut data = Vec::new();
+        header().write(&mut data).unwrap();
+        data.truncate(data.len() - 1);
+        assert!(Journal::replay(&data[..]).is_err());
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+use bag::Bag;
+use piece::UNIQUE_PIECE_COUNT;
+use results::Results;
+use rules::Rules;
+use state::State;
+
+// Results as a JSON document, for post-processing in other tools:
+//
+//   {"version": 1,
+//    "rules": {"scoring": "Linear", "adjacency": "Orthogonal",
+//              "max_layers": null, "copies": 2,
+//              "values": [0, 1, 2, 3, 4, 5, 6, 7, 8, 9]},
+//    "bags": [
+//     {"bag": "0120000000", "tiles": [1, 2, 2], "score": 2, "layers": 2,
+//      "placements": [{"tile": 2, "rotation": 0, "x": 0, "y": 0, "z": 1},
+//                     ...]},
+//     ...
+//   ]}
+//
+// Each entry in "bags" is a bag which was solved exactly:
+//
+//   bag         count of each tile, as one digit per tile
+//   tiles       the bag's tiles, in ascending order
+//   score       the bag's best score
+//   layers      number of layers in the best arrangement, including the
+//               ground, or null if the arrangement isn't known
+//   placements  the best arrangement's tiles, from the top layer down, or
+//               null if it isn't known.  Each has its tile, its rotation
+//               (in quarter turns), the position of its bounding box's
+//               corner (with the arrangement shifted so that the smallest
+//               x and y are 0), and its layer (0 is the ground).
+//
+// Bags are written one per line as they're solved, so they're in the
+// order that they finished.  If the run is interrupted, the file ends
+// after the last complete line; removing the trailing comma and adding
+// "]}" gives a valid document.
+//
+// Any change to this format (including new fields) must bump VERSION.
+pub const VERSION: usize = 1;
+
+fn rules(r: &Rules) -> String {
+    let values: Vec<String> = r.values.iter().map(|v| v.to_string()).collect();
+    format!("{{\"scoring\": \"{:?}\", \"adjacency\": \"{:?}\", \
+             \"max_layers\": {}, \"copies\": {}, \"values\": [{}]}}",
+            r.scoring, r.adjacency,
+            r.max_layers.map(|n| n.to_string()).unwrap_or("null".to_string()),
+            r.copies, values.join(", "))
+}
+
+// Formats one entry of the "bags" list
+pub fn bag(bag: &Bag, score: isize, state: Option<&State>) -> String {
+    let mut tiles = Vec::new();
+    for i in 0..UNIQUE_PIECE_COUNT {
+        for _ in 0..bag.digit(i) {
+            tiles.push(i.to_string());
+        }
+    }
+    let (layers, placements) = match state {
+        Some(s) => {
+            let p: Vec<String> = s.pieces.iter()
+                .map(|p| format!("{{\"tile\": {}, \"rotation\": {}, \
+                                  \"x\": {}, \"y\": {}, \"z\": {}}}",
+                                 p.index(), p.orientation(), p.x, p.y, p.z))
+                .collect();
+            let layers = if s.is_empty() { 0 } else { s.layers() + 1 };
+            (layers.to_string(), format!("[{}]", p.join(", ")))
+        },
+        None => ("null".to_string(), "null".to_string()),
+    };
+    return format!("{{\"bag\": \"{}\", \"tiles\": [{}], \"score\": {}, \
+                    \"layers\": {}, \"placements\": {}}}",
+                   bag.digits(), tiles.join(", "), score, layers, placements);
+}
+
+// Formats how many arrangements of a bag reach each score (see the census
+// module) as a document of its own:
+//
+//   {"version": 1, "rules": {...}, "bag": "2100000000", "exact": true,
+//    "scores": [{"score": 0, "count": 1928}, {"score": 1, "count": 1}]}
+//
+// "exact" is false if the counts are from random games, rather than from
+// every distinct arrangement.
+pub fn histogram(r: &Rules, bag: &Bag, exact: bool,
+                 counts: &BTreeMap<isize, usize>) -> String {
+    let scores: Vec<String> = counts.iter()
+        .map(|(s, n)| format!("{{\"score\": {}, \"count\": {}}}", s, n))
+        .collect();
+    return format!("{{\"version\": {}, \"rules\": {}, \"bag\": \"{}\", \
+                    \"exact\": {}, \"scores\": [{}]}}",
+                   VERSION, rules(r), bag.digits(), exact, scores.join(", "));
+}
+
+// Writes a JSON document as bags are solved.  Call finish() to close it.
+pub struct JsonWriter {
+    // The output, and whether any bags have been written to it
+    out: Mutex<(Box<dyn Write + Send>, bool)>,
+}
+
+impl JsonWriter {
+    pub fn new(mut out: Box<dyn Write + Send>, r: &Rules)
+        -> io::Result<JsonWriter>
+    {
+        write!(out, "{{\"version\": {},\n \"rules\": {},\n \"bags\": [",
+               VERSION, rules(r))?;
+        return Ok(JsonWriter { out: Mutex::new((out, false)) });
+    }
+
+    // Adds a bag to the document.  Each bag is flushed as it's written,
+    // so that an interrupted run keeps everything solved so far.
+    pub fn write(&self, b: &Bag, score: isize, state: Option<&State>)
+        -> io::Result<()>
+    {
+        let mut lock = self.out.lock().unwrap();
+        let (ref mut out, ref mut started) = *lock;
+        write!(out, "{}\n  {}", if *started { "," } else { "" },
+               bag(b, score, state))?;
+        *started = true;
+        out.flush()
+    }
+
+    // Adds every bag which has been solved exactly
+    pub fn write_results(&self, results: &Results) -> io::Result<()> {
+        for b in results.bags() {
+            if let Some(score) = results.score(&b) {
+                self.write(&b, score, results.state(&b))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(&self) -> io::Result<()> {
+        let mut lock = self.out.lock().unwrap();
+        writeln!(lock.0, "\n]}}")?;
+        lock.0.flush()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    // A Write which can be read back after it's handed to a JsonWriter
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn bag() {
+        let b = Bag::from_usize(0).add(4).add(8).add(8);
+        assert_eq!(super::bag(&b, 3, None),
+                   "{\"bag\": \"0120000000\", \"tiles\": [1, 2, 2], \
+                    \"score\": 3, \"layers\": null, \"placements\": null}");
+
+        let state = State::new().try_place(36, 0, 0).unwrap();
+        assert_eq!(super::bag(&Bag::from_usize(0).add(36), 0, Some(&state)),
+                   "{\"bag\": \"0000000001\", \"tiles\": [9], \"score\": 0, \
+                    \"layers\": 1, \"placements\": [{\"tile\": 9, \
+                    \"rotation\": 0, \"x\": 0, \"y\": 0, \"z\": 0}]}");
+    }
+
+    #[test]
+    fn histogram() {
+        let mut counts = BTreeMap::new();
+        counts.insert(0, 3);
+        counts.insert(2, 1);
+        let r = Rules { max_layers: Some(2), ..Rules::default() };
+        assert_eq!(super::histogram(&r, &Bag::from_usize(0).add(8), false,
+                                    &counts),
+                   "{\"version\": 1, \"rules\": {\"scoring\": \"Linear\", \
+                    \"adjacency\": \"Orthogonal\", \"max_layers\": 2, \
+                    \"copies\": 2, \"values\": [0, 1, 2, 3, 4, 5, 6, 7, 8, 9]}, \
+                    \"bag\": \"0010000000\", \"exact\": false, \
+                    \"scores\": [{\"score\": 0, \"count\": 3}, \
+                    {\"score\": 2, \"count\": 1}]}");
+    }
+
+    #[test]
+    fn writer() {
+        let buf = Shared::default();
+        let w = JsonWriter::new(Box::new(buf.clone()), &Rules::default())
+            .unwrap();
+        w.write(&Bag::from_usize(0), 0, Some(&State::new())).unwrap();
+        w.write(&Bag::from_usize(0).add(36), 0, None).unwrap();
+        w.finish().unwrap();
+
+        let text = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(text, "{\"version\": 1,\n \
+            \"rules\": {\"scoring\": \"Linear\", \"adjacency\": \"Orthogonal\", \
+            \"max_layers\": null, \"copies\": 2, \
+            \"values\": [0, 1, 2, 3, 4, 5, 6, 7, 8, 9]},\n \
+            \"bags\": [\n  \
+            {\"bag\": \"0000000000\", \"tiles\": [], \"score\": 0, \
+            \"layers\": 0, \"placements\": []},\n  \
+            {\"bag\": \"0000000001\", \"tiles\": [9], \"score\": 0, \
+            \"layers\": null, \"placements\": null}\n]}\n");
+    }
+}
@@ -0,0 +1,133 @@
+//! A solver for the tile-stacking game NMBR 9.
+//!
+//! Pieces and their rotations are in [`piece`], arrangements (and the
+//! rules for placing a piece onto one) are in [`State`], and scoring is
+//! in [`Rules`].  [`Solver`] finds the best score for every bag of
+//! tiles, recording them in [`Results`]; [`Worker`] solves a single bag.
+
+// The code base prefers explicit returns, explicit field initialization,
+// and index-based loops over the piece arrays.
+#![allow(clippy::needless_return)]
+#![allow(clippy::redundant_field_names)]
+#![allow(clippy::needless_range_loop)]
+#![allow(clippy::manual_range_contains)]
+#![allow(clippy::print_with_newline)]
+#![allow(clippy::new_without_default)]
+
+extern crate arrayvec;
+extern crate colored;
+extern crate rayon;
+extern crate libc;
+extern crate png;
+extern crate toml;
+extern crate serde_json;
+// pyo3's macros refer to ::core, which the 2015 edition needs declared
+#[cfg(feature = "python")]
+extern crate core;
+#[cfg(feature = "python")]
+extern crate pyo3;
+
+#[macro_use]
+extern crate lazy_static;
+
+// Prints a human-readable status message at the given level (see the
+// logging module).  These go to stdout, unless porcelain output has taken
+// it over, in which case they go to stderr.
+#[macro_export]
+macro_rules! log_at {
+    ($level:expr, $($arg:tt)*) => {
+        if $crate::logging::enabled($level) {
+            if $crate::porcelain::enabled() {
+                eprintln!($($arg)*);
+            } else {
+                println!($($arg)*);
+            }
+        }
+    }
+}
+
+// Progress, shown unless --log-level is below info
+#[macro_export]
+macro_rules! status {
+    ($($arg:tt)*) => { $crate::log_at!($crate::logging::Level::Info, $($arg)*) }
+}
+
+// Details which are only shown with --log-level debug
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::log_at!($crate::logging::Level::Debug, $($arg)*) }
+}
+
+// Problems which don't stop the program, which always go to stderr
+#[macro_export]
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        if $crate::logging::enabled($crate::logging::Level::Warn) {
+            eprintln!($($arg)*);
+        }
+    }
+}
+
+pub mod error;
+pub mod bag;
+pub mod state;
+pub mod piece;
+pub mod tables;
+pub mod results;
+pub mod worker;
+pub mod scoring;
+pub mod rules;
+pub mod tuning;
+pub mod trace;
+pub mod watchdog;
+pub mod numa;
+#[cfg(feature = "huge-pages")]
+pub mod hugepage;
+pub mod bitboard;
+pub mod image;
+pub mod theme;
+pub mod card;
+pub mod overlay;
+pub mod porcelain;
+pub mod logging;
+pub mod progress;
+pub mod profile;
+pub mod completions;
+pub mod config;
+pub mod estimate;
+pub mod optimum;
+pub mod journal;
+pub mod database;
+pub mod telemetry;
+pub mod json;
+pub mod html;
+pub mod mesh;
+pub mod rng;
+pub mod deck;
+pub mod expectimax;
+pub mod census;
+pub mod strategy;
+pub mod sim;
+pub mod mcts;
+pub mod target;
+pub mod hint;
+pub mod game;
+pub mod ffi;
+pub mod engine;
+pub mod distributed;
+pub mod http;
+pub mod future;
+pub mod footprint;
+pub mod dominance;
+pub mod suspend;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod solver;
+
+pub use bag::Bag;
+pub use piece::Piece;
+pub use results::Results;
+pub use rules::Rules;
+pub use solver::Solver;
+pub use state::State;
+pub use worker::Worker;
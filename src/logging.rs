@@ -0,0 +1,140 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bag::Bag;
+use porcelain;
+
+// Status messages have a level, and the ones above the current level (set
+// with --log-level) are dropped.  Separately, the events of each bag's
+// search (see event) can be written to a file as JSON lines:
+//
+//   {"event": "start", "bag": "0120000000", "score": 3, "unix_ms": ...}
+//
+// with "elapsed_ms" (the bag's search time) once it's finished.  Events are
+// start, improved, solved, timeout, limited, cancelled, and done, as in
+// porcelain.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+const LEVELS: [Level; 4] = [Level::Error, Level::Warn, Level::Info,
+                            Level::Debug];
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Level, String> {
+        LEVELS.iter().find(|l| l.to_string() == s).cloned()
+            .ok_or_else(|| format!("Unknown log level '{}'", s))
+    }
+}
+
+static LEVEL: AtomicUsize = AtomicUsize::new(Level::Info as usize);
+
+lazy_static! {
+    static ref EVENTS: Mutex<Option<BufWriter<File>>> = Mutex::new(None);
+}
+
+pub fn set_level(level: Level) {
+    LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+// Checks whether messages at the given level are printed
+pub fn enabled(level: Level) -> bool {
+    level as usize <= LEVEL.load(Ordering::Relaxed)
+}
+
+// Starts writing events to the given file, replacing its contents
+pub fn open_events(filename: &str) -> io::Result<()> {
+    let f = File::create(filename)?;
+    *EVENTS.lock().unwrap() = Some(BufWriter::new(f));
+    Ok(())
+}
+
+// Formats one event as a JSON object, without the trailing newline
+pub fn json_line(event: &str, bag: Option<&Bag>, score: Option<isize>,
+                 unix_ms: u128, elapsed: Option<Duration>) -> String {
+    let mut out = format!("{{\"event\": \"{}\"", event);
+    if let Some(b) = bag {
+        out += &format!(", \"bag\": \"{}\"", b.digits());
+    }
+    if let Some(s) = score {
+        out += &format!(", \"score\": {}", s);
+    }
+    out += &format!(", \"unix_ms\": {}", unix_ms);
+    if let Some(e) = elapsed {
+        out += &format!(", \"elapsed_ms\": {}", e.as_millis());
+    }
+    out += "}";
+    return out;
+}
+
+// Records an event of a search:  it's printed as porcelain (if enabled)
+// and written to the event file (if open).  Events which end a bag's
+// search should give how long it took.
+pub fn event(event: &str, bag: Option<&Bag>, score: Option<isize>,
+             elapsed: Option<Duration>) {
+    porcelain::emit(event, bag, score);
+
+    let mut events = EVENTS.lock().unwrap();
+    if let Some(ref mut out) = *events {
+        let unix_ms = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis()).unwrap_or(0);
+        // Flushed line by line, so that a long run can be followed
+        let written = writeln!(out, "{}",
+                               json_line(event, bag, score, unix_ms, elapsed))
+            .and_then(|_| out.flush());
+        if let Err(e) = written {
+            *events = None;
+            warning!("Failed to write events, so stopped writing them: {}", e);
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn level() {
+        for l in LEVELS.iter() {
+            assert_eq!(l.to_string().parse::<Level>(), Ok(*l));
+        }
+        assert!("loud".parse::<Level>().is_err());
+        assert!(Level::Error < Level::Debug);
+    }
+
+    #[test]
+    fn json_line() {
+        let bag = Bag::from_usize(0).add(4).add(8).add(8);
+        assert_eq!(super::json_line("solved", Some(&bag), Some(-3), 1000,
+                                    Some(Duration::from_millis(20))),
+                   "{\"event\": \"solved\", \"bag\": \"0120000000\", \
+                    \"score\": -3, \"unix_ms\": 1000, \"elapsed_ms\": 20}");
+        assert_eq!(super::json_line("done", None, None, 1000, None),
+                   "{\"event\": \"done\", \"unix_ms\": 1000}");
+    }
+}
@@ -1,54 +1,2756 @@
-extern crate arrayvec;
-extern crate colored;
+#[macro_use]
+extern crate nmbr9;
 extern crate rayon;
 
-#[macro_use]
-extern crate lazy_static;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, IsTerminal, Write};
+use std::net::TcpListener;
+use std::path::Path;
+use std::process::exit;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use std::sync::RwLock;
-use std::time::SystemTime;
-use rayon::prelude::*;
+use nmbr9::results::{self, Results};
+use nmbr9::bag::Bag;
+use nmbr9::state::{self, State};
+use nmbr9::worker::{CancelToken, Worker};
+use nmbr9::piece::{self, PieceSet, MAX_COPIES, UNIQUE_PIECE_COUNT};
+use nmbr9::rules::Rules;
+use nmbr9::tuning::{MoveOrder, PieceOrder, Tiebreak, Tuning};
+use nmbr9::trace::Trace;
+use nmbr9::watchdog::Watchdog;
+use nmbr9::progress::{Snapshot, Tracker};
+use nmbr9::tables::Tables;
+use nmbr9::numa;
+#[cfg(feature = "huge-pages")]
+use nmbr9::hugepage::HugePages;
+use nmbr9::card;
+use nmbr9::overlay::Overlay;
+use nmbr9::porcelain;
+use nmbr9::logging::{self, Level};
+use nmbr9::completions::{self, Flag, Arg};
+use nmbr9::config;
+use nmbr9::theme::{self, Theme};
+use nmbr9::estimate::{self, Sample};
+use nmbr9::solver::Solver;
+use nmbr9::suspend;
+use nmbr9::journal::Journal;
+use nmbr9::database::{self, Database};
+use nmbr9::telemetry::Telemetry;
+use nmbr9::html;
+use nmbr9::json::{self, JsonWriter};
+use nmbr9::mesh::{self, Dimensions};
+use nmbr9::deck;
+use nmbr9::game;
+use nmbr9::engine::Engine;
+use nmbr9::expectimax::Expectimax;
+use nmbr9::census::{self, Census};
+use nmbr9::sim;
+use nmbr9::profile::Profile;
+use nmbr9::strategy::{self, Greedy, Strategy};
+use nmbr9::mcts::Mcts;
+use nmbr9::target::TargetSearch;
+use nmbr9::distributed::{self, Coordinator};
+use nmbr9::http::Server;
+use nmbr9::footprint::Footprint;
 
-mod bag;
-mod state;
-mod piece;
-mod tables;
-mod results;
-mod worker;
+#[cfg(feature = "huge-pages")]
+#[global_allocator]
+static ALLOC: HugePages = HugePages;
 
-use results::Results;
-use bag::Bag;
-use worker::Worker;
-use piece::UNIQUE_PIECE_COUNT;
+#[cfg(feature = "huge-pages")]
+fn enable_huge_pages() {
+    HugePages::enable();
+}
 
-fn run(combos: &[usize], results: &RwLock<Results>) {
-    let _: Vec<bool> = combos.par_iter().map(
-        |i| {
-            let mut worker = Worker::new(*i, results);
-            worker.run();
-            true
-        }).collect();
+#[cfg(not(feature = "huge-pages"))]
+fn enable_huge_pages() {
+    usage("--huge-pages needs a build with the huge-pages feature");
 }
 
-fn main() {
-    let mut ordered : Vec<usize> = (0..3_usize.pow(UNIQUE_PIECE_COUNT as u32)).collect();
-    ordered.sort_by(|a, b| Bag::from_usize(*a).len().cmp(&Bag::from_usize(*b).len()));
+// Command-line options:  the game's rules, plus search settings
+// which override the per-bag defaults
+struct Options {
+    rules: Rules,
+
+    // If present, a custom set of tile shapes to play with.  Results are
+    // only meaningful under the set that they were solved with.
+    pieces: Option<PieceSet>,
+
+    // Colors for drawing tiles, in the terminal and in images
+    theme: Theme,
+
+    order: Option<PieceOrder>,
+    moves: Option<MoveOrder>,
+    shared_seen: bool,
+
+    // Skip states dominated by one already searched
+    dominance: bool,
+
+    // If present, the placement cache's memory budget in megabytes
+    placement_cache: Option<usize>,
+
+    // If present, the support table's memory budget in megabytes
+    support_table: Option<usize>,
+
+    // If present, the seen-set memory budget in megabytes, and whether
+    // to evict old states past it
+    seen_limit: Option<usize>,
+    seen_evict: bool,
+
+    // If present, where to write a trace of each bag's solve
+    trace: Option<String>,
+
+    // If present, how long a worker can go without improving its
+    // best score before its diagnostics are dumped
+    watchdog: Option<Duration>,
+
+    // If present, how often to refresh the progress line
+    progress: Option<Duration>,
+
+    // If present, the wall-clock budget for each bag, and how many times
+    // to retry bags which ran out of time (with four times the budget)
+    timeout: Option<Duration>,
+    retries: usize,
+
+    // If present, the most nodes that each bag's search may expand, and
+    // the most pieces that it may place
+    node_limit: Option<usize>,
+    depth_limit: Option<usize>,
+
+    // Number of randomized searches (each with the final budget) used to
+    // estimate the best score of bags which still ran out of time
+    random_runs: usize,
+
+    // How many of each bag's highest-scoring distinct arrangements to
+    // keep and show, rather than only the best
+    leaders: usize,
+
+    // After solving, collect every distinct arrangement which reaches the
+    // best score of the full bag (and of any bags picked with --bag)
+    all_optima: bool,
+
+    // After solving, report where the sweep's time went
+    profile: bool,
+
+    // If present, what to minimize among each bag's best arrangements when
+    // picking the one to report
+    tiebreak: Option<Tiebreak>,
+
+    // Largest number of bags which rayon may hand to a thread as one task.
+    // The cost of a bag varies wildly, so the default of 1 lets idle
+    // threads steal any bag which hasn't started yet.
+    chunk: usize,
+
+    // Solve bags one at a time in a fixed order, so that runs repeat
+    deterministic: bool,
+
+    // Pin threads to NUMA nodes, with a copy of the tables on each node
+    numa: bool,
+
+    // If present, where to write a score card image for the full bag
+    score_card: Option<String>,
+
+    // If present, where to keep images and stats of the latest best
+    overlay: Option<Arc<Overlay>>,
+
+    // If present, how many threads rayon should use
+    threads: Option<usize>,
+
+    // If present, a directory in which to record a journal of each bag's
+    // search, for replaying later
+    journal: Option<String>,
+
+    // If present, a file to which every finished search is appended, for
+    // querying with the results subcommand
+    database: Option<String>,
+
+    // If present, a CSV file to which each finished search's statistics
+    // are appended (see the telemetry module)
+    telemetry: Option<String>,
+
+    // If present, a directory in which to save the overlap tables, so that
+    // later runs can load them rather than building them again
+    table_cache: Option<String>,
+
+    // If present, a directory in which to draw the best arrangement of the
+    // full bag (and of any bags picked with --bag) at the end of a solve
+    layout_png: Option<String>,
+
+    // If present, a directory in which to export the best arrangements as
+    // STL and OpenSCAD models, like layout_png, at the given size
+    layout_3d: Option<String>,
+    dimensions: Dimensions,
+
+    // How to print layouts
+    view: View,
+
+    // If present, where to periodically save the results during a sweep,
+    // and whether to pick up from that file if it already exists
+    checkpoint: Option<String>,
+    checkpoint_interval: Duration,
+    resume: bool,
+
+    // If present, where to save running searches when interrupted (and
+    // whether to leave out their seen-sets), and a file of searches saved
+    // that way to continue
+    suspend_to: Option<String>,
+    suspend_prune: bool,
+    resume_from: Option<String>,
+
+    // If present, a file of scores solved (or published) elsewhere, which
+    // are loaded before the sweep and trusted
+    known: Option<String>,
+
+    // If present, only these bags (and the subsets that they need) are
+    // solved; otherwise, every bag with at most max_pieces tiles is
+    bags: Option<Vec<Bag>>,
+    max_pieces: Option<usize>,
+
+    // If present, where to write the results (or rendered image)
+    output: Option<String>,
+
+    command: Command,
+}
+
+// Ways of printing a layout in the terminal
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum View {
+    // Each layer on its own
+    Layers,
+    // An oblique 3D view of the stack
+    Oblique,
+    // One grid, shaded by the height of each cell
+    Elevation,
+}
+
+fn print_layout(opts: &Options, state: &State) {
+    match opts.view {
+        View::Layers => state.pretty_print(),
+        View::Oblique => state.pretty_print_3d(),
+        View::Elevation => state.pretty_print_elevation(),
+    }
+}
+
+// What to do, from the first argument
+enum Command {
+    // Solve the selected bags (the default)
+    Solve,
+
+    // Check a results file, re-solving any selected bags
+    Verify(String),
+
+    // Combine these results files into the one named by --output
+    Merge(Vec<String>),
+
+    // Solve one bag, and draw its best arrangement
+    Render,
+
+    // Time how long the selected bags take to solve
+    Bench,
+
+    // Estimate the cost of a sweep from this many sample bags of each size
+    Estimate(usize),
+
+    // Replay a journal or a game's moves, stopping at the given step, and
+    // animating a game with this long between moves
+    Replay(String, Option<u64>, Option<Duration>),
 
-    let results = RwLock::new(Results::new());
-    let start_time = SystemTime::now();
+    // Find the best score when tiles must be placed in the given order,
+    // using scores from a results file (if given) to bound the search
+    Draw(Vec<usize>, Option<String>),
 
-    let mut start = 0;
-    for num in 0..(2 * UNIQUE_PIECE_COUNT) {
-        let mut end = start;
-        while Bag::from_usize(ordered[end]).len() <= num
-        {
-            end += 1;
+    // Find the best score of one bag among arrangements with exactly this
+    // many layers, using scores from a results file (if given) to bound
+    // the search
+    Tower(Bag, usize, Option<String>),
+
+    // Find the expected score of a perfect player (or of the named
+    // strategy) over random shuffles of one bag, sampling this many draws
+    // per chance node (with a seed) or trying every draw
+    Expect(Bag, Option<(usize, u64)>, Option<String>),
+
+    // Count the distinct arrangements of every tile in one bag, optionally
+    // treating turned copies as the same
+    Count(Bag, bool),
+
+    // Tally the scores of one bag's arrangements, as in Count, or of this
+    // many random games (with a seed)
+    Histogram(Bag, bool, Option<(usize, u64)>),
+
+    // Summarize this many random games of one bag (with a seed), comparing
+    // them to the bag's best score from a results file (if given)
+    Baseline(Bag, usize, u64, Option<String>),
+
+    // Play this many deals of the full deck between the named strategies,
+    // shuffling with a seed
+    Sim(Vec<String>, usize, u64),
+
+    // Search one bag approximately, with this many Monte Carlo rollouts,
+    // using scores from a results file (if given) to cut them short
+    Mcts(Bag, usize, Option<String>),
+
+    // Rank one bag's tiles by how much its best score drops without each,
+    // reusing scores from a results file (if given)
+    Analyze(Bag, Option<String>),
+
+    // Search one bag best-first for an arrangement reaching this score,
+    // using scores from a results file (if given) to order the search
+    Target(Bag, isize, Option<String>),
+
+    // Solve for the best way to place one bag's tiles on top of the game
+    // in progress in this file, using scores from a results file (if
+    // given) to bound the search
+    Position(String, Bag, Option<String>),
+
+    // Answer placement queries over stdin and stdout (see the engine
+    // module), with the hint search or the named strategy
+    Engine(Option<String>),
+
+    // Hand out the selected bags to workers which connect to this address
+    Serve(String),
+
+    // Solve bags for the coordinator at this address
+    Work(String),
+
+    // Answer hint, verify and results requests over HTTP at this address
+    // (see the http module), with scores from a results file (if given)
+    Http(String, Option<String>),
+
+    // Look up a bag's records in the database, or its best-scoring bags
+    Results(Query),
+
+    // Draw a state in every view, given as a file or as text (see
+    // read_state), with its score and shape
+    Show(String),
+}
+
+// Queries for the results subcommand
+enum Query {
+    Show(Bag),
+    Top(usize),
+}
+
+// Every subcommand, with the positional argument that it takes
+const COMMANDS: &[Flag] = &[
+    Flag { name: "solve", arg: Arg::None,
+           help: "Solve the selected bags (the default)" },
+    Flag { name: "verify", arg: Arg::File,
+           help: "Check a results file, or replay a game's moves" },
+    Flag { name: "merge", arg: Arg::File,
+           help: "Combine results files from partial runs into one" },
+    Flag { name: "render", arg: Arg::None,
+           help: "Draw the best arrangement of one bag" },
+    Flag { name: "bench", arg: Arg::None,
+           help: "Time the solve of the selected bags" },
+    Flag { name: "estimate", arg: Arg::None,
+           help: "Predict the cost of a full sweep" },
+    Flag { name: "replay", arg: Arg::File,
+           help: "Replay a journal of a search, or a game move by move" },
+    Flag { name: "draw", arg: Arg::None,
+           help: "Find the best score for one draw order of the deck" },
+    Flag { name: "tower", arg: Arg::None,
+           help: "Find the best score of one bag with exactly N layers" },
+    Flag { name: "expect", arg: Arg::None,
+           help: "Find the expected score over random draw orders" },
+    Flag { name: "analyze", arg: Arg::None,
+           help: "Rank one bag's tiles by how much its best score needs them" },
+    Flag { name: "count", arg: Arg::None,
+           help: "Count the distinct arrangements of one bag" },
+    Flag { name: "histogram", arg: Arg::None,
+           help: "Count how many arrangements of one bag reach each score" },
+    Flag { name: "baseline", arg: Arg::None,
+           help: "Summarize the scores of random play on one bag" },
+    Flag { name: "sim", arg: Arg::None,
+           help: "Play strategies against each other over many deals" },
+    Flag { name: "mcts", arg: Arg::None,
+           help: "Search one bag approximately by Monte Carlo tree search" },
+    Flag { name: "engine", arg: Arg::None,
+           help: "Suggest placements for a GUI, over stdin and stdout" },
+    Flag { name: "serve", arg: Arg::Text,
+           help: "Hand out bags to workers on other machines" },
+    Flag { name: "work", arg: Arg::Text,
+           help: "Solve bags for a coordinator on another machine" },
+    Flag { name: "http", arg: Arg::Text,
+           help: "Answer hint, verify and results requests over HTTP" },
+    Flag { name: "show", arg: Arg::File,
+           help: "Draw a game, layout or results line in every view" },
+    Flag { name: "results", arg: Arg::Words("show top"),
+           help: "Show one bag's records in the database, or the top bags" },
+    Flag { name: "completions", arg: Arg::Words(completions::SHELLS),
+           help: "Print shell completions" },
+];
+
+// Handles `nmbr9 replay GAME`, which places a game's moves one at a time,
+// drawing the table after each.  With a delay, each table is redrawn in
+// place after waiting, as an animation of the stack being built.  Returns
+// false if the file isn't a game (journals are binary, so they won't parse
+// as one).
+fn replay_game(opts: &Options, filename: &str, stop_at: Option<u64>,
+               delay: Option<Duration>) -> bool {
+    let moves = match std::fs::read_to_string(filename).ok()
+        .and_then(|text| game::parse_text(&text).ok())
+    {
+        Some(ref moves) if moves.is_empty() => return false,
+        Some(moves) => moves,
+        None => return false,
+    };
+    let count = stop_at.map(|n| n as usize).unwrap_or(moves.len())
+        .min(moves.len());
+    let (states, err) = game::steps(&moves[..count], &opts.rules);
+    for (i, state) in states.iter().enumerate() {
+        if let Some(d) = delay.filter(|_| !porcelain::enabled()) {
+            if i > 0 {
+                thread::sleep(d);
+            }
+            // Clear the terminal and go back to its top-left corner
+            print!("\x1b[2J\x1b[H");
+        }
+        status!("Move {}: {}", i + 1, moves[i]);
+        if !porcelain::enabled() {
+            print_layout(opts, state);
         }
+        status!("Score: {}", opts.rules.score(state));
+    }
+    if let Some((i, e)) = err {
+        eprintln!("Move {} ({}) is illegal: {}", i + 1, moves[i], e);
+        exit(1);
+    }
+    true
+}
+
+// Handles `nmbr9 replay FILE`, which repeats a journaled search step by
+// step, panicking if it ever takes a different step
+fn run_replay(opts: &Options, filename: &str, stop_at: Option<u64>,
+              delay: Option<Duration>) {
+    if replay_game(opts, filename, stop_at, delay) {
+        return;
+    }
+    if delay.is_some() {
+        usage("--delay only applies to replaying a game");
+    }
+    let journal = File::open(filename)
+        .and_then(Journal::replay)
+        .unwrap_or_else(|e| usage(&format!("Could not read {}: {}", filename, e)));
+    let journal = match stop_at {
+        Some(n) => journal.with_stop(n),
+        None => journal,
+    };
+    let header = journal.header().clone();
+    status!("Replaying {:?} under {:?}", header.bag, header.rules);
 
-        println!("============================================================");
-        println!("BEGINNING {}-PIECE COMBINATIONS ({} to do)", num, end - start);
-        run(&ordered[start..end], &results);
-        println!("FINISHED {}-piece tests in {:?}", num, start_time.elapsed());
-        start = end;
+    let results = RwLock::new(Results::new(header.rules));
+    let mut worker = Worker::new(header.bag, &results)
+        .with_tuning(header.tuning);
+    if let Some(seed) = header.seed {
+        worker = worker.with_seed(seed);
+    }
+    worker = worker.with_journal(journal);
+    worker.run();
+
+    let (steps, finished) = worker.journal_steps().unwrap();
+    if finished {
+        status!("Replay matched all {} steps", steps);
+    } else if stop_at.is_some() {
+        status!("Replay matched the first {} steps", steps);
+    } else {
+        eprintln!("Replay ended after {} steps, before the journal did", steps);
+        exit(1);
+    }
+}
+
+fn write_score_card(filename: &str, bag: &Bag, score: isize, state: &State) {
+    let written = File::create(filename)
+        .and_then(|f| card::score_card(bag, score, state).write_png(f));
+    match written {
+        Ok(()) => status!("Wrote score card to {}", filename),
+        Err(e) => warning!("Failed to write score card to {}: {}", filename, e),
+    }
+}
+
+fn write_layout_pngs(dir: &Path, bag: &Bag, state: &State) {
+    let mut images = vec![(format!("{}.png", bag.digits()), state.to_image())];
+    for (z, img) in state.layer_images().into_iter().enumerate() {
+        images.push((format!("{}-layer{}.png", bag.digits(), z), img));
+    }
+    for (name, img) in images {
+        let path = dir.join(name);
+        let written = File::create(&path)
+            .and_then(|f| img.write_png(BufWriter::new(f)));
+        if let Err(e) = written {
+            warning!("Failed to write {}: {}", path.display(), e);
+        }
+    }
+    status!("Drew layout of {} in {}", bag.digits(), dir.display());
+}
+
+fn write_layout_models(dir: &Path, bag: &Bag, state: &State, dims: Dimensions) {
+    let models = [("stl", mesh::stl(state, dims)),
+                  ("scad", mesh::openscad(state, dims))];
+    for (ext, text) in models.iter() {
+        let path = dir.join(format!("{}.{}", bag.digits(), ext));
+        if let Err(e) = std::fs::write(&path, text) {
+            warning!("Failed to write {}: {}", path.display(), e);
+        }
+    }
+    status!("Exported models of {} to {}", bag.digits(), dir.display());
+}
+
+// Every flag that parse_options accepts, for shell completions
+const FLAGS: &[Flag] = &[
+    Flag { name: "--config", arg: Arg::File,
+           help: "Read flags from a TOML file" },
+    Flag { name: "--max-layers", arg: Arg::Text,
+           help: "Highest number of layers allowed" },
+    Flag { name: "--single-copy", arg: Arg::None,
+           help: "Play with one copy of each tile" },
+    Flag { name: "--copies", arg: Arg::Text,
+           help: "Number of copies of each tile" },
+    Flag { name: "--values", arg: Arg::Text,
+           help: "Comma-separated value for each tile" },
+    Flag { name: "--pieces", arg: Arg::File,
+           help: "Read the tile shapes from a TOML file" },
+    Flag { name: "--joker", arg: Arg::Text,
+           help: "Tile which scores as the highest value" },
+    Flag { name: "--mirrored", arg: Arg::None,
+           help: "Allow tiles to be flipped over" },
+    Flag { name: "--order", arg: Arg::Words("index area value"),
+           help: "Order in which pieces are tried" },
+    Flag { name: "--move-order", arg: Arg::Words("score bound"),
+           help: "Order in which placements are tried (by default, bound for bags of 5 or more tiles)" },
+    Flag { name: "--shared-seen", arg: Arg::None,
+           help: "Share one seen-set between a split bag's threads" },
+    Flag { name: "--dominance", arg: Arg::None,
+           help: "Skip states dominated by one already searched (using up to --seen-limit more memory)" },
+    Flag { name: "--placement-cache", arg: Arg::Text,
+           help: "Memory budget for cached placements of each footprint, in megabytes" },
+    Flag { name: "--support-table", arg: Arg::Text,
+           help: "Memory budget for the tiles which can rest on each top layer, used to bound each bag's last tile, in megabytes" },
+    Flag { name: "--seen-limit", arg: Arg::Text,
+           help: "Memory budget for each seen-set, in megabytes" },
+    Flag { name: "--seen-evict", arg: Arg::None,
+           help: "Evict old states past the budget, instead of using a Bloom filter (which leaves only lower bounds)" },
+    Flag { name: "--trace", arg: Arg::File,
+           help: "Write a Chrome trace of each bag's solve" },
+    Flag { name: "--watchdog", arg: Arg::Text,
+           help: "Report workers which make no progress for this many seconds" },
+    Flag { name: "--progress", arg: Arg::Text,
+           help: "Show each batch's progress and ETA every this many seconds" },
+    Flag { name: "--timeout", arg: Arg::Text,
+           help: "Time budget for each bag, in seconds" },
+    Flag { name: "--retries", arg: Arg::Text,
+           help: "Times to retry bags which ran out of time" },
+    Flag { name: "--node-limit", arg: Arg::Text,
+           help: "Most nodes to expand for each bag" },
+    Flag { name: "--depth-limit", arg: Arg::Text,
+           help: "Most pieces to place in each bag's search" },
+    Flag { name: "--random-runs", arg: Arg::Text,
+           help: "Estimate unsolved bags from this many randomized searches" },
+    Flag { name: "--up-to-rotation", arg: Arg::None,
+           help: "Count turned copies of an arrangement once" },
+    Flag { name: "--tiebreak", arg: Arg::Words("footprint layers"),
+           help: "Report the best layout with the smallest footprint or fewest layers" },
+    Flag { name: "--all-optima", arg: Arg::None,
+           help: "Find every distinct layout with the best score after solving" },
+    Flag { name: "--profile", arg: Arg::None,
+           help: "Print where a sweep's time went after solving" },
+    Flag { name: "--leaders", arg: Arg::Text,
+           help: "Keep this many of each bag's best distinct layouts" },
+    Flag { name: "--chunk", arg: Arg::Text,
+           help: "Number of bags each thread takes at a time" },
+    Flag { name: "--deterministic", arg: Arg::None,
+           help: "Solve bags one at a time in a fixed order, so that runs log the same lines" },
+    Flag { name: "--numa", arg: Arg::None,
+           help: "Pin threads and replicate tables per NUMA node" },
+    Flag { name: "--table-cache", arg: Arg::Dir,
+           help: "Save the overlap tables here, and load them on later runs" },
+    Flag { name: "--huge-pages", arg: Arg::None,
+           help: "Back large allocations with huge pages (needs the huge-pages feature)" },
+    Flag { name: "--score-card", arg: Arg::File,
+           help: "Write a PNG score card for the full bag" },
+    Flag { name: "--overlay", arg: Arg::Dir,
+           help: "Keep the latest best solution in this directory" },
+    Flag { name: "--porcelain", arg: Arg::None,
+           help: "Print machine-readable progress" },
+    Flag { name: "--log-level", arg: Arg::Words("error warn info debug"),
+           help: "Most detailed status messages to print" },
+    Flag { name: "--events", arg: Arg::File,
+           help: "Write each bag's search events to this file as JSON lines" },
+    Flag { name: "--threads", arg: Arg::Text,
+           help: "Number of worker threads" },
+    Flag { name: "--journal", arg: Arg::Dir,
+           help: "Record a replayable journal of each search here (each bag's search then runs on one thread)" },
+    Flag { name: "--database", arg: Arg::File,
+           help: "Append a record of each finished search to this file" },
+    Flag { name: "--telemetry", arg: Arg::File,
+           help: "Append each finished search's statistics to this CSV file" },
+    Flag { name: "--layout-png", arg: Arg::Dir,
+           help: "Draw the best layouts as PNG images in this directory" },
+    Flag { name: "--layout-3d", arg: Arg::Dir,
+           help: "Export the best layouts as STL and OpenSCAD models here" },
+    Flag { name: "--cell-size", arg: Arg::Text,
+           help: "Width of a grid cell in exported models, in millimeters" },
+    Flag { name: "--layer-height", arg: Arg::Text,
+           help: "Thickness of a tile in exported models, in millimeters" },
+    Flag { name: "--3d", arg: Arg::None,
+           help: "Print layouts as a 3D view of their stacks" },
+    Flag { name: "--elevation", arg: Arg::None,
+           help: "Print layouts as one grid, shaded by the height of each cell" },
+    Flag { name: "--no-color", arg: Arg::None,
+           help: "Print layouts as digits rather than colors (the default when not in a terminal)" },
+    Flag { name: "--theme", arg: Arg::Words("default colorblind high-contrast"),
+           help: "Colors for drawing tiles, in the terminal and in images" },
+    Flag { name: "--tile-colors", arg: Arg::Text,
+           help: "Colors for each tile (#rrggbb or a terminal color name), overriding the theme" },
+    Flag { name: "--checkpoint", arg: Arg::File,
+           help: "Periodically save results to this file during a sweep" },
+    Flag { name: "--checkpoint-interval", arg: Arg::Text,
+           help: "Seconds between checkpoints" },
+    Flag { name: "--resume", arg: Arg::None,
+           help: "Continue a sweep from its checkpoint" },
+    Flag { name: "--suspend-to", arg: Arg::File,
+           help: "Save running searches to this file when interrupted with Ctrl-C" },
+    Flag { name: "--suspend-prune", arg: Arg::None,
+           help: "Leave seen-sets out of suspended searches, for a smaller file" },
+    Flag { name: "--resume-from", arg: Arg::File,
+           help: "Continue the searches saved by --suspend-to" },
+    Flag { name: "--known", arg: Arg::File,
+           help: "Load trusted scores from this file before a sweep, skipping those bags" },
+    Flag { name: "--stop-at", arg: Arg::Text,
+           help: "Step at which to stop a replay" },
+    Flag { name: "--delay", arg: Arg::Text,
+           help: "Milliseconds between moves, to animate a game's replay" },
+    Flag { name: "--draw-order", arg: Arg::Text,
+           help: "Tiles in the order they're drawn, as one digit per card" },
+    Flag { name: "--draw-seed", arg: Arg::Text,
+           help: "Seed for shuffling the deck into a draw order, or for random games" },
+    Flag { name: "--results", arg: Arg::File,
+           help: "Results file whose scores bound a draw's search" },
+    Flag { name: "--strategy", arg: Arg::Text,
+           help: "Strategy for expect and engine: random[:SEED], greedy, hint:SECONDS or mcts:SECONDS" },
+    Flag { name: "--players", arg: Arg::Text,
+           help: "Comma-separated strategies for sim, e.g. greedy,hint:0.1" },
+    Flag { name: "--deals", arg: Arg::Text,
+           help: "Number of games for sim" },
+    Flag { name: "--iterations", arg: Arg::Text,
+           help: "Rollouts for the mcts subcommand" },
+    Flag { name: "--target-score", arg: Arg::Text,
+           help: "Stop at the first arrangement of one bag reaching this score" },
+    Flag { name: "--position", arg: Arg::File,
+           help: "Place one bag's tiles on top of the game in progress (moves or JSON) in this file" },
+    Flag { name: "--layers", arg: Arg::Text,
+           help: "Exact number of layers for the tower subcommand" },
+    Flag { name: "--samples", arg: Arg::Text,
+           help: "Bags of each size to sample when estimating, draws per turn for expect, or random games for histogram" },
+    Flag { name: "--bag", arg: Arg::Text,
+           help: "Comma-separated bags to solve, as one digit per tile, or one bag as a list of tiles (repeatable)" },
+    Flag { name: "--max-pieces", arg: Arg::Text,
+           help: "Only solve bags with at most this many tiles" },
+    Flag { name: "--output", arg: Arg::File,
+           help: "Where to write results (as JSON for .json, a viewer for .html), or a rendered image" },
+];
+
+// Handles `nmbr9 completions SHELL`, which prints a completion script
+fn print_completions(shell: Option<String>) -> ! {
+    let shell = shell.unwrap_or_default();
+    match completions::script(&shell, "nmbr9", COMMANDS, FLAGS) {
+        Some(s) => print!("{}", s),
+        None => usage(&format!("completions needs one of {}",
+                               completions::SHELLS)),
+    }
+    exit(0);
+}
+
+// The usage text, printed for --help and after argument errors
+const USAGE: &[&str] = &[
+    "Usage: nmbr9 [solve] [--bag DIGITS,...] [--max-pieces N] [--output FILE]",
+    "             [--max-layers N] [--single-copy | --copies N] [--joker TILE]",
+    "             [--values V0,V1,...,V9] [--pieces FILE] [--mirrored]",
+    "             [--order index|area|value]",
+    "             [--move-order score|bound] [--shared-seen] [--dominance]",
+    "             [--placement-cache MB] [--support-table MB]",
+    "             [--seen-limit MB] [--seen-evict]",
+    "             [--trace FILE] [--watchdog SECONDS] [--progress SECONDS]",
+    "             [--timeout SECONDS [--retries N] [--random-runs N]]",
+    "             [--chunk N] [--node-limit N] [--depth-limit N]",
+    "             [--leaders N] [--all-optima] [--tiebreak footprint|layers]",
+    "             [--profile]",
+    "             [--numa] [--huge-pages] [--table-cache DIR]",
+    "             [--score-card FILE]",
+    "             [--overlay DIR] [--porcelain] [--threads N]",
+    "             [--log-level error|warn|info|debug] [--events FILE]",
+    "             [--journal DIR] [--database FILE] [--telemetry FILE]",
+    "             [--layout-png DIR] [--3d] [--config FILE]",
+    "             [--theme default|colorblind|high-contrast] [--tile-colors C0,...,C9]",
+    "             [--layout-3d DIR [--cell-size MM] [--layer-height MM]]",
+    "             [--checkpoint FILE [--checkpoint-interval SECONDS] [--resume]]",
+    "             [--suspend-to FILE [--suspend-prune]] [--resume-from FILE]",
+    "             [--known FILE]",
+    "       nmbr9 [solve] --bag DIGITS --target-score S [--results FILE] [flags]",
+    "       nmbr9 [solve] --bag DIGITS --position FILE [--results FILE] [flags]",
+    "       nmbr9 verify FILE [--bag DIGITS,...] [flags]",
+    "       nmbr9 verify GAME [flags]",
+    "       nmbr9 merge FILE... --output FILE [flags]",
+    "       nmbr9 render --bag DIGITS --output FILE [flags]",
+    "       nmbr9 bench [--bag DIGITS,... | --max-pieces N] [flags]",
+    "       nmbr9 estimate [--samples N] [flags]",
+    "       nmbr9 replay FILE|GAME [--stop-at STEP] [--delay MS] [flags]",
+    "       nmbr9 draw [--draw-order DIGITS | --draw-seed N] [--results FILE] [flags]",
+    "       nmbr9 tower --bag DIGITS --layers N [--results FILE] [flags]",
+    "       nmbr9 expect [--bag DIGITS] [--samples N [--draw-seed N]] [--strategy NAME] [flags]",
+    "       nmbr9 analyze --bag DIGITS [--results FILE] [--output FILE] [flags]",
+    "       nmbr9 count --bag DIGITS [--up-to-rotation] [flags]",
+    "       nmbr9 histogram --bag DIGITS [--up-to-rotation | --samples N [--draw-seed N]]",
+    "       nmbr9 baseline [--bag DIGITS] [--samples N] [--draw-seed N] [--results FILE]",
+    "                       [--output FILE] [flags]",
+    "       nmbr9 sim [--players STRATEGY,...] [--deals N] [--draw-seed N] [flags]",
+    "       nmbr9 mcts [--bag DIGITS] [--iterations N] [--timeout SECONDS] [--results FILE] [flags]",
+    "       nmbr9 engine [--strategy NAME] [flags]",
+    "       nmbr9 serve ADDR [--bag DIGITS,... | --max-pieces N] [--output FILE] [flags]",
+    "       nmbr9 work ADDR [flags]",
+    "       nmbr9 http ADDR [--results FILE] [flags]",
+    "       nmbr9 show FILE|STATE [flags]",
+    "       nmbr9 results show DIGITS --database FILE [flags]",
+    "       nmbr9 results top [N] --database FILE [flags]",
+    "       nmbr9 completions bash|zsh|fish",
+    "       nmbr9 -h | --help",
+    "Any flag can also be set with an environment variable, such as",
+    "NMBR9_MAX_LAYERS=3 or NMBR9_SINGLE_COPY=1; the command line takes",
+    "precedence over these, and they take precedence over --config.",
+];
+
+fn usage(err: &str) -> ! {
+    eprintln!("Error: {}", err);
+    for line in USAGE {
+        eprintln!("{}", line);
+    }
+    exit(1);
+}
+
+// Handles -h and --help, which print the usage on stdout instead
+fn help() -> ! {
+    for line in USAGE {
+        println!("{}", line);
+    }
+    exit(0);
+}
+
+fn parse_options() -> Options {
+    let mut rules = Rules::default();
+    let mut pieces = None;
+    let mut theme = Theme::standard();
+    let mut tile_colors = None;
+    let mut order = None;
+    let mut moves = None;
+    let mut shared_seen = false;
+    let mut dominance = false;
+    let mut placement_cache = None;
+    let mut support_table = None;
+    let mut seen_limit = None;
+    let mut seen_evict = false;
+    let mut trace = None;
+    let mut watchdog = None;
+    let mut progress = None;
+    let mut node_limit = None;
+    let mut depth_limit = None;
+    let mut timeout = None;
+    let mut retries = 0;
+    let mut random_runs = 0;
+    let mut leaders = 0;
+    let mut all_optima = false;
+    let mut profile = false;
+    let mut tiebreak = None;
+    let mut up_to_rotation = false;
+    let mut chunk = 1;
+    let mut deterministic = false;
+    let mut numa = false;
+    let mut score_card = None;
+    let mut overlay = None;
+    let mut threads = None;
+    let mut samples = None;
+    let mut draw_order = None;
+    let mut draw_seed = None;
+    let mut results_file = None;
+    let mut iterations = None;
+    let mut target_score = None;
+    let mut position = None;
+    let mut layers = None;
+    let mut players = None;
+    let mut strategy = None;
+    let mut deals = None;
+    let mut journal = None;
+    let mut database = None;
+    let mut telemetry = None;
+    let mut table_cache = None;
+    let mut layout_png = None;
+    let mut view = View::Layers;
+
+    // Colors are only useful in a terminal, and can be turned off with the
+    // NO_COLOR convention
+    let mut color = std::io::stdout().is_terminal() &&
+        std::env::var_os("NO_COLOR").is_none();
+    let mut layout_3d = None;
+    let mut dimensions = Dimensions::default();
+    let mut checkpoint = None;
+    let mut checkpoint_interval = Duration::from_secs(300);
+    let mut resume = false;
+    let mut suspend_to = None;
+    let mut suspend_prune = false;
+    let mut resume_from = None;
+    let mut known = None;
+    let mut stop_at = None;
+    let mut delay = None;
+    let mut bags: Option<Vec<String>> = None;
+    let mut max_pieces = None;
+    let mut output = None;
+    let mut args = std::env::args().skip(1).peekable();
+    if std::env::args().skip(1).any(|a| a == "-h" || a == "--help") {
+        help();
+    }
+
+    let name = match args.peek() {
+        Some(a) if COMMANDS.iter().any(|c| c.name == a) => args.next().unwrap(),
+        _ => "solve".to_string(),
+    };
+    if name == "completions" {
+        print_completions(args.next());
+    }
+    let file = if name == "verify" || name == "replay" {
+        Some(args.next()
+            .unwrap_or_else(|| usage(&format!("{} needs a filename", name))))
+    } else if name == "show" {
+        Some(args.next()
+            .unwrap_or_else(|| usage("show needs a filename or a state")))
+    } else if name == "serve" || name == "work" || name == "http" {
+        Some(args.next()
+            .unwrap_or_else(|| usage(&format!("{} needs an address", name))))
+    } else {
+        None
+    };
+    let mut inputs = Vec::new();
+    if name == "merge" {
+        while args.peek().map(|a| !a.starts_with("--")).unwrap_or(false) {
+            inputs.push(args.next().unwrap());
+        }
+    }
+
+    // `results show` takes a bag, which is parsed with the others below,
+    // and `results top` takes an optional count
+    let mut query = None;
+    if name == "results" {
+        match args.next().as_deref() {
+            Some("show") => {
+                let bag = args.next()
+                    .unwrap_or_else(|| usage("results show needs a bag"));
+                query = Some((bag, 0));
+            }
+            Some("top") => {
+                let n = match args.peek() {
+                    Some(a) if !a.starts_with("--") => args.next()
+                        .and_then(|n| n.parse().ok())
+                        .filter(|&n| n > 0)
+                        .unwrap_or_else(|| usage("results top needs a positive integer")),
+                    _ => 10,
+                };
+                query = Some((String::new(), n));
+            }
+            _ => usage("results needs show or top"),
+        }
+    }
+
+    // Flags from a config file go first, then environment variables, then
+    // the command line, so that each can override the ones before it
+    let mut cli: Vec<String> = args.collect();
+    let mut filename = std::env::var(format!("{}CONFIG", config::ENV_PREFIX))
+        .ok();
+    if let Some(i) = cli.iter().position(|a| a == "--config") {
+        if i + 1 >= cli.len() {
+            usage("--config needs a filename");
+        }
+        filename = Some(cli.remove(i + 1));
+        cli.remove(i);
+    }
+    let mut all = Vec::new();
+    if let Some(filename) = filename {
+        let text = std::fs::read_to_string(&filename).unwrap_or_else(
+            |e| usage(&format!("Could not read {}: {}", filename, e)));
+        all = config::to_args(&text, FLAGS).unwrap_or_else(
+            |e| usage(&format!("Invalid config {}: {}", filename, e)));
+    }
+    all.extend(config::env_args(std::env::vars(), FLAGS)
+        .unwrap_or_else(|e| usage(&e)));
+    all.extend(cli);
+    let mut args = all.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--max-layers" => {
+                let n = args.next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|&n| n > 0)
+                    .unwrap_or_else(|| usage("--max-layers needs a positive integer"));
+                rules.max_layers = Some(n);
+            },
+            "--single-copy" => rules.copies = 1,
+            "--mirrored" => rules.mirrored = true,
+            "--copies" => {
+                rules.copies = args.next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|&n| n > 0 && n <= MAX_COPIES)
+                    .unwrap_or_else(|| usage(&format!(
+                        "--copies needs an integer from 1 to {}", MAX_COPIES)));
+            },
+            "--values" => {
+                let values: Vec<isize> = args.next()
+                    .and_then(|v| v.split(',').map(|n| n.parse().ok()).collect())
+                    .filter(|v: &Vec<isize>| v.len() == UNIQUE_PIECE_COUNT)
+                    .unwrap_or_else(|| usage("--values needs one integer per tile"));
+                rules.values.copy_from_slice(&values);
+            },
+            "--pieces" => {
+                let filename = args.next()
+                    .unwrap_or_else(|| usage("--pieces needs a filename"));
+                let text = std::fs::read_to_string(&filename).unwrap_or_else(
+                    |e| usage(&format!("Could not read {}: {}", filename, e)));
+                pieces = Some(PieceSet::parse(&text).unwrap_or_else(
+                    |e| usage(&format!("Invalid pieces in {}: {}", filename, e))));
+            },
+            "--joker" => {
+                let tile: isize = args.next()
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or_else(|| usage("--joker needs a tile value"));
+                let piece = rules.values.iter().position(|&v| v == tile)
+                    .unwrap_or_else(|| usage("--joker needs a tile value"));
+                rules = rules.with_joker(piece);
+            },
+            "--order" => {
+                order = match args.next().as_deref() {
+                    Some("index") => Some(PieceOrder::Index),
+                    Some("area") => Some(PieceOrder::LargestArea),
+                    Some("value") => Some(PieceOrder::HighestValue),
+                    _ => usage("--order needs one of index, area, or value"),
+                };
+            },
+            "--move-order" => {
+                moves = match args.next().as_deref() {
+                    Some("score") => Some(MoveOrder::Score),
+                    Some("bound") => Some(MoveOrder::Bound),
+                    _ => usage("--move-order needs one of score or bound"),
+                };
+            },
+            "--trace" => {
+                trace = Some(args.next()
+                    .unwrap_or_else(|| usage("--trace needs a filename")));
+            },
+            "--watchdog" => {
+                let secs = args.next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|&n| n > 0)
+                    .unwrap_or_else(|| usage("--watchdog needs a positive number of seconds"));
+                watchdog = Some(Duration::from_secs(secs));
+            },
+            "--progress" => {
+                let secs = args.next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|&n| n > 0)
+                    .unwrap_or_else(|| usage("--progress needs a positive number of seconds"));
+                progress = Some(Duration::from_secs(secs));
+            },
+            "--timeout" => {
+                let secs = args.next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|&n| n > 0)
+                    .unwrap_or_else(|| usage("--timeout needs a positive number of seconds"));
+                timeout = Some(Duration::from_secs(secs));
+            },
+            "--node-limit" => {
+                node_limit = Some(args.next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|&n| n > 0)
+                    .unwrap_or_else(|| usage("--node-limit needs a positive integer")));
+            },
+            "--up-to-rotation" => up_to_rotation = true,
+            "--all-optima" => all_optima = true,
+            "--profile" => profile = true,
+            "--tiebreak" => {
+                tiebreak = match args.next().as_deref() {
+                    Some("footprint") => Some(Tiebreak::Footprint),
+                    Some("layers") => Some(Tiebreak::Layers),
+                    _ => usage("--tiebreak needs one of footprint or layers"),
+                };
+            },
+            "--leaders" => {
+                leaders = args.next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|&n: &usize| n > 0)
+                    .unwrap_or_else(|| usage("--leaders needs a positive integer"));
+            },
+            "--depth-limit" => {
+                depth_limit = Some(args.next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|&n| n > 0)
+                    .unwrap_or_else(|| usage("--depth-limit needs a positive integer")));
+            },
+            "--retries" => {
+                retries = args.next()
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or_else(|| usage("--retries needs an integer"));
+            },
+            "--chunk" => {
+                chunk = args.next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|&n| n > 0)
+                    .unwrap_or_else(|| usage("--chunk needs a positive integer"));
+            },
+            "--deterministic" => deterministic = true,
+            "--numa" => numa = true,
+            "--shared-seen" => shared_seen = true,
+            "--dominance" => dominance = true,
+            "--placement-cache" => {
+                placement_cache = Some(args.next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|&n| n > 0)
+                    .unwrap_or_else(|| usage("--placement-cache needs a positive integer")));
+            },
+            "--support-table" => {
+                support_table = Some(args.next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|&n| n > 0)
+                    .unwrap_or_else(|| usage("--support-table needs a positive integer")));
+            },
+            "--seen-limit" => {
+                seen_limit = Some(args.next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|&n| n > 0)
+                    .unwrap_or_else(|| usage("--seen-limit needs a positive integer")));
+            },
+            "--seen-evict" => seen_evict = true,
+            "--huge-pages" => enable_huge_pages(),
+            "--porcelain" => porcelain::enable(),
+            "--log-level" => {
+                let level: Level = args.next()
+                    .ok_or("--log-level needs a level".to_string())
+                    .and_then(|s| s.parse())
+                    .unwrap_or_else(|e| usage(&e));
+                logging::set_level(level);
+            },
+            "--events" => {
+                let f = args.next()
+                    .unwrap_or_else(|| usage("--events needs a filename"));
+                if let Err(e) = logging::open_events(&f) {
+                    usage(&format!("Could not write {}: {}", f, e));
+                }
+            },
+            "--threads" => {
+                threads = Some(args.next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|&n| n > 0)
+                    .unwrap_or_else(|| usage("--threads needs a positive integer")));
+            },
+            "--random-runs" => {
+                random_runs = args.next()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|&n: &usize| n >= 2)
+                    .unwrap_or_else(|| usage("--random-runs needs an integer of at least 2"));
+            }
+            "--journal" => {
+                let dir = args.next()
+                    .unwrap_or_else(|| usage("--journal needs a directory"));
+                if let Err(e) = std::fs::create_dir_all(&dir) {
+                    usage(&format!("Could not use {} for --journal: {}", dir, e));
+                }
+                journal = Some(dir);
+            }
+            "--database" => {
+                database = Some(args.next()
+                    .unwrap_or_else(|| usage("--database needs a filename")));
+            }
+            "--telemetry" => {
+                telemetry = Some(args.next()
+                    .unwrap_or_else(|| usage("--telemetry needs a filename")));
+            }
+            "--table-cache" => {
+                let dir = args.next()
+                    .unwrap_or_else(|| usage("--table-cache needs a directory"));
+                if let Err(e) = std::fs::create_dir_all(&dir) {
+                    usage(&format!("Could not use {} for --table-cache: {}", dir, e));
+                }
+                table_cache = Some(dir);
+            }
+            "--layout-png" => {
+                let dir = args.next()
+                    .unwrap_or_else(|| usage("--layout-png needs a directory"));
+                if let Err(e) = std::fs::create_dir_all(&dir) {
+                    usage(&format!("Could not use {} for --layout-png: {}", dir, e));
+                }
+                layout_png = Some(dir);
+            }
+            "--3d" | "--elevation" => {
+                if view != View::Layers {
+                    usage("--3d and --elevation can't be combined");
+                }
+                view = if arg == "--3d" { View::Oblique } else { View::Elevation };
+            }
+            "--no-color" => color = false,
+            "--theme" => {
+                let name = args.next()
+                    .unwrap_or_else(|| usage("--theme needs a name"));
+                theme = Theme::named(&name).unwrap_or_else(
+                    || usage(&format!("Invalid theme '{}' (expected {})",
+                                      name, theme::NAMES.join(", "))));
+            }
+            "--tile-colors" => {
+                tile_colors = Some(args.next()
+                    .unwrap_or_else(|| usage("--tile-colors needs a list of colors")));
+            }
+            "--layout-3d" => {
+                let dir = args.next()
+                    .unwrap_or_else(|| usage("--layout-3d needs a directory"));
+                if let Err(e) = std::fs::create_dir_all(&dir) {
+                    usage(&format!("Could not use {} for --layout-3d: {}", dir, e));
+                }
+                layout_3d = Some(dir);
+            }
+            "--cell-size" => {
+                dimensions.cell = args.next()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|&n: &f64| n > 0.0)
+                    .unwrap_or_else(|| usage("--cell-size needs a positive number"));
+            }
+            "--layer-height" => {
+                dimensions.layer = args.next()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|&n: &f64| n > 0.0)
+                    .unwrap_or_else(|| usage("--layer-height needs a positive number"));
+            }
+            "--checkpoint" => {
+                checkpoint = Some(args.next()
+                    .unwrap_or_else(|| usage("--checkpoint needs a filename")));
+            }
+            "--checkpoint-interval" => {
+                let secs = args.next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|&n| n > 0)
+                    .unwrap_or_else(|| usage("--checkpoint-interval needs a positive number of seconds"));
+                checkpoint_interval = Duration::from_secs(secs);
+            }
+            "--resume" => resume = true,
+            "--suspend-to" => {
+                suspend_to = Some(args.next()
+                    .unwrap_or_else(|| usage("--suspend-to needs a filename")));
+            },
+            "--suspend-prune" => suspend_prune = true,
+            "--resume-from" => {
+                resume_from = Some(args.next()
+                    .unwrap_or_else(|| usage("--resume-from needs a filename")));
+            },
+            "--known" => {
+                known = Some(args.next()
+                    .unwrap_or_else(|| usage("--known needs a filename")));
+            }
+            "--stop-at" => {
+                stop_at = Some(args.next()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|&n: &u64| n > 0)
+                    .unwrap_or_else(|| usage("--stop-at needs a positive step number")));
+            }
+            "--delay" => {
+                delay = Some(args.next()
+                    .and_then(|s| s.parse().ok())
+                    .map(Duration::from_millis)
+                    .unwrap_or_else(|| usage("--delay needs a number of milliseconds")));
+            }
+            "--bag" => {
+                bags.get_or_insert_with(Vec::new).push(args.next()
+                    .unwrap_or_else(|| usage("--bag needs a list of bags")));
+            }
+            "--max-pieces" => {
+                max_pieces = Some(args.next()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or_else(|| usage("--max-pieces needs an integer")));
+            }
+            "--output" => {
+                output = Some(args.next()
+                    .unwrap_or_else(|| usage("--output needs a filename")));
+            }
+            "--draw-order" => {
+                draw_order = Some(args.next()
+                    .unwrap_or_else(|| usage("--draw-order needs a list of tiles")));
+            }
+            "--draw-seed" => {
+                draw_seed = Some(args.next()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or_else(|| usage("--draw-seed needs an integer")));
+            }
+            "--results" => {
+                results_file = Some(args.next()
+                    .unwrap_or_else(|| usage("--results needs a filename")));
+            }
+            "--strategy" => {
+                strategy = Some(args.next()
+                    .unwrap_or_else(|| usage("--strategy needs a name")));
+            }
+            "--players" => {
+                players = Some(args.next()
+                    .unwrap_or_else(|| usage("--players needs a list of strategies")));
+            }
+            "--deals" => {
+                deals = Some(args.next()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|&n: &usize| n > 0)
+                    .unwrap_or_else(|| usage("--deals needs a positive integer")));
+            }
+            "--iterations" => {
+                iterations = Some(args.next()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|&n: &usize| n > 0)
+                    .unwrap_or_else(|| usage("--iterations needs a positive integer")));
+            }
+            "--target-score" => {
+                target_score = Some(args.next()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or_else(|| usage("--target-score needs an integer")));
+            }
+            "--position" => {
+                position = Some(args.next()
+                    .unwrap_or_else(|| usage("--position needs a filename")));
+            }
+            "--layers" => {
+                layers = Some(args.next()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|&n: &usize| n > 0)
+                    .unwrap_or_else(|| usage("--layers needs a positive integer")));
+            }
+            "--samples" => {
+                samples = Some(args.next()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|&n: &usize| n > 0)
+                    .unwrap_or_else(|| usage("--samples needs a positive integer")));
+            }
+            "--config" => usage("--config can only be given once"),
+            "--score-card" => {
+                score_card = Some(args.next()
+                    .unwrap_or_else(|| usage("--score-card needs a filename")));
+            },
+            "--overlay" => {
+                let dir = args.next()
+                    .unwrap_or_else(|| usage("--overlay needs a directory"));
+                match Overlay::new(Path::new(&dir)) {
+                    Ok(o) => overlay = Some(Arc::new(o)),
+                    Err(e) => usage(&format!("Could not use {} for --overlay: {}",
+                                             dir, e)),
+                }
+            },
+            _ => usage(&format!("Unknown argument '{}'", arg)),
+        }
+    }
+    if retries > 0 && timeout.is_none() {
+        usage("--retries needs --timeout");
+    }
+    if random_runs > 0 && timeout.is_none() {
+        usage("--random-runs needs --timeout");
+    }
+    if deterministic && timeout.is_some() {
+        usage("--deterministic can't be combined with --timeout (try --node-limit)");
+    }
+    if numa && threads.is_some() {
+        usage("--threads can't be combined with --numa");
+    }
+    if samples.is_some() && name != "estimate" && name != "expect" &&
+        name != "histogram" && name != "baseline"
+    {
+        usage("--samples only applies to the estimate, expect, histogram, and baseline subcommands");
+    }
+    if draw_order.is_some() && name != "draw" {
+        usage("--draw-order only applies to the draw subcommand");
+    }
+    if results_file.is_some() && name != "draw" && name != "tower" &&
+        name != "mcts" && name != "baseline" && name != "http" &&
+        name != "analyze" && target_score.is_none() && position.is_none()
+    {
+        usage("--results only applies to the draw, tower, mcts, baseline, analyze, and http subcommands, and to --target-score and --position");
+    }
+    if target_score.is_some() && name != "solve" {
+        usage("--target-score only applies to the solve subcommand");
+    }
+    if position.is_some() && name != "solve" {
+        usage("--position only applies to the solve subcommand");
+    }
+    if all_optima && name != "solve" {
+        usage("--all-optima only applies to the solve subcommand");
+    }
+    if profile && name != "solve" {
+        usage("--profile only applies to the solve subcommand");
+    }
+    if up_to_rotation && name != "count" && name != "histogram" {
+        usage("--up-to-rotation only applies to the count and histogram subcommands");
+    }
+    if up_to_rotation && samples.is_some() {
+        usage("--up-to-rotation can't be combined with --samples");
+    }
+    if strategy.is_some() && name != "expect" && name != "engine" {
+        usage("--strategy only applies to the expect and engine subcommands");
+    }
+    if let Some(Err(e)) = strategy.as_ref().map(|s| strategy::parse(s, rules)) {
+        usage(&e);
+    }
+    if (players.is_some() || deals.is_some()) && name != "sim" {
+        usage("--players and --deals only apply to the sim subcommand");
+    }
+    if iterations.is_some() && name != "mcts" {
+        usage("--iterations only applies to the mcts subcommand");
+    }
+    if layers.is_some() && name != "tower" {
+        usage("--layers only applies to the tower subcommand");
+    }
+    if draw_seed.is_some() && name != "draw" && name != "expect" &&
+        name != "histogram" && name != "sim" && name != "baseline"
+    {
+        usage("--draw-seed only applies to the draw, expect, histogram, sim, and baseline subcommands");
+    }
+    if draw_seed.is_some() && name != "draw" && name != "sim" && name != "baseline" &&
+        samples.is_none()
+    {
+        usage(&format!("--draw-seed needs --samples for the {} subcommand", name));
+    }
+    if dimensions != Dimensions::default() && layout_3d.is_none() {
+        usage("--cell-size and --layer-height need --layout-3d");
+    }
+    if resume && checkpoint.is_none() {
+        usage("--resume needs --checkpoint");
+    }
+    if known.is_some() && name != "solve" {
+        usage("--known only applies to the solve subcommand");
+    }
+    if checkpoint.is_some() && name != "solve" {
+        usage("--checkpoint only applies to the solve subcommand");
+    }
+    if suspend_prune && suspend_to.is_none() {
+        usage("--suspend-prune needs --suspend-to");
+    }
+    if (suspend_to.is_some() || resume_from.is_some()) && name != "solve" {
+        usage("--suspend-to and --resume-from only apply to the solve subcommand");
+    }
+    state::set_color(color);
+    // Tile colors are applied after the theme, whichever came first
+    if let Some(ref c) = tile_colors {
+        theme = theme.with_colors(c).unwrap_or_else(
+            |e| usage(&format!("Invalid --tile-colors: {}", e)));
+    }
+    if stop_at.is_some() && name != "replay" {
+        usage("--stop-at only applies to the replay subcommand");
+    }
+    if delay.is_some() && name != "replay" {
+        usage("--delay only applies to the replay subcommand");
+    }
+
+    if !results::scores_fit(&rules) {
+        usage("Scores under these rules could be too large to store");
+    }
+
+    // Bags are parsed last, since they depend on the number of copies.
+    // Each --bag is either a comma-separated list of bags in digits, or
+    // one bag written as a list of its tiles (e.g. 9,9,8,7,3).
+    let bags = bags.map(|lists| lists.iter().flat_map(|list| {
+        let parts: Vec<&str> = list.split(',').collect();
+        if parts.iter().all(|p| p.len() == UNIQUE_PIECE_COUNT) {
+            parts
+        } else {
+            vec![list.as_str()]
+        }
+    }).map(|b| Bag::parse(b, rules.copies).unwrap_or_else(|e| usage(&e)))
+        .collect::<Vec<Bag>>());
+    if bags.is_some() && max_pieces.is_some() {
+        usage("--bag can't be combined with --max-pieces");
+    }
+
+    let command = match name.as_str() {
+        "verify" => Command::Verify(file.unwrap()),
+        "merge" => {
+            if inputs.is_empty() || output.is_none() {
+                usage("merge needs at least one file and an --output");
+            }
+            Command::Merge(inputs)
+        }
+        "render" => {
+            if bags.as_ref().map(|b| b.len()) != Some(1) || output.is_none() {
+                usage("render needs one --bag and an --output");
+            }
+            Command::Render
+        }
+        "bench" => Command::Bench,
+        "engine" => Command::Engine(strategy),
+        "serve" => Command::Serve(file.unwrap()),
+        "work" => Command::Work(file.unwrap()),
+        "http" => Command::Http(file.unwrap(), results_file),
+        "show" => Command::Show(file.unwrap()),
+        "results" => {
+            if database.is_none() {
+                usage("results needs a --database");
+            }
+            match query.unwrap() {
+                (_, n) if n > 0 => Command::Results(Query::Top(n)),
+                (bag, _) => Command::Results(Query::Show(
+                    Bag::parse(&bag, rules.copies).unwrap_or_else(|e| usage(&e)))),
+            }
+        }
+        "estimate" => Command::Estimate(samples.unwrap_or(3)),
+        "replay" => Command::Replay(file.unwrap(), stop_at, delay),
+        "draw" => {
+            let order = match (draw_order, draw_seed) {
+                (Some(_), Some(_)) =>
+                    usage("--draw-order can't be combined with --draw-seed"),
+                (Some(s), None) => deck::parse(&s, rules.copies)
+                    .unwrap_or_else(|| usage(&format!("Invalid draw order '{}'", s))),
+                (None, seed) => {
+                    let count = (rules.copies + 1).pow(UNIQUE_PIECE_COUNT as u32);
+                    let full = Bag::from_index(count - 1, rules.copies);
+                    deck::shuffle(&full, seed.unwrap_or(0))
+                },
+            };
+            Command::Draw(order, results_file)
+        }
+        "tower" => match (&bags, layers) {
+            (Some(b), Some(n)) if b.len() == 1 =>
+                Command::Tower(b[0].clone(), n, results_file),
+            _ => usage("tower needs one --bag and --layers"),
+        },
+        "expect" => {
+            let bag = match bags {
+                Some(ref b) if b.len() == 1 => b[0].clone(),
+                Some(_) => usage("expect takes a single --bag"),
+                None => {
+                    let count = (rules.copies + 1).pow(UNIQUE_PIECE_COUNT as u32);
+                    Bag::from_index(count - 1, rules.copies)
+                },
+            };
+            Command::Expect(bag, samples.map(|n| (n, draw_seed.unwrap_or(0))),
+                            strategy)
+        }
+        "analyze" => match bags {
+            Some(ref b) if b.len() == 1 => Command::Analyze(b[0].clone(), results_file),
+            _ => usage("analyze needs one --bag"),
+        },
+        "count" => match bags {
+            Some(ref b) if b.len() == 1 => Command::Count(b[0].clone(), up_to_rotation),
+            _ => usage("count needs one --bag"),
+        },
+        "sim" => {
+            let players: Vec<String> = players
+                .unwrap_or_else(|| "greedy,hint:0.1".to_string())
+                .split(',').map(|s| s.to_string()).collect();
+            if players.len() < 2 {
+                usage("sim needs at least two --players");
+            }
+            for p in &players {
+                if let Err(e) = strategy::parse(p, rules) {
+                    usage(&e);
+                }
+            }
+            Command::Sim(players, deals.unwrap_or(100), draw_seed.unwrap_or(0))
+        },
+        "baseline" => {
+            let bag = match bags {
+                Some(ref b) if b.len() == 1 => b[0].clone(),
+                Some(_) => usage("baseline takes a single --bag"),
+                None => {
+                    let count = (rules.copies + 1).pow(UNIQUE_PIECE_COUNT as u32);
+                    Bag::from_index(count - 1, rules.copies)
+                },
+            };
+            Command::Baseline(bag, samples.unwrap_or(1000), draw_seed.unwrap_or(0),
+                              results_file)
+        },
+        "histogram" => match bags {
+            Some(ref b) if b.len() == 1 => Command::Histogram(
+                b[0].clone(), up_to_rotation,
+                samples.map(|n| (n, draw_seed.unwrap_or(0)))),
+            _ => usage("histogram needs one --bag"),
+        },
+        "mcts" => {
+            let bag = match bags {
+                Some(ref b) if b.len() == 1 => b[0].clone(),
+                Some(_) => usage("mcts takes a single --bag"),
+                None => {
+                    let count = (rules.copies + 1).pow(UNIQUE_PIECE_COUNT as u32);
+                    Bag::from_index(count - 1, rules.copies)
+                },
+            };
+            Command::Mcts(bag, iterations.unwrap_or(10_000), results_file)
+        }
+        _ => match (target_score, position, &bags) {
+            (Some(_), Some(_), _) =>
+                usage("--target-score can't be combined with --position"),
+            (Some(s), None, Some(b)) if b.len() == 1 =>
+                Command::Target(b[0].clone(), s, results_file),
+            (Some(_), None, _) => usage("--target-score needs one --bag"),
+            (None, Some(f), Some(b)) if b.len() == 1 =>
+                Command::Position(f, b[0].clone(), results_file),
+            (None, Some(_), _) =>
+                usage("--position needs one --bag of the tiles left to place"),
+            (None, None, _) => Command::Solve,
+        },
+    };
+    Options { rules, pieces, theme, order, moves, shared_seen, dominance, placement_cache, support_table, seen_limit, seen_evict,
+              trace, watchdog, progress, timeout, retries,
+              node_limit, depth_limit, random_runs, leaders, all_optima, profile, tiebreak,
+              chunk, deterministic, numa, score_card, overlay, threads, journal, database, telemetry, table_cache, layout_png,
+              layout_3d, dimensions, view, checkpoint, checkpoint_interval, resume, suspend_to, suspend_prune, resume_from, known, bags, max_pieces, output, command }
+}
+
+// Spreads rayon's threads across NUMA nodes, pinning each thread to
+// one node's CPUs, and gives each node its own copy of the tables
+fn setup_numa() {
+    let nodes = numa::nodes();
+    if nodes.len() < 2 {
+        status!("Only found {} NUMA node(s); ignoring --numa", nodes.len());
+        return;
+    }
+    let threads: usize = nodes.iter().map(|n| n.len()).sum();
+    status!("Spreading {} threads across {} NUMA nodes",
+             threads, nodes.len());
+
+    Tables::replicate(nodes.len());
+    numa::pool(nodes).build_global().expect("Failed to build thread pool");
+}
+
+// Handles `nmbr9 draw`, which finds the best score when tiles are placed
+// in the order that they're drawn from the deck, as in the real game
+fn run_draw(opts: &Options, order: &[usize], filename: &Option<String>) {
+    let results = RwLock::new(read_results(opts, filename));
+
+    let bag = deck::bag(order, opts.rules.copies).unwrap();
+    let digits: String = order.iter().map(|t| t.to_string()).collect();
+    status!("Drawing tiles in the order {}", digits);
+    let mut worker = Worker::new(bag.clone(), &results)
+        .with_draw_order(order.to_vec());
+    if let Some(order) = opts.order {
+        worker = worker.with_order(order);
+    }
+    if let Some(timeout) = opts.timeout {
+        worker = worker.with_deadline(Instant::now() + timeout);
+    }
+    if let Some(nodes) = opts.node_limit {
+        worker = worker.with_node_limit(nodes);
+    }
+    if let Some(depth) = opts.depth_limit {
+        worker = worker.with_depth_limit(depth);
+    }
+    let (score, state) = match worker.run() {
+        Some(r) => r,
+        None => return,
+    };
+
+    status!("============================================================");
+    let qualifier = if worker.timed_out() || worker.limited() {
+        "at least "
+    } else {
+        ""
+    };
+    status!("BEST SCORE IN DRAW ORDER: {}{}", qualifier, score);
+    if let Some(free) = results.read().unwrap().score(&bag) {
+        status!("(in any order, the best score is {})", free);
+    }
+    if !porcelain::enabled() {
+        print_layout(opts, &state);
+    }
+}
+
+// Handles `nmbr9 tower`, which finds the best arrangement of one bag with
+// exactly the given number of layers
+fn run_tower(opts: &Options, bag: &Bag, layers: usize,
+             filename: &Option<String>) {
+    let results = RwLock::new(read_results(opts, filename));
+
+    status!("Building {} up to exactly {} layers", bag.digits(), layers);
+    let mut worker = Worker::new(bag.clone(), &results)
+        .with_exact_layers(layers);
+    if let Some(order) = opts.order {
+        worker = worker.with_order(order);
+    }
+    if let Some(timeout) = opts.timeout {
+        worker = worker.with_deadline(Instant::now() + timeout);
+    }
+    if let Some(nodes) = opts.node_limit {
+        worker = worker.with_node_limit(nodes);
+    }
+    if let Some(depth) = opts.depth_limit {
+        worker = worker.with_depth_limit(depth);
+    }
+    let result = worker.run();
+
+    status!("============================================================");
+    let (score, state) = match result {
+        Some(r) => r,
+        None if worker.timed_out() || worker.limited() => {
+            status!("NO ARRANGEMENT WITH {} LAYERS FOUND BEFORE THE LIMIT", layers);
+            return;
+        },
+        None => {
+            status!("NO ARRANGEMENT HAS {} LAYERS", layers);
+            return;
+        },
+    };
+    let qualifier = if worker.timed_out() || worker.limited() {
+        "at least "
+    } else {
+        ""
+    };
+    status!("BEST SCORE WITH {} LAYERS: {}{}", layers, qualifier, score);
+    if let Some(free) = results.read().unwrap().score(bag) {
+        status!("(with any number of layers, the best score is {})", free);
+    }
+    if !porcelain::enabled() {
+        print_layout(opts, &state);
+    }
+}
+
+// Handles `nmbr9 expect`, which finds how well a perfect player (or a
+// strategy) can expect to score when the bag's tiles are drawn in a random
+// order
+fn run_expect(opts: &Options, bag: &Bag, samples: Option<(usize, u64)>,
+              strategy: Option<&str>) {
+    let mut e = Expectimax::new(opts.rules);
+    if let Some((n, seed)) = samples {
+        e = e.with_samples(n, seed);
+    }
+    let start = Instant::now();
+    let qualifier = if samples.is_some() { "about " } else { "" };
+    if let Some(name) = strategy {
+        let mut s = strategy::parse(name, opts.rules).unwrap();
+        status!("Finding the expected score of {} with {}", bag.digits(), name);
+        let v = e.evaluate(&mut *s, bag);
+
+        status!("============================================================");
+        status!("EXPECTED SCORE: {}{:.3}", qualifier, v);
+        status!("({:.1}s)", start.elapsed().as_secs_f64());
+        return;
+    }
+    status!("Finding the expected score of {}", bag.digits());
+    let v = e.expected(bag);
+
+    status!("============================================================");
+    status!("EXPECTED SCORE: {}{:.3}", qualifier, v);
+    status!("({} positions evaluated in {:.1}s)", e.memo_len(),
+            start.elapsed().as_secs_f64());
+}
+
+// Handles `nmbr9 count`, which counts the distinct arrangements of one bag
+fn run_count(opts: &Options, bag: &Bag, up_to_rotation: bool) {
+    let mut census = Census::new(opts.rules);
+    if up_to_rotation {
+        census = census.up_to_rotation();
+    }
+    status!("Counting the arrangements of {}", bag.digits());
+    let start = Instant::now();
+    let n = census.count(bag);
+
+    status!("============================================================");
+    status!("DISTINCT ARRANGEMENTS: {}", n);
+    status!("({} partial arrangements expanded in {:.1}s)", census.expanded(),
+            start.elapsed().as_secs_f64());
+}
+
+// Handles `nmbr9 sim`, which plays strategies against each other over many
+// deals of the full deck
+fn run_sim(opts: &Options, names: &[String], deals: usize, seed: u64) {
+    let mut players: Vec<_> = names.iter()
+        .map(|n| strategy::parse(n, opts.rules).unwrap())
+        .collect();
+    let count = (opts.rules.copies + 1).pow(UNIQUE_PIECE_COUNT as u32);
+    let full = Bag::from_index(count - 1, opts.rules.copies);
+    status!("Playing {} deals between {}", deals, names.join(", "));
+    let start = Instant::now();
+    let standings = sim::simulate(&mut players, &full, deals, seed, &opts.rules);
+
+    status!("============================================================");
+    status!("{}", standings);
+    status!("({:.1}s)", start.elapsed().as_secs_f64());
+}
+
+// Handles `nmbr9 histogram`, which writes how many arrangements of one bag
+// (or how many random games) reach each score, as CSV or (for .json) JSON
+fn run_histogram(opts: &Options, bag: &Bag, up_to_rotation: bool,
+                 samples: Option<(usize, u64)>) {
+    let mut census = Census::new(opts.rules);
+    if up_to_rotation {
+        census = census.up_to_rotation();
+    }
+    // Nothing else is printed, so that the CSV can go to stdout
+    let counts = match samples {
+        Some((n, seed)) => census.sample(bag, n, seed),
+        None => {
+            census.count(bag);
+            census.histogram()
+        },
+    };
+
+    let text = match opts.output {
+        Some(ref f) if is_json(f) =>
+            json::histogram(&opts.rules, bag, samples.is_none(), &counts) + "\n",
+        _ => {
+            let mut text = "score,count\n".to_string();
+            for (score, n) in &counts {
+                text += &format!("{},{}\n", score, n);
+            }
+            text
+        },
+    };
+    match opts.output {
+        Some(ref f) => match std::fs::write(f, text) {
+            Ok(()) => status!("Wrote histogram to {}", f),
+            Err(e) => warning!("Failed to write histogram to {}: {}", f, e),
+        },
+        None => print!("{}", text),
+    }
+}
+
+// Handles `nmbr9 baseline`, which plays random games of one bag, both
+// placing any tile anywhere and placing tiles as they're dealt from a
+// shuffled deck, and summarizes their scores
+fn run_baseline(opts: &Options, bag: &Bag, runs: usize, seed: u64,
+                results_file: &Option<String>) {
+    let results = read_results(opts, results_file);
+    let census = Census::new(opts.rules);
+    status!("Playing {} random games of {}", runs, bag.digits());
+    let start = Instant::now();
+    let rows = [("any order", census.sample(bag, runs, seed)),
+                ("shuffled deck", census.sample_deck(bag, runs, seed))];
+
+    status!("============================================================");
+    status!("RANDOM PLAY ON {}", bag.digits());
+    status!("{:<14} {:>6} {:>8} {:>5} {:>5} {:>6} {:>5} {:>5}",
+            "", "games", "mean", "min", "p10", "median", "p90", "max");
+    for &(name, ref counts) in rows.iter() {
+        match census::summarize(counts) {
+            Some(s) => status!("{:<14} {:>6} {:>8.2} {:>5} {:>5} {:>6} {:>5} {:>5}",
+                               name, s.games, s.mean, s.min, s.p10, s.median,
+                               s.p90, s.max),
+            None => status!("{:<14} no complete games", name),
+        }
+    }
+    if let Some(best) = results.score(bag) {
+        status!("BEST SCORE: {}", best);
+    }
+    status!("({:.1}s)", start.elapsed().as_secs_f64());
+}
+
+// Returns the results in the given file, or empty results if there's no
+// file
+fn read_results(opts: &Options, filename: &Option<String>) -> Results {
+    let mut results = Results::new(opts.rules);
+    if let Some(ref filename) = *filename {
+        match load_results(&mut results, filename) {
+            Ok(n) => status!("Loaded {} bags from {}", n, filename),
+            Err(e) => usage(&format!("Could not read {}: {}", filename, e)),
+        }
+    }
+    results
+}
+
+// Handles `nmbr9 mcts`, which searches for a good arrangement of one bag
+// without trying to prove that it's the best
+fn run_mcts(opts: &Options, bag: &Bag, iterations: usize,
+            filename: &Option<String>) {
+    let results = read_results(opts, filename);
+    let mut m = Mcts::new(opts.rules).with_results(&results)
+        .with_iterations(iterations);
+    if let Some(timeout) = opts.timeout {
+        m = m.with_budget(timeout);
+    }
+    status!("Searching {} with {} rollouts", bag.digits(), iterations);
+    let start = Instant::now();
+    let (score, state) = m.run(bag);
+
+    status!("============================================================");
+    status!("BEST SCORE FOUND: at least {}", score);
+    status!("(searched in {:.1}s)", start.elapsed().as_secs_f64());
+    if !porcelain::enabled() {
+        print_layout(opts, &state);
+    }
+}
+
+// Prints the bag's tiles ranked by how much its best score drops without
+// one copy of each (see Results::marginal_values), returning false if the
+// bag or one of its one-tile-smaller subsets hasn't been solved
+fn print_marginal_values(results: &Results, bag: &Bag) -> bool {
+    let (score, values) = match (results.score(bag), results.marginal_values(bag)) {
+        (Some(score), Some(values)) => (score, values),
+        _ => return false,
+    };
+    status!("============================================================");
+    status!("MARGINAL TILE VALUES for {} (best score {})", bag.digits(), score);
+    status!("  rank  tile  best without  score lost");
+    for (rank, (tile, lost)) in values.into_iter().enumerate() {
+        status!("  {:>4}  {:>4}  {:>12}  {:>10}", rank + 1, tile, score - lost, lost);
+    }
+    true
+}
+
+// Handles `nmbr9 analyze`, which solves one bag and its subsets (skipping
+// any whose scores were loaded from a results file), then ranks the bag's
+// tiles by how much they're worth to it.  The results can be written out
+// with --output, to be reused by later analyses.
+fn run_analyze(opts: &Options, bag: &Bag, filename: &Option<String>) {
+    let solver = solver(opts);
+    if let Some(ref filename) = *filename {
+        match solver.preload(Path::new(filename)) {
+            Ok(n) => status!("Loaded {} bags from {}", n, filename),
+            Err(e) => usage(&format!("Could not read {}: {}", filename, e)),
+        }
+    }
+    solver.sweep_bags(std::slice::from_ref(bag));
+
+    let results = solver.results().read().unwrap();
+    if !print_marginal_values(&results, bag) {
+        status!("============================================================");
+        status!("STOPPED before {} and its subsets were solved", bag.digits());
+    }
+    if let Some(ref filename) = opts.output {
+        write_results(filename, &results);
+    }
+}
+
+// Handles `nmbr9 --target-score`, which looks for any arrangement of one
+// bag reaching the target score, without proving that it's the best
+fn run_target(opts: &Options, bag: &Bag, target: isize,
+              filename: &Option<String>) {
+    let results = read_results(opts, filename);
+    let mut search = TargetSearch::new(opts.rules).with_results(&results);
+    if let Some(timeout) = opts.timeout {
+        search = search.with_budget(timeout);
+    }
+    if let Some(nodes) = opts.node_limit {
+        search = search.with_node_limit(nodes);
+    }
+    status!("Searching {} for an arrangement scoring at least {}",
+            bag.digits(), target);
+    let start = Instant::now();
+    let found = search.run(bag, target);
+
+    status!("============================================================");
+    match found {
+        Some((score, _)) =>
+            status!("REACHED {}: found an arrangement scoring {}", target, score),
+        None if search.stopped() =>
+            status!("STOPPED before finding an arrangement scoring {}", target),
+        None => status!("UNREACHABLE: no arrangement scores {}", target),
+    }
+    status!("({} nodes expanded in {:.1}s)", search.expanded(),
+            start.elapsed().as_secs_f64());
+    if let Some((_, state)) = found {
+        if !porcelain::enabled() {
+            print_layout(opts, &state);
+        }
+    }
+}
+
+// Handles `nmbr9 --position`, which finds the best way to place one bag's
+// tiles on top of a game in progress (read as in `nmbr9 verify`)
+fn run_position(opts: &Options, filename: &str, bag: &Bag,
+                results_file: &Option<String>) {
+    let text = std::fs::read_to_string(filename)
+        .unwrap_or_else(|e| usage(&format!("Could not read {}: {}", filename, e)));
+    let (moves, in_order) = if is_json(filename) {
+        (game::parse_json(&text), false)
+    } else {
+        (game::parse_text(&text), true)
+    };
+    let moves = moves
+        .unwrap_or_else(|e| usage(&format!("Could not read {}: {}", filename, e)));
+    let state = game::replay(&moves, &opts.rules, in_order)
+        .unwrap_or_else(|(i, e)| {
+            eprintln!("Move {} ({}) is illegal: {}", i + 1, moves[i], e);
+            exit(1);
+        });
+
+    // The tiles on the table and those left can't add up to more copies
+    // than the game has
+    let mut all = bag.clone();
+    for p in &state.pieces {
+        if all.digit(p.index()) >= opts.rules.copies {
+            usage(&format!("Too many {}s between {} and the bag", p.index(), filename));
+        }
+        all = all.add(p.index() * piece::MAX_ROTATIONS);
+    }
+
+    let results = RwLock::new(read_results(opts, results_file));
+    if !state.has_rules(&results.read().unwrap().rules()) {
+        usage(&format!("{} doesn't match the rules of the results", filename));
+    }
+    status!("Placing {} on top of {} moves scoring {}", bag.digits(),
+            moves.len(), opts.rules.score(&state));
+    let mut worker = Worker::new(bag.clone(), &results).with_start(state);
+    if let Some(order) = opts.order {
+        worker = worker.with_order(order);
+    }
+    if let Some(timeout) = opts.timeout {
+        worker = worker.with_deadline(Instant::now() + timeout);
+    }
+    if let Some(nodes) = opts.node_limit {
+        worker = worker.with_node_limit(nodes);
+    }
+    if let Some(depth) = opts.depth_limit {
+        worker = worker.with_depth_limit(depth);
+    }
+    let (score, state) = match worker.run() {
+        Some(r) => r,
+        None => return,
+    };
+
+    status!("============================================================");
+    let qualifier = if worker.timed_out() || worker.limited() {
+        "at least "
+    } else {
+        ""
+    };
+    status!("BEST SCORE FROM THIS POSITION: {}{}", qualifier, score);
+    if !porcelain::enabled() {
+        print_layout(opts, &state);
+    }
+}
+
+// Handles `nmbr9 engine`, which answers commands from stdin until it's
+// told to quit (or stdin closes)
+fn run_engine(opts: &Options, strategy: Option<&str>) {
+    let mut engine = Engine::new(opts.rules);
+    if let Some(name) = strategy {
+        engine = engine.with_strategy(strategy::parse(name, opts.rules).unwrap());
+    }
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    let stdin = std::io::stdin();
+    for line in stdin.lock().lines() {
+        let line = line.unwrap_or_else(|e| usage(&format!("Could not read stdin: {}", e)));
+        match engine.handle(&line, &mut out) {
+            Ok(true) => (),
+            Ok(false) => break,
+            Err(e) => {
+                eprintln!("Could not write to stdout: {}", e);
+                exit(1);
+            },
+        }
+    }
+}
+
+// Handles `nmbr9 estimate`, which solves a few bags of each size with a
+// short budget (the --timeout, or 5 seconds) and predicts how long the
+// full sweep would take and how much memory it would need.  Each sample
+// runs on one thread, and the estimate divides the work between threads.
+fn run_estimate(opts: &Options, per_count: usize) {
+    let results = RwLock::new(Results::new(opts.rules));
+    let mut bags = results.read().unwrap().bags();
+    bags.sort_by_key(|b| b.len());
+    let budget = opts.timeout.unwrap_or(Duration::from_secs(5));
+
+    // Build the tables up front, so that the first sample isn't charged
+    // for them
+    Tables::get(opts.rules.adjacency, opts.rules.mirrored);
+
+    let mut samples = Vec::new();
+    for bag in estimate::stratify(&bags, per_count) {
+        estimate::prepare(&mut results.write().unwrap(), &bag);
+        let begin = Instant::now();
+        let mut worker = Worker::new(bag.clone(), &results)
+            .with_deadline(begin + budget)
+            .with_split_depth(0);
+        if let Some(order) = opts.order {
+            worker = worker.with_order(order);
+        }
+        let progress = worker.progress();
+        worker.run();
+        samples.push(Sample {
+            pieces: bag.len(),
+            seconds: begin.elapsed().as_secs_f64(),
+            nodes: progress.nodes(),
+            complete: !worker.timed_out(),
+        });
+    }
+
+    let most = bags.iter().map(|b| b.len()).max().unwrap_or(0);
+    let mut counts = vec![0; most + 1];
+    for b in bags.iter() {
+        counts[b.len()] += 1;
+    }
+    let threads = rayon::current_num_threads();
+    let e = estimate::estimate(&samples, &counts, threads,
+                               &results.read().unwrap());
+
+    status!("============================================================");
+    status!("ESTIMATE ({} sample(s) per size, {:?} budget, {} threads)",
+            per_count, budget, threads);
+    status!("pieces   bags   seconds/bag   memory/bag");
+    for r in e.rows.iter() {
+        status!("{:>6} {:>6} {:>13.3} {:>9.1} MB{}", r.pieces, r.bags,
+                r.seconds, r.bytes as f64 / 1e6,
+                if r.extrapolated { "  (extrapolated)" } else { "" });
+    }
+    if !e.unknown.is_empty() {
+        let sizes: Vec<String> = e.unknown.iter().map(|n| n.to_string()).collect();
+        warning!("No estimate for {}-piece bags, which are left out",
+                 sizes.join(", "));
+    }
+    status!("Predicted wall-clock time: {:.0} seconds ({:.1} hours)",
+            e.seconds, e.seconds / 3600.0);
+    status!("Predicted peak memory: {:.1} MB", e.peak_bytes as f64 / 1e6);
+}
+
+// Builds a solver from the search settings in the options
+fn solver(opts: &Options) -> Solver {
+    let mut solver = Solver::new(opts.rules).with_chunk(opts.chunk)
+        .with_random_runs(opts.random_runs)
+        .with_leaders(opts.leaders)
+        .with_limits(opts.node_limit, opts.depth_limit);
+    if opts.deterministic {
+        solver = solver.with_deterministic();
+    }
+    if let Some(order) = opts.order {
+        solver = solver.with_order(order);
+    }
+    if let Some(tiebreak) = opts.tiebreak {
+        solver = solver.with_tiebreak(tiebreak);
+    }
+    if let Some(moves) = opts.moves {
+        solver = solver.with_move_order(moves);
+    }
+    if opts.shared_seen {
+        solver = solver.with_shared_seen();
+    }
+    if opts.dominance {
+        solver = solver.with_dominance();
+    }
+    if let Some(mb) = opts.placement_cache {
+        solver = solver.with_placement_cache(mb << 20);
+    }
+    if let Some(mb) = opts.support_table {
+        solver = solver.with_support_table(mb << 20);
+    }
+    if opts.seen_limit.is_some() || opts.seen_evict {
+        let mb = opts.seen_limit.unwrap_or(Tuning::for_len(0).seen_limit >> 20);
+        solver = solver.with_seen_limit(mb << 20, opts.seen_evict);
+    }
+    if let Some(timeout) = opts.timeout {
+        solver = solver.with_timeout(timeout, opts.retries);
+    }
+    if let Some(ref overlay) = opts.overlay {
+        solver = solver.with_overlay(overlay.clone());
+    }
+    if let Some(ref dir) = opts.journal {
+        solver = solver.with_journal(Path::new(dir));
+    }
+    if let Some(ref filename) = opts.database {
+        let db = Database::open(Path::new(filename), &opts.rules)
+            .unwrap_or_else(|e| usage(&format!("Could not open {}: {}",
+                                               filename, e)));
+        solver = solver.with_database(Arc::new(db));
+    }
+    if let Some(ref filename) = opts.telemetry {
+        let t = Telemetry::open(Path::new(filename))
+            .unwrap_or_else(|e| usage(&format!("Could not open {}: {}",
+                                               filename, e)));
+        solver = solver.with_telemetry(Arc::new(t));
+    }
+    if let Some(period) = opts.watchdog {
+        let dog = Arc::new(Watchdog::new(period));
+        solver = solver.with_watchdog(dog.clone());
+        every(dog.period(), &dog, |dog| { dog.check(); });
+    }
+    if let Some(period) = opts.progress {
+        let tracker = Arc::new(Tracker::new());
+        solver = solver.with_progress(tracker.clone());
+        show_progress(period, &tracker, |t| t.snapshot());
+    }
+    solver
+}
+
+// Calls f with the value every period, on a thread of its own.  The thread
+// only keeps a weak reference, and stops once the value is dropped (along
+// with the solver or coordinator which holds it), so it ends with the solve.
+fn every<T, F>(period: Duration, value: &Arc<T>, f: F)
+    where T: Send + Sync + 'static, F: Fn(&T) + Send + 'static
+{
+    let value = Arc::downgrade(value);
+    thread::spawn(move || loop {
+        thread::sleep(period);
+        match value.upgrade() {
+            Some(v) => f(&v),
+            None => return,
+        }
+    });
+}
+
+// Refreshes a progress line on stderr every period, in place if it's a
+// terminal, for as long as the source of its snapshots is alive
+fn show_progress<T, F>(period: Duration, source: &Arc<T>, snapshot: F)
+    where T: Send + Sync + 'static, F: Fn(&T) -> Snapshot + Send + 'static
+{
+    every(period, source, move |t| {
+        let s = snapshot(t);
+        if s.total == 0 || !logging::enabled(Level::Info) {
+            return;
+        }
+        if std::io::stderr().is_terminal() {
+            eprint!("\r{}\x1b[K", s);
+        } else {
+            eprintln!("{}", s);
+        }
+    });
+}
+
+// Returns the bags selected by --bag or --max-pieces, or every bag
+fn targets(opts: &Options, default_max: Option<usize>) -> Vec<Bag> {
+    if let Some(ref bags) = opts.bags {
+        return bags.clone();
+    }
+    let most = opts.max_pieces.or(default_max).unwrap_or(usize::MAX);
+    Results::new(opts.rules).bags().into_iter()
+        .filter(|b| b.len() <= most)
+        .collect()
+}
+
+// Results files ending in .json are written as JSON (see nmbr9::json),
+// those ending in .html as a page for viewing them (see nmbr9::html), and
+// anything else as tab-separated text
+fn is_json(filename: &str) -> bool {
+    Path::new(filename).extension().map(|e| e == "json").unwrap_or(false)
+}
+
+fn is_html(filename: &str) -> bool {
+    Path::new(filename).extension().map(|e| e == "html").unwrap_or(false)
+}
+
+fn is_csv(filename: &str) -> bool {
+    Path::new(filename).extension().map(|e| e == "csv").unwrap_or(false)
+}
+
+// Loads a results file (as CSV if it ends with .csv) into the results,
+// returning the number of bags loaded
+fn load_results(results: &mut Results, filename: &str) -> Result<usize, String> {
+    let f = BufReader::new(File::open(filename).map_err(|e| e.to_string())?);
+    if is_csv(filename) {
+        results.read_csv(f)
+    } else {
+        results.read(f)
+    }
+}
+
+fn write_results(filename: &str, results: &Results) {
+    let written = File::create(filename).and_then(|f| {
+        if is_json(filename) {
+            let w = JsonWriter::new(Box::new(BufWriter::new(f)),
+                                    &results.rules())?;
+            w.write_results(results)?;
+            return w.finish();
+        }
+        let mut out = BufWriter::new(f);
+        if is_html(filename) {
+            html::write(&mut out, results)?;
+            return out.flush();
+        }
+        if is_csv(filename) {
+            results.write_csv(&mut out)?;
+            return out.flush();
+        }
+        results.write(&mut out)?;
+        out.flush()
+    });
+    match written {
+        Ok(()) => status!("Wrote results to {}", filename),
+        Err(e) => warning!("Failed to write results to {}: {}", filename, e),
+    }
+}
+
+// Handles `nmbr9 merge`, which combines the results of partial runs (as
+// from different machines) and writes them to --output.  Exact scores win
+// over lower bounds, and any bags whose exact scores disagree are reported
+// and keep the first file's score.
+fn run_merge(opts: &Options, inputs: &[String]) {
+    let mut merged = Results::new(opts.rules);
+    for filename in inputs {
+        let mut results = Results::new(opts.rules);
+        match load_results(&mut results, filename) {
+            Ok(n) => status!("Loaded {} bags from {}", n, filename),
+            Err(e) => usage(&format!("Could not read {}: {}", filename, e)),
+        }
+        let problems = merged.merge(&results).unwrap_or_else(
+            |e| usage(&format!("Could not merge {}: {}", filename, e)));
+        for problem in problems {
+            warning!("{}: {}", filename, problem);
+        }
+    }
+    write_results(opts.output.as_ref().unwrap(), &merged);
+}
+
+// Handles `nmbr9 solve`, the default:  solves the selected bags, then
+// reports on them
+fn run_solve(opts: &Options) {
+    let mut solver = solver(opts);
+    let trace = Arc::new(Trace::new());
+    if opts.trace.is_some() {
+        solver = solver.with_trace(trace.clone());
+    }
+    let profile = Arc::new(Profile::new());
+    if opts.profile {
+        // Build the tables up front, so that their time is counted apart
+        // from the first batch of bags
+        let begin = Instant::now();
+        Tables::get(opts.rules.adjacency, opts.rules.mirrored);
+        profile.tables(begin.elapsed());
+        solver = solver.with_profile(profile.clone());
+    }
+    let json = opts.output.as_ref().filter(|f| is_json(f)).map(|f| {
+        Arc::new(File::create(f)
+            .and_then(|out| JsonWriter::new(Box::new(BufWriter::new(out)),
+                                            &opts.rules))
+            .unwrap_or_else(|e| usage(&format!("Could not write {}: {}", f, e))))
+    });
+    if opts.score_card.is_some() || json.is_some() {
+        let full = solver.results().read().unwrap().full_bag();
+        let card = opts.score_card.clone();
+        let writer = json.clone();
+        solver = solver.with_callback(Box::new(move |b, score, exact, state| {
+            match card {
+                Some(ref filename) if *b == full =>
+                    write_score_card(filename, b, score, state),
+                _ => (),
+            }
+            if let (Some(ref w), true) = (&writer, exact) {
+                // An empty arrangement means that it isn't known, unless
+                // the bag is empty too
+                let state = if state.is_empty() && !b.is_empty() {
+                    None
+                } else {
+                    Some(state)
+                };
+                if let Err(e) = w.write(b, score, state) {
+                    warning!("Failed to write JSON results: {}", e);
+                }
+            }
+        }));
+    }
+    if let Some(ref filename) = opts.known {
+        match solver.preload(Path::new(filename)) {
+            Ok(n) => status!("Loaded {} known scores from {}", n, filename),
+            Err(e) => usage(&format!("Could not read {}: {}", filename, e)),
+        }
+        for problem in solver.results().read().unwrap().check() {
+            warning!("{}: {}", filename, problem);
+        }
+    }
+    if let Some(ref filename) = opts.checkpoint {
+        let path = Path::new(filename);
+        if opts.resume && path.exists() {
+            match solver.resume(path) {
+                Ok(n) => status!("Resumed {} bags from {}", n, filename),
+                Err(e) => usage(&format!("Could not resume from {}: {}",
+                                         filename, e)),
+            }
+        }
+        solver = solver.with_checkpoint(path, opts.checkpoint_interval);
+    }
+    if let Some(ref filename) = opts.resume_from {
+        match suspend::read_file(Path::new(filename), &opts.rules) {
+            Ok(snapshots) => {
+                status!("Continuing {} suspended searches from {}",
+                        snapshots.len(), filename);
+                solver = solver.with_snapshots(snapshots);
+            },
+            Err(e) => usage(&format!("Could not read {}: {}", filename, e)),
+        }
+    }
+    if opts.suspend_to.is_some() {
+        let token = CancelToken::new();
+        suspend::cancel_on_interrupt(token.clone());
+        solver = solver.with_cancel(token).with_suspend(opts.suspend_prune);
+    }
+
+    // Bags loaded from a checkpoint won't be solved again, so they're
+    // written up front
+    if let Some(ref w) = json {
+        if let Err(e) = w.write_results(&solver.results().read().unwrap()) {
+            warning!("Failed to write JSON results: {}", e);
+        }
+    }
+    let began = Instant::now();
+    solver.sweep_bags(&targets(opts, None));
+    if let (Some(ref filename), true) = (&opts.suspend_to, solver.is_cancelled()) {
+        let snapshots = solver.suspended();
+        match suspend::write_file(Path::new(filename), &snapshots, &opts.rules) {
+            Ok(()) => status!("Suspended {} searches to {}", snapshots.len(),
+                              filename),
+            Err(e) => warning!("Failed to write {}: {}", filename, e),
+        }
+    }
+
+    if let Some(ref filename) = opts.trace {
+        let written = File::create(filename)
+            .and_then(|mut f| trace.write(&mut f));
+        if let Err(e) = written {
+            warning!("Failed to write trace to {}: {}", filename, e);
+        }
+    }
+    if opts.profile {
+        status!("============================================================");
+        status!("PROFILE");
+        for line in profile.to_string().lines() {
+            status!("  {}", line);
+        }
+    }
+
+    if opts.all_optima && !solver.is_cancelled() {
+        find_optima(opts, solver.results());
+    }
+
+    let results = solver.results().read().unwrap();
+    print_marginal_values(&results, &results.full_bag());
+    print_layouts(opts, &results);
+    if let Some(ref w) = json {
+        match w.finish() {
+            Ok(()) => status!("Wrote results to {}", opts.output.as_ref().unwrap()),
+            Err(e) => warning!("Failed to write JSON results: {}", e),
+        }
+    } else if let Some(ref filename) = opts.output {
+        write_results(filename, &results);
+    }
+    logging::event("done", None, None, Some(began.elapsed()));
+}
+
+// Searches the full bag and any bags picked with --bag again, once they're
+// solved, for every distinct arrangement which reaches their best score.
+// These replace the bags' leaderboards, so print_layouts shows them.
+fn find_optima(opts: &Options, results: &RwLock<Results>) {
+    let full = results.read().unwrap().full_bag();
+    let mut bags = vec![full.clone()];
+    if let Some(ref picked) = opts.bags {
+        bags.extend(picked.iter().filter(|&b| *b != full).cloned());
+    }
+    for bag in bags {
+        let score = match results.read().unwrap().score(&bag) {
+            Some(score) => score,
+            None => continue,
+        };
+        let mut w = Worker::new(bag.clone(), results).with_optimum(score);
+        if w.run().is_some() {
+            status!("FOUND {} DISTINCT LAYOUTS OF {} SCORING {}",
+                    w.leaderboard().len(), bag.digits(), score);
+        }
+    }
+}
+
+// Shows the best arrangement of the full bag and of any bags picked with
+// --bag, as text (which can be loaded with State::from_layout) and drawn
+// layer by layer.  With --layout-png, each is also drawn to <digits>.png,
+// and each of its layers to <digits>-layer<z>.png; with --layout-3d, each
+// is exported to <digits>.stl and <digits>.scad.
+fn print_layouts(opts: &Options, results: &Results) {
+    let full = results.full_bag();
+    let mut bags = vec![full.clone()];
+    if let Some(ref picked) = opts.bags {
+        bags.extend(picked.iter().filter(|&b| *b != full).cloned());
+    }
+    for bag in bags {
+        let (score, state) = match (results.score(&bag), results.state(&bag)) {
+            (Some(score), Some(state)) => (score, state),
+            _ => continue,
+        };
+        status!("============================================================");
+        match opts.tiebreak {
+            Some(t) => status!("BEST LAYOUT FOR {} (score {}, {} {})",
+                               bag.digits(), score, match t {
+                                   Tiebreak::Footprint => "footprint",
+                                   Tiebreak::Layers => "layers",
+                               }, t.cost(state)),
+            None => status!("BEST LAYOUT FOR {} (score {})", bag.digits(), score),
+        }
+        status!("{}", state.layout());
+        if !porcelain::enabled() {
+            print_layout(opts, state);
+        }
+        if let Some(ref dir) = opts.layout_png {
+            write_layout_pngs(Path::new(dir), &bag, state);
+        }
+        if let Some(ref dir) = opts.layout_3d {
+            write_layout_models(Path::new(dir), &bag, state, opts.dimensions);
+        }
+        let leaders = results.leaders(&bag);
+        if !leaders.is_empty() {
+            status!("TOP {} DISTINCT LAYOUTS FOR {}", leaders.len(), bag.digits());
+            for (score, state) in leaders {
+                status!("{}\t{}", score, state.layout());
+            }
+        }
+    }
+}
+
+// Handles `nmbr9 verify FILE` for a game's moves (in JSON, or one per
+// line), which replays them and reports the score.  Returns false if the
+// file isn't a list of moves.
+fn verify_game(opts: &Options, filename: &str) -> bool {
+    let text = std::fs::read_to_string(filename)
+        .unwrap_or_else(|e| usage(&format!("Could not read {}: {}", filename, e)));
+    let (moves, in_order) = if is_json(filename) {
+        let moves = game::parse_json(&text)
+            .unwrap_or_else(|e| usage(&format!("Could not read {}: {}", filename, e)));
+        (moves, false)
+    } else {
+        match game::parse_text(&text) {
+            // A results file with no bags has no moves either
+            Ok(ref moves) if moves.is_empty() => return false,
+            Ok(moves) => (moves, true),
+            Err(_) => return false,
+        }
+    };
+
+    match game::replay(&moves, &opts.rules, in_order) {
+        Ok(state) => {
+            if !porcelain::enabled() {
+                state.pretty_print();
+            }
+            status!("All {} moves are legal, scoring {}", moves.len(),
+                    opts.rules.score(&state));
+        },
+        Err((i, e)) => {
+            eprintln!("Move {} ({}) is illegal: {}", i + 1, moves[i], e);
+            exit(1);
+        },
+    }
+    true
+}
+
+// Reads a state for `nmbr9 show`, from the named file or (if there's no
+// such file) from the argument itself.  This is either a game's moves, in
+// JSON or move notation (as in `nmbr9 verify`), or a layout as written by
+// State::layout, which may be the last field of a line from a results
+// file or a checkpoint.
+fn read_state(opts: &Options, arg: &str) -> State {
+    let text = if Path::new(arg).is_file() {
+        std::fs::read_to_string(arg)
+            .unwrap_or_else(|e| usage(&format!("Could not read {}: {}", arg, e)))
+    } else {
+        arg.to_string()
+    };
+    let text = text.trim();
+    if text.is_empty() {
+        usage(&format!("{} is empty", arg));
+    }
+
+    // Layouts have no spaces or @s, unlike either form of move notation
+    let lines: Vec<&str> = text.lines()
+        .filter(|l| !l.trim().is_empty() && !l.starts_with('#'))
+        .collect();
+    if let [line] = lines[..] {
+        let layout = line.rsplit('\t').next().unwrap().trim();
+        if !layout.contains(' ') && !layout.contains('@') {
+            return State::from_layout(layout, opts.rules.adjacency)
+                .unwrap_or_else(|| usage(&format!("{} isn't a legal layout", layout)));
+        }
+    }
+
+    let (moves, in_order) = if is_json(arg) || text.starts_with('{') {
+        (game::parse_json(text), false)
+    } else {
+        (game::parse_text(text), true)
+    };
+    let moves = moves
+        .unwrap_or_else(|e| usage(&format!("Could not read {}: {}", arg, e)));
+    game::replay(&moves, &opts.rules, in_order)
+        .unwrap_or_else(|(i, e)| {
+            eprintln!("Move {} ({}) is illegal: {}", i + 1, moves[i], e);
+            exit(1);
+        })
+}
+
+// Handles `nmbr9 show FILE|STATE`, which draws a state in each of the
+// views that --3d and --elevation pick between, then describes it
+fn run_show(opts: &Options, arg: &str) {
+    let state = read_state(opts, arg);
+    if !porcelain::enabled() {
+        state.pretty_print();
+        state.pretty_print_3d();
+        state.pretty_print_elevation();
+    }
+    let layers = if state.is_empty() { 0 } else { state.layers() + 1 };
+
+    // The squares covered on the table, and the smallest box around them
+    // (State::size is rounded out to whole 4x4 tiles)
+    let ground: Vec<(u32, u32)> = Footprint::new(&state).cells.iter()
+        .filter(|&&c| c >> 16 == 0)
+        .map(|&c| ((c >> 8) & 0xff, c & 0xff))
+        .collect();
+    let extent = |f: fn(&(u32, u32)) -> u32| match ground.iter().map(f).max() {
+        Some(hi) => hi - ground.iter().map(f).min().unwrap() + 1,
+        None => 0,
+    };
+    let (w, h, covered) = (extent(|c| c.0), extent(|c| c.1), ground.len());
+    status!("Score: {}", opts.rules.score(&state));
+    status!("Tiles: {}", state.pieces.len());
+    status!("Layers: {}", layers);
+    status!("Footprint: {}x{}, covering {} squares", w, h, covered);
+    status!("Layout: {}", state.layout());
+}
+
+// Handles `nmbr9 verify FILE`, which checks that a results file is
+// consistent, then re-solves any bags selected with --bag (using the
+// file's scores for their subsets) and compares their scores
+fn run_verify(opts: &Options, filename: &str) {
+    if verify_game(opts, filename) {
+        return;
+    }
+    let solver = solver(opts);
+    let loaded = load_results(&mut solver.results().write().unwrap(), filename);
+    match loaded {
+        Ok(n) => status!("Loaded {} bags from {}", n, filename),
+        Err(e) => usage(&format!("Could not read {}: {}", filename, e)),
+    }
+    let mut problems = solver.results().read().unwrap().check();
+
+    let mut bags = opts.bags.clone().unwrap_or_default();
+    bags.sort_by_key(|b| b.len());
+    for b in bags {
+        let (recorded, missing) = {
+            let results = solver.results().read().unwrap();
+            let missing = results.bags().into_iter().find(|s|
+                s.len() < b.len() && b.contains(s) &&
+                results.lower_bound(s).is_none());
+            (results.score(&b), missing)
+        };
+        if let Some(s) = missing {
+            problems.push(format!("Can't re-solve {}: {} isn't in the file",
+                                  b.digits(), s.digits()));
+            continue;
+        }
+        let found = solver.solve(&b).map(|r| r.0);
+        if solver.results().read().unwrap().score(&b).is_none() {
+            problems.push(format!("Re-solving {} didn't finish", b.digits()));
+        } else if found != recorded {
+            problems.push(format!("{} scored {:?}, but the file has {:?}",
+                                  b.digits(), found, recorded));
+        }
+    }
+
+    for p in problems.iter() {
+        eprintln!("{}", p);
+    }
+    if !problems.is_empty() {
+        eprintln!("Found {} problem(s) in {}", problems.len(), filename);
+        exit(1);
+    }
+    status!("{} is consistent", filename);
+}
+
+// Handles `nmbr9 serve`, which hands out the selected bags (and their
+// subsets) to workers until they're all solved
+fn run_serve(opts: &Options, addr: &str) {
+    let listener = TcpListener::bind(addr)
+        .unwrap_or_else(|e| usage(&format!("Could not listen on {}: {}", addr, e)));
+    let coordinator = Arc::new(Coordinator::new(opts.rules,
+                                                 &targets(opts, None)));
+    if let Some(period) = opts.progress {
+        show_progress(period, &coordinator, |c| c.progress());
+    }
+    status!("Waiting for workers on {}", addr);
+    if let Err(e) = coordinator.serve(listener) {
+        eprintln!("Stopped serving: {}", e);
+        exit(1);
+    }
+    if let Some(ref filename) = opts.output {
+        write_results(filename, &coordinator.results().read().unwrap());
+    }
+}
+
+// Handles `nmbr9 work`, which solves bags for a coordinator until it says
+// that they're all done
+fn run_work(opts: &Options, addr: &str) {
+    match distributed::work(addr, &solver(opts)) {
+        Ok(n) => status!("Solved {} bags for {}", n, addr),
+        Err(e) => {
+            eprintln!("Could not work for {}: {}", addr, e);
+            exit(1);
+        },
+    }
+}
+
+// Handles `nmbr9 http`, which answers requests until it's killed
+fn run_http(opts: &Options, addr: &str, filename: &Option<String>) {
+    let listener = TcpListener::bind(addr)
+        .unwrap_or_else(|e| usage(&format!("Could not listen on {}: {}", addr, e)));
+    let server = Server::new(read_results(opts, filename));
+    status!("Listening for requests on {}", addr);
+    if let Err(e) = server.serve(listener) {
+        eprintln!("Stopped serving: {}", e);
+        exit(1);
+    }
+}
+
+// Handles `nmbr9 results`, which prints records from the database in its
+// own format:  every record of one bag, or the best record of the
+// highest-scoring bags
+fn run_results(opts: &Options, query: &Query) {
+    let filename = opts.database.as_ref().unwrap();
+    let records = database::read_file(Path::new(filename), &opts.rules)
+        .unwrap_or_else(|e| usage(&format!("Could not read {}: {}",
+                                           filename, e)));
+    let found = match *query {
+        Query::Show(ref bag) => database::show(&records, bag),
+        Query::Top(n) => database::top(&records, n),
+    };
+    if found.is_empty() {
+        status!("No matching records in {}", filename);
+    }
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    for r in found {
+        if let Err(e) = r.write(&mut out) {
+            eprintln!("Failed to write records: {}", e);
+            exit(1);
+        }
+    }
+}
+
+// Handles `nmbr9 render`, which solves one bag and writes a score card of
+// its best arrangement
+fn run_render(opts: &Options) {
+    let bag = opts.bags.as_ref().unwrap()[0].clone();
+    let filename = opts.output.clone().unwrap();
+    let target = bag.clone();
+    let solver = solver(opts)
+        .with_callback(Box::new(move |b, score, _, state| {
+            if *b == target {
+                write_score_card(&filename, b, score, state);
+            }
+        }));
+    solver.sweep_bags(&[bag]);
+}
+
+// Times try_place with each orientation the rules allow, at every
+// position around the arrangements made by greedily placing tiles 9, 8,
+// ... 0 in turn (as in benches/hot_paths.rs), returning the number of
+// placements tried and how long they took
+fn bench_try_place(rules: Rules) -> (usize, Duration) {
+    let mut greedy = Greedy::new(rules);
+    let mut states = vec![State::with_rules(&rules)];
+    for tile in (0..UNIQUE_PIECE_COUNT).rev() {
+        let last = states.last().unwrap();
+        let p = match greedy.choose(last, tile) {
+            Some(p) => p,
+            None => break,
+        };
+        let next = last.try_place(p.id(),
+                                  p.x, p.y).unwrap();
+        states.push(next);
+    }
+    let mut tries = Vec::new();
+    for s in &states {
+        let (w, h) = s.size();
+        let ids = (0..UNIQUE_PIECE_COUNT)
+            .flat_map(|t| piece::pieces().orientations(t, rules.mirrored));
+        for b in ids {
+            for x in -piece::MAX_EDGE_LENGTH..=w + piece::MAX_EDGE_LENGTH {
+                for y in -piece::MAX_EDGE_LENGTH..=h + piece::MAX_EDGE_LENGTH {
+                    tries.push((s, b, x, y));
+                }
+            }
+        }
+    }
+
+    // Repeated until it's taken long enough to time reliably
+    let begin = Instant::now();
+    let mut count = 0;
+    while begin.elapsed() < Duration::from_millis(500) {
+        let placed = tries.iter()
+            .filter(|&&(s, b, x, y)| s.try_place(b, x, y).is_some())
+            .count();
+        std::hint::black_box(placed);
+        count += tries.len();
+    }
+    (count, begin.elapsed())
+}
+
+// Handles `nmbr9 bench`, which times the solve of the selected bags
+// (by default, every bag of up to four tiles)
+fn run_bench(opts: &Options) {
+    let bags = targets(opts, Some(4));
+    let begin = Instant::now();
+    std::hint::black_box(Tables::build_fresh(opts.rules.adjacency,
+                                             opts.rules.mirrored));
+    let tables = begin.elapsed().as_secs_f64();
+    Tables::get(opts.rules.adjacency, opts.rules.mirrored);
+    let (tries, tried) = bench_try_place(opts.rules);
+    let tried = tried.as_secs_f64();
+
+    let solver = solver(opts);
+    let begin = Instant::now();
+    solver.sweep_bags(&bags);
+    let elapsed = begin.elapsed().as_secs_f64();
+
+    let results = solver.results().read().unwrap();
+    let solved = results.bags().iter()
+        .filter(|b| results.score(b).is_some())
+        .count();
+    status!("============================================================");
+    status!("BENCHMARK: built overlap tables in {:.3} seconds", tables);
+    status!("BENCHMARK: tried {} placements in {:.3} seconds ({:.1} million/second)",
+            tries, tried, tries as f64 / tried / 1e6);
+    status!("BENCHMARK: solved {} bags in {:.3} seconds ({:.1} bags/second) \
+             on {} threads", solved, elapsed, solved as f64 / elapsed,
+            rayon::current_num_threads());
+    status!("BENCHMARK: batches spent {:.3} seconds with idle threads \
+             (--chunk {})", solver.tail_time().as_secs_f64(), opts.chunk);
+    if let Some(ref filename) = opts.output {
+        write_results(filename, &results);
+    }
+}
+
+fn main() {
+    let opts = parse_options();
+    if let Some(ref set) = opts.pieces {
+        piece::use_pieces(set.clone());
+    }
+    theme::use_theme(opts.theme.clone());
+    if let Some(ref dir) = opts.table_cache {
+        Tables::cache_in(Path::new(dir));
+    }
+    if opts.numa {
+        setup_numa();
+    } else if let Some(n) = opts.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(n)
+            .build_global()
+            .expect("Failed to build thread pool");
+    }
+    match opts.command {
+        Command::Replay(ref filename, stop_at, delay) =>
+            run_replay(&opts, filename, stop_at, delay),
+        Command::Estimate(per_count) => run_estimate(&opts, per_count),
+        Command::Draw(ref order, ref results) => run_draw(&opts, order, results),
+        Command::Tower(ref bag, layers, ref results) =>
+            run_tower(&opts, bag, layers, results),
+        Command::Expect(ref bag, samples, ref strategy) =>
+            run_expect(&opts, bag, samples, strategy.as_deref()),
+        Command::Mcts(ref bag, iterations, ref results) =>
+            run_mcts(&opts, bag, iterations, results),
+        Command::Analyze(ref bag, ref results) => run_analyze(&opts, bag, results),
+        Command::Target(ref bag, target, ref results) =>
+            run_target(&opts, bag, target, results),
+        Command::Position(ref filename, ref bag, ref results) =>
+            run_position(&opts, filename, bag, results),
+        Command::Count(ref bag, up_to_rotation) =>
+            run_count(&opts, bag, up_to_rotation),
+        Command::Baseline(ref bag, runs, seed, ref results) =>
+            run_baseline(&opts, bag, runs, seed, results),
+        Command::Sim(ref players, deals, seed) => run_sim(&opts, players, deals, seed),
+        Command::Histogram(ref bag, up_to_rotation, samples) =>
+            run_histogram(&opts, bag, up_to_rotation, samples),
+        Command::Engine(ref strategy) => run_engine(&opts, strategy.as_deref()),
+        Command::Serve(ref addr) => run_serve(&opts, addr),
+        Command::Work(ref addr) => run_work(&opts, addr),
+        Command::Http(ref addr, ref results) => run_http(&opts, addr, results),
+        Command::Results(ref query) => run_results(&opts, query),
+        Command::Show(ref arg) => run_show(&opts, arg),
+        Command::Verify(ref filename) => run_verify(&opts, filename),
+        Command::Merge(ref inputs) => run_merge(&opts, inputs),
+        Command::Render => run_render(&opts),
+        Command::Bench => run_bench(&opts),
+        Command::Solve => run_solve(&opts),
     }
 }
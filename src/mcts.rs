@@ -0,0 +1,326 @@
+// Approximate solving by Monte Carlo tree search, for bags which are too
+// big to solve exactly.  Each action places one tile from the bag; a
+// rollout then places random tiles at random until the bag is empty, or
+// until Results::upper_score_bound shows that it can't beat the best
+// arrangement found so far.
+
+use std::time::{Duration, Instant};
+
+use bag::Bag;
+use expectimax::placements;
+use piece::{MAX_ROTATIONS, UNIQUE_PIECE_COUNT};
+use results::Results;
+use rules::Rules;
+use rng::Rng;
+use state::{Placed, State};
+
+pub struct Mcts<'a> {
+    rules: Rules,
+
+    // If present, solved bags tighten the bound used to cut rollouts short
+    results: Option<&'a Results>,
+
+    // Number of rollouts to run, and if present, a wall-clock budget which
+    // stops the search sooner
+    iterations: usize,
+    budget: Option<Duration>,
+
+    // Weight of the exploration term in UCB1
+    exploration: f64,
+    seed: u64,
+}
+
+struct Node {
+    state: State,
+    bag: Bag,
+    // The placement which made this node's state from its parent's
+    placed: Option<Placed>,
+    // Indices of child nodes, once this node has been expanded
+    children: Option<Vec<usize>>,
+    visits: u32,
+    total: f64,
+}
+
+// One search's tree, stored as an arena of nodes with the root first
+struct Tree<'b> {
+    rules: Rules,
+    results: &'b Results,
+    exploration: f64,
+    rng: Rng,
+    nodes: Vec<Node>,
+    best: (isize, State),
+}
+
+impl<'a> Mcts<'a> {
+    pub fn new(rules: Rules) -> Mcts<'a> {
+        Mcts { rules: rules, results: None, iterations: 10_000, budget: None,
+               exploration: 2f64.sqrt(), seed: 0 }
+    }
+
+    pub fn with_results(mut self, results: &'a Results) -> Mcts<'a> {
+        assert_eq!(results.rules(), self.rules);
+        self.results = Some(results);
+        self
+    }
+
+    pub fn with_iterations(mut self, iterations: usize) -> Mcts<'a> {
+        self.iterations = iterations;
+        self
+    }
+
+    pub fn with_budget(mut self, budget: Duration) -> Mcts<'a> {
+        self.budget = Some(budget);
+        self
+    }
+
+    pub fn with_exploration(mut self, exploration: f64) -> Mcts<'a> {
+        self.exploration = exploration;
+        self
+    }
+
+    // The search only depends on the seed (unless it's stopped by its
+    // budget), so it can be repeated
+    pub fn with_seed(mut self, seed: u64) -> Mcts<'a> {
+        self.seed = seed;
+        self
+    }
+
+    // Returns the best score and arrangement found for the bag.  This is
+    // only a lower bound on the bag's best score.
+    pub fn run(&self, bag: &Bag) -> (isize, State) {
+        let state = State::with_rules(&self.rules);
+        let fallback;
+        let results = match self.results {
+            Some(r) => r,
+            None => {
+                fallback = Results::new(self.rules);
+                &fallback
+            },
+        };
+        let mut tree = Tree::new(self, results, state, bag.clone());
+        self.search(&mut tree);
+        return tree.best;
+    }
+
+    // Returns the most promising placement of a tile (0-9) which was just
+    // drawn, in the state's coordinates, assuming that the remaining tiles
+    // (not counting the drawn one) are then placed in the best order
+    pub fn choose(&self, state: &State, tile: usize, remaining: &Bag)
+        -> Placed
+    {
+        let fallback;
+        let results = match self.results {
+            Some(r) => r,
+            None => {
+                fallback = Results::new(self.rules);
+                &fallback
+            },
+        };
+        let bag = remaining.add(tile * MAX_ROTATIONS);
+        let mut tree = Tree::new(self, results, state.clone(), bag);
+        let root = tree.expand(0, Some(tile));
+        self.search(&mut tree);
+
+        return root.iter().map(|&c| &tree.nodes[c])
+            .max_by_key(|n| n.visits)
+            .and_then(|n| n.placed)
+            .expect("Tiles can always be placed on the table");
+    }
+
+    fn search(&self, tree: &mut Tree) {
+        let start = Instant::now();
+        for _ in 0..self.iterations {
+            if self.budget.map(|b| start.elapsed() >= b).unwrap_or(false) {
+                break;
+            }
+            if !tree.iterate() {
+                break;
+            }
+        }
+    }
+}
+
+impl<'b> Tree<'b> {
+    fn new(m: &Mcts, results: &'b Results, state: State, bag: Bag)
+        -> Tree<'b>
+    {
+        let score = m.rules.score(&state);
+        let root = Node { state: state.clone(), bag: bag, placed: None,
+                          children: None, visits: 0, total: 0.0 };
+        Tree { rules: m.rules, results: results, exploration: m.exploration,
+               rng: Rng::new(m.seed), nodes: vec![root],
+               best: (score, state) }
+    }
+
+    // Returns a pseudo-random number below n, which only depends on the
+    // seed and how many numbers have been drawn before
+    fn random(&mut self, n: usize) -> usize {
+        return self.rng.below(n);
+    }
+
+    // Returns the best score that the state could reach with the tiles in
+    // the bag
+    fn bound(&self, state: &State, bag: &Bag) -> isize {
+        if bag.is_empty() {
+            return self.rules.score(state);
+        }
+        self.rules.score(state) + self.results.upper_score_bound(bag, state)
+    }
+
+    fn improve(&mut self, state: &State) {
+        let score = self.rules.score(state);
+        if score > self.best.0 {
+            self.best = (score, state.clone());
+        }
+    }
+
+    // Adds a node's children, skipping those which can't beat the best
+    // score, and returns their indices.  If a tile is given, only its
+    // placements are children.
+    fn expand(&mut self, i: usize, tile: Option<usize>) -> Vec<usize> {
+        let (state, bag) = (self.nodes[i].state.clone(),
+                            self.nodes[i].bag.clone());
+        let mut children = Vec::new();
+        for t in (0..UNIQUE_PIECE_COUNT)
+            .filter(|&t| bag.digit(t) > 0 && tile.map(|u| u == t).unwrap_or(true))
+        {
+            let rest = bag.take(t * MAX_ROTATIONS);
+            for (p, s) in placements(&state, t, &self.rules) {
+                // The chosen tile is always a child, so there's a move
+                if tile.is_none() && self.bound(&s, &rest) <= self.best.0 {
+                    continue;
+                }
+                children.push(self.nodes.len());
+                self.nodes.push(Node { state: s, bag: rest.clone(),
+                                       placed: Some(p), children: None,
+                                       visits: 0, total: 0.0 });
+            }
+        }
+        self.nodes[i].children = Some(children.clone());
+        return children;
+    }
+
+    // Picks the child with the highest UCB1 score, trying unvisited
+    // children first.  Rewards are scaled by the root's bound, so that
+    // they're roughly between 0 and 1.
+    fn select(&self, i: usize, children: &[usize]) -> Option<usize> {
+        let scale = self.bound(&self.nodes[0].state, &self.nodes[0].bag)
+            .max(1) as f64;
+        let ln = (self.nodes[i].visits.max(1) as f64).ln();
+        let ucb = |c: usize| {
+            let n = &self.nodes[c];
+            if n.visits == 0 {
+                return f64::INFINITY;
+            }
+            n.total / n.visits as f64 / scale +
+                self.exploration * (ln / n.visits as f64).sqrt()
+        };
+        // Children which can't beat the best score are skipped, as are
+        // those with no children of their own (other than full boards)
+        let open = |c: usize| {
+            let n = &self.nodes[c];
+            self.bound(&n.state, &n.bag) > self.best.0 &&
+                (n.bag.is_empty() || n.children.as_ref()
+                     .map(|c| !c.is_empty()).unwrap_or(true))
+        };
+        children.iter().cloned()
+            .filter(|&c| open(c))
+            .max_by(|&a, &b| ucb(a).partial_cmp(&ucb(b)).unwrap())
+    }
+
+    // Places random tiles until the bag is empty, or until the arrangement
+    // can't beat the best score, returning the (estimated) final score
+    fn rollout(&mut self, mut state: State, mut bag: Bag) -> f64 {
+        loop {
+            let bound = self.bound(&state, &bag);
+            if bag.is_empty() || bound <= self.best.0 {
+                self.improve(&state);
+                return bound as f64;
+            }
+            let tiles: Vec<usize> = (0..UNIQUE_PIECE_COUNT)
+                .filter(|&t| bag.digit(t) > 0).collect();
+            let t = tiles[self.random(tiles.len())];
+            let moves = placements(&state, t, &self.rules);
+            if moves.is_empty() {
+                // The tile can't go anywhere (e.g. under a layer limit)
+                self.improve(&state);
+                return self.rules.score(&state) as f64;
+            }
+            let k = self.random(moves.len());
+            state = moves.into_iter().nth(k).unwrap().1;
+            bag = bag.take(t * MAX_ROTATIONS);
+        }
+    }
+
+    // Runs one round of selection, expansion, rollout, and backup.
+    // Returns false once there's nothing left which could beat the best
+    // score.
+    fn iterate(&mut self) -> bool {
+        let mut path = vec![0];
+        let mut i = 0;
+        while !self.nodes[i].bag.is_empty() {
+            let (children, expanded) = match self.nodes[i].children.clone() {
+                Some(c) => (c, false),
+                None => (self.expand(i, None), true),
+            };
+            match self.select(i, &children) {
+                Some(j) => {
+                    path.push(j);
+                    i = j;
+                },
+                None if i == 0 => return false,
+                None => break,
+            }
+            // Only one node is added per round
+            if expanded {
+                break;
+            }
+        }
+
+        let (state, bag) = (self.nodes[i].state.clone(),
+                            self.nodes[i].bag.clone());
+        let reward = self.rollout(state, bag);
+        for &n in &path {
+            self.nodes[n].visits += 1;
+            self.nodes[n].total += reward;
+        }
+        return true;
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run() {
+        // The 1 bridges two 0s for a point, and a few rollouts find it
+        let bag = Bag::from_usize(0).add(0).add(0).add(4);
+        let (score, state) = Mcts::new(Rules::default())
+            .with_iterations(200).run(&bag);
+        assert_eq!(score, 1);
+        assert_eq!(state.pieces.len(), 3);
+        assert_eq!(state.score(), 1);
+
+        // The same seed gives the same search
+        let m = Mcts::new(Rules::default()).with_iterations(20).with_seed(3);
+        assert_eq!(m.run(&bag), m.run(&bag));
+    }
+
+    #[test]
+    fn choose() {
+        // With a 1 left to draw, the second 0 goes where it can be bridged
+        let one = State::new().try_place(0, 0, 0).unwrap();
+        let rest = Bag::from_usize(0).add(4);
+        let p = Mcts::new(Rules::default()).with_iterations(500)
+            .choose(&one, 0, &rest);
+        assert_eq!(p.index(), 0);
+        let two = one.try_place(p.id(), p.x, p.y)
+            .unwrap();
+        let best = placements(&two, 1, &Rules::default()).into_iter()
+            .map(|(_, s)| s.score()).max();
+        assert_eq!(best, Some(1));
+    }
+}
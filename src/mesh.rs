@@ -0,0 +1,194 @@
+// Solid geometry for 3D-printing an arrangement, as STL or OpenSCAD
+
+use std::collections::HashSet;
+
+use theme::theme;
+use piece;
+use state::State;
+
+// Physical size of the printed arrangement, in millimeters
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Dimensions {
+    // Width of one grid cell
+    pub cell: f64,
+    // Thickness of one tile
+    pub layer: f64,
+}
+
+impl Default for Dimensions {
+    fn default() -> Dimensions {
+        Dimensions { cell: 10.0, layer: 3.0 }
+    }
+}
+
+type Vertex = [f64; 3];
+
+// A piece's tile, layer, and the grid cells that it covers
+type Cells = (usize, usize, Vec<(i32, i32)>);
+
+// Returns the cells of each piece.  Cells are flipped in both x and y so
+// that the model, seen from above, matches pretty_print (which draws y
+// downwards); flipping both is a rotation, so tiles aren't mirrored.
+fn pieces(state: &State) -> Vec<Cells> {
+    let (w, h) = state.size();
+    state.pieces.iter().map(|p| {
+        let shape = piece::pieces().shape(p.id());
+        let cells = shape.pts.iter()
+            .map(|&(cx, cy)| (w - 1 - (p.x + cx), h - 1 - (p.y + cy)))
+            .collect();
+        (p.index(), p.z, cells)
+    }).collect()
+}
+
+// Returns the triangles on the surface of each piece, along with their
+// outward normals.  Each piece is one closed solid:  faces between cells
+// of the same piece are left out, so its cells are merged together.
+fn triangles(state: &State, dims: Dimensions) -> Vec<(Vertex, [Vertex; 3])> {
+    let mut out = Vec::new();
+    for (_, z, cells) in pieces(state) {
+        let filled: HashSet<(i32, i32)> = cells.iter().cloned().collect();
+        let (z0, z1) = (z as f64 * dims.layer, (z + 1) as f64 * dims.layer);
+        for &(x, y) in &cells {
+            let (x0, x1) = (x as f64 * dims.cell, (x + 1) as f64 * dims.cell);
+            let (y0, y1) = (y as f64 * dims.cell, (y + 1) as f64 * dims.cell);
+
+            // Each face's corners go counterclockwise, seen from outside
+            let mut faces = vec![
+                ([0.0, 0.0, 1.0], [[x0, y0, z1], [x1, y0, z1],
+                                   [x1, y1, z1], [x0, y1, z1]]),
+                ([0.0, 0.0, -1.0], [[x0, y0, z0], [x0, y1, z0],
+                                    [x1, y1, z0], [x1, y0, z0]]),
+            ];
+            if !filled.contains(&(x + 1, y)) {
+                faces.push(([1.0, 0.0, 0.0], [[x1, y0, z0], [x1, y1, z0],
+                                              [x1, y1, z1], [x1, y0, z1]]));
+            }
+            if !filled.contains(&(x - 1, y)) {
+                faces.push(([-1.0, 0.0, 0.0], [[x0, y1, z0], [x0, y0, z0],
+                                               [x0, y0, z1], [x0, y1, z1]]));
+            }
+            if !filled.contains(&(x, y + 1)) {
+                faces.push(([0.0, 1.0, 0.0], [[x1, y1, z0], [x0, y1, z0],
+                                              [x0, y1, z1], [x1, y1, z1]]));
+            }
+            if !filled.contains(&(x, y - 1)) {
+                faces.push(([0.0, -1.0, 0.0], [[x0, y0, z0], [x1, y0, z0],
+                                               [x1, y0, z1], [x0, y0, z1]]));
+            }
+            for (n, q) in faces {
+                out.push((n, [q[0], q[1], q[2]]));
+                out.push((n, [q[0], q[2], q[3]]));
+            }
+        }
+    }
+    return out;
+}
+
+// Writes the arrangement as an ASCII STL file
+pub fn stl(state: &State, dims: Dimensions) -> String {
+    let mut out = "solid nmbr9\n".to_string();
+    for (n, tri) in triangles(state, dims) {
+        out += &format!("  facet normal {} {} {}\n    outer loop\n",
+                        n[0], n[1], n[2]);
+        for v in tri.iter() {
+            out += &format!("      vertex {} {} {}\n", v[0], v[1], v[2]);
+        }
+        out += "    endloop\n  endfacet\n";
+    }
+    out += "endsolid nmbr9\n";
+    return out;
+}
+
+// Writes the arrangement as an OpenSCAD script, with one colored solid per
+// piece.  The dimensions are variables at the top of the script, so they
+// can be tweaked without exporting again.
+pub fn openscad(state: &State, dims: Dimensions) -> String {
+    let mut out = format!("// NMBR 9 arrangement, exported by nmbr9\n\
+                           cell = {};\nlayer = {};\n\n",
+                          dims.cell, dims.layer);
+    for (index, z, cells) in pieces(state) {
+        let c = theme().rgb[index];
+        out += &format!("// Tile {} on layer {}\n\
+                         color([{:.3}, {:.3}, {:.3}])\n\
+                         translate([0, 0, {} * layer])\n\
+                         linear_extrude(layer) union() {{\n",
+                        index, z, c[0] as f64 / 255.0, c[1] as f64 / 255.0,
+                        c[2] as f64 / 255.0, z);
+        for (x, y) in cells {
+            out += &format!("    translate([{} * cell, {} * cell]) square(cell);\n",
+                            x, y);
+        }
+        out += "}\n";
+    }
+    return out;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use piece::{UNIQUE_PIECE_COUNT, MAX_ROTATIONS};
+
+    fn state() -> State {
+        State::new().try_place(0, 0, 0).unwrap()
+            .try_place(0, 3, 0).unwrap()
+            .try_place(4, 2, 0).unwrap()
+    }
+
+    #[test]
+    fn triangles() {
+        let dims = Dimensions { cell: 1.0, layer: 1.0 };
+
+        // Every tile is watertight:  each edge of each triangle is shared
+        // with exactly one other triangle, which runs along it backwards
+        let key = |v: Vertex| (v[0] as i64, v[1] as i64, v[2] as i64);
+        for i in 0..UNIQUE_PIECE_COUNT {
+            let tile = State::new().try_place(i * MAX_ROTATIONS + 1, 0, 0)
+                .unwrap();
+            let mut edges = HashMap::new();
+            for (_, t) in super::triangles(&tile, dims) {
+                for j in 0..3 {
+                    let e = (key(t[j]), key(t[(j + 1) % 3]));
+                    *edges.entry(e).or_insert(0) += 1;
+                }
+            }
+            for (&(a, b), &n) in &edges {
+                assert_eq!(n, 1);
+                assert_eq!(edges.get(&(b, a)), Some(&1));
+            }
+        }
+        let tris = super::triangles(&state(), dims);
+
+        // Normals point out of each piece, and are consistent with the
+        // winding of their triangles
+        for (n, t) in &tris {
+            let u = [t[1][0] - t[0][0], t[1][1] - t[0][1], t[1][2] - t[0][2]];
+            let v = [t[2][0] - t[0][0], t[2][1] - t[0][1], t[2][2] - t[0][2]];
+            let cross = [u[1] * v[2] - u[2] * v[1], u[2] * v[0] - u[0] * v[2],
+                         u[0] * v[1] - u[1] * v[0]];
+            assert!(cross[0] * n[0] + cross[1] * n[1] + cross[2] * n[2] > 0.0);
+        }
+
+        // The top piece sits one layer up
+        let top = tris.iter().map(|(_, t)| t[0][2].max(t[1][2]).max(t[2][2]))
+            .fold(0.0, f64::max);
+        assert_eq!(top, 2.0);
+    }
+
+    #[test]
+    fn formats() {
+        let stl = stl(&state(), Dimensions::default());
+        assert!(stl.starts_with("solid nmbr9\n  facet normal 0 0 1\n"));
+        assert!(stl.ends_with("endsolid nmbr9\n"));
+        assert!(stl.contains(" 6\n"));
+        assert!(!stl.contains(" 9\n"));
+
+        let scad = openscad(&state(), Dimensions::default());
+        assert!(scad.contains("cell = 10;\nlayer = 3;\n"));
+        assert_eq!(scad.matches("linear_extrude").count(), 3);
+        assert!(scad.contains("// Tile 1 on layer 1\n"));
+        assert!(scad.contains("translate([0, 0, 1 * layer])"));
+    }
+}
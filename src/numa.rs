@@ -0,0 +1,135 @@
+use std::cell::Cell;
+use std::fs;
+use std::mem;
+use std::path::Path;
+
+use libc;
+use rayon;
+
+thread_local! {
+    // The NUMA node that this thread is pinned to (0 if unpinned)
+    static NODE: Cell<usize> = const { Cell::new(0) };
+}
+
+// Returns the CPUs belonging to each NUMA node, as reported by sysfs.
+// On machines without NUMA (or without sysfs), this is empty.
+pub fn nodes() -> Vec<Vec<usize>> {
+    nodes_in(Path::new("/sys/devices/system/node"))
+}
+
+// As nodes, from a directory laid out like sysfs's node directory
+fn nodes_in(root: &Path) -> Vec<Vec<usize>> {
+    let mut out = Vec::new();
+    while let Ok(s) = fs::read_to_string(
+            root.join(format!("node{}", out.len())).join("cpulist"))
+    {
+        out.push(parse_cpulist(s.trim()));
+    }
+    return out;
+}
+
+// Returns a thread pool builder with a thread for every CPU of every node,
+// where thread i is pinned to node i % nodes.len(), so that the threads
+// are spread evenly across the nodes
+pub fn pool(nodes: Vec<Vec<usize>>) -> rayon::ThreadPoolBuilder {
+    let threads: usize = nodes.iter().map(|n| n.len()).sum();
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .start_handler(move |i| {
+            let node = i % nodes.len();
+            pin(node, &nodes[node]);
+        })
+}
+
+// Parses a list like "0-3,8-11" into individual CPU numbers
+fn parse_cpulist(s: &str) -> Vec<usize> {
+    let mut out = Vec::new();
+    for range in s.split(',').filter(|r| !r.is_empty()) {
+        let mut ends = range.splitn(2, '-').filter_map(|n| n.parse::<usize>().ok());
+        if let Some(lo) = ends.next() {
+            let hi = ends.next().unwrap_or(lo);
+            out.extend(lo..=hi);
+        }
+    }
+    return out;
+}
+
+pub fn current_node() -> usize {
+    NODE.with(|n| n.get())
+}
+
+// Pins the calling thread to the given node's CPUs.  Memory is placed on
+// the node which first touches it, so anything which the thread allocates
+// and fills in afterwards will be local to that node.
+pub fn pin(node: usize, cpus: &[usize]) {
+    NODE.with(|n| n.set(node));
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        for &c in cpus {
+            libc::CPU_SET(c, &mut set);
+        }
+        if libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(),
+                                   &set) != 0 {
+            warning!("Failed to pin thread to NUMA node {}", node);
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The CPUs which this process may run on, which a test can pin its
+    // threads to (as if they belonged to any node)
+    fn allowed_cpus() -> Vec<usize> {
+        unsafe {
+            let mut set: libc::cpu_set_t = mem::zeroed();
+            libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(),
+                                    &mut set);
+            (0..libc::CPU_SETSIZE as usize)
+                .filter(|&c| libc::CPU_ISSET(c, &set))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn topology() {
+        use std::{env, process};
+
+        let root = env::temp_dir()
+            .join(format!("nmbr9-numa-{}", process::id()));
+        for (i, cpus) in ["0-3,8-11", "4-7,12-15"].iter().enumerate() {
+            let dir = root.join(format!("node{}", i));
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("cpulist"), format!("{}\n", cpus)).unwrap();
+        }
+        let nodes = nodes_in(&root);
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(nodes, vec![vec![0, 1, 2, 3, 8, 9, 10, 11],
+                               vec![4, 5, 6, 7, 12, 13, 14, 15]]);
+        assert!(nodes_in(&root).is_empty());
+    }
+
+    #[test]
+    fn spread() {
+        // Two nodes which share this machine's CPUs, so that the threads
+        // can be pinned to either
+        let cpus = allowed_cpus();
+        let pool = pool(vec![cpus.clone(), cpus.clone()]).build().unwrap();
+        let mut nodes = pool.broadcast(|_| current_node());
+        nodes.sort();
+        let half = cpus.len();
+        assert_eq!(nodes.len(), 2 * half);
+        assert!(nodes[..half].iter().all(|&n| n == 0));
+        assert!(nodes[half..].iter().all(|&n| n == 1));
+    }
+
+    #[test]
+    fn cpulist() {
+        assert_eq!(parse_cpulist("0"), vec![0]);
+        assert_eq!(parse_cpulist("0-3,8-9"), vec![0, 1, 2, 3, 8, 9]);
+        assert_eq!(parse_cpulist(""), Vec::<usize>::new());
+    }
+}
@@ -0,0 +1,129 @@
+use std::cmp::{min, max};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use bag::Bag;
+use results::Results;
+use state::State;
+use tuning::PieceOrder;
+use worker::Worker;
+
+pub const CONFIDENCE: f64 = 0.95;
+
+// An estimate of a bag's best score, from the best scores found by many
+// randomized searches that were each cut short.  This isn't a proof:  the
+// only thing known for certain is that the best score is at least `found`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ScoreEstimate {
+    // The best score that any of the searches actually reached
+    pub found: isize,
+
+    // Estimated best score, with a confidence interval around it
+    pub score: f64,
+    pub low: isize,
+    pub high: isize,
+    pub confidence: f64,
+
+    pub runs: usize,
+}
+
+impl ScoreEstimate {
+    // Estimates the best score from the best score found by each search,
+    // treating them as samples from a distribution whose upper endpoint
+    // is the true best score.  This uses the Robson-Whitlock estimator,
+    // which looks at the gap between the two highest samples: the point
+    // estimate is 2 X(1) - X(2), and the endpoint is below
+    // X(1) + (X(1) - X(2)) * (1 - a) / a with confidence 1 - a.
+    //
+    // `upper` is a proven upper bound on the score, which caps the interval.
+    // Returns None with fewer than two searches.
+    pub fn from_maxima(maxima: &[isize], confidence: f64, upper: isize)
+        -> Option<ScoreEstimate>
+    {
+        if maxima.len() < 2 {
+            return None;
+        }
+        let mut sorted = maxima.to_vec();
+        sorted.sort_by(|a, b| b.cmp(a));
+        let (x1, x2) = (sorted[0], sorted[1]);
+        let gap = (x1 - x2) as f64;
+
+        let alpha = 1.0 - confidence;
+        let high = x1 + (gap * (1.0 - alpha) / alpha).ceil() as isize;
+        let upper = max(upper, x1);
+        return Some(ScoreEstimate {
+            found: x1,
+            score: (x1 as f64 + gap).min(upper as f64),
+            low: x1,
+            high: min(high, upper),
+            confidence: confidence,
+            runs: maxima.len(),
+        });
+    }
+}
+
+// Runs the given number of randomized searches on a bag, each with its
+// own seed and the given budget, then records an estimate of its best
+// score in the results (along with the best score found, as a lower
+// bound).  Returns the estimate and the best arrangement found, which
+// is empty if no search beat the bag's subsets.
+pub fn sample(bag: &Bag, results: &RwLock<Results>, runs: usize,
+              budget: Duration, order: Option<PieceOrder>)
+    -> Option<(ScoreEstimate, State)>
+{
+    let mut maxima = Vec::new();
+    let mut best: Option<(isize, State)> = None;
+    for seed in 0..runs {
+        let mut worker = Worker::new(bag.clone(), results)
+            .with_seed(seed as u64)
+            .with_deadline(Instant::now() + budget);
+        if let Some(order) = order {
+            worker = worker.with_order(order);
+        }
+        let (score, state) = worker.run()?;
+
+        // A search which finished has solved the bag exactly, so there's
+        // nothing left to estimate
+        if !worker.timed_out() {
+            return None;
+        }
+        maxima.push(score);
+        if best.as_ref().map(|b| score > b.0).unwrap_or(true) {
+            best = Some((score, state));
+        }
+    }
+
+    let mut writer = results.write().unwrap();
+    let upper = writer.upper_score_bound(bag, &State::new());
+    let e = ScoreEstimate::from_maxima(&maxima, CONFIDENCE, upper)?;
+    writer.write_estimate(bag, e);
+    status!("Estimated best score {:.1} ({:.0}% interval {} to {}) from {} runs",
+            e.score, e.confidence * 100.0, e.low, e.high, e.runs);
+    return best.map(|(_, state)| (e, state));
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_maxima() {
+        assert!(ScoreEstimate::from_maxima(&[10], 0.95, 100).is_none());
+
+        let e = ScoreEstimate::from_maxima(&[7, 10, 9, 8], 0.95, 100).unwrap();
+        assert_eq!(e.found, 10);
+        assert_eq!(e.score, 11.0);
+        assert_eq!((e.low, e.high), (10, 29));
+        assert_eq!(e.runs, 4);
+
+        // The interval can't go past a proven bound
+        let e = ScoreEstimate::from_maxima(&[7, 10], 0.95, 20).unwrap();
+        assert_eq!((e.score, e.low, e.high), (13.0, 10, 20));
+
+        // If every search agrees, then so does the interval
+        let e = ScoreEstimate::from_maxima(&[12, 12, 12], 0.95, 20).unwrap();
+        assert_eq!((e.score, e.low, e.high), (12.0, 12, 12));
+    }
+}
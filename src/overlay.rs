@@ -0,0 +1,182 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bag::Bag;
+use card;
+use state::State;
+
+// The overlay's throttling state, shared with the thread which writes a
+// held-back best once its interval is up
+#[derive(Default)]
+struct Pending {
+    // When the files were last written, and how many improvements there
+    // have been in total
+    written: Option<Instant>,
+    improvements: usize,
+
+    // The latest best, if it hasn't been written yet, and whether a thread
+    // is waiting to write it
+    best: Option<(Bag, isize, State)>,
+    timer: bool,
+}
+
+// Writes the latest best solution to fixed paths in a directory, for use
+// as file sources in streaming software:  best.png is its score card, and
+// stats.txt is a few lines of plain text about the run.
+pub struct Overlay {
+    dir: PathBuf,
+    start: Instant,
+
+    // Small bags improve thousands of times a second, so files are only
+    // rewritten this often.  The latest best is always written in the
+    // end, either once the interval is up or by finish().
+    interval: Duration,
+
+    pending: Arc<Mutex<Pending>>,
+}
+
+impl Overlay {
+    pub fn new(dir: &Path) -> io::Result<Overlay> {
+        fs::create_dir_all(dir)?;
+        Ok(Overlay {
+            dir: dir.to_path_buf(),
+            start: Instant::now(),
+            interval: Duration::from_millis(250),
+            pending: Arc::new(Mutex::new(Pending::default())),
+        })
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Overlay {
+        self.interval = interval;
+        self
+    }
+
+    // Called by workers whenever they find a new best score
+    pub fn improved(&self, bag: &Bag, score: isize, state: &State) {
+        let mut pending = self.pending.lock().unwrap();
+        pending.improvements += 1;
+        pending.best = Some((bag.clone(), score, state.clone()));
+
+        let wait = pending.written
+            .map(|t| self.interval.saturating_sub(t.elapsed()))
+            .unwrap_or_default();
+        if wait == Duration::from_secs(0) {
+            self.flush(&mut pending);
+        } else if !pending.timer {
+            pending.timer = true;
+            let overlay = Overlay {
+                dir: self.dir.clone(),
+                start: self.start,
+                interval: self.interval,
+                pending: self.pending.clone(),
+            };
+            thread::spawn(move || {
+                thread::sleep(wait);
+                overlay.expire();
+            });
+        }
+    }
+
+    // Writes the latest best, if it hasn't been written already.  Call this
+    // once the run is over, so that a best found within the interval
+    // isn't lost.
+    pub fn finish(&self) {
+        self.flush(&mut self.pending.lock().unwrap());
+    }
+
+    // Called by the waiting thread once the interval is up
+    fn expire(&self) {
+        let mut pending = self.pending.lock().unwrap();
+        pending.timer = false;
+        self.flush(&mut pending);
+    }
+
+    fn flush(&self, pending: &mut Pending) {
+        if let Some((bag, score, state)) = pending.best.take() {
+            pending.written = Some(Instant::now());
+            if let Err(e) = self.write(&bag, score, &state,
+                                       pending.improvements) {
+                warning!("Failed to write overlay to {:?}: {}", self.dir, e);
+            }
+        }
+    }
+
+    fn write(&self, bag: &Bag, score: isize, state: &State,
+             improvements: usize) -> io::Result<()> {
+        self.replace("best.png", |f| {
+            card::score_card(bag, score, state).write_png(f)
+        })?;
+        self.replace("stats.txt", |mut f| {
+            writeln!(f, "Bag: {}", card::tiles(bag))?;
+            writeln!(f, "Score: {}", score)?;
+            writeln!(f, "Improvements: {}", improvements)?;
+            writeln!(f, "Elapsed: {}s", self.start.elapsed().as_secs())
+        })
+    }
+
+    // Writes a file next to its final path, then renames it into place, so
+    // that readers never see it half-written
+    fn replace<F>(&self, name: &str, f: F) -> io::Result<()>
+        where F: FnOnce(File) -> io::Result<()>
+    {
+        let path = self.dir.join(name);
+        let tmp = self.dir.join(format!(".{}.tmp", name));
+        f(File::create(&tmp)?)?;
+        fs::rename(&tmp, &path)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn improved() {
+        let dir = env::temp_dir()
+            .join(format!("nmbr9-overlay-{}", process::id()));
+        let overlay = Overlay::new(&dir).unwrap()
+            .with_interval(Duration::from_secs(3600));
+        let bag = Bag::from_usize(0).add(0).add(4);
+        let state = State::new().try_place(0, 0, 0).unwrap();
+
+        overlay.improved(&bag, 0, &state);
+        let stats = fs::read_to_string(dir.join("stats.txt")).unwrap();
+        assert!(stats.contains("Bag: 0 1\n"));
+        assert!(stats.contains("Score: 0\n"));
+        assert!(dir.join("best.png").exists());
+
+        // Within the interval, improvements are held back, but the latest
+        // is written in the end
+        overlay.improved(&bag, 1, &state);
+        overlay.improved(&bag, 2, &state);
+        let stats = fs::read_to_string(dir.join("stats.txt")).unwrap();
+        assert!(stats.contains("Score: 0\n"));
+        overlay.finish();
+        let stats = fs::read_to_string(dir.join("stats.txt")).unwrap();
+        assert!(stats.contains("Score: 2\n"));
+        assert!(stats.contains("Improvements: 3\n"));
+
+        // ...or once the interval is up, without any more improvements
+        let overlay = Overlay::new(&dir).unwrap()
+            .with_interval(Duration::from_secs(3600));
+        overlay.improved(&bag, 2, &state);
+        overlay.improved(&bag, 3, &state);
+        let stats = fs::read_to_string(dir.join("stats.txt")).unwrap();
+        assert!(stats.contains("Score: 2\n"));
+        assert!(overlay.pending.lock().unwrap().timer);
+        overlay.expire();
+        assert!(!overlay.pending.lock().unwrap().timer);
+        let stats = fs::read_to_string(dir.join("stats.txt")).unwrap();
+        assert!(stats.contains("Score: 3\n"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
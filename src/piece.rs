@@ -1,9 +1,33 @@
+use std::sync::OnceLock;
+
 use colored::Color;
+use toml;
+
+use rng::Rng;
 
 pub const UNIQUE_PIECE_COUNT: usize = 10;
+
+// Most copies of any one tile that a game may have.  Bags, states, and
+// results are all sized from this and UNIQUE_PIECE_COUNT, so an expanded
+// set only needs these changed (and its shapes, values, and colors added).
+pub const MAX_COPIES: usize = 2;
 pub const MAX_ROTATIONS: usize = 4;
+
+// Under the mirrored rule, tiles may also be flipped over, giving each
+// one up to twice as many orientations.  Orientations past MAX_ROTATIONS
+// are the mirror image, turned by the orientation less MAX_ROTATIONS.
+pub const MAX_ORIENTATIONS: usize = 2 * MAX_ROTATIONS;
+
+// Piece ids are tile * MAX_ROTATIONS + rotation, for the 40 unmirrored
+// pieces.  Mirrored pieces come after them, numbered the same way from
+// MIRRORED_ID, so that the ids of unmirrored pieces (which saved files
+// are full of) stay as they were.
+pub const MIRRORED_ID: usize = UNIQUE_PIECE_COUNT * MAX_ROTATIONS;
+pub const PIECE_ID_COUNT: usize = 2 * MIRRORED_ID;
 pub const MAX_EDGE_LENGTH: i32 = 4;
 
+// Shapes of the standard tiles, as 4x4 bitmaps read a row at a time from
+// the most significant bit.  Other sets can be loaded with PieceSet::parse.
 pub const PIECES: [u16; UNIQUE_PIECE_COUNT] = [
 0b1110101010101110, // 0
 0b1100010001000100, // 1
@@ -17,6 +41,60 @@ pub const PIECES: [u16; UNIQUE_PIECE_COUNT] = [
 0b1110111011001100, // 9
 ];
 
+// Number of distinct rotations of each piece.  The 0 and 8 look the same
+// when turned halfway around, so only their first two rotations need to
+// be tried; rotations past these repeat the earlier ones.
+pub const DISTINCT_ROTATIONS: [usize; UNIQUE_PIECE_COUNT] =
+    [2, 4, 4, 4, 4, 4, 4, 4, 2, 4];
+
+// Whether each piece differs from its mirror image (in every rotation).
+// Only the 0 is symmetric, so it has no mirrored orientations to try.
+pub const CHIRAL: [bool; UNIQUE_PIECE_COUNT] =
+    [false, true, true, true, true, true, true, true, true, true];
+
+// Returns the id of a tile in the given orientation (see MAX_ORIENTATIONS)
+pub fn piece_id(tile: usize, orientation: usize) -> usize {
+    debug_assert!(orientation < MAX_ORIENTATIONS);
+    if orientation < MAX_ROTATIONS {
+        return tile * MAX_ROTATIONS + orientation;
+    } else {
+        return MIRRORED_ID + tile * MAX_ROTATIONS + orientation - MAX_ROTATIONS;
+    }
+}
+
+// Returns the tile (0-9) of a piece id, mirrored or not
+pub fn tile(id: usize) -> usize {
+    id % MIRRORED_ID / MAX_ROTATIONS
+}
+
+// Returns the shape of a piece id, given the shapes of a set's tiles
+pub fn shape_of(shapes: &[u16; UNIQUE_PIECE_COUNT], id: usize) -> Piece {
+    let p = Piece::from_u16(shapes[tile(id)]);
+    let p = if id >= MIRRORED_ID { p.mirror() } else { p };
+    return p.rotn(id % MAX_ROTATIONS);
+}
+
+// Tile values are signed, since custom sets may include penalty tiles
+pub type Values = [isize; UNIQUE_PIECE_COUNT];
+
+// Point value of each piece, kept separate from its shape (PIECES) so that
+// custom sets can reuse one with a different value.  For the standard tiles,
+// this matches the index; nothing else should assume that it does.
+pub const PIECE_VALUES: Values = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+
+// Number of cells covered by each standard piece, evaluated at compile time
+pub const PIECE_AREA: [usize; UNIQUE_PIECE_COUNT] = piece_areas();
+
+const fn piece_areas() -> [usize; UNIQUE_PIECE_COUNT] {
+    let mut out = [0; UNIQUE_PIECE_COUNT];
+    let mut i = 0;
+    while i < UNIQUE_PIECE_COUNT {
+        out[i] = PIECES[i].count_ones() as usize;
+        i += 1;
+    }
+    out
+}
+
 pub const PIECE_COLORS: [Color; UNIQUE_PIECE_COUNT] = [
     Color::White,
     Color::Yellow,
@@ -32,6 +110,189 @@ pub const PIECE_COLORS: [Color; UNIQUE_PIECE_COUNT] = [
 
 ////////////////////////////////////////////////////////////////////////////////
 
+// A set of tile shapes, along with the facts about them that the solver
+// looks up while searching.  Everything reads the set through pieces(),
+// which is the standard set unless use_pieces picked another first.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PieceSet {
+    pub shapes: [u16; UNIQUE_PIECE_COUNT],
+    pub areas: [usize; UNIQUE_PIECE_COUNT],
+    pub distinct_rotations: [usize; UNIQUE_PIECE_COUNT],
+    pub chiral: [bool; UNIQUE_PIECE_COUNT],
+}
+
+static PIECE_SET: OnceLock<PieceSet> = OnceLock::new();
+
+pub fn pieces() -> &'static PieceSet {
+    PIECE_SET.get_or_init(PieceSet::standard)
+}
+
+// Switches every later search to the given piece set.  This must be
+// called before anything uses the pieces (in particular, before the
+// overlap tables are built).
+pub fn use_pieces(set: PieceSet) {
+    if PIECE_SET.set(set).is_err() {
+        panic!("Pieces were already in use");
+    }
+}
+
+impl PieceSet {
+    pub fn standard() -> PieceSet {
+        PieceSet {
+            shapes: PIECES,
+            areas: PIECE_AREA,
+            distinct_rotations: DISTINCT_ROTATIONS,
+            chiral: CHIRAL,
+        }
+    }
+
+    // Identifies the shapes, for files whose scores only hold for them.
+    // This only depends on the shapes, so it's the same with any release.
+    pub fn fingerprint(&self) -> u64 {
+        Rng::keyed(self.shapes.iter().map(|&s| s as u64)).next_u64()
+    }
+
+    // Returns the shape of the given piece id
+    pub fn shape(&self, id: usize) -> Piece {
+        shape_of(&self.shapes, id)
+    }
+
+    // Returns the ids of a tile's distinct orientations:  its distinct
+    // rotations, then (if mirrored pieces are allowed, and the tile isn't
+    // its own mirror image) the same rotations of its mirror image
+    pub fn orientations(&self, tile: usize, mirrored: bool)
+        -> impl Iterator<Item=usize>
+    {
+        let n = self.distinct_rotations[tile];
+        let flips = if mirrored && self.chiral[tile] { 2 } else { 1 };
+        (0..flips).flat_map(move |f| (0..n)
+            .map(move |r| piece_id(tile, f * MAX_ROTATIONS + r)))
+    }
+
+    // Checks that each shape is a single non-empty piece, then works out
+    // its area, how many of its rotations are distinct, and whether it
+    // differs from its mirror image
+    pub fn new(shapes: [u16; UNIQUE_PIECE_COUNT]) -> Result<PieceSet, String> {
+        let mut out = PieceSet {
+            shapes: shapes,
+            areas: [0; UNIQUE_PIECE_COUNT],
+            distinct_rotations: [MAX_ROTATIONS; UNIQUE_PIECE_COUNT],
+            chiral: [true; UNIQUE_PIECE_COUNT],
+        };
+        for i in 0..UNIQUE_PIECE_COUNT {
+            let p = Piece::from_u16(shapes[i]);
+            if p.pts.is_empty() {
+                return Err(format!("Piece {} is empty", i));
+            }
+
+            // Flood-fill from one cell, which should reach all of them
+            let mut todo = vec![p.pts[0]];
+            let mut seen = vec![p.pts[0]];
+            while let Some((x, y)) = todo.pop() {
+                for &(dx, dy) in Adjacency::Orthogonal.offsets() {
+                    let q = (x + dx, y + dy);
+                    if p.at(q.0, q.1) && !seen.contains(&q) {
+                        seen.push(q);
+                        todo.push(q);
+                    }
+                }
+            }
+            if seen.len() != p.pts.len() {
+                return Err(format!("Piece {} isn't connected", i));
+            }
+
+            out.areas[i] = p.pts.len();
+            out.distinct_rotations[i] = (1..=MAX_ROTATIONS)
+                .find(|&r| p.rotn(r).normalized() == p.normalized())
+                .unwrap();
+            out.chiral[i] = is_chiral(&p);
+        }
+        return Ok(out);
+    }
+
+    // Parses a piece set from a TOML file with a list of ten shapes, each
+    // drawn with # for filled cells and . for empty ones, with rows split
+    // by newlines or slashes, e.g.
+    //
+    //   pieces = ["###/#.#/#.#/###", "##/.#/.#/.#", ...]
+    //
+    // Each shape fits in a 4x4 grid, drawn like the bitmaps in PIECES.
+    pub fn parse(text: &str) -> Result<PieceSet, String> {
+        let table: toml::Table = text.parse().map_err(|e| format!("{}", e))?;
+        if let Some(key) = table.keys().find(|&k| k != "pieces") {
+            return Err(format!("Unknown key '{}'", key));
+        }
+        let list = table.get("pieces").and_then(|v| v.as_array())
+            .ok_or("Missing list of pieces")?;
+        if list.len() != UNIQUE_PIECE_COUNT {
+            return Err(format!("Expected {} pieces, found {}",
+                               UNIQUE_PIECE_COUNT, list.len()));
+        }
+        let mut shapes = [0; UNIQUE_PIECE_COUNT];
+        for (i, v) in list.iter().enumerate() {
+            let art = v.as_str()
+                .ok_or_else(|| format!("Piece {} isn't a string", i))?;
+            shapes[i] = parse_shape(art)
+                .map_err(|e| format!("Piece {}: {}", i, e))?;
+        }
+        return PieceSet::new(shapes);
+    }
+}
+
+// Parses one shape drawn as described in PieceSet::parse
+fn parse_shape(art: &str) -> Result<u16, String> {
+    let rows: Vec<&str> = art.split(['\n', '/'])
+        .map(|r| r.trim())
+        .filter(|r| !r.is_empty())
+        .collect();
+    if rows.len() > 4 {
+        return Err("more than 4 rows".to_string());
+    }
+    let mut out = 0;
+    for (y, row) in rows.iter().enumerate() {
+        if row.chars().count() > 4 {
+            return Err(format!("row '{}' is wider than 4 cells", row));
+        }
+        for (x, c) in row.chars().enumerate() {
+            match c {
+                '#' => out |= 1 << (15 - (y * 4 + x)),
+                '.' => (),
+                _ => return Err(format!("invalid cell '{}'", c)),
+            }
+        }
+    }
+    return Ok(out);
+}
+
+// Checks whether no rotation of a piece matches its mirror image
+fn is_chiral(p: &Piece) -> bool {
+    let m = p.mirror().normalized();
+    return (0..MAX_ROTATIONS).all(|r| p.rotn(r).normalized() != m);
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Which cells count as touching a piece on the same layer
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Adjacency {
+    // Pieces must share an edge (the standard rules)
+    Orthogonal,
+    // Pieces touching at a corner also count (a common house rule)
+    Diagonal,
+}
+
+impl Adjacency {
+    pub fn offsets(&self) -> &'static [(i32, i32)] {
+        match *self {
+            Adjacency::Orthogonal => &[(0, 1), (0, -1), (1, 0), (-1, 0)],
+            Adjacency::Diagonal => &[(0, 1), (0, -1), (1, 0), (-1, 0),
+                                     (1, 1), (1, -1), (-1, 1), (-1, -1)],
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
 #[derive(Debug, Clone)]
 pub struct Piece {
     pub pts: Vec<(i32, i32)>,
@@ -72,6 +333,22 @@ impl Piece {
         }
     }
 
+    // Returns the piece's cells, moved to touch the x and y axes and
+    // sorted, so that shapes can be compared wherever they sit in the grid
+    pub fn normalized(&self) -> Vec<(i32, i32)> {
+        let x = self.pts.iter().map(|q| q.0).min().unwrap_or(0);
+        let y = self.pts.iter().map(|q| q.1).min().unwrap_or(0);
+        let mut pts: Vec<_> = self.pts.iter().map(|q| (q.0 - x, q.1 - y))
+            .collect();
+        pts.sort();
+        return pts;
+    }
+
+    // Flips a Piece over, mirroring it in x
+    pub fn mirror(&self) -> Piece {
+        Piece::from_pts(self.pts.iter().map(|&(x, y)| (3 - x, y)).collect())
+    }
+
     // Rotates a Piece by 90° clockwise
     pub fn rot(&self) -> Piece {
         Piece::from_pts(self.pts.iter().map(|&(x, y)| (y, -x + 3)).collect())
@@ -85,8 +362,10 @@ impl Piece {
         return out;
     }
 
-    // Checks for overlap with a second piece offset by some distance
-    pub fn check(&self, other: &Piece, dx: i32, dy: i32) -> Overlap {
+    // Checks for overlap with a second piece offset by some distance,
+    // using the given rule for which cells count as neighbors
+    pub fn check(&self, other: &Piece, dx: i32, dy: i32,
+                 adjacency: Adjacency) -> Overlap {
         let mut all_over = true;
         let mut none_over = true;
         let mut has_neighbor = false;
@@ -100,7 +379,7 @@ impl Piece {
                 all_over = false;
             }
 
-            for &(nx, ny) in [(0, 1), (0, -1), (1, 0), (-1, 0)].iter()
+            for &(nx, ny) in adjacency.offsets()
             {
                 has_neighbor |= self.at(x + dx + nx, y + dy + ny);
             }
@@ -136,7 +415,9 @@ pub enum Overlap {
 
 #[cfg(test)]
 mod tests {
-    use piece::{Piece, Overlap, PIECES};
+    use piece::{Piece, PieceSet, Overlap, Adjacency, PIECES, PIECE_AREA,
+                UNIQUE_PIECE_COUNT, DISTINCT_ROTATIONS, MAX_ROTATIONS};
+    use piece::{CHIRAL, MIRRORED_ID, is_chiral, piece_id, tile};
 
     #[test]
     fn construction() {
@@ -154,19 +435,103 @@ mod tests {
         }
     }
 
+    #[test]
+    fn distinct_rotations() {
+        for i in 0..UNIQUE_PIECE_COUNT {
+            let p = Piece::from_u16(PIECES[i]);
+            let n = (1..=MAX_ROTATIONS)
+                .find(|&r| p.rotn(r).normalized() == p.normalized()).unwrap();
+            assert_eq!(DISTINCT_ROTATIONS[i], n, "piece {}", i);
+        }
+    }
+
+    #[test]
+    fn mirrored() {
+        for i in 0..UNIQUE_PIECE_COUNT {
+            let p = Piece::from_u16(PIECES[i]);
+            assert_eq!(CHIRAL[i], is_chiral(&p), "piece {}", i);
+            assert_eq!(p.mirror().mirror().to_u16(), PIECES[i]);
+        }
+
+        // Mirrored ids come after the original 40, in the same order
+        assert_eq!(piece_id(7, 1), 29);
+        assert_eq!(piece_id(7, 5), MIRRORED_ID + 29);
+        assert_eq!(tile(MIRRORED_ID + 29), 7);
+
+        // The 0 has no mirrored orientations to try; the 1 has four
+        let set = PieceSet::standard();
+        assert_eq!(set.orientations(0, true).collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(set.orientations(1, false).count(), 4);
+        assert_eq!(set.orientations(1, true).collect::<Vec<_>>(),
+                   vec![4, 5, 6, 7, 44, 45, 46, 47]);
+        assert_eq!(set.shape(44).to_u16(),
+                   Piece::from_u16(PIECES[1]).mirror().to_u16());
+    }
+
+    #[test]
+    fn piece_set() {
+        // The standard set's tables agree with what new() works out
+        assert_eq!(PieceSet::new(PIECES), Ok(PieceSet::standard()));
+
+        let text = "pieces = [
+            '###/#.#/#.#/###', '##/.#/.#/.#', '.##/.##/##./###',
+            '###/..#/.##/###', '.##/.#./###/.##', '###/#.../###/###',
+            '##/#.../###/###', '###/.#../##../#...', '.##/.##/##../##..',
+            '''
+            ###.
+            ###.
+            ##..
+            ##..
+            ''']";
+        assert_eq!(PieceSet::parse(text), Ok(PieceSet::standard()));
+
+        let set = PieceSet::parse(&text.replace("'##/.#/.#/.#'", "'#...'"))
+            .unwrap();
+        assert_eq!(set.areas[1], 1);
+        assert_eq!(set.distinct_rotations[1], 1);
+
+        assert!(PieceSet::parse("pieces = ['#']").unwrap_err()
+                .contains("Expected 10 pieces"));
+        assert!(PieceSet::parse(&text.replace("'##/.#/.#/.#'", "'#.#'"))
+                .unwrap_err().contains("Piece 1 isn't connected"));
+        assert!(PieceSet::parse(&text.replace("'##/.#/.#/.#'", "'#####'"))
+                .unwrap_err().contains("wider than 4"));
+        assert!(PieceSet::parse(&text.replace("'##/.#/.#/.#'", "'..'"))
+                .unwrap_err().contains("Piece 1 is empty"));
+        assert!(PieceSet::parse(&format!("{}\nvalues = 1", text))
+                .unwrap_err().contains("Unknown key"));
+    }
+
+    #[test]
+    fn area() {
+        for i in 0..UNIQUE_PIECE_COUNT {
+            assert_eq!(PIECE_AREA[i], Piece::from_u16(PIECES[i]).pts.len());
+        }
+        assert_eq!(PIECE_AREA[1], 5);
+    }
+
     #[test]
     fn check() {
         let zero = Piece::from_u16(PIECES[0]);
         let one = Piece::from_u16(PIECES[1]);
-        assert_eq!(zero.check(&one, 0, 0),
+        assert_eq!(zero.check(&one, 0, 0, Adjacency::Orthogonal),
                    Overlap::_Partial(0b0000010001000000));
-        assert_eq!(zero.check(&one, 1, 0),
+        assert_eq!(zero.check(&one, 1, 0, Adjacency::Orthogonal),
                    Overlap::Full);
-        assert_eq!(zero.check(&one, -1, 0),
+        assert_eq!(zero.check(&one, -1, 0, Adjacency::Orthogonal),
                    Overlap::_Partial(0b1000000000000000));
-        assert_eq!(zero.check(&one, -1, -1),
+        assert_eq!(zero.check(&one, -1, -1, Adjacency::Orthogonal),
             Overlap::_Partial(0b1000000000000100));
-        assert_eq!(zero.check(&one, -1, 1),
+        assert_eq!(zero.check(&one, -1, 1, Adjacency::Orthogonal),
             Overlap::_Partial(0b1100000000000000));
     }
+
+    #[test]
+    fn check_diagonal() {
+        let dot = Piece::from_u16(0b1000000000000000);
+        assert_eq!(dot.check(&dot, 1, 1, Adjacency::Orthogonal), Overlap::None);
+        assert_eq!(dot.check(&dot, 1, 1, Adjacency::Diagonal), Overlap::Neighbor);
+        assert_eq!(dot.check(&dot, 1, 0, Adjacency::Diagonal), Overlap::Neighbor);
+        assert_eq!(dot.check(&dot, 2, 1, Adjacency::Diagonal), Overlap::None);
+    }
 }
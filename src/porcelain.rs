@@ -0,0 +1,79 @@
+use std::io::{self, Write};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use bag::Bag;
+
+// Machine-readable progress on stdout, for wrapper scripts.  The first
+// line is "version", a tab, and VERSION.  Every line after that has five
+// tab-separated fields:
+//
+//   event       start, improved, solved, timeout, limited, cancelled, or
+//               done
+//   bag         count of each tile, as one digit per tile (e.g. 0120000000
+//               is a 1 and two 2s), or "-" if the event isn't for a bag
+//   score       the score so far (or final score), or "-"
+//   unix_ms     wall-clock time, in milliseconds since the Unix epoch
+//   elapsed_ms  milliseconds since porcelain output was enabled
+//
+// Any change to this format (including new events) must bump VERSION.
+pub const VERSION: usize = 2;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static START: OnceLock<Instant> = OnceLock::new();
+
+// Turns on porcelain output, printing the version line.  Human-readable
+// status messages go to stderr from then on.  The flag can be given more
+// than once (e.g. in a config file and on the command line), but only the
+// first call prints anything.
+pub fn enable() {
+    START.get_or_init(Instant::now);
+    if !ENABLED.swap(true, Ordering::Relaxed) {
+        println!("version\t{}", VERSION);
+    }
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// Formats one line of output, without the trailing newline
+pub fn line(event: &str, bag: Option<&Bag>, score: Option<isize>,
+            unix_ms: u128, elapsed_ms: u128) -> String {
+    let bag = bag.map(|b| b.digits()).unwrap_or("-".to_string());
+    let score = score.map(|s| s.to_string()).unwrap_or("-".to_string());
+    return format!("{}\t{}\t{}\t{}\t{}",
+                   event, bag, score, unix_ms, elapsed_ms);
+}
+
+// Prints one line of output, if porcelain output is enabled
+pub fn emit(event: &str, bag: Option<&Bag>, score: Option<isize>) {
+    if !enabled() {
+        return;
+    }
+    let unix_ms = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis()).unwrap_or(0);
+    let elapsed_ms = START.get().map(|s| s.elapsed().as_millis()).unwrap_or(0);
+
+    // Lines from different threads must not interleave
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let _ = writeln!(out, "{}", line(event, bag, score, unix_ms, elapsed_ms));
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line() {
+        let bag = Bag::from_usize(0).add(4).add(8).add(8);
+        assert_eq!(super::line("solved", Some(&bag), Some(-3), 1000, 20),
+                   "solved\t0120000000\t-3\t1000\t20");
+        assert_eq!(super::line("done", None, None, 1000, 20),
+                   "done\t-\t-\t1000\t20");
+    }
+}
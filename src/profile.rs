@@ -0,0 +1,160 @@
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use bag::Bag;
+
+// Workers time one node in this many, so that profiling barely slows
+// the search down
+pub const SAMPLE_EVERY: usize = 64;
+
+// The steps of a node's search which are sampled
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Step {
+    // Bounding what the rest of the bag could add to the score
+    Bound,
+    // Finding every placement of every tile left in the bag
+    Moves,
+}
+
+#[derive(Default)]
+struct Sampled {
+    count: AtomicU64,
+    nanos: AtomicU64,
+}
+
+impl Sampled {
+    fn add(&self, d: Duration) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.nanos.fetch_add(d.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+// Where a sweep's time went:  loading the overlap tables, each batch of
+// bags with one number of pieces, the slowest bags, and (from samples)
+// each node's bound and placements
+#[derive(Default)]
+pub struct Profile {
+    tables: Mutex<Option<Duration>>,
+    phases: Mutex<Vec<(usize, usize, Duration)>>,
+    bags: Mutex<Vec<(Bag, Duration)>>,
+    bound: Sampled,
+    moves: Sampled,
+
+    // Shard locks taken in shared seen-sets, and how many of them waited
+    seen_locks: AtomicU64,
+    seen_waits: AtomicU64,
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile::default()
+    }
+
+    pub fn tables(&self, d: Duration) {
+        *self.tables.lock().unwrap() = Some(d);
+    }
+
+    // Records a batch of bags with the given number of pieces, including
+    // any retries
+    pub fn phase(&self, pieces: usize, bags: usize, d: Duration) {
+        self.phases.lock().unwrap().push((pieces, bags, d));
+    }
+
+    pub fn bag(&self, bag: &Bag, d: Duration) {
+        self.bags.lock().unwrap().push((bag.clone(), d));
+    }
+
+    pub fn sampled(&self, step: Step, d: Duration) {
+        match step {
+            Step::Bound => self.bound.add(d),
+            Step::Moves => self.moves.add(d),
+        }
+    }
+
+    // Records the contention in a shared seen-set (see SharedSeen)
+    pub fn shared_seen(&self, locks: u64, waits: u64) {
+        self.seen_locks.fetch_add(locks, Ordering::Relaxed);
+        self.seen_waits.fetch_add(waits, Ordering::Relaxed);
+    }
+
+    // Returns the n bags whose searches took longest, slowest first
+    pub fn slowest(&self, n: usize) -> Vec<(Bag, Duration)> {
+        let mut bags = self.bags.lock().unwrap().clone();
+        bags.sort_by_key(|b| std::cmp::Reverse(b.1));
+        bags.truncate(n);
+        return bags;
+    }
+}
+
+// Prints as a multi-line report, e.g.
+//   overlap tables: 0.412s
+//   3 pieces: 220 bags in 0.530s
+//   slowest: 2211000000 (0.021s), ...
+//   bounds: 1504 samples, 180ns each, about 0.017s across threads
+//   shared seen-sets: 8812 locks, 31 waited (0.4%)
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(d) = *self.tables.lock().unwrap() {
+            writeln!(f, "overlap tables: {:.3}s", d.as_secs_f64())?;
+        }
+        for &(pieces, bags, d) in self.phases.lock().unwrap().iter() {
+            writeln!(f, "{} pieces: {} bags in {:.3}s", pieces, bags,
+                     d.as_secs_f64())?;
+        }
+        let slowest: Vec<String> = self.slowest(5).iter()
+            .map(|(b, d)| format!("{} ({:.3}s)", b.digits(), d.as_secs_f64()))
+            .collect();
+        if !slowest.is_empty() {
+            writeln!(f, "slowest: {}", slowest.join(", "))?;
+        }
+        for &(name, s) in [("bounds", &self.bound),
+                           ("placements", &self.moves)].iter() {
+            let count = s.count.load(Ordering::Relaxed);
+            if count == 0 {
+                continue;
+            }
+            let nanos = s.nanos.load(Ordering::Relaxed);
+            writeln!(f, "{}: {} samples, {}ns each, about {:.3}s across threads",
+                     name, count, nanos / count,
+                     (nanos * SAMPLE_EVERY as u64) as f64 / 1e9)?;
+        }
+        let locks = self.seen_locks.load(Ordering::Relaxed);
+        if locks > 0 {
+            let waits = self.seen_waits.load(Ordering::Relaxed);
+            writeln!(f, "shared seen-sets: {} locks, {} waited ({:.1}%)",
+                     locks, waits, 100.0 * waits as f64 / locks as f64)?;
+        }
+        Ok(())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report() {
+        let p = Profile::new();
+        assert_eq!(p.to_string(), "");
+
+        let bag = Bag::from_digits("2000000000", 2).unwrap();
+        p.tables(Duration::from_millis(250));
+        p.phase(2, 1, Duration::from_millis(30));
+        p.bag(&bag, Duration::from_millis(10));
+        p.bag(&bag.take(0), Duration::from_millis(20));
+        p.sampled(Step::Bound, Duration::from_nanos(100));
+        p.sampled(Step::Bound, Duration::from_nanos(300));
+        p.shared_seen(200, 1);
+        assert_eq!(p.slowest(1), vec![(bag.take(0), Duration::from_millis(20))]);
+        assert_eq!(p.to_string(),
+                   "overlap tables: 0.250s\n\
+                    2 pieces: 1 bags in 0.030s\n\
+                    slowest: 1000000000 (0.020s), 2000000000 (0.010s)\n\
+                    bounds: 2 samples, 200ns each, about 0.000s across threads\n\
+                    shared seen-sets: 200 locks, 1 waited (0.5%)\n");
+    }
+}
@@ -0,0 +1,175 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use watchdog::Progress;
+
+// Tracks how far a sweep is through its current phase (the batch of bags
+// with one number of pieces, or a retry of that batch), for a status line
+// or for a program driving the solver
+pub struct Tracker {
+    phase: Mutex<Phase>,
+
+    // Searches which are running now
+    active: Mutex<Vec<Arc<Progress>>>,
+
+    // Nodes expanded by this phase's finished searches
+    nodes: AtomicUsize,
+}
+
+struct Phase {
+    pieces: usize,
+    total: usize,
+    done: usize,
+    start: Instant,
+}
+
+// How far along the current phase is, at one moment
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    pub pieces: usize,
+    pub done: usize,
+    pub total: usize,
+    // Nodes expanded in this phase, including by running searches.  These
+    // are only counted for local searches.
+    pub nodes: usize,
+    pub elapsed: Duration,
+}
+
+impl Snapshot {
+    pub fn nodes_per_second(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 { self.nodes as f64 / secs } else { 0.0 }
+    }
+
+    // Estimates the time left in the phase, assuming that the remaining
+    // bags take as long as the finished ones did on average.  Returns None
+    // until a bag has been finished.
+    pub fn eta(&self) -> Option<Duration> {
+        if self.done == 0 {
+            return None;
+        }
+        let left = self.total.saturating_sub(self.done) as f64;
+        return Some(Duration::from_secs_f64(
+            self.elapsed.as_secs_f64() * left / self.done as f64));
+    }
+}
+
+// Prints as a one-line summary, e.g.
+//   7 pieces: 120/5005 bags (2.4%), 1.3M nodes/s, ETA 1h12m
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let percent = if self.total > 0 {
+            100.0 * self.done as f64 / self.total as f64
+        } else {
+            100.0
+        };
+        write!(f, "{} pieces: {}/{} bags ({:.1}%)",
+               self.pieces, self.done, self.total, percent)?;
+        if self.nodes > 0 {
+            let rate = self.nodes_per_second();
+            if rate >= 1e6 {
+                write!(f, ", {:.1}M nodes/s", rate / 1e6)?;
+            } else if rate >= 1e3 {
+                write!(f, ", {:.1}k nodes/s", rate / 1e3)?;
+            } else {
+                write!(f, ", {:.0} nodes/s", rate)?;
+            }
+        }
+        match self.eta().map(|d| d.as_secs()) {
+            Some(s) if s >= 3600 => write!(f, ", ETA {}h{:02}m", s / 3600,
+                                           s % 3600 / 60),
+            Some(s) => write!(f, ", ETA {}m{:02}s", s / 60, s % 60),
+            None => write!(f, ", ETA unknown"),
+        }
+    }
+}
+
+impl Tracker {
+    pub fn new() -> Tracker {
+        Tracker {
+            phase: Mutex::new(Phase { pieces: 0, total: 0, done: 0,
+                                      start: Instant::now() }),
+            active: Mutex::new(Vec::new()),
+            nodes: AtomicUsize::new(0),
+        }
+    }
+
+    // Starts a phase of the given number of bags
+    pub fn begin(&self, pieces: usize, total: usize) {
+        *self.phase.lock().unwrap() = Phase { pieces: pieces, total: total,
+                                              done: 0, start: Instant::now() };
+        self.nodes.store(0, Ordering::Relaxed);
+    }
+
+    pub fn add(&self, p: Arc<Progress>) {
+        self.active.lock().unwrap().push(p);
+    }
+
+    // Marks a running search's bag as finished
+    pub fn remove(&self, p: &Arc<Progress>) {
+        self.active.lock().unwrap().retain(|q| !Arc::ptr_eq(p, q));
+        self.solved(p.nodes());
+    }
+
+    // Marks a bag as finished, after expanding the given number of nodes
+    pub fn solved(&self, nodes: usize) {
+        self.nodes.fetch_add(nodes, Ordering::Relaxed);
+        self.phase.lock().unwrap().done += 1;
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let phase = self.phase.lock().unwrap();
+        let running: usize = self.active.lock().unwrap().iter()
+            .map(|p| p.nodes()).sum();
+        Snapshot {
+            pieces: phase.pieces,
+            done: phase.done,
+            total: phase.total,
+            nodes: self.nodes.load(Ordering::Relaxed) + running,
+            elapsed: phase.start.elapsed(),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bag::Bag;
+
+    #[test]
+    fn tracker() {
+        let t = Tracker::new();
+        t.begin(1, 4);
+        let p = Arc::new(Progress::new(Bag::from_usize(0).add(0)));
+        p.expanded(0, 3);
+        t.add(p.clone());
+        let s = t.snapshot();
+        assert_eq!((s.done, s.total, s.nodes), (0, 4, 1));
+        assert_eq!(s.eta(), None);
+
+        t.remove(&p);
+        t.solved(0);
+        let s = t.snapshot();
+        assert_eq!((s.done, s.nodes), (2, 1));
+        assert!(s.eta().is_some());
+
+        // A new phase starts from scratch
+        t.begin(2, 10);
+        assert_eq!(t.snapshot().nodes, 0);
+    }
+
+    #[test]
+    fn display() {
+        let s = Snapshot { pieces: 7, done: 120, total: 480, nodes: 2_400_000,
+                           elapsed: Duration::from_secs(2) };
+        assert_eq!(s.eta(), Some(Duration::from_secs(6)));
+        assert_eq!(s.to_string(),
+                   "7 pieces: 120/480 bags (25.0%), 1.2M nodes/s, ETA 0m06s");
+        let s = Snapshot { done: 0, nodes: 0, ..s };
+        assert_eq!(s.to_string(), "7 pieces: 0/480 bags (0.0%), ETA unknown");
+    }
+}
@@ -0,0 +1,183 @@
+// Python bindings, built with the "python" feature into the shared library
+// from cdylib/, e.g. with maturin:
+//
+//   maturin develop -m cdylib/Cargo.toml --features python
+//
+// and then used as
+//
+//   import nmbr9
+//   bag = nmbr9.Bag("2200000000")
+//   score, state, exact = nmbr9.solve(bag, time_limit=10.0)
+//   print(score, state.layout())
+
+// pyo3's generated wrappers convert errors into the type they already are
+#![allow(clippy::useless_conversion)]
+
+use std::sync::RwLock;
+use std::time::Duration;
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+use bag::Bag;
+use error::Error;
+use piece::{Adjacency, MAX_COPIES, UNIQUE_PIECE_COUNT};
+use rules::Rules;
+use solver::Solver;
+use state::State;
+
+// A multiset of tiles, with at most `copies` of each
+#[pyclass(name = "Bag")]
+#[derive(Clone)]
+struct PyBag {
+    bag: Bag,
+    copies: usize,
+}
+
+#[pymethods]
+impl PyBag {
+    // Bags are written as one digit per tile, counting its copies
+    #[new]
+    #[pyo3(signature = (digits, copies=2))]
+    fn new(digits: &str, copies: usize) -> PyResult<PyBag> {
+        match Bag::from_digits(digits, copies) {
+            Some(bag) if copies <= MAX_COPIES => Ok(PyBag { bag: bag, copies: copies }),
+            _ => Err(PyValueError::new_err(format!("Invalid bag '{}'", digits))),
+        }
+    }
+
+    fn digits(&self) -> String {
+        self.bag.digits()
+    }
+
+    fn count(&self, tile: usize) -> PyResult<usize> {
+        check_tile(tile)?;
+        Ok(self.bag.digit(tile))
+    }
+
+    // The bag's tiles, in ascending order
+    fn tiles(&self) -> Vec<usize> {
+        (0..UNIQUE_PIECE_COUNT)
+            .flat_map(|t| (0..self.bag.digit(t)).map(move |_| t))
+            .collect()
+    }
+
+    fn __len__(&self) -> usize {
+        self.bag.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Bag('{}')", self.bag.digits())
+    }
+}
+
+// An arrangement of tiles on the table
+#[pyclass(name = "State")]
+#[derive(Clone)]
+struct PyState {
+    state: State,
+}
+
+#[pymethods]
+impl PyState {
+    #[new]
+    #[pyo3(signature = (diagonal=false))]
+    fn new(diagonal: bool) -> PyState {
+        PyState { state: State::with_adjacency(adjacency(diagonal)) }
+    }
+
+    // Places a tile (0-9), turned by the given number of quarter turns,
+    // returning the new arrangement or None if the placement is illegal.
+    // Arguments out of range raise ValueError.
+    // Positions are in the arrangement's own frame, whose corner is 0, 0.
+    #[pyo3(signature = (tile, x, y, rotation=0))]
+    fn try_place(&self, tile: usize, x: i32, y: i32, rotation: usize)
+        -> PyResult<Option<PyState>>
+    {
+        match self.state.place(tile, rotation, x, y) {
+            Ok(s) => Ok(Some(PyState { state: s })),
+            Err(Error::Illegal) => Ok(None),
+            Err(e) => Err(PyValueError::new_err(e.to_string())),
+        }
+    }
+
+    fn score(&self) -> isize {
+        self.state.score()
+    }
+
+    // Each placed tile, as (tile, rotation, x, y, layer), from the top
+    // layer down
+    fn pieces(&self) -> Vec<(usize, usize, i32, i32, usize)> {
+        self.state.pieces.iter()
+            .map(|p| (p.index(), p.orientation(), p.x, p.y, p.z))
+            .collect()
+    }
+
+    fn layout(&self) -> String {
+        self.state.layout()
+    }
+
+    fn __len__(&self) -> usize {
+        self.state.pieces.len()
+    }
+
+    fn __repr__(&self) -> String {
+        let pieces: Vec<String> = self.state.pieces.iter()
+            .map(|p| p.to_string()).collect();
+        format!("State([{}])", pieces.join("; "))
+    }
+}
+
+fn adjacency(diagonal: bool) -> Adjacency {
+    if diagonal { Adjacency::Diagonal } else { Adjacency::Orthogonal }
+}
+
+fn check_tile(tile: usize) -> PyResult<()> {
+    if tile < UNIQUE_PIECE_COUNT {
+        Ok(())
+    } else {
+        Err(PyValueError::new_err("Tile must be 0-9"))
+    }
+}
+
+// Finds the best score for a bag, solving its subsets along the way.
+// Returns the score, the best arrangement, and whether the score is
+// exact; with a time limit (in seconds, for each bag searched), it may
+// only be a lower bound.
+#[pyfunction]
+#[pyo3(signature = (bag, time_limit=None, max_layers=None, diagonal=false))]
+fn solve(py: Python, bag: &PyBag, time_limit: Option<f64>,
+         max_layers: Option<usize>, diagonal: bool)
+    -> PyResult<(isize, PyState, bool)>
+{
+    let rules = Rules { max_layers: max_layers, copies: bag.copies,
+                        adjacency: adjacency(diagonal),
+                        ..Rules::default() };
+    let mut solver = Solver::new(rules);
+    if let Some(t) = time_limit {
+        if t.is_nan() || t <= 0.0 {
+            return Err(PyValueError::new_err("time_limit must be positive"));
+        }
+        solver = solver.with_timeout(Duration::from_secs_f64(t), 0);
+    }
+    let target = bag.bag.clone();
+    py.allow_threads(|| solver.sweep_bags(std::slice::from_ref(&target)));
+
+    let results: &RwLock<_> = solver.results();
+    let results = results.read().unwrap();
+    let exact = results.score(&target).is_some();
+    let score = results.lower_bound(&target).unwrap_or(0);
+    let state = results.state(&target).cloned()
+        .or_else(|| results.subset_state(&target, score))
+        .unwrap_or_else(|| State::with_rules(&rules));
+    Ok((score, PyState { state: state }, exact))
+}
+
+#[pymodule]
+fn nmbr9(m: &Bound<PyModule>) -> PyResult<()> {
+    m.add_class::<PyBag>()?;
+    m.add_class::<PyState>()?;
+    // Paths in macros are from the crate root, in the 2015 edition
+    m.add_function(wrap_pyfunction!(python::solve, m)?)?;
+    Ok(())
+}
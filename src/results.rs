@@ -1,64 +1,1217 @@
-use std::cmp::max;
+use std::cmp::{min, max};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::io::{self, BufRead, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI32, AtomicU32, Ordering};
 
-use piece::UNIQUE_PIECE_COUNT;
+use piece::{UNIQUE_PIECE_COUNT, MAX_ROTATIONS, pieces};
 use bag::Bag;
 use state::State;
+use rules::Rules;
+use optimum::ScoreEstimate;
+use bitboard::Bitboards;
+
+// The score and delta for a bag are read together in the innermost
+// pruning check, so they're packed side by side into one atomic word (see
+// Entry::pack), which workers read without taking a lock.  Scores are kept
+// in 16 bits, which halves the table (so that more of it stays in cache);
+// rules whose scores could need more are refused (see scores_fit).
+#[derive(Copy, Clone)]
+struct Entry {
+    // The best score for the bag, or UNSOLVED
+    score: i16,
+
+    // How much the score could go up if the bag were raised a layer, which
+    // is never negative
+    delta: i16,
+
+    // False if the solve was cut short, in which case the score is only
+    // a lower bound on the bag's true best score
+    complete: bool,
+}
+
+const UNSOLVED: i16 = i16::MIN;
+
+// The version of the results format (which checkpoints also use), named
+// in each file's header.  Older files are refused rather than trusted:
+// files without a version are from before version 2, whose search never
+// scored arrangements that use every tile, so their exact scores may be
+// too low; version 2 named its rules in a form which can't be read back
+// (see Rules::encode); and version 3 didn't name the tile shapes (see
+// PieceSet::fingerprint), so scores for custom pieces could be mixed up
+// with the standard ones.
+pub const VERSION: u32 = 4;
+const HEADER: &str = "# nmbr9 results";
+
+// Returns whether the text starts with the header of a results file from
+// another version of the format
+pub fn is_outdated(text: &str) -> bool {
+    text.starts_with(HEADER) &&
+        !text.starts_with(&format!("{} v{} ", HEADER, VERSION))
+}
+
+// Returns the rules and piece fingerprint named by the header that the
+// text starts with, if it starts with a header from this version
+pub fn read_header(text: &str) -> Option<(Rules, u64)> {
+    let line = text.lines().next()?;
+    let rest = line.strip_prefix(&format!("{} v{} for ", HEADER, VERSION))?;
+    let (rules, shapes) = rest.rsplit_once(" pieces=")?;
+    Some((Rules::decode(rules)?, u64::from_str_radix(shapes, 16).ok()?))
+}
+
+// Checks that the text starts with a header for the given rules and the
+// pieces in use
+pub fn header_matches(text: &str, rules: &Rules) -> bool {
+    read_header(text) == Some((*rules, pieces().fingerprint()))
+}
+
+// Bags with more ways to split them than this aren't split when bounding
+// their score (see layered_bound), since it's done at every search node
+const MAX_SPLITS: usize = 64;
+
+// Returns whether every score that the rules allow (and every delta) fits
+// in an Entry, with room for UNSOLVED.  No arrangement of the whole game's
+// tiles scores more than stacking them, and none scores less than stacking
+// them with their values negated.
+pub fn scores_fit(rules: &Rules) -> bool {
+    let count = (rules.copies + 1).pow(UNIQUE_PIECE_COUNT as u32);
+    let full = Bag::from_index(count - 1, rules.copies);
+    let positive = rules.values.map(|v| max(v, 0));
+    let negated = rules.values.map(|v| -v);
+    let limit = i16::MAX as isize;
+    return rules.scoring.score_stacked(&full, &rules.values) <= limit &&
+        rules.scoring.score_stacked(&full, &negated) <= limit &&
+        full.score_flat_with(&positive) <= limit;
+}
+
+// Narrows a score or delta to fit an Entry, which scores_fit has checked
+// that the rules allow
+fn narrow(score: isize) -> i16 {
+    return i16::try_from(score).ok()
+        .filter(|&s| s != UNSOLVED)
+        .unwrap_or_else(|| panic!("Score {} is out of range", score));
+}
+
+impl Entry {
+    // The score takes the low 16 bits, then the delta takes 15 bits, and
+    // the top bit is the complete flag
+    fn pack(&self) -> u32 {
+        (self.score as u16 as u32) |
+        ((self.delta as u16 as u32) & 0x7fff) << 16 |
+        (self.complete as u32) << 31
+    }
+
+    fn unpack(e: u32) -> Entry {
+        Entry {
+            score: e as u16 as i16,
+            delta: ((e >> 16) & 0x7fff) as i16,
+            complete: e >> 31 != 0,
+        }
+    }
+
+    fn lower_bound(&self) -> Option<isize> {
+        if self.score == UNSOLVED {
+            None
+        } else {
+            Some(self.score as isize)
+        }
+    }
+
+    fn score(&self) -> Option<isize> {
+        if self.complete {
+            self.lower_bound()
+        } else {
+            None
+        }
+    }
+}
+
+// The part of the results which bounds the search:  each bag's score (or
+// lower bound), and the best score of its subsets.  These are atomics, so
+// that workers can share the table (see Results::bounds) and read it at
+// every node without taking the results' lock.
+pub struct Bounds {
+    rules: Rules,
+
+    // For a particular set of pieces (represented by a 10-digit value, in
+    // base 3 for the standard two-copy game), what is the highest possible
+    // score (if we start with the pieces placed on a flat, empty table), and
+    // how much could it go up if we place them a layer higher?  (Only
+    // positive tiles need to be raised.)  These are packed Entries.
+    entries: Vec<AtomicU32>,
+
+    // For each bag, the highest score found by it or any of its subsets
+    // (or UNSOLVED), kept up to date as scores are written
+    subsets: Vec<AtomicI32>,
+}
 
 pub struct Results {
-    // For a particular set of pieces (represented by a 10-digit ternary value),
-    // what is the highest possible score (if we start with the pieces placed
-    // on a flat, empty table)?
-    scores: Vec<Option<usize>>,
-
-    // For a particular set of pieces, how much does the score go up if we
-    // place them a layer higher?
-    deltas: Vec<usize>
+    // Which rules the scores were computed under, and the fingerprint of
+    // the tile shapes they're for (see PieceSet::fingerprint)
+    rules: Rules,
+    pieces: u64,
+
+    bounds: Arc<Bounds>,
+
+    // Statistical estimates of the best score, for bags which were too
+    // large to solve exactly.  These are never used to bound the search.
+    estimates: BTreeMap<usize, ScoreEstimate>,
+
+    // The best arrangement found for each solved bag, where known
+    states: BTreeMap<usize, State>,
+
+    // The highest-scoring distinct arrangements of each bag, best first,
+    // for bags which were solved with a leaderboard
+    leaders: BTreeMap<usize, Vec<(isize, State)>>,
 }
 
-impl Results {
-    pub fn new() -> Results {
-        Results {
-            scores: vec![None; 3_usize.pow(UNIQUE_PIECE_COUNT as u32)],
-            deltas: (0..3_usize.pow(UNIQUE_PIECE_COUNT as u32)).map(
-                |i| Bag::from_usize(i).score_flat()).collect(),
+impl Bounds {
+    fn new(rules: Rules) -> Bounds {
+        let count = (rules.copies + 1).pow(UNIQUE_PIECE_COUNT as u32);
+        let positive = rules.values.map(|v| max(v, 0));
+        Bounds {
+            rules: rules,
+            entries: (0..count).map(|i| AtomicU32::new(Entry {
+                score: UNSOLVED,
+                complete: false,
+                delta: narrow(Bag::from_index(i, rules.copies)
+                    .score_flat_with(&positive)),
+            }.pack())).collect(),
+            subsets: (0..count).map(|_| AtomicI32::new(UNSOLVED as i32)).collect(),
         }
     }
 
-    // Returns the highest score found by any subset of the given bag.
-    // This assumes that scores are being populated in lowest-to-highest
-    // order by piece count, and may panic otherwise.
-    //
-    // This makes the overall calculation O(N^2), but is far from
-    // the slowest part of the computation.
-    pub fn upper_subset_score(&self, bag: &Bag) -> usize {
+    fn index(&self, bag: &Bag) -> usize {
+        bag.index(self.rules.copies)
+    }
+
+    fn entry(&self, i: usize) -> Entry {
+        Entry::unpack(self.entries[i].load(Ordering::Relaxed))
+    }
+
+    // Returns the bag's best score, if it has been fully solved
+    pub fn score(&self, bag: &Bag) -> Option<isize> {
+        self.entry(self.index(bag)).score()
+    }
+
+    // Returns the best score found for the bag, even if its solve was
+    // cut short (in which case the true best score may be higher)
+    pub fn lower_bound(&self, bag: &Bag) -> Option<isize> {
+        self.entry(self.index(bag)).lower_bound()
+    }
+
+    // Returns the highest score found by any proper subset of the given
+    // bag (including incomplete solves, since any score found is
+    // achievable), or 0 if none have been solved.  Both are scores the
+    // whole bag can reach, even with negative tiles:  the tiles left over
+    // can always be laid on the ground, where they score nothing.
+    pub fn upper_subset_score(&self, bag: &Bag) -> isize {
         let mut out = 0;
-        for i in 0..self.scores.len() {
-            let b = Bag::from_usize(i);
-            if b.len() >= bag.len() {
+        for p in 0..UNIQUE_PIECE_COUNT {
+            if bag.digit(p) > 0 {
+                let sub = self.index(&bag.take(p * MAX_ROTATIONS));
+                let best = self.subsets[sub].load(Ordering::Relaxed);
+                out = max(out, best as isize);
+            }
+        }
+        return out;
+    }
+
+    // Sets a bag's score, keeping the subset maxima up to date.  A higher
+    // score is pushed up to the supersets which it beats; a lower one means
+    // that the table has to be rebuilt.  Pushing stops at a superset which
+    // already has the score, since it was pushed on from there when that
+    // bag got it.  A search reading the table during a push may see a
+    // lower maximum, which only starts it from a weaker incumbent.
+    fn set_score(&self, i: usize, score: isize, complete: bool) {
+        let score = narrow(score);
+        let mut entry = self.entry(i);
+        let old = entry.score;
+        entry.score = score;
+        entry.complete = complete;
+        self.entries[i].store(entry.pack(), Ordering::Relaxed);
+        if score < old {
+            self.rebuild_subsets();
+            return;
+        }
+
+        let mut todo = vec![i];
+        while let Some(j) = todo.pop() {
+            if self.subsets[j].fetch_max(score as i32, Ordering::Relaxed)
+                >= score as i32
+            {
                 continue;
             }
-            else if bag.contains(&b) {
-                out = max(out, self.scores[i].unwrap());
+            let bag = Bag::from_index(j, self.rules.copies);
+            for p in 0..UNIQUE_PIECE_COUNT {
+                if bag.digit(p) < self.rules.copies {
+                    todo.push(self.index(&bag.add(p * MAX_ROTATIONS)));
+                }
             }
         }
-        return out;
+    }
+
+    fn rebuild_subsets(&self) {
+        // A subset's digits are no larger, so its index comes first
+        for i in 0..self.entries.len() {
+            let bag = Bag::from_index(i, self.rules.copies);
+            let mut best = self.entry(i).score as i32;
+            for p in 0..UNIQUE_PIECE_COUNT {
+                if bag.digit(p) > 0 {
+                    let sub = self.index(&bag.take(p * MAX_ROTATIONS));
+                    best = max(best, self.subsets[sub].load(Ordering::Relaxed));
+                }
+            }
+            self.subsets[i].store(best, Ordering::Relaxed);
+        }
     }
 
     // Returns an upper bound score for a given state, with a certain number
-    // of pieces remaining in the bag to be placed.
-    pub fn upper_score_bound(&self, bag: &Bag, state: &State) -> usize {
+    // of pieces remaining in the bag to be placed.  Incomplete solves only
+    // give a lower bound, so those bags fall back to the stacking estimate.
+    pub fn upper_score_bound(&self, bag: &Bag, state: &State) -> isize {
         let layers = state.layers();
-        let b = bag.as_usize();
+        let bound = self.raised_bound(bag, layers + 1);
+
+        // Raising the whole bag assumes that it fits on the top layer,
+        // which isn't true when that layer is small
+        let bound = if state.is_empty() || self.fits_above(bag, state, 0) {
+            bound
+        } else {
+            min(bound, self.layered_bound(bag, state))
+        };
+        return match self.rules.max_layers {
+            Some(n) => min(bound, self.rules.scoring.capped_bound(
+                    bag, &self.rules.values,
+                    self.entry(self.index(bag)).delta as isize, n - 1)),
+            None => bound,
+        };
+    }
 
-        let score = if let Some(available_score) = self.scores[b] {
+    // Returns an upper bound on the bag's score, if its tiles were raised
+    // by the given number of levels
+    fn raised_bound(&self, bag: &Bag, shift: usize) -> isize {
+        let entry = self.entry(self.index(bag));
+        let score = if let Some(available_score) = entry.score() {
             available_score
         } else {
-            bag.score_stacked()
+            self.rules.scoring.score_stacked(bag, &self.rules.values)
+        };
+        self.rules.scoring.shift_bound(bag, score, entry.delta as isize, shift)
+    }
+
+    // Checks whether the tiles of the bag could all be above the state's
+    // top layer, given that the rest of the tiles (with the given area)
+    // are beneath them.  Each layer covers no more cells than the one
+    // below it, so every layer of the raised tiles fits within the top
+    // layer plus the rest of the tiles; and every layer but the highest
+    // has at least two tiles.
+    fn fits_above(&self, bag: &Bag, state: &State, rest: usize) -> bool {
+        if bag.is_empty() {
+            return true;
+        }
+        let top: usize = state.layer(state.layers()).iter()
+            .map(|p| pieces().areas[p.index()]).sum();
+        let room = top + rest;
+        let largest = (0..UNIQUE_PIECE_COUNT).filter(|&i| bag.digit(i) > 0)
+            .map(|i| pieces().areas[i]).max().unwrap_or(0);
+        return largest <= room && bag.area() <= room * bag.len().div_ceil(2);
+    }
+
+    // Returns an upper bound on the score of the bag on top of the state,
+    // by splitting it into the tiles which are raised above the state's
+    // top layer (which must fit there) and those which aren't (which can't
+    // be higher than the top layer).  Every split is tried, unless there
+    // are too many.
+    //
+    // Any arrangement of the bag makes one of these splits, and its raised
+    // tiles must pass fits_above, so the best split bounds it.  Past
+    // MAX_SPLITS, this gives the raised bound which upper_score_bound
+    // already has, so the cutoff only loses tightness, never safety.
+    fn layered_bound(&self, bag: &Bag, state: &State) -> isize {
+        let layers = state.layers();
+        let splits: usize = (0..UNIQUE_PIECE_COUNT)
+            .map(|i| bag.digit(i) + 1).product();
+        if splits > MAX_SPLITS {
+            return self.raised_bound(bag, layers + 1);
+        }
+
+        let mut out = isize::MIN;
+        for n in 0..splits {
+            // Picks the raised tiles, with n as a mixed-radix number
+            let mut raised = Bag::from_usize(0);
+            let mut lower = bag.clone();
+            let mut k = n;
+            for i in 0..UNIQUE_PIECE_COUNT {
+                for _ in 0..k % (bag.digit(i) + 1) {
+                    raised = raised.add(i * MAX_ROTATIONS);
+                    lower = lower.take(i * MAX_ROTATIONS);
+                }
+                k /= bag.digit(i) + 1;
+            }
+            if !self.fits_above(&raised, state, lower.area()) {
+                continue;
+            }
+            let flat = self.entry(self.index(&lower)).delta as isize;
+            let score = self.rules.scoring.capped_bound(
+                &lower, &self.rules.values, flat, layers) +
+                if raised.is_empty() { 0 } else {
+                    self.raised_bound(&raised, layers + 1)
+                };
+            out = max(out, score);
+        }
+        return out;
+    }
+
+}
+
+impl Results {
+    pub fn new(rules: Rules) -> Results {
+        Results {
+            rules: rules,
+            pieces: pieces().fingerprint(),
+            bounds: Arc::new(Bounds::new(rules)),
+            estimates: BTreeMap::new(),
+            states: BTreeMap::new(),
+            leaders: BTreeMap::new(),
+        }
+    }
+
+    // Returns the table which bounds the search.  It's shared, so it sees
+    // scores as they're written to the results.
+    pub fn bounds(&self) -> Arc<Bounds> {
+        self.bounds.clone()
+    }
+
+    fn entry(&self, i: usize) -> Entry {
+        self.bounds.entry(i)
+    }
+
+    fn set_score(&mut self, i: usize, score: isize, complete: bool) {
+        self.bounds.set_score(i, score, complete)
+    }
+
+    fn index(&self, bag: &Bag) -> usize {
+        bag.index(self.rules.copies)
+    }
+
+    // Returns every bag that can be drawn under these rules
+    pub fn bags(&self) -> Vec<Bag> {
+        (0..self.bounds.entries.len())
+            .map(|i| Bag::from_index(i, self.rules.copies))
+            .collect()
+    }
+
+    // Returns the bag containing every tile
+    pub fn full_bag(&self) -> Bag {
+        Bag::from_index(self.bounds.entries.len() - 1, self.rules.copies)
+    }
+
+    // See Bounds::upper_subset_score
+    pub fn upper_subset_score(&self, bag: &Bag) -> isize {
+        self.bounds.upper_subset_score(bag)
+    }
+
+    // See Bounds::upper_score_bound
+    pub fn upper_score_bound(&self, bag: &Bag, state: &State) -> isize {
+        self.bounds.upper_score_bound(bag, state)
+    }
+
+    pub fn rules(&self) -> Rules {
+        self.rules
+    }
+
+    // Records the bag's best score, along with an arrangement which
+    // reaches it (if known)
+    pub fn write_score(&mut self, bag: &Bag, score: isize,
+                       state: Option<&State>) {
+        let i = self.index(bag);
+        self.set_score(i, score, true);
+        self.estimates.remove(&i);
+        match state {
+            Some(s) => self.states.insert(i, s.clone()),
+            None => self.states.remove(&i),
         };
-        return score + (layers + 1) * self.deltas[b];
     }
 
-    pub fn write_score(&mut self, target: usize, score: usize) {
-        self.scores[target] = Some(score);
+    // Returns the best arrangement for the bag, if it has been solved and
+    // the arrangement is known
+    pub fn state(&self, bag: &Bag) -> Option<&State> {
+        self.states.get(&self.index(bag))
+    }
+
+    // Records the highest-scoring distinct arrangements found for the bag
+    // (see Worker::with_leaders), best first
+    pub fn write_leaders(&mut self, bag: &Bag, leaders: Vec<(isize, State)>) {
+        let i = self.index(bag);
+        self.leaders.insert(i, leaders);
+    }
+
+    // Returns the bag's leaderboard, which is empty unless it was kept
+    pub fn leaders(&self, bag: &Bag) -> &[(isize, State)] {
+        self.leaders.get(&self.index(bag)).map(|v| &v[..]).unwrap_or(&[])
+    }
+
+    // Returns an arrangement of the whole bag which reaches the given
+    // score, made from a known arrangement of a solved proper subset by
+    // laying the tiles left over on the table beside it (which doesn't
+    // change the score), so that it's complete.
+    pub fn subset_state(&self, bag: &Bag, score: isize) -> Option<State> {
+        let (sub, state) = self.states.iter()
+            .filter(|&(&i, _)| self.entry(i).score() == Some(score))
+            .map(|(&i, s)| (Bag::from_index(i, self.rules.copies), s))
+            .find(|(b, _)| b.len() < bag.len() && bag.contains(b))?;
+        let mut state = state.clone();
+        for t in 0..UNIQUE_PIECE_COUNT {
+            for _ in sub.digit(t)..bag.digit(t) {
+                let boards = Bitboards::new(&state);
+                state = boards.placements(&state, t * MAX_ROTATIONS)
+                    .find(|(p, _)| p.z == 0)?.1;
+            }
+        }
+        return Some(state).filter(|s| self.rules.score(s) == score);
+    }
+
+    // Records the best score found by a solve which was cut short
+    pub fn write_lower_bound(&mut self, bag: &Bag, score: isize) {
+        let i = self.index(bag);
+        self.set_score(i, score, false);
+    }
+
+    // Records an estimate of the bag's best score, along with the best
+    // score actually found as a lower bound.  The bag stays unsolved.
+    pub fn write_estimate(&mut self, bag: &Bag, e: ScoreEstimate) {
+        self.write_lower_bound(bag, e.found);
+        let i = self.index(bag);
+        self.estimates.insert(i, e);
+    }
+
+    // Returns the estimate of the bag's best score, if it has one.  This
+    // is only statistical:  see score() for exact results.
+    pub fn estimate(&self, bag: &Bag) -> Option<ScoreEstimate> {
+        self.estimates.get(&self.index(bag)).cloned()
+    }
+
+    // Returns the bag's best score, if it has been fully solved
+    pub fn score(&self, bag: &Bag) -> Option<isize> {
+        self.bounds.score(bag)
+    }
+
+    // Returns the best score found for the bag, even if its solve was
+    // cut short (in which case the true best score may be higher)
+    pub fn lower_bound(&self, bag: &Bag) -> Option<isize> {
+        self.bounds.lower_bound(bag)
+    }
+
+    // Writes every bag with a score or lower bound as a line of
+    // tab-separated fields:  the bag's digits, its score, and "exact",
+    // "lower" (for a lower bound), or "estimate".  Exact scores may be
+    // followed by their best arrangement (see State::layout), and estimates
+    // by the estimated score, its interval, confidence, and number of runs.
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        self.write_header(out)?;
+        for i in 0..self.bounds.entries.len() {
+            self.write_entry(out, i)?;
+        }
+        Ok(())
+    }
+
+    // Writes one bag's line of write()'s output, or nothing if the bag
+    // has no score or bound
+    pub fn write_bag<W: Write>(&self, out: &mut W, bag: &Bag) -> io::Result<()> {
+        self.write_entry(out, self.index(bag))
+    }
+
+    fn write_entry<W: Write>(&self, out: &mut W, i: usize) -> io::Result<()> {
+        let e = self.entry(i);
+        let score = match e.lower_bound() {
+            Some(s) => s,
+            None => return Ok(()),
+        };
+        let bag = Bag::from_index(i, self.rules.copies).digits();
+        if e.complete {
+            match self.states.get(&i) {
+                Some(s) if !s.is_empty() => writeln!(
+                    out, "{}\t{}\texact\t{}", bag, score, s.layout())?,
+                _ => writeln!(out, "{}\t{}\texact", bag, score)?,
+            }
+        } else if let Some(est) = self.estimates.get(&i) {
+            writeln!(out, "{}\t{}\testimate\t{}\t{}\t{}\t{}\t{}", bag,
+                     score, est.score, est.low, est.high, est.confidence,
+                     est.runs)?;
+        } else {
+            writeln!(out, "{}\t{}\tlower", bag, score)?;
+        }
+        Ok(())
+    }
+
+    // Writes the comment line which starts write()'s output, naming the
+    // rules and pieces that the scores are for
+    pub fn write_header<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "{} v{} for {} pieces={:016x}", HEADER, VERSION,
+                 self.rules.encode(), self.pieces)
+    }
+
+    // Loads lines in the format from write(), returning the number of bags
+    // which were loaded.  Lines starting with '#' are ignored, except for
+    // a header from another version of the format, and a line with only a
+    // bag and a score (as in a list of published scores) is taken as exact.
+    pub fn read<R: BufRead>(&mut self, input: R) -> Result<usize, String> {
+        let mut count = 0;
+        for (n, line) in input.lines().enumerate() {
+            let line = line.map_err(|e| e.to_string())?;
+            if is_outdated(&line) {
+                return Err(format!("The results on line {} are from an \
+                                    older version of nmbr9, and need to be \
+                                    solved again", n + 1));
+            }
+            if line.starts_with('#') || line.trim().is_empty() {
+                continue;
+            }
+            self.read_line(&line)
+                .ok_or_else(|| format!("Invalid results on line {}", n + 1))?;
+            count += 1;
+        }
+        return Ok(count);
+    }
+
+    fn read_line(&mut self, line: &str) -> Option<()> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let bag = Bag::from_digits(fields.first()?, self.rules.copies)?;
+        // Scores out of an Entry's range can't be right for these rules
+        let score = fields.get(1)?.parse::<i16>().ok()
+            .filter(|&s| s != UNSOLVED)? as isize;
+        match (fields.get(2).cloned().unwrap_or("exact"), fields.len()) {
+            ("exact", 2) | ("exact", 3) => self.write_score(&bag, score, None),
+            ("exact", 4) => {
+                let state = State::from_layout(fields[3], self.rules.adjacency)?;
+                self.write_score(&bag, score, Some(&state));
+            }
+            ("lower", 3) => self.write_lower_bound(&bag, score),
+            ("estimate", 8) => self.write_estimate(&bag, ScoreEstimate {
+                found: score,
+                score: fields[3].parse().ok()?,
+                low: fields[4].parse().ok()?,
+                high: fields[5].parse().ok()?,
+                confidence: fields[6].parse().ok()?,
+                runs: fields[7].parse().ok()?,
+            }),
+            _ => return None,
+        }
+        return Some(());
+    }
+
+    // Writes every bag with a score or lower bound as comma-separated
+    // values, with a header row:  the bag's digits, its score, "exact" or
+    // "lower" (estimates are written as their lower bound), and its best
+    // arrangement (quoted, since a layout has commas) if known.  Bags are
+    // in the table's order, so two files can be compared line by line.
+    pub fn write_csv<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "bag,score,status,layout")?;
+        for i in 0..self.bounds.entries.len() {
+            let e = self.entry(i);
+            let score = match e.lower_bound() {
+                Some(s) => s,
+                None => continue,
+            };
+            let bag = Bag::from_index(i, self.rules.copies).digits();
+            let layout = match self.states.get(&i) {
+                Some(s) if e.complete && !s.is_empty() => s.layout(),
+                _ => String::new(),
+            };
+            writeln!(out, "{},{},{},\"{}\"", bag, score,
+                     if e.complete { "exact" } else { "lower" }, layout)?;
+        }
+        Ok(())
+    }
+
+    // Loads rows in the format from write_csv, returning the number of
+    // bags which were loaded
+    pub fn read_csv<R: BufRead>(&mut self, input: R) -> Result<usize, String> {
+        let mut count = 0;
+        for (n, line) in input.lines().enumerate() {
+            let line = line.map_err(|e| e.to_string())?;
+            if (n == 0 && line.starts_with("bag,")) || line.trim().is_empty() {
+                continue;
+            }
+            self.read_csv_row(&line)
+                .ok_or_else(|| format!("Invalid CSV on line {}", n + 1))?;
+            count += 1;
+        }
+        return Ok(count);
+    }
+
+    fn read_csv_row(&mut self, line: &str) -> Option<()> {
+        let fields: Vec<&str> = line.splitn(4, ',').collect();
+        if fields.len() != 4 {
+            return None;
+        }
+        let bag = Bag::from_digits(fields[0], self.rules.copies)?;
+        // As in read_line, scores out of an Entry's range are refused
+        let score = fields[1].parse::<i16>().ok()
+            .filter(|&s| s != UNSOLVED)? as isize;
+        let layout = fields[3].trim_matches('"');
+        match (fields[2], layout.is_empty()) {
+            ("exact", true) => self.write_score(&bag, score, None),
+            ("exact", false) => {
+                let state = State::from_layout(layout, self.rules.adjacency)?;
+                self.write_score(&bag, score, Some(&state));
+            }
+            ("lower", true) => self.write_lower_bound(&bag, score),
+            _ => return None,
+        }
+        return Some(());
+    }
+
+    // Merges in another table under the same rules, as from a partial run
+    // on another machine:  exact scores replace lower bounds, and lower
+    // bounds only go up.  Returns a description of each bag where the two
+    // disagree (keeping the higher of the two proven scores), or an error
+    // if the other table is for different rules or pieces.
+    pub fn merge(&mut self, other: &Results) -> Result<Vec<String>, String> {
+        if other.rules != self.rules {
+            return Err(format!("The other results are for different rules \
+                                ({})", other.rules.encode()));
+        } else if other.pieces != self.pieces {
+            return Err("The other results are for different pieces"
+                       .to_string());
+        }
+        let mut out = Vec::new();
+        for i in 0..other.bounds.entries.len() {
+            let theirs = other.entry(i);
+            let score = match theirs.lower_bound() {
+                Some(s) => s,
+                None => continue,
+            };
+            let bag = Bag::from_index(i, self.rules.copies);
+            let ours = self.entry(i);
+            match (ours.score(), theirs.complete) {
+                (Some(s), true) if s != score => out.push(format!(
+                    "{} scores {} here, but {} in the other results",
+                    bag.digits(), s, score)),
+                (Some(s), false) if s < score => out.push(format!(
+                    "{} scores {} here, below the other results' bound of {}",
+                    bag.digits(), s, score)),
+                (Some(_), true) => if let Some(state) = other.states.get(&i) {
+                    self.states.entry(i).or_insert_with(|| state.clone());
+                },
+                (Some(_), false) => (),
+                (None, true) => match ours.lower_bound() {
+                    Some(s) if s > score => out.push(format!(
+                        "{} has a lower bound of {} here, above the other \
+                         results' score of {}", bag.digits(), s, score)),
+                    _ => self.write_score(&bag, score, other.states.get(&i)),
+                },
+                (None, false) => if ours.lower_bound().is_none_or(|s| s < score) {
+                    match other.estimates.get(&i) {
+                        Some(&e) => self.write_estimate(&bag, e),
+                        None => self.write_lower_bound(&bag, score),
+                    }
+                },
+            }
+        }
+        return Ok(out);
+    }
+
+    // Checks that the table is consistent, returning a description of each
+    // problem:  no bag can score less than its subsets (since the extra
+    // tiles could be laid on the table), and exact scores must be within
+    // the stacking bound.
+    pub fn check(&self) -> Vec<String> {
+        let mut out = Vec::new();
+        for i in 0..self.bounds.entries.len() {
+            let e = self.entry(i);
+            let bag = Bag::from_index(i, self.rules.copies);
+            if let Some(score) = e.score() {
+                let bound = self.rules.scoring.score_stacked(&bag, &self.rules.values);
+                if score > bound {
+                    out.push(format!("{} scores {}, above its bound of {}",
+                                     bag.digits(), score, bound));
+                }
+                for p in 0..UNIQUE_PIECE_COUNT {
+                    if bag.digit(p) == 0 {
+                        continue;
+                    }
+                    let sub = bag.take(p * MAX_ROTATIONS);
+                    match self.lower_bound(&sub) {
+                        Some(s) if s > score => out.push(format!(
+                            "{} scores {}, below its subset {} at {}",
+                            bag.digits(), score, sub.digits(), s)),
+                        _ => (),
+                    }
+                }
+            }
+            if let Some(est) = self.estimates.get(&i) {
+                if est.low > est.high || e.lower_bound() != Some(est.found) {
+                    out.push(format!("{} has an inconsistent estimate",
+                                     bag.digits()));
+                }
+            }
+        }
+        return out;
+    }
+
+    // For each distinct piece in the bag, returns how much the optimal score
+    // drops if one copy of that piece is removed, sorted with the most
+    // valuable piece first.  Returns None unless the bag and all of its
+    // one-piece-smaller subsets have been solved.
+    pub fn marginal_values(&self, bag: &Bag) -> Option<Vec<(usize, isize)>> {
+        let score = self.score(bag)?;
+        let mut out = Vec::new();
+        for i in 0..UNIQUE_PIECE_COUNT {
+            if bag.digit(i) > 0 {
+                let sub = bag.take(i * MAX_ROTATIONS);
+                out.push((i, score - self.score(&sub)?));
+            }
+        }
+        out.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        return Some(out);
+    }
+
+    // In the two-player half-deck variant, each player is dealt a random
+    // half of the tiles.  Returns the optimal scores for a hand and for
+    // the opponent's hand (the rest of the tiles), if both are solved.
+    pub fn half_deck_scores(&self, hand: &Bag) -> Option<(isize, isize)> {
+        Some((self.score(hand)?,
+              self.score(&hand.complement(self.rules.copies))?))
+    }
+
+    // Returns the expected difference between the two players' optimal
+    // scores in the half-deck variant, over every possible deal.  Since
+    // the table holds every hand, this is exact rather than sampled: each
+    // hand is weighted by the number of ways to deal its physical tiles.
+    pub fn half_deck_expected_gap(&self) -> Option<f64> {
+        let copies = self.rules.copies;
+        let mut total = 0;
+        let mut deals = 0;
+        for hand in self.bags() {
+            if hand.len() * 2 != UNIQUE_PIECE_COUNT * copies {
+                continue;
+            }
+            let (a, b) = self.half_deck_scores(&hand)?;
+            let ways: usize = (0..UNIQUE_PIECE_COUNT)
+                .map(|j| if hand.digit(j) == 1 { copies } else { 1 })
+                .product();
+            total += ways * a.abs_diff(b);
+            deals += ways;
+        }
+        return Some(total as f64 / deals as f64);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use piece::PieceSet;
+    use scoring::Scoring;
+
+    #[test]
+    fn marginal_values() {
+        let mut results = Results::new(Rules::default());
+        let bag = Bag::from_usize(0).add(4).add(8).add(9);
+        assert_eq!(results.marginal_values(&bag), None);
+
+        results.write_score(&bag, 4, None);
+        results.write_score(&bag.take(4), 3, None);
+        assert_eq!(results.marginal_values(&bag), None);
+
+        results.write_score(&bag.take(8), 0, None);
+        assert_eq!(results.marginal_values(&bag),
+                   Some(vec![(2, 4), (1, 1)]));
+    }
+
+    #[test]
+    fn half_deck() {
+        let mut results = Results::new(Rules::default());
+        assert_eq!(results.half_deck_expected_gap(), None);
+
+        // Score every hand by its face value, so that the gap is the
+        // difference in face value between the two hands
+        for hand in results.bags() {
+            if hand.len() == UNIQUE_PIECE_COUNT {
+                results.write_score(&hand, hand.score_flat(), None);
+            }
+        }
+
+        let hand = Bag::from_usize(0).add(4).add(5).add(8).add(9).add(12)
+                                    .add(13).add(16).add(17).add(20).add(21);
+        assert_eq!(results.half_deck_scores(&hand), Some((30, 60)));
+
+        // The most lopsided deal has a gap of 50
+        let low = (0..5).map(|t| t * MAX_ROTATIONS)
+            .fold(Bag::from_usize(0), |b, p| b.add(p).add(p));
+        assert_eq!(results.half_deck_scores(&low), Some((20, 70)));
+
+        // Summing |2S - 90| over all C(20, 10) = 184756 ways to deal the
+        // physical tiles, where S is the first hand's face value, gives an
+        // average of 44412 / 4199
+        let gap = results.half_deck_expected_gap().unwrap();
+        assert!((gap - 44412.0 / 4199.0).abs() < 1e-9, "{}", gap);
+    }
+
+    #[test]
+    fn single_copy() {
+        let mut results = Results::new(Rules { copies: 1, ..Rules::default() });
+        assert_eq!(results.bags().len(), 1024);
+        assert_eq!(results.full_bag().len(), UNIQUE_PIECE_COUNT);
+
+        let bag = Bag::from_usize(0).add(4).add(8);
+        results.write_score(&bag, 1, None);
+        assert_eq!(results.score(&bag), Some(1));
+        assert_eq!(results.score(&bag.take(4)), None);
+    }
+
+    #[test]
+    fn incomplete() {
+        let mut results = Results::new(Rules::default());
+        let nine = Bag::from_usize(0).add(36);
+        let zero = Bag::from_usize(0).add(0);
+        results.write_score(&Bag::from_usize(0), 0, None);
+        results.write_score(&nine, 0, None);
+        results.write_score(&zero, 0, None);
+        results.write_score(&nine.add(0), 0, None);
+
+        // Pretend that the solve for two 9s was cut short
+        let bag = nine.add(37);
+        results.write_lower_bound(&bag, 5);
+        assert_eq!(results.score(&bag), None);
+        assert_eq!(results.lower_bound(&bag), Some(5));
+
+        // Lower bounds can seed the search, but can't bound it from above.
+        // Two 9s can't be stacked, but could be raised by a layer.
+        assert_eq!(results.upper_subset_score(&bag.add(0)), 5);
+        assert_eq!(results.upper_score_bound(&bag, &State::new()), 18);
+
+        results.write_score(&bag, 3, None);
+        assert_eq!(results.score(&bag), Some(3));
+        assert_eq!(results.upper_score_bound(&bag, &State::new()), 3 + 18);
+    }
+
+    #[test]
+    fn layered_bound() {
+        let mut results = Results::new(Rules::default());
+        let nine = Bag::from_usize(0).add(36);
+        results.write_score(&nine, 0, None);
+
+        // A 1 bridging two 0s is too small to hold a 9, so the 9 can't be
+        // more than one layer up
+        let state = State::new().try_place(0, 0, 0).unwrap()
+            .try_place(0, 3, 0).unwrap()
+            .try_place(4, 2, 0).unwrap();
+        assert_eq!(state.layers(), 1);
+        assert_eq!(results.upper_score_bound(&nine, &state), 9);
+
+        // Another 0 is too small to hold the 9 on top of the 1, but a 1
+        // and a 0 may be beside it; everything can be on the top layer.
+        let bag = nine.add(0).add(4);
+        results.write_score(&bag, 5, None);
+        assert!(results.upper_score_bound(&bag, &state) < 5 + 2 * 10);
+        assert!(results.upper_score_bound(&bag, &state) >= 10);
+
+        // Nothing is lost on an empty table
+        assert_eq!(results.upper_score_bound(&nine, &State::new()), 9);
+    }
+
+    #[test]
+    fn layered_bound_brute() {
+        // The best that the bag's tiles can add to the state's score,
+        // trying every placement of every tile in every order
+        fn best(state: &State, bag: &Bag) -> isize {
+            let rules = Rules::default();
+            if bag.is_empty() {
+                return rules.score(state);
+            }
+            // Any arrangement can be turned so its first tile is unrotated
+            let rotations = if state.is_empty() { 1 } else { MAX_ROTATIONS };
+            let boards = Bitboards::new(state);
+            let mut out = isize::MIN;
+            for t in (0..UNIQUE_PIECE_COUNT).filter(|&t| bag.digit(t) > 0) {
+                let rest = bag.take(t * MAX_ROTATIONS);
+                for p in (0..rotations).map(|r| t * MAX_ROTATIONS + r) {
+                    for (_, s) in boards.placements(state, p) {
+                        out = max(out, best(&s, &rest));
+                    }
+                }
+            }
+            return out;
+        }
+
+        // Every bag of up to two tiles, solved exactly as the solver would
+        let mut results = Results::new(Rules::default());
+        let small: Vec<Bag> = results.bags().into_iter()
+            .filter(|b| b.len() <= 2).collect();
+        for b in &small {
+            results.write_score(b, best(&State::new(), b), None);
+        }
+
+        // The bound holds for each of them on top of states with small top
+        // layers, where the raised tiles may not fit
+        let one = State::new().try_place(4, 0, 0).unwrap();
+        let bridge = State::new().try_place(0, 0, 0).unwrap()
+            .try_place(0, 3, 0).unwrap()
+            .try_place(4, 2, 0).unwrap();
+        let mut tighter = 0;
+        for state in &[one, bridge] {
+            let base = Rules::default().score(state);
+            let raised = state.layers() + 1;
+            for b in small.iter().filter(|b| !b.is_empty()) {
+                let actual = best(state, b) - base;
+                let bound = results.upper_score_bound(b, state);
+                assert!(bound >= actual,
+                        "{:?} can score {} on\n{:?}", b, actual, state);
+                if bound < results.bounds.raised_bound(b, raised) {
+                    tighter += 1;
+                }
+            }
+        }
+        // ...and it's tighter than raising the whole bag for some of them,
+        // so the splits were checked
+        assert!(tighter > 0);
+
+        // Bags with too many ways to split them keep the raised bound
+        let bag = (0..8).fold(Bag::from_usize(0), |b, t| b.add(t * MAX_ROTATIONS));
+        let state = State::new().try_place(4, 0, 0).unwrap();
+        assert_eq!(results.bounds.layered_bound(&bag, &state),
+                   results.bounds.raised_bound(&bag, 1));
+    }
+
+    #[test]
+    fn bounds() {
+        for &(score, delta, complete) in &[(UNSOLVED, 0, false), (-3, 45, true),
+                                           (7, 12, false), (i16::MAX, 0, true),
+                                           (-i16::MAX, i16::MAX, true)] {
+            let e = Entry::unpack(Entry { score: score, delta: delta,
+                                          complete: complete }.pack());
+            assert_eq!((e.score, e.delta, e.complete), (score, delta, complete));
+        }
+
+        // Every built-in scoring fits with the standard values, but not
+        // with values big enough to overflow an Entry
+        for &scoring in &[Scoring::Linear, Scoring::Squared, Scoring::TopBonus] {
+            assert!(scores_fit(&Rules { scoring: scoring, ..Rules::default() }));
+        }
+        let mut rules = Rules::default();
+        rules.values[9] = 2000;
+        assert!(!scores_fit(&rules));
+        rules.values[9] = -2000;
+        assert!(!scores_fit(&rules));
+
+        // The shared bounds see scores as they're written
+        let mut results = Results::new(Rules::default());
+        let bounds = results.bounds();
+        let bag = Bag::from_usize(0).add(0).add(4);
+        assert_eq!(bounds.score(&bag), None);
+        results.write_score(&bag, 1, None);
+        assert_eq!(bounds.score(&bag), Some(1));
+        assert_eq!(bounds.upper_subset_score(&bag.add(8)), 1);
+    }
+
+    #[test]
+    fn upper_subset_score() {
+        let mut results = Results::new(Rules::default());
+        let nine = Bag::from_usize(0).add(36);
+        let bag = nine.add(0).add(4);
+        assert_eq!(results.upper_subset_score(&bag), 0);
+
+        // Scores count for every superset, whatever order they're written in
+        results.write_lower_bound(&nine.add(4), 4);
+        results.write_score(&nine, 2, None);
+        assert_eq!(results.upper_subset_score(&bag), 4);
+        assert_eq!(results.upper_subset_score(&nine.add(0)), 2);
+        assert_eq!(results.upper_subset_score(&nine.add(4)), 2);
+        assert_eq!(results.upper_subset_score(&bag.add(8)), 4);
+
+        // A bag doesn't count as its own subset
+        results.write_score(&bag, 7, None);
+        assert_eq!(results.upper_subset_score(&bag), 4);
+        assert_eq!(results.upper_subset_score(&bag.add(8)), 7);
+
+        // Lowering a score takes it back out
+        results.write_score(&nine.add(4), 3, None);
+        assert_eq!(results.upper_subset_score(&bag), 3);
+    }
+
+    #[test]
+    fn write_read() {
+        let mut results = Results::new(Rules::default());
+        let bag = Bag::from_usize(0).add(36).add(37);
+        let nine = State::new().try_place(36, 0, 0).unwrap();
+        results.write_score(&Bag::from_usize(0), 0, None);
+        results.write_score(&bag.take(36), 0, Some(&nine));
+        results.write_lower_bound(&bag.add(0), 4);
+        let both = results.subset_state(&bag, 0).unwrap();
+        assert_eq!(both.pieces.len(), 2);
+        assert!(both.pieces.iter().all(|p| p.index() == 9 && p.z == 0));
+        assert_eq!(results.subset_state(&bag, 1), None);
+        let e = ScoreEstimate::from_maxima(&[4, 6], 0.95, 18).unwrap();
+        results.write_estimate(&bag, e);
+
+        let mut out = Vec::new();
+        results.write(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("0000000000\t0\texact\n"));
+        assert!(text.contains("0000000001\t0\texact\t36,0,0,0\n"));
+        assert!(text.contains("1000000002\t4\tlower\n"));
+        assert!(text.contains("0000000002\t6\testimate\t8\t6\t18\t0.95\t2\n"));
+
+        // Single bags are written the same way, and unsolved bags not at all
+        let mut one = Vec::new();
+        results.write_bag(&mut one, &bag.add(0)).unwrap();
+        results.write_bag(&mut one, &bag.add(4)).unwrap();
+        assert_eq!(one, b"1000000002\t4\tlower\n");
+
+        let mut loaded = Results::new(Rules::default());
+        assert_eq!(loaded.read(text.as_bytes()), Ok(4));
+        assert_eq!(loaded.score(&bag.take(36)), Some(0));
+        assert_eq!(loaded.state(&bag.take(36)), Some(&nine));
+        assert_eq!(loaded.score(&bag.add(0)), None);
+        assert_eq!(loaded.lower_bound(&bag.add(0)), Some(4));
+        assert_eq!(loaded.estimate(&bag), Some(e));
+
+        // Bare scores are exact
+        assert_eq!(loaded.read("0000000012\t3\n".as_bytes()), Ok(1));
+        assert_eq!(loaded.score(&bag.add(32)), Some(3));
+
+        let mut bad = Results::new(Rules::default());
+        assert!(bad.read("0000000001\n".as_bytes()).is_err());
+        assert_eq!(bad.read("0000000001\t0\texact\n00\t1\texact\n".as_bytes()),
+                   Err("Invalid results on line 2".to_string()));
+        assert!(bad.read("0000000001\t0\texact\t36,0,0,1\n".as_bytes()).is_err());
+        let mut bad = Results::new(Rules { copies: 1, ..Rules::default() });
+        assert!(bad.read("0000000002\t0\texact\n".as_bytes()).is_err());
+
+        // Files from before the format was versioned may have scores which
+        // are too low, so they aren't loaded
+        let old = text.replacen(&format!(" v{} ", VERSION), " ", 1);
+        assert!(is_outdated(&old));
+        assert!(!is_outdated(&text));
+        assert!(Results::new(Rules::default()).read(old.as_bytes()).is_err());
+
+        // The header's rules and pieces can be read back and compared
+        assert_eq!(read_header(&text),
+                   Some((Rules::default(), pieces().fingerprint())));
+        assert!(header_matches(&text, &Rules::default()));
+        assert!(!header_matches(&text, &Rules { copies: 1, ..Rules::default() }));
+        assert_eq!(read_header(&old), None);
+        assert_eq!(read_header("0000000001\t0\texact\n"), None);
+
+        // Scores for other shapes don't match, even under the same rules
+        let mut shapes = pieces().shapes;
+        shapes.swap(0, 1);
+        let other = PieceSet::new(shapes).unwrap().fingerprint();
+        assert_ne!(other, pieces().fingerprint());
+        let custom = text.replacen(&format!("{:016x}", pieces().fingerprint()),
+                                   &format!("{:016x}", other), 1);
+        assert!(!header_matches(&custom, &Rules::default()));
+    }
+
+    #[test]
+    fn csv() {
+        let mut results = Results::new(Rules::default());
+        let bag = Bag::from_usize(0).add(36).add(37);
+        let nine = State::new().try_place(36, 0, 0).unwrap();
+        results.write_score(&Bag::from_usize(0), 0, None);
+        results.write_score(&bag.take(36), 0, Some(&nine));
+        results.write_lower_bound(&bag, 4);
+
+        let mut out = Vec::new();
+        results.write_csv(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(text, "bag,score,status,layout\n\
+                          0000000000,0,exact,\"\"\n\
+                          0000000001,0,exact,\"36,0,0,0\"\n\
+                          0000000002,4,lower,\"\"\n");
+
+        let mut loaded = Results::new(Rules::default());
+        assert_eq!(loaded.read_csv(text.as_bytes()), Ok(3));
+        assert_eq!(loaded.state(&bag.take(36)), Some(&nine));
+        assert_eq!(loaded.score(&bag), None);
+        assert_eq!(loaded.lower_bound(&bag), Some(4));
+        assert_eq!(loaded.read_csv("0000000001,0,lower\n".as_bytes()),
+                   Err("Invalid CSV on line 1".to_string()));
+        for row in ["0000000001,99999,exact,\"\"",
+                    "0000000001,-32768,lower,\"\""] {
+            assert_eq!(loaded.read_csv(row.as_bytes()),
+                       Err("Invalid CSV on line 1".to_string()));
+        }
+    }
+
+    #[test]
+    fn merge() {
+        let rules = Rules::default();
+        let bag = Bag::from_usize(0).add(36).add(37);
+        let nine = State::new().try_place(36, 0, 0).unwrap();
+        let mut a = Results::new(rules);
+        a.write_score(&bag.take(36), 0, None);
+        a.write_lower_bound(&bag, 2);
+        a.write_lower_bound(&bag.add(0), 5);
+        let mut b = Results::new(rules);
+        b.write_score(&Bag::from_usize(0), 0, None);
+        b.write_score(&bag.take(36), 0, Some(&nine));
+        b.write_score(&bag, 3, None);
+        b.write_lower_bound(&bag.add(0), 4);
+        assert!(a.merge(&b).unwrap().is_empty());
+
+        // Exact scores (and missing arrangements) are filled in, and the
+        // higher bound is kept
+        assert_eq!(a.score(&Bag::from_usize(0)), Some(0));
+        assert_eq!(a.state(&bag.take(36)), Some(&nine));
+        assert_eq!(a.score(&bag), Some(3));
+        assert_eq!(a.lower_bound(&bag.add(0)), Some(5));
+
+        let mut c = Results::new(rules);
+        c.write_score(&bag, 4, None);
+        assert_eq!(a.merge(&c).unwrap().len(), 1);
+        assert_eq!(a.score(&bag), Some(3));
+
+        // An exact score below a proven lower bound is a disagreement too,
+        // and the bound is kept
+        let mut d = Results::new(rules);
+        d.write_score(&bag.add(0), 4, None);
+        assert_eq!(a.merge(&d).unwrap().len(), 1);
+        assert_eq!(a.lower_bound(&bag.add(0)), Some(5));
+        assert_eq!(a.score(&bag.add(0)), None);
+
+        // Tables for other rules or pieces can't be merged
+        let mut e = Results::new(Rules { copies: 1, ..rules });
+        assert!(a.merge(&e).is_err());
+        e = Results::new(rules);
+        e.pieces ^= 1;
+        assert!(a.merge(&e).is_err());
+    }
+
+    #[test]
+    fn check() {
+        let mut results = Results::new(Rules::default());
+        let bag = Bag::from_usize(0).add(36).add(37);
+        results.write_score(&bag.take(36), 0, None);
+        results.write_score(&bag, 0, None);
+        assert!(results.check().is_empty());
+
+        // Two 9s can't be stacked, and a bag can't do worse than its
+        // subsets
+        results.write_score(&bag, 20, None);
+        results.write_score(&bag.take(36), 21, None);
+        assert_eq!(results.check().len(), 3);
+    }
+
+    #[test]
+    fn estimate() {
+        let mut results = Results::new(Rules::default());
+        let bag = Bag::from_usize(0).add(36).add(37);
+        let e = ScoreEstimate::from_maxima(&[4, 6], 0.95, 18).unwrap();
+        results.write_estimate(&bag, e);
+
+        // Estimates are kept apart from exact scores
+        assert_eq!(results.estimate(&bag), Some(e));
+        assert_eq!(results.score(&bag), None);
+        assert_eq!(results.lower_bound(&bag), Some(6));
+
+        results.write_score(&bag, 9, None);
+        assert_eq!(results.estimate(&bag), None);
+        assert_eq!(results.score(&bag), Some(9));
     }
 }
@@ -0,0 +1,76 @@
+// A small pseudo-random number generator (SplitMix64) for everything that
+// takes a seed:  shuffled decks, random games and strategies, Monte Carlo
+// searches, and seeded move orders.  Unlike std's DefaultHasher, which
+// these used to hash their seeds with, its numbers are fixed by the
+// algorithm, so a seed gives the same results with any Rust release.
+
+use state::State;
+
+const GAMMA: u64 = 0x9e3779b97f4a7c15;
+
+// SplitMix64's output function, which scrambles every bit of a word
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+#[derive(Clone, Debug)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    // Seeds a generator from several words (such as a seed and the number
+    // of a run), so that each combination gets its own numbers
+    pub fn keyed<I: IntoIterator<Item=u64>>(words: I) -> Rng {
+        Rng(words.into_iter().fold(0, |h, w| mix(h.wrapping_add(GAMMA) ^ w)))
+    }
+
+    // As keyed, with a state's placements (see Placed::pack) after the
+    // words, for choices which only depend on where the search is
+    pub fn for_state(words: &[u64], state: &State) -> Rng {
+        Rng::keyed(words.iter().cloned()
+            .chain(state.pieces.iter().map(|p| p.pack() as u64)))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(GAMMA);
+        mix(self.0)
+    }
+
+    // Returns a number below n (which must be positive), by scaling the
+    // next number down rather than taking a remainder, so every value is
+    // equally likely
+    pub fn below(&mut self, n: usize) -> usize {
+        debug_assert!(n > 0);
+        ((self.next_u64() as u128 * n as u128) >> 64) as usize
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splitmix() {
+        // The reference implementation's first outputs for seed 0, which
+        // must never change, or seeded runs won't repeat
+        let mut r = Rng::new(0);
+        assert_eq!(r.next_u64(), 0xe220a8397b1dcdaf);
+        assert_eq!(r.next_u64(), 0x6e789e6aa1b965f4);
+        assert_eq!(r.next_u64(), 0x06c45d188009454f);
+
+        let mut r = Rng::new(7);
+        assert!((0..1000).map(|_| r.below(3)).all(|i| i < 3));
+
+        assert_eq!(Rng::keyed(vec![1, 2]).next_u64(),
+                   Rng::keyed(vec![1, 2]).next_u64());
+        assert_ne!(Rng::keyed(vec![1, 2]).next_u64(),
+                   Rng::keyed(vec![2, 1]).next_u64());
+    }
+}
@@ -0,0 +1,170 @@
+use piece::{Adjacency, Values, PIECE_VALUES, UNIQUE_PIECE_COUNT};
+use scoring::Scoring;
+use state::State;
+
+// Variant rules for a solve.  Scores computed under one set of rules
+// can't be reused under another, so these live alongside the Results.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Rules {
+    pub scoring: Scoring,
+    pub adjacency: Adjacency,
+
+    // If present, the maximum number of layers (including the ground)
+    pub max_layers: Option<usize>,
+
+    // How many copies of each tile are in the game (at most MAX_COPIES)
+    pub copies: usize,
+
+    // The value scored by each tile, per layer
+    pub values: Values,
+
+    // Whether tiles may be flipped over, so that they can be placed in
+    // their mirror images' orientations too
+    pub mirrored: bool,
+}
+
+impl Default for Rules {
+    fn default() -> Rules {
+        Rules {
+            scoring: Scoring::Linear,
+            adjacency: Adjacency::Orthogonal,
+            max_layers: None,
+            copies: 2,
+            values: PIECE_VALUES,
+            mirrored: false,
+        }
+    }
+}
+
+impl Rules {
+    // Makes the given tile wild:  it can count as any face value, chosen
+    // when it's scored.  Level multipliers are never negative, so the best
+    // choice is always the highest value in the game, and we can fold the
+    // choice directly into the value table.  Every Scoring only grows with
+    // a tile's value (TopBonus takes the top layer's highest), so this is
+    // the joker's true best score, and the bounds built from the same
+    // table stay sound.
+    pub fn with_joker(mut self, piece: usize) -> Rules {
+        self.values[piece] = *self.values.iter().max().unwrap();
+        self
+    }
+
+    pub fn score(&self, state: &State) -> isize {
+        self.scoring.score(state, &self.values)
+    }
+
+    // Writes the rules as text for file headers, such as
+    //
+    //   scoring=linear adjacency=orthogonal max_layers=none copies=2
+    //   values=0,1,2,3,4,5,6,7,8,9
+    //
+    // (on one line).  Unlike the Debug output, this doesn't change when
+    // the struct does, so files can be checked with decode.  Rules added
+    // since (such as mirrored=yes) are only written when they're on, so
+    // files for the rules before them keep the same headers.
+    pub fn encode(&self) -> String {
+        let scoring = match self.scoring {
+            Scoring::Linear => "linear",
+            Scoring::Squared => "squared",
+            Scoring::TopBonus => "top-bonus",
+        };
+        let adjacency = match self.adjacency {
+            Adjacency::Orthogonal => "orthogonal",
+            Adjacency::Diagonal => "diagonal",
+        };
+        let values: Vec<String> = self.values.iter()
+            .map(|v| v.to_string()).collect();
+        return format!("scoring={} adjacency={} max_layers={} copies={} \
+                        values={}{}", scoring, adjacency,
+                       self.max_layers.map(|n| n.to_string())
+                           .unwrap_or("none".to_string()),
+                       self.copies, values.join(","),
+                       if self.mirrored { " mirrored=yes" } else { "" });
+    }
+
+    // Reads rules written by encode, which must have every field but the
+    // optional ones at the end
+    pub fn decode(text: &str) -> Option<Rules> {
+        let mut fields = [None; 6];
+        for word in text.split_whitespace() {
+            let (key, value) = word.split_at(word.find('=')?);
+            let i = ["scoring", "adjacency", "max_layers", "copies", "values",
+                     "mirrored"]
+                .iter().position(|&k| k == key)?;
+            fields[i] = Some(&value[1..]);
+        }
+        let mut values = [0; UNIQUE_PIECE_COUNT];
+        let mut given = fields[4]?.split(',');
+        for v in values.iter_mut() {
+            *v = given.next()?.parse().ok()?;
+        }
+        if given.next().is_some() {
+            return None;
+        }
+        return Some(Rules {
+            scoring: match fields[0]? {
+                "linear" => Scoring::Linear,
+                "squared" => Scoring::Squared,
+                "top-bonus" => Scoring::TopBonus,
+                _ => return None,
+            },
+            adjacency: match fields[1]? {
+                "orthogonal" => Adjacency::Orthogonal,
+                "diagonal" => Adjacency::Diagonal,
+                _ => return None,
+            },
+            max_layers: match fields[2]? {
+                "none" => None,
+                n => Some(n.parse().ok()?),
+            },
+            copies: fields[3]?.parse().ok()?,
+            values: values,
+            mirrored: match fields[5] {
+                None | Some("no") => false,
+                Some("yes") => true,
+                _ => return None,
+            },
+        });
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use state::Placed;
+
+    #[test]
+    fn joker() {
+        let rules = Rules::default().with_joker(0);
+        assert_eq!(rules.values[0], 9);
+        assert_eq!(rules.values[9], 9);
+
+        // A 0 on level 1 now scores as a 9
+        let state = State::new()
+            .insert(Placed::new(4, 0, 0, 0))
+            .insert(Placed::new(0, 0, 0, 1));
+        assert_eq!(Rules::default().score(&state), 0);
+        assert_eq!(rules.score(&state), 9);
+    }
+    #[test]
+    fn encode() {
+        let rules = Rules::default();
+        assert_eq!(rules.encode(),
+                   "scoring=linear adjacency=orthogonal max_layers=none \
+                    copies=2 values=0,1,2,3,4,5,6,7,8,9");
+        let odd = Rules { scoring: Scoring::TopBonus,
+                          adjacency: Adjacency::Diagonal,
+                          max_layers: Some(3), copies: 1, mirrored: true,
+                          ..Rules::default() }.with_joker(0);
+        assert!(odd.encode().ends_with(" mirrored=yes"));
+        for r in &[rules, odd] {
+            assert_eq!(Rules::decode(&r.encode()), Some(*r));
+        }
+        assert_eq!(Rules::decode("scoring=linear"), None);
+        assert_eq!(Rules::decode(&rules.encode().replace("2 values", "2 colors")),
+                   None);
+        assert_eq!(Rules::decode(&rules.encode().replace(",9", "")), None);
+    }
+}
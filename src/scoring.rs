@@ -0,0 +1,158 @@
+use piece::{UNIQUE_PIECE_COUNT, Values};
+use bag::Bag;
+use state::State;
+
+// Different groups score stacks differently; this selects which rules the
+// solver is maximizing.  The bounds below must stay sound for each variant,
+// since the worker uses them to prune the search.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Scoring {
+    // Each tile scores its face value times its level (the standard rules)
+    Linear,
+
+    // Each tile scores its face value times the square of its level
+    Squared,
+
+    // Standard scoring, plus a bonus equal to the face value of the best
+    // tile on the top layer (if that layer is above the ground)
+    TopBonus,
+}
+
+impl Scoring {
+    pub fn score(&self, state: &State, values: &Values) -> isize {
+        match *self {
+            Scoring::Linear => state.score_with(values),
+            Scoring::Squared => state.pieces.iter()
+                .map(|p| values[p.index()] * (p.z * p.z) as isize).sum(),
+            Scoring::TopBonus => {
+                let top = state.layers();
+                let bonus = if top == 0 {
+                    0
+                } else {
+                    state.pieces.iter()
+                        .filter(|p| p.z == top)
+                        .map(|p| values[p.index()])
+                        .max().unwrap_or(0)
+                };
+                state.score_with(values) + bonus
+            },
+        }
+    }
+
+    // Returns an upper bound on the score of the tiles in the bag,
+    // if they are stacked starting from an empty table.
+    pub fn score_stacked(&self, bag: &Bag, values: &Values) -> isize {
+        match *self {
+            Scoring::Linear => bag.score_stacked_by(values, |z| z),
+            Scoring::Squared => bag.score_stacked_by(values, |z| z * z),
+            Scoring::TopBonus => bag.score_stacked_by(values, |z| z)
+                                 + max_value(bag, values),
+        }
+    }
+
+    // Given an upper bound on the score of a bag (starting from an empty
+    // table), returns an upper bound on its score if the whole structure
+    // were raised by the given number of levels.  `flat` is the sum of the
+    // positive face values in the bag (negative tiles can stay put).
+    pub fn shift_bound(&self, bag: &Bag, score: isize,
+                       flat: isize, shift: usize) -> isize {
+        let shift = shift as isize;
+        match *self {
+            Scoring::Linear | Scoring::TopBonus => score + shift * flat,
+            Scoring::Squared => {
+                // (z + s)^2 = z^2 + s * (2z + s), and no tile in the bag
+                // can be higher than the tallest possible stack.
+                let top = bag.len().div_ceil(2).saturating_sub(1) as isize;
+                score + shift * (2 * top + shift) * flat
+            },
+        }
+    }
+
+    // Returns an upper bound on the score of the tiles in the bag,
+    // if none of them can be placed above the given level.
+    pub fn capped_bound(&self, bag: &Bag, values: &Values,
+                        flat: isize, level: usize) -> isize {
+        let level = level as isize;
+        match *self {
+            Scoring::Linear => flat * level,
+            Scoring::Squared => flat * level * level,
+            Scoring::TopBonus => flat * level + max_value(bag, values),
+        }
+    }
+}
+
+fn max_value(bag: &Bag, values: &Values) -> isize {
+    (0..UNIQUE_PIECE_COUNT).filter(|&i| bag.digit(i) > 0)
+        .map(|i| values[i])
+        .max().unwrap_or(0).max(0)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use state::Placed;
+    use piece::PIECE_VALUES;
+
+    #[test]
+    fn score() {
+        let state = State::new()
+            .try_place(0, 0, 0).unwrap()
+            .try_place(0, 3, 0).unwrap()
+            .try_place(4, 2, 0).unwrap();
+        assert_eq!(Scoring::Linear.score(&state, &PIECE_VALUES), 1);
+        assert_eq!(Scoring::Squared.score(&state, &PIECE_VALUES), 1);
+        assert_eq!(Scoring::TopBonus.score(&state, &PIECE_VALUES), 2);
+
+        let state = State::new()
+            .insert(Placed::new(8, 0, 0, 0))
+            .insert(Placed::new(12, 0, 0, 1))
+            .insert(Placed::new(36, 0, 0, 2));
+        assert_eq!(Scoring::Linear.score(&state, &PIECE_VALUES), 2 * 9 + 3);
+        assert_eq!(Scoring::Squared.score(&state, &PIECE_VALUES), 4 * 9 + 3);
+        assert_eq!(Scoring::TopBonus.score(&state, &PIECE_VALUES), 2 * 9 + 3 + 9);
+        assert_eq!(Scoring::TopBonus.score(&State::new(), &PIECE_VALUES), 0);
+    }
+
+    #[test]
+    fn bounds() {
+        // Two 9s and a 1 could at best be stacked with a 9 on level 1
+        let bag = Bag::from_usize(0).add(36).add(37).add(4);
+        assert_eq!(Scoring::Linear.score_stacked(&bag, &PIECE_VALUES), 9);
+        assert_eq!(Scoring::Squared.score_stacked(&bag, &PIECE_VALUES), 9);
+        assert_eq!(Scoring::TopBonus.score_stacked(&bag, &PIECE_VALUES), 18);
+
+        // Raising that by two levels puts tiles at levels 2 and 3
+        assert_eq!(Scoring::Linear.shift_bound(&bag, 9, 19, 2), 47);
+        assert_eq!(Scoring::Squared.shift_bound(&bag, 9, 19, 2), 9 + 2 * 4 * 19);
+
+        // Capping everything at level 2
+        assert_eq!(Scoring::Linear.capped_bound(&bag, &PIECE_VALUES, 19, 2), 38);
+        assert_eq!(Scoring::Squared.capped_bound(&bag, &PIECE_VALUES, 19, 2), 76);
+        assert_eq!(Scoring::TopBonus.capped_bound(&bag, &PIECE_VALUES, 19, 2), 47);
+    }
+
+    #[test]
+    fn negative() {
+        // Make the 0 a penalty tile
+        let mut values = PIECE_VALUES;
+        values[0] = -5;
+
+        let state = State::new()
+            .insert(Placed::new(36, 0, 0, 0))
+            .insert(Placed::new(37, 0, 0, 0))
+            .insert(Placed::new(0, 0, 0, 1));
+        assert_eq!(Scoring::Linear.score(&state, &values), -5);
+        assert_eq!(Scoring::TopBonus.score(&state, &values), -10);
+
+        // The penalty tiles are best left on the ground, so they don't
+        // add anything to the bounds (but still help to hold others up)
+        let bag = Bag::from_usize(0).add(0).add(1).add(36);
+        assert_eq!(Scoring::Linear.score_stacked(&bag, &values), 9);
+        assert_eq!(Scoring::TopBonus.score_stacked(&bag, &values), 18);
+
+        let bag = Bag::from_usize(0).add(0).add(1);
+        assert_eq!(Scoring::TopBonus.score_stacked(&bag, &values), 0);
+    }
+}
@@ -0,0 +1,143 @@
+// Games between several players, as in the real game:  tiles are drawn
+// from one shuffled deck, and every player lays each tile on their own
+// table as it's drawn
+
+use std::fmt;
+
+use bag::Bag;
+use deck;
+use rules::Rules;
+use state::State;
+use strategy::Strategy;
+
+// Results of many games between the same players
+#[derive(Clone, Debug, PartialEq)]
+pub struct Standings {
+    pub names: Vec<String>,
+    pub games: usize,
+
+    // For each player, the sum of their scores, and the number of games
+    // that they won outright
+    pub totals: Vec<isize>,
+    pub wins: Vec<usize>,
+
+    // Games where the best score was shared
+    pub ties: usize,
+
+    // For each player, the sum of their margin over the best of the other
+    // players in each game (negative when they lost)
+    pub margins: Vec<isize>,
+}
+
+impl Standings {
+    fn new(names: Vec<String>) -> Standings {
+        let n = names.len();
+        Standings { names: names, games: 0, totals: vec![0; n],
+                    wins: vec![0; n], ties: 0, margins: vec![0; n] }
+    }
+
+    fn add(&mut self, scores: &[isize]) {
+        self.games += 1;
+        let best = *scores.iter().max().unwrap();
+        if scores.iter().filter(|&&s| s == best).count() > 1 {
+            self.ties += 1;
+        }
+        for (i, &s) in scores.iter().enumerate() {
+            let others = scores.iter().enumerate()
+                .filter(|&(j, _)| j != i)
+                .map(|(_, &t)| t)
+                .max()
+                .unwrap_or(s);
+            self.totals[i] += s;
+            self.margins[i] += s - others;
+            if s > others {
+                self.wins[i] += 1;
+            }
+        }
+    }
+}
+
+impl fmt::Display for Standings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let games = self.games.max(1) as f64;
+        for i in 0..self.names.len() {
+            writeln!(f, "{}: mean score {:.2}, won {} of {}, mean margin {:+.2}",
+                     self.names[i], self.totals[i] as f64 / games,
+                     self.wins[i], self.games, self.margins[i] as f64 / games)?;
+        }
+        write!(f, "Tied: {} of {}", self.ties, self.games)
+    }
+}
+
+// Plays one game with the tiles drawn in the given order, returning each
+// player's final arrangement.  A tile which a player can't place is left
+// off their table.
+pub fn play(players: &mut [Box<dyn Strategy + Send>], order: &[usize],
+            rules: &Rules) -> Vec<State> {
+    let mut states = vec![State::with_rules(rules); players.len()];
+    for &tile in order {
+        for (player, state) in players.iter_mut().zip(states.iter_mut()) {
+            if let Some(p) = player.choose(state, tile) {
+                *state = state.try_place(p.id(),
+                                         p.x, p.y)
+                    .unwrap_or_else(|| panic!("{} chose an illegal placement {}",
+                                              player.name(), p));
+            }
+        }
+    }
+    return states;
+}
+
+// Plays the given number of games, shuffling the bag's tiles into a new
+// draw order for each.  The deals only depend on the seed, so the same
+// seed pits different players against the same decks.
+pub fn simulate(players: &mut [Box<dyn Strategy + Send>], bag: &Bag,
+                deals: usize, seed: u64, rules: &Rules) -> Standings {
+    let mut out = Standings::new(players.iter().map(|p| p.name()).collect());
+    for deal in 0..deals {
+        let order = deck::shuffle(bag, seed.wrapping_add(deal as u64));
+        let scores: Vec<isize> = play(players, &order, rules).iter()
+            .map(|s| rules.score(s))
+            .collect();
+        out.add(&scores);
+    }
+    return out;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use strategy::Greedy;
+
+    #[test]
+    fn standings() {
+        let mut s = Standings::new(vec!["a".to_string(), "b".to_string()]);
+        s.add(&[5, 3]);
+        s.add(&[2, 2]);
+        s.add(&[0, 4]);
+        assert_eq!(s.games, 3);
+        assert_eq!(s.totals, vec![7, 9]);
+        assert_eq!(s.wins, vec![1, 1]);
+        assert_eq!(s.ties, 1);
+        assert_eq!(s.margins, vec![-2, 2]);
+    }
+
+    #[test]
+    fn simulate() {
+        // Identical players make identical tables from the same deck
+        let rules = Rules::default();
+        let bag = Bag::from_digits("2110000000", 2).unwrap();
+        let mut players: Vec<Box<dyn Strategy + Send>> =
+            vec![Box::new(Greedy::new(rules)), Box::new(Greedy::new(rules))];
+        let s = super::simulate(&mut players, &bag, 4, 1, &rules);
+        assert_eq!(s.games, 4);
+        assert_eq!(s.ties, 4);
+        assert_eq!(s.totals[0], s.totals[1]);
+        assert_eq!(s.margins, vec![0, 0]);
+
+        let states = play(&mut players, &deck::shuffle(&bag, 1), &rules);
+        assert!(states.iter().all(|s| s.pieces.len() == bag.len()));
+    }
+}
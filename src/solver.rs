@@ -0,0 +1,992 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+use rayon::prelude::*;
+
+use bag::Bag;
+use database::{Database, Record};
+use footprint::{PlacementCache, SupportTable};
+use journal::Journal;
+use optimum;
+use overlay::Overlay;
+use piece::{MAX_ROTATIONS, UNIQUE_PIECE_COUNT};
+use porcelain;
+use profile::Profile;
+use progress::Tracker;
+use results::{self, Results};
+use rules::Rules;
+use state::State;
+use trace::Trace;
+use tuning::{MoveOrder, PieceOrder, Tiebreak};
+use watchdog::Watchdog;
+use suspend::Snapshot;
+use telemetry::{Row, Telemetry};
+use worker::{CancelToken, Worker};
+
+pub type Callback = dyn Fn(&Bag, isize, bool, &State) + Send + Sync;
+
+// Solves bags under a set of rules, filling in a results table.  Bags
+// are solved in order of size, since each one's search is bounded by the
+// scores of its subsets; sweep() does this for every bag in the game.
+pub struct Solver {
+    results: RwLock<Results>,
+    order: Option<PieceOrder>,
+    moves: Option<MoveOrder>,
+    shared_seen: bool,
+
+    // If set, workers also skip states dominated by ones they've searched
+    dominance: bool,
+
+    // If present, the legal placements found for each footprint, shared
+    // by every search (see PlacementCache)
+    placement_cache: Option<Arc<PlacementCache>>,
+
+    // If present, the tiles which can rest on each top layer, shared by
+    // every search (see SupportTable)
+    support: Option<Arc<SupportTable>>,
+
+    // If present, each seen-set's memory budget in bytes, and whether to
+    // evict old states past it (rather than falling back to a Bloom filter)
+    seen_limit: Option<(usize, bool)>,
+
+    // If present, the wall-clock budget for each bag, and how many times
+    // to retry bags which ran out of time (with four times the budget)
+    timeout: Option<Duration>,
+    retries: usize,
+
+    // If present, the most nodes that each bag's search may expand, and
+    // the most pieces that it may place before giving up on a branch
+    node_limit: Option<usize>,
+    depth_limit: Option<usize>,
+
+    // Number of randomized searches used to estimate the best score of
+    // bags which still ran out of time
+    random_runs: usize,
+
+    // How many of each bag's highest-scoring distinct arrangements to
+    // keep in the results (see Worker::with_leaders), or 0 for none
+    leaders: usize,
+
+    // If present, what to minimize among each bag's best arrangements
+    tiebreak: Option<Tiebreak>,
+
+    // Number of bags which a thread takes from a batch at a time
+    chunk: usize,
+
+    // If set, bags are solved one at a time in a fixed order, each on a
+    // single thread, so that two runs log exactly the same lines
+    deterministic: bool,
+
+    overlay: Option<Arc<Overlay>>,
+    trace: Option<Arc<Trace>>,
+    watchdog: Option<Arc<Watchdog>>,
+    progress: Option<Arc<Tracker>>,
+
+    // If present, where to record the time spent in each phase and bag
+    profile: Option<Arc<Profile>>,
+
+    // If present, a directory in which to record a journal of each search
+    journal: Option<PathBuf>,
+
+    // How long each bag's search took (keyed by as_usize), used to guess
+    // which bags of the next batch will be slowest
+    timings: Mutex<HashMap<usize, Duration>>,
+
+    // Total tail time of every batch so far (see dispatch)
+    tail: Mutex<Duration>,
+
+    // Called with each bag's best score, whether that score is exact (or
+    // only a lower bound, if the search ran out of time), and its best
+    // arrangement, as it's solved
+    callback: Option<Box<Callback>>,
+
+    // If present, where to record every finished search
+    database: Option<Arc<Database>>,
+
+    // If present, where to append statistics of every finished search
+    telemetry: Option<Arc<Telemetry>>,
+
+    // If present, where to save the results, and how often
+    checkpoint: Option<(PathBuf, Duration)>,
+    last_checkpoint: Mutex<Instant>,
+
+    // Bags with fewer pieces than this are finished, including retries,
+    // so they're skipped if they have a lower bound (even if unsolved)
+    cursor: AtomicUsize,
+
+    // Stops every search when cancelled, and if set, whether to keep
+    // snapshots of the searches that it stops (and whether to prune them)
+    cancel: CancelToken,
+    suspend: Option<bool>,
+
+    // Snapshots of the searches which were stopped, and of searches to
+    // continue, keyed by as_usize
+    suspended: Mutex<Vec<Snapshot>>,
+    snapshots: Mutex<HashMap<usize, Snapshot>>,
+}
+
+// Checkpoints hold the results, with a comment line for the cursor
+const CURSOR: &str = "# cursor ";
+
+// Calls f on each item from every thread in the pool, handing the items
+// out in order through a shared cursor, chunk at a time.  Unlike par_iter,
+// which splits the slice into ranges for threads to steal from (so that a
+// thread may start in the middle), items start in the slice's order, which
+// is what lets schedule() start the slowest bags first.
+//
+// Returns the tail time:  how long the last threads kept working after the
+// first one ran out of items.
+fn dispatch<T: Sync, F: Fn(&T) + Sync>(items: &[T], chunk: usize, f: F)
+    -> Duration
+{
+    let next = AtomicUsize::new(0);
+    let idle = Mutex::new(None);
+    let threads = rayon::current_num_threads().min(items.len());
+    (0..threads).into_par_iter().for_each(|_| loop {
+        let i = next.fetch_add(chunk, Ordering::Relaxed);
+        if i >= items.len() {
+            idle.lock().unwrap().get_or_insert_with(Instant::now);
+            break;
+        }
+        for item in &items[i..(i + chunk).min(items.len())] {
+            f(item);
+        }
+    });
+    return idle.into_inner().unwrap()
+        .map(|t: Instant| t.elapsed())
+        .unwrap_or_default();
+}
+
+impl Solver {
+    pub fn new(rules: Rules) -> Solver {
+        Solver {
+            results: RwLock::new(Results::new(rules)),
+            order: None,
+            moves: None,
+            shared_seen: false,
+            dominance: false,
+            placement_cache: None,
+            support: None,
+            seen_limit: None,
+            timeout: None,
+            retries: 0,
+            node_limit: None,
+            depth_limit: None,
+            random_runs: 0,
+            leaders: 0,
+            tiebreak: None,
+            chunk: 1,
+            deterministic: false,
+            overlay: None,
+            trace: None,
+            watchdog: None,
+            progress: None,
+            profile: None,
+            journal: None,
+            timings: Mutex::new(HashMap::new()),
+            tail: Mutex::new(Duration::default()),
+            callback: None,
+            database: None,
+            telemetry: None,
+            checkpoint: None,
+            last_checkpoint: Mutex::new(Instant::now()),
+            cursor: AtomicUsize::new(0),
+            cancel: CancelToken::new(),
+            suspend: None,
+            suspended: Mutex::new(Vec::new()),
+            snapshots: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_order(mut self, order: PieceOrder) -> Solver {
+        self.order = Some(order);
+        self
+    }
+
+    pub fn with_move_order(mut self, moves: MoveOrder) -> Solver {
+        self.moves = Some(moves);
+        self
+    }
+
+    pub fn with_seen_limit(mut self, bytes: usize, evict: bool) -> Solver {
+        self.seen_limit = Some((bytes, evict));
+        self
+    }
+
+    // Shares one seen-set between the threads searching each large bag
+    pub fn with_shared_seen(mut self) -> Solver {
+        self.shared_seen = true;
+        self
+    }
+
+    // Skips states which can't beat one already searched (see Dominance)
+    pub fn with_dominance(mut self) -> Solver {
+        self.dominance = true;
+        self
+    }
+
+    // Caches each footprint's legal placements, in roughly the given
+    // number of bytes, so that searches which reach the same arrangements
+    // don't work out their placements again
+    pub fn with_placement_cache(mut self, bytes: usize) -> Solver {
+        self.placement_cache = Some(Arc::new(PlacementCache::new(bytes)));
+        self
+    }
+
+    // Bounds the last tile of each search by whether it can rest on the
+    // top layer, keeping which tiles can rest on each top layer in roughly
+    // the given number of bytes
+    pub fn with_support_table(mut self, bytes: usize) -> Solver {
+        self.support = Some(Arc::new(SupportTable::new(bytes)));
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration, retries: usize) -> Solver {
+        self.timeout = Some(timeout);
+        self.retries = retries;
+        self
+    }
+
+    // Bags whose searches hit either limit are recorded as lower bounds
+    pub fn with_limits(mut self, nodes: Option<usize>, depth: Option<usize>)
+        -> Solver
+    {
+        self.node_limit = nodes;
+        self.depth_limit = depth;
+        self
+    }
+
+    // Only used along with a timeout
+    pub fn with_random_runs(mut self, runs: usize) -> Solver {
+        self.random_runs = runs;
+        self
+    }
+
+    pub fn with_leaders(mut self, n: usize) -> Solver {
+        self.leaders = n;
+        self
+    }
+
+    pub fn with_tiebreak(mut self, tiebreak: Tiebreak) -> Solver {
+        self.tiebreak = Some(tiebreak);
+        self
+    }
+
+    // Solves bags one at a time, in order, with each search on a single
+    // thread.  Without a timeout, the scores, arrangements and log are then
+    // the same from run to run (apart from the time each batch took).
+    pub fn with_deterministic(mut self) -> Solver {
+        self.deterministic = true;
+        self
+    }
+
+    pub fn with_chunk(mut self, chunk: usize) -> Solver {
+        self.chunk = chunk;
+        self
+    }
+
+    pub fn with_overlay(mut self, overlay: Arc<Overlay>) -> Solver {
+        self.overlay = Some(overlay);
+        self
+    }
+
+    pub fn with_trace(mut self, trace: Arc<Trace>) -> Solver {
+        self.trace = Some(trace);
+        self
+    }
+
+    pub fn with_watchdog(mut self, watchdog: Arc<Watchdog>) -> Solver {
+        self.watchdog = Some(watchdog);
+        self
+    }
+
+    // Reports how far each batch of a sweep has got
+    pub fn with_progress(mut self, progress: Arc<Tracker>) -> Solver {
+        self.progress = Some(progress);
+        self
+    }
+
+    pub fn with_profile(mut self, profile: Arc<Profile>) -> Solver {
+        self.profile = Some(profile);
+        self
+    }
+
+    // Records each search in a journal in the directory.  A journal is one
+    // sequence of steps, so journaled bags aren't split across threads
+    // (though different bags still run in parallel).
+    pub fn with_journal(mut self, dir: &Path) -> Solver {
+        self.journal = Some(dir.to_path_buf());
+        self
+    }
+
+    pub fn with_callback(mut self, callback: Box<Callback>) -> Solver {
+        self.callback = Some(callback);
+        self
+    }
+
+    pub fn with_database(mut self, database: Arc<Database>) -> Solver {
+        self.database = Some(database);
+        self
+    }
+
+    pub fn with_telemetry(mut self, telemetry: Arc<Telemetry>) -> Solver {
+        self.telemetry = Some(telemetry);
+        self
+    }
+
+    // Saves the results to the given file as bags are solved, at most
+    // once per interval (and after every batch)
+    pub fn with_checkpoint(mut self, path: &Path, interval: Duration) -> Solver {
+        self.checkpoint = Some((path.to_path_buf(), interval));
+        self
+    }
+
+    // Stops the sweep, and every running search, when the token is
+    // cancelled.  Bags whose searches were stopped are left unsolved.
+    pub fn with_cancel(mut self, cancel: CancelToken) -> Solver {
+        self.cancel = cancel;
+        self
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    // Keeps a snapshot of each search which is stopped by cancelling, to
+    // be returned by suspended.  Pruned snapshots leave out seen-sets.
+    pub fn with_suspend(mut self, prune: bool) -> Solver {
+        self.suspend = Some(prune);
+        self
+    }
+
+    // Continues the given searches (see suspended) when their bags come up,
+    // rather than starting them over
+    pub fn with_snapshots(self, snapshots: Vec<Snapshot>) -> Solver {
+        self.snapshots.lock().unwrap().extend(
+            snapshots.into_iter().map(|s| (s.bag.as_usize(), s)));
+        self
+    }
+
+    // Returns the snapshots of the searches which were stopped
+    pub fn suspended(&self) -> Vec<Snapshot> {
+        std::mem::take(&mut *self.suspended.lock().unwrap())
+    }
+
+    // Loads a checkpoint, so that a sweep picks up where it left off.
+    // Returns the number of bags which were loaded.
+    pub fn resume(&self, path: &Path) -> Result<usize, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        if results::is_outdated(&text) {
+            return Err("The checkpoint was saved by an older version of \
+                        nmbr9, and can't be resumed".to_string());
+        }
+        if !results::header_matches(&text, &self.rules()) {
+            return Err("The checkpoint was saved under different rules or \
+                        pieces".to_string());
+        }
+        let cursor = text.lines()
+            .find(|l| l.starts_with(CURSOR))
+            .and_then(|l| l[CURSOR.len()..].parse().ok())
+            .ok_or_else(|| "The checkpoint has no cursor".to_string())?;
+        let count = self.results.write().unwrap().read(text.as_bytes())?;
+        self.cursor.store(cursor, Ordering::Relaxed);
+        return Ok(count);
+    }
+
+    // Loads scores solved (or published) elsewhere before a sweep, which
+    // skips the bags with exact scores and starts their supersets from
+    // tighter bounds.  Unlike resume, every bag without an exact score is
+    // still solved.  A file ending in .csv is read as from write_csv;
+    // otherwise, it may leave out the header, but if it has one,
+    // it must be for these rules.  Returns the number of bags loaded.
+    pub fn preload(&self, path: &Path) -> Result<usize, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        if text.starts_with("# nmbr9 results") && !results::is_outdated(&text) &&
+           !results::header_matches(&text, &self.rules())
+        {
+            return Err("The scores are for different rules or pieces"
+                       .to_string());
+        }
+        let mut results = self.results.write().unwrap();
+        if path.extension().map(|e| e == "csv").unwrap_or(false) {
+            return results.read_csv(text.as_bytes());
+        }
+        return results.read(text.as_bytes());
+    }
+
+    fn rules(&self) -> Rules {
+        self.results.read().unwrap().rules()
+    }
+
+    // Writes a checkpoint, if it's been long enough since the last one
+    // (or unconditionally, if forced)
+    fn checkpoint(&self, force: bool) {
+        let (path, interval) = match self.checkpoint {
+            Some((ref p, i)) => (p, i),
+            None => return,
+        };
+        {
+            let mut last = self.last_checkpoint.lock().unwrap();
+            if !force && last.elapsed() < interval {
+                return;
+            }
+            *last = Instant::now();
+        }
+        if let Err(e) = self.write_checkpoint(path) {
+            warning!("Failed to write checkpoint {}: {}", path.display(), e);
+        }
+    }
+
+    // Writes next to the checkpoint, then renames it into place, so that
+    // a crash partway through leaves the previous checkpoint intact
+    fn write_checkpoint(&self, path: &Path) -> io::Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        // Workers need the write lock to record scores, so the results are
+        // copied out first, rather than held locked through the disk writes
+        let mut data = Vec::new();
+        self.results.read().unwrap().write(&mut data)?;
+        writeln!(data, "{}{}", CURSOR, self.cursor.load(Ordering::Relaxed))?;
+        let mut out = File::create(&tmp)?;
+        out.write_all(&data)?;
+        out.sync_all()?;
+        fs::rename(&tmp, path)
+    }
+
+    pub fn results(&self) -> &RwLock<Results> {
+        &self.results
+    }
+
+    // Solves one bag, whose proper subsets must already have been solved
+    // (or given lower bounds).  Returns None if the search was cancelled.
+    pub fn solve(&self, bag: &Bag) -> Option<(isize, State)> {
+        let out = self.solve_with(bag, self.timeout);
+        self.finish_overlay();
+        out
+    }
+
+    // Writes any best which the overlay held back (see Overlay::finish)
+    fn finish_overlay(&self) {
+        if let Some(ref overlay) = self.overlay {
+            overlay.finish();
+        }
+    }
+
+    fn solve_with(&self, bag: &Bag, budget: Option<Duration>)
+        -> Option<(isize, State)>
+    {
+        if self.is_cancelled() {
+            return None;
+        }
+        let begin = Instant::now();
+        let mut worker = Worker::new(bag.clone(), &self.results)
+            .with_cancel(self.cancel.clone());
+        if let Some(order) = self.order {
+            worker = worker.with_order(order);
+        }
+        if let Some(moves) = self.moves {
+            worker = worker.with_move_order(moves);
+        }
+        if self.shared_seen {
+            worker = worker.with_shared_seen(true);
+        }
+        if self.dominance {
+            worker = worker.with_dominance(true);
+        }
+        if let Some(ref cache) = self.placement_cache {
+            worker = worker.with_placement_cache(cache.clone());
+        }
+        if let Some(ref table) = self.support {
+            worker = worker.with_support_table(table.clone());
+        }
+        if let Some((bytes, evict)) = self.seen_limit {
+            worker = worker.with_seen_limit(bytes, evict);
+        }
+        if let Some(budget) = budget {
+            worker = worker.with_deadline(begin + budget);
+        }
+        if let Some(nodes) = self.node_limit {
+            worker = worker.with_node_limit(nodes);
+        }
+        if let Some(depth) = self.depth_limit {
+            worker = worker.with_depth_limit(depth);
+        }
+        if self.deterministic {
+            worker = worker.with_split_depth(0);
+        }
+        if let Some(ref profile) = self.profile {
+            worker = worker.with_profile(profile.clone());
+        }
+        if self.leaders > 0 {
+            worker = worker.with_leaders(self.leaders);
+        }
+        if let Some(tiebreak) = self.tiebreak {
+            worker = worker.with_tiebreak(tiebreak);
+        }
+        if let Some(ref overlay) = self.overlay {
+            worker = worker.with_overlay(overlay.clone());
+        }
+        if let Some(ref dir) = self.journal {
+            worker = start_journal(worker, dir, bag);
+        }
+        let snapshot = self.snapshots.lock().unwrap().remove(&bag.as_usize());
+        match snapshot {
+            Some(_) if self.journal.is_some() =>
+                warning!("Journaled searches can't be continued; starting {:?} over",
+                         bag),
+            Some(s) => worker = worker.with_snapshot(s),
+            None => (),
+        }
+        let progress = worker.progress();
+        if let Some(ref dog) = self.watchdog {
+            dog.add(progress.clone());
+        }
+        if let Some(ref tracker) = self.progress {
+            tracker.add(progress.clone());
+        }
+        let result = worker.run();
+        if let (None, Some(prune)) = (&result, self.suspend) {
+            if let Some(mut s) = worker.suspended() {
+                if prune {
+                    s.prune();
+                }
+                self.suspended.lock().unwrap().push(s);
+            }
+        }
+        if let Some(ref dog) = self.watchdog {
+            dog.remove(&progress);
+        }
+        if let Some(ref tracker) = self.progress {
+            tracker.remove(&progress);
+        }
+        if let Some(ref trace) = self.trace {
+            let mut args = vec![("pieces", bag.len() as isize)];
+            if let Some((score, _)) = result {
+                args.push(("score", score));
+            }
+            trace.record(format!("{:?}", bag), begin, args);
+        }
+        let exact = !worker.timed_out() && !worker.limited();
+        if let (Some(ref f), Some((score, ref state))) = (&self.callback, &result) {
+            f(bag, *score, exact, state);
+        }
+        if let (Some(ref db), Some((score, ref state))) = (&self.database, &result) {
+            let record = Record::new(bag, *score, exact, progress.nodes(),
+                                     begin.elapsed(), state);
+            if let Err(e) = db.append(&record) {
+                warning!("Failed to record {:?} in the database: {}", bag, e);
+            }
+        }
+        if let (Some(ref t), Some((score, _))) = (&self.telemetry, &result) {
+            let row = Row::new(bag, &progress, begin.elapsed(), *score, exact);
+            if let Err(e) = t.append(&row) {
+                warning!("Failed to record {:?} in the telemetry: {}", bag, e);
+            }
+        }
+        self.timings.lock().unwrap().insert(bag.as_usize(), begin.elapsed());
+        if let Some(ref profile) = self.profile {
+            profile.bag(bag, begin.elapsed());
+        }
+        self.checkpoint(false);
+        return result;
+    }
+
+    // Sorts a batch so that the bags which look slowest come first, so
+    // that the batch doesn't end waiting on one hard bag that started
+    // late.  A bag's cost is guessed from how long its one-smaller subsets
+    // took, then from how many distinct tiles it has (which sets the
+    // branching factor), then from its area.
+    fn schedule(&self, batch: &mut [Bag]) {
+        let timings = self.timings.lock().unwrap();
+        batch.sort_by_cached_key(|b| {
+            let mut prior = Duration::from_secs(0);
+            let mut distinct = 0;
+            for i in 0..UNIQUE_PIECE_COUNT {
+                if b.digit(i) > 0 {
+                    distinct += 1;
+                    let sub = b.take(i * MAX_ROTATIONS).as_usize();
+                    prior += timings.get(&sub).cloned().unwrap_or_default();
+                }
+            }
+            return Reverse((prior, distinct, b.area()));
+        });
+    }
+
+    // Solves a batch of bags in parallel.  None of them can be a subset
+    // of another, unless the subset has already been solved.
+    pub fn solve_all(&self, bags: &[Bag]) {
+        self.solve_all_with(bags, self.timeout);
+        self.finish_overlay();
+    }
+
+    fn solve_all_with(&self, bags: &[Bag], budget: Option<Duration>) {
+        if self.deterministic {
+            for b in bags {
+                self.solve_with(b, budget);
+            }
+            return;
+        }
+        let tail = dispatch(bags, self.chunk, |b| {
+            self.solve_with(b, budget);
+        });
+        *self.tail.lock().unwrap() += tail;
+    }
+
+    // Returns the total time that batches spent with some threads idle
+    // because no bags were left to hand out, which is what --chunk trades
+    // against the overhead of handing them out
+    pub fn tail_time(&self) -> Duration {
+        return *self.tail.lock().unwrap();
+    }
+
+    // Returns the bags in the batch which haven't been solved exactly
+    fn unsolved(&self, bags: &[Bag]) -> Vec<Bag> {
+        let results = self.results.read().unwrap();
+        bags.iter().filter(|b| results.score(b).is_none()).cloned().collect()
+    }
+
+    // Solves every bag in the game, in batches by number of pieces
+    pub fn sweep(&self) {
+        let bags = self.results.read().unwrap().bags();
+        self.sweep_bags(&bags);
+    }
+
+    // Solves the given bags, along with every subset that their searches
+    // need, in batches by number of pieces.  Bags which have already been
+    // solved exactly (e.g. loaded from a file) are skipped, as are bags in
+    // batches which a resumed checkpoint had finished.
+    pub fn sweep_bags(&self, targets: &[Bag]) {
+        let start_time = SystemTime::now();
+        let rules = self.results.read().unwrap().rules();
+        let mut ordered: Vec<Bag> = {
+            let results = self.results.read().unwrap();
+            results.bags().into_iter()
+                .filter(|b| targets.iter().any(|t| t.contains(b)))
+                .filter(|b| results.score(b).is_none())
+                .filter(|b| b.len() >= self.cursor.load(Ordering::Relaxed) ||
+                            results.lower_bound(b).is_none())
+                .collect()
+        };
+        ordered.sort_by_key(|b| b.len());
+
+        let mut start = 0;
+        for num in 0..=(rules.copies * UNIQUE_PIECE_COUNT) {
+            let mut end = start;
+            while end < ordered.len() && ordered[end].len() <= num
+            {
+                end += 1;
+            }
+            // Scheduling depends on timings, so a deterministic sweep keeps
+            // each batch in the results' order
+            if !self.deterministic {
+                self.schedule(&mut ordered[start..end]);
+            }
+            let batch = &ordered[start..end];
+            if batch.is_empty() {
+                continue;
+            }
+
+            status!("============================================================");
+            status!("BEGINNING {}-PIECE COMBINATIONS ({} to do)", num, end - start);
+            let began = Instant::now();
+            if let Some(ref tracker) = self.progress {
+                tracker.begin(num, batch.len());
+            }
+            self.solve_all(batch);
+            if self.is_cancelled() {
+                status!("STOPPED during {}-piece combinations", num);
+                self.checkpoint(true);
+                return;
+            }
+
+            // Give bags which ran out of time another go, before later
+            // batches rely on their scores
+            let mut budget = self.timeout;
+            for _ in 0..self.retries {
+                let todo = self.unsolved(batch);
+                if todo.is_empty() {
+                    break;
+                }
+                budget = budget.map(|b| b * 4);
+                status!("RETRYING {} incomplete combinations with a {:?} budget",
+                         todo.len(), budget.unwrap());
+                if let Some(ref tracker) = self.progress {
+                    tracker.begin(num, todo.len());
+                }
+                self.solve_all_with(&todo, budget);
+            }
+            // Estimates need a budget for each of their searches
+            if let Some(budget) = budget.filter(|_| self.random_runs > 0) {
+                self.estimate_unsolved(batch, budget);
+            }
+            if let Some(ref profile) = self.profile {
+                profile.phase(num, batch.len(), began.elapsed());
+            }
+            if self.deterministic {
+                status!("FINISHED {}-piece tests", num);
+            } else {
+                status!("FINISHED {}-piece tests in {:?}", num, start_time.elapsed());
+            }
+            start = end;
+            self.cursor.fetch_max(num + 1, Ordering::Relaxed);
+            self.checkpoint(true);
+
+            if num * 2 == rules.copies * UNIQUE_PIECE_COUNT {
+                let gap = self.results.read().unwrap().half_deck_expected_gap();
+                if let Some(gap) = gap {
+                    status!("Expected score gap in the half-deck variant: {:.3}", gap);
+                }
+            }
+        }
+        if let Some(ref cache) = self.placement_cache {
+            let (hits, misses) = cache.stats();
+            debug!("Placement cache: {} hits, {} misses", hits, misses);
+        }
+        if let Some(ref table) = self.support {
+            let (hits, misses) = table.stats();
+            debug!("Support table: {} hits, {} misses", hits, misses);
+        }
+        self.finish_overlay();
+    }
+
+    // For bags which are still unsolved, estimates their best scores from
+    // several randomized searches.  These estimates aren't exact, so later
+    // bags don't use them to prune.
+    fn estimate_unsolved(&self, bags: &[Bag], budget: Duration) {
+        let todo = self.unsolved(bags);
+        if todo.is_empty() {
+            return;
+        }
+        status!("ESTIMATING {} unsolved combinations from {} randomized runs each",
+                todo.len(), self.random_runs);
+        for b in todo {
+            let found = optimum::sample(&b, &self.results, self.random_runs,
+                                        budget, self.order);
+            if let Some((e, state)) = found {
+                status!("Best score for {:?} is roughly {:.1} (not exact)", b, e.score);
+                if !porcelain::enabled() {
+                    state.pretty_print();
+                }
+            }
+        }
+    }
+}
+
+// Records the worker's search in a journal named after its bag
+fn start_journal<'a>(worker: Worker<'a>, dir: &Path, bag: &Bag) -> Worker<'a> {
+    let path = dir.join(format!("{}.journal", bag.digits()));
+    let header = worker.header();
+    let journal = File::create(&path)
+        .and_then(|f| Journal::record(Box::new(BufWriter::new(f)), header));
+    match journal {
+        Ok(j) => worker.with_journal(j),
+        Err(e) => {
+            warning!("Failed to start journal {}: {}", path.display(), e);
+            worker
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn solve() {
+        let solver = Solver::new(Rules::default());
+        let bag = Bag::from_usize(0).add(0).add(4);
+        let subsets = [bag.take(0).take(4), bag.take(0), bag.take(4)];
+        solver.solve_all(&subsets[..1]);
+        solver.solve_all(&subsets[1..]);
+
+        let (score, _) = solver.solve(&bag).unwrap();
+        assert_eq!(score, 0);
+        assert_eq!(solver.results().read().unwrap().score(&bag), Some(0));
+    }
+
+    #[test]
+    fn sweep_bags() {
+        let solver = Solver::new(Rules::default());
+        let bag = Bag::from_usize(0).add(0).add(4);
+        solver.sweep_bags(std::slice::from_ref(&bag));
+
+        let results = solver.results().read().unwrap();
+        assert_eq!(results.score(&bag), Some(0));
+        assert_eq!(results.score(&bag.take(0)), Some(0));
+        assert_eq!(results.score(&bag.add(8)), None);
+        assert_eq!(results.score(&Bag::from_usize(0).add(8)), None);
+    }
+
+    #[test]
+    fn mirrored() {
+        // Flipping tiles over only adds moves, so it can't lower a score
+        let bag = Bag::from_usize(0).add(4).add(8).add(12);
+        let plain = Solver::new(Rules::default());
+        let mirrored = Solver::new(Rules { mirrored: true, ..Rules::default() });
+        plain.sweep_bags(std::slice::from_ref(&bag));
+        mirrored.sweep_bags(std::slice::from_ref(&bag));
+
+        let plain = plain.results().read().unwrap().score(&bag).unwrap();
+        let mirrored = mirrored.results().read().unwrap().score(&bag).unwrap();
+        assert!(mirrored >= plain);
+    }
+
+    #[test]
+    fn move_orders() {
+        // The best arrangement of 4, 7, 8, 9, 9 lifts a 9 onto the 4 and
+        // 7, which rest on the 8 and the other 9.  Its search has to count
+        // the placed tiles' score when bounding the rest of the bag, or
+        // this branch is pruned once any arrangement beats the lone 9.
+        let bag = [4, 7, 8, 9, 9].iter()
+            .fold(Bag::from_usize(0), |b, &t| b.add(t * MAX_ROTATIONS));
+        for &order in &[MoveOrder::Score, MoveOrder::Bound] {
+            let solver = Solver::new(Rules::default()).with_move_order(order);
+            solver.sweep_bags(std::slice::from_ref(&bag));
+            let score = solver.results().read().unwrap().score(&bag);
+            assert_eq!(score, Some(29), "{:?}", order);
+        }
+    }
+
+    #[test]
+    fn schedule() {
+        let solver = Solver::new(Rules::default());
+        let empty = Bag::from_usize(0);
+        let mut batch = vec![empty.add(0).add(1), empty.add(0).add(4),
+                             empty.add(20).add(24)];
+        solver.schedule(&mut batch);
+        assert_eq!(batch[2], empty.add(0).add(1));
+
+        // Once the subsets have been timed, the bag whose subsets took
+        // longest goes first
+        solver.timings.lock().unwrap()
+            .insert(empty.add(20).as_usize(), Duration::from_secs(5));
+        solver.schedule(&mut batch);
+        assert_eq!(batch[0], empty.add(20).add(24));
+        assert_eq!(batch[2], empty.add(0).add(1));
+    }
+
+    #[test]
+    fn dispatch() {
+        // Items start in order on any number of threads:  when the nth
+        // starts, the only items after it which may have started are those
+        // already taken by the other threads, a chunk each
+        let threads = 4;
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(threads)
+            .build().unwrap();
+        let items: Vec<usize> = (0..64).collect();
+        for &chunk in &[1, 3] {
+            let started = Mutex::new(Vec::new());
+            let begin = Instant::now();
+            let tail = pool.install(|| super::dispatch(&items, chunk, |&i| {
+                started.lock().unwrap().push(i);
+                std::thread::sleep(Duration::from_millis(1));
+            }));
+            assert!(tail <= begin.elapsed());
+            let started = started.into_inner().unwrap();
+            for (n, &i) in started.iter().enumerate() {
+                assert!(i < n + threads * chunk, "{} started {}th", i, n);
+            }
+            let mut sorted = started.clone();
+            sorted.sort();
+            assert_eq!(sorted, items);
+        }
+    }
+
+    #[test]
+    fn checkpoint() {
+        use std::{env, process};
+
+        let path = env::temp_dir()
+            .join(format!("nmbr9-checkpoint-{}", process::id()));
+        let bag = Bag::from_usize(0).add(0).add(4);
+        let solver = Solver::new(Rules::default())
+            .with_checkpoint(&path, Duration::from_secs(3600));
+        solver.sweep_bags(&[bag.take(0)]);
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.contains("0000000000\t0\texact\n"));
+        assert!(text.ends_with("# cursor 2\n"));
+
+        // A resumed solver keeps the scores, so it can go on to bigger
+        // bags, and it skips the finished batches
+        let resumed = Solver::new(Rules::default());
+        assert_eq!(resumed.resume(&path), Ok(2));
+        assert_eq!(resumed.results().read().unwrap().score(&bag.take(0)), Some(0));
+        resumed.sweep_bags(std::slice::from_ref(&bag));
+        assert_eq!(resumed.results().read().unwrap().score(&bag), Some(0));
+
+        let other = Solver::new(Rules { copies: 1, ..Rules::default() });
+        assert!(other.resume(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn preload() {
+        use std::{env, process};
+
+        let path = env::temp_dir()
+            .join(format!("nmbr9-known-{}", process::id()));
+        let bag = Bag::from_usize(0).add(36).add(37);
+        fs::write(&path, "0000000000\t0\n0000000001\t0\n0000000002\t5\n")
+            .unwrap();
+
+        // Bags with known scores aren't solved again, even if the scores
+        // are wrong
+        let solver = Solver::new(Rules::default());
+        assert_eq!(solver.preload(&path), Ok(3));
+        solver.sweep_bags(std::slice::from_ref(&bag));
+        assert_eq!(solver.results().read().unwrap().score(&bag), Some(5));
+
+        let mut text = Vec::new();
+        solver.results().read().unwrap().write(&mut text).unwrap();
+        fs::write(&path, &text).unwrap();
+        assert_eq!(solver.preload(&path), Ok(3));
+        let other = Solver::new(Rules { copies: 1, ..Rules::default() });
+        assert!(other.preload(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn callback() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let s = seen.clone();
+        let solver = Solver::new(Rules::default())
+            .with_callback(Box::new(move |b, score, exact, _| {
+                s.lock().unwrap().push((b.clone(), score, exact));
+            }));
+        let bag = Bag::from_usize(0).add(36);
+        solver.solve_all(&[Bag::from_usize(0)]);
+        solver.solve(&bag);
+        assert_eq!(*seen.lock().unwrap(),
+                   vec![(Bag::from_usize(0), 0, true), (bag, 0, true)]);
+    }
+    #[test]
+    fn deterministic() {
+        // Bags are solved in the results' order, by size, every time
+        let order = || {
+            let seen = Arc::new(Mutex::new(Vec::new()));
+            let s = seen.clone();
+            let solver = Solver::new(Rules::default())
+                .with_deterministic()
+                .with_callback(Box::new(move |b, score, _, state| {
+                    s.lock().unwrap().push((b.clone(), score, state.layout()));
+                }));
+            solver.sweep_bags(&[Bag::from_digits("2110000000", 2).unwrap()]);
+            let out = seen.lock().unwrap().clone();
+            out
+        };
+        let first = order();
+        assert_eq!(first.len(), 3 * 2 * 2);
+        assert!(first.windows(2).all(|w| {
+            (w[0].0.len(), w[0].0.as_usize()) < (w[1].0.len(), w[1].0.as_usize())
+        }));
+        assert_eq!(first, order());
+    }
+}
@@ -1,13 +1,43 @@
 use arrayvec::ArrayVec;
 use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::sync::atomic::{self, AtomicBool};
 
 use colored::*;
 
-use piece::{UNIQUE_PIECE_COUNT, MAX_ROTATIONS, PIECES, PIECE_COLORS, Overlap, Piece};
-use tables::{OVERLAP_TABLES};
+use piece::{UNIQUE_PIECE_COUNT, MAX_COPIES, MAX_ROTATIONS, pieces};
+use piece::{MAX_ORIENTATIONS, MIRRORED_ID, PIECE_ID_COUNT, piece_id, tile};
+use piece::{MAX_EDGE_LENGTH, PIECE_VALUES, Values};
+use piece::{Overlap, Adjacency};
+use tables::Tables;
+use bag::Bag;
+use rules::Rules;
+use bitboard::{Bitboards, Placements};
+use image::Image;
+use theme::theme;
+use error::{self, Error, MAX_COORDINATE};
 
 ////////////////////////////////////////////////////////////////////////////////
 
+// Widths of the fields of a packed placement (see Placed::pack).  A
+// normalized state of twenty tiles is at most 80 cells wide, and there
+// are 80 piece ids (with the mirrored ones), so each has plenty of room
+// for larger layouts; z gets the rest, and can't go above the number
+// of tiles in a game.  Packing must never lose a bit, since exact
+// seen-sets would then skip states that were never searched, so each
+// field's width is checked here.
+const PACK_COORD_BITS: u32 = 10;
+const PACK_ID_BITS: u32 = 7;
+const PACK_Z_BITS: u32 = 32 - 2 * PACK_COORD_BITS - PACK_ID_BITS;
+const _: () = assert!(UNIQUE_PIECE_COUNT * MAX_COPIES <= 1 << PACK_Z_BITS);
+const _: () = assert!(PIECE_ID_COUNT <= 1 << PACK_ID_BITS);
+const _: () = assert!(UNIQUE_PIECE_COUNT * MAX_COPIES * MAX_EDGE_LENGTH as usize
+                      <= 1 << PACK_COORD_BITS);
+
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
 pub struct Placed {
     id: usize,
@@ -20,16 +50,88 @@ impl Placed {
     pub fn new(id: usize, x: i32, y: i32, z: usize) -> Placed {
         Placed { id: id, x: x, y: y, z: z}
     }
+    pub fn id(&self) -> usize {
+        self.id
+    }
     pub fn rot(&self) -> usize {
-        debug_assert!(self.id < UNIQUE_PIECE_COUNT * MAX_ROTATIONS);
+        debug_assert!(self.id < PIECE_ID_COUNT);
         self.id % MAX_ROTATIONS
     }
     pub fn index(&self) -> usize {
-        debug_assert!(self.id < UNIQUE_PIECE_COUNT * MAX_ROTATIONS);
-        self.id / MAX_ROTATIONS
+        debug_assert!(self.id < PIECE_ID_COUNT);
+        tile(self.id)
+    }
+    pub fn mirrored(&self) -> bool {
+        self.id >= MIRRORED_ID
+    }
+    // Returns the rotation, plus MAX_ROTATIONS for a mirrored piece (see
+    // MAX_ORIENTATIONS), as written in the notation and the public APIs
+    pub fn orientation(&self) -> usize {
+        self.rot() + if self.mirrored() { MAX_ROTATIONS } else { 0 }
+    }
+    pub fn value(&self) -> isize {
+        PIECE_VALUES[self.index()]
+    }
+
+    // Packs the placement into 32 bits:  PACK_COORD_BITS each for x and
+    // y, PACK_ID_BITS for the piece id, and the rest for z.  Only
+    // placements in a normalized state fit, since their coordinates aren't
+    // negative.  Fields are ordered so that packed placements sort by z,
+    // then id, then position.
+    pub fn pack(&self) -> u32 {
+        debug_assert!(self.x >= 0 && self.x < 1 << PACK_COORD_BITS &&
+                      self.y >= 0 && self.y < 1 << PACK_COORD_BITS);
+        debug_assert!(self.id < 1 << PACK_ID_BITS);
+        debug_assert!(self.z < 1 << PACK_Z_BITS);
+        return (self.z as u32) << (PACK_ID_BITS + 2 * PACK_COORD_BITS) |
+               (self.id as u32) << (2 * PACK_COORD_BITS) |
+               (self.x as u32) << PACK_COORD_BITS | self.y as u32;
+    }
+
+    pub fn unpack(p: u32) -> Placed {
+        let coord = (1 << PACK_COORD_BITS) - 1;
+        Placed::new((p >> (2 * PACK_COORD_BITS) & ((1 << PACK_ID_BITS) - 1))
+                        as usize,
+                    (p >> PACK_COORD_BITS & coord) as i32,
+                    (p & coord) as i32,
+                    (p >> (PACK_ID_BITS + 2 * PACK_COORD_BITS)) as usize)
+    }
+
+    // Returns the placement turned a quarter turn clockwise about the
+    // origin (see State::rotated)
+    fn turned(&self) -> Placed {
+        let (id, dx, dy) = turns()[self.id];
+        Placed::new(id, self.y + dx, -self.x + dy, self.z)
     }
 }
 
+// For each piece id, the id that it becomes when turned a quarter turn
+// clockwise, and the offset which keeps it covering the same cells.  This
+// depends on the piece set, which can't change once it's in use.  (A
+// mirrored piece stays mirrored when it's turned.)
+fn turns() -> &'static [(usize, i32, i32); PIECE_ID_COUNT] {
+    static TURNS: OnceLock<[(usize, i32, i32); PIECE_ID_COUNT]>
+        = OnceLock::new();
+    TURNS.get_or_init(|| {
+        let mut out = [(0, 0, 0); PIECE_ID_COUNT];
+        for (id, t) in out.iter_mut().enumerate() {
+            let (index, rot) = (tile(id), id % MAX_ROTATIONS);
+            let base = id - rot;
+            let shape = pieces().shape(base);
+            let rot = (rot + 1) % MAX_ROTATIONS;
+            let first = rot % pieces().distinct_rotations[index];
+            let corner = |r| {
+                let pts = shape.rotn(r).pts;
+                (pts.iter().map(|q| q.0).min().unwrap_or(0),
+                 pts.iter().map(|q| q.1).min().unwrap_or(0))
+            };
+            let (a, b) = (corner(rot), corner(first));
+            *t = (base + first, a.0 - b.0, a.1 - b.1 - 3);
+        }
+        out
+    })
+}
+
 impl Ord for Placed {
     fn cmp(&self, other: &Placed) -> Ordering {
         if self.z != other.z {
@@ -46,43 +148,264 @@ impl PartialOrd for Placed {
     }
 }
 
+// Placements are written as the tile, its position, its rotation (in
+// quarter turns, plus 4 if it's mirrored), and its layer, e.g. 7@3,2 r1 z0
+impl fmt::Display for Placed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}@{},{} r{} z{}", self.index(), self.x, self.y,
+               self.orientation(), self.z)
+    }
+}
+
+impl FromStr for Placed {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Placed, String> {
+        let (tile, x, y, rot, z) = parse_notation(s)?;
+        let z = z.ok_or(format!("Missing layer in '{}'", s))?;
+        return Ok(Placed::new(piece_id(tile, rot), x, y, z));
+    }
+}
+
+// Parses a placement in the notation above, returning its tile, position,
+// rotation, and layer.  The rotation defaults to 0 and the layer is
+// optional, so that a game's moves can leave them out.
+pub(crate) fn parse_notation(s: &str)
+    -> Result<(usize, i32, i32, usize, Option<usize>), String>
+{
+    let err = || format!("Invalid placement '{}'", s);
+    let mut tokens = s.split_whitespace();
+    let mut pos = tokens.next().ok_or_else(err)?.splitn(2, '@');
+    let tile: usize = pos.next().unwrap().parse().map_err(|_| err())?;
+    let mut xy = pos.next().ok_or_else(err)?.splitn(2, ',');
+    let x = xy.next().unwrap().parse().map_err(|_| err())?;
+    let y = xy.next().ok_or_else(err)?.parse().map_err(|_| err())?;
+
+    let (mut rot, mut z) = (None, None);
+    for t in tokens {
+        let mut chars = t.chars();
+        let field = match chars.next() {
+            Some('r') => &mut rot,
+            Some('z') => &mut z,
+            _ => return Err(err()),
+        };
+        let v = chars.as_str().parse().map_err(|_| err())?;
+        if field.replace(v).is_some() {
+            return Err(err());
+        }
+    }
+    let rot = rot.unwrap_or(0);
+    if tile >= UNIQUE_PIECE_COUNT || rot >= MAX_ORIENTATIONS {
+        return Err(err());
+    }
+    return Ok((tile, x, y, rot, z));
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+// Whether pretty_print and pretty_print_3d use colors, rather than
+// writing each cell as its tile's digit followed by a mark for its layer
+static COLOR: AtomicBool = AtomicBool::new(true);
+
+// Layer marks for uncolored drawings, from the ground up (repeating if
+// there are more layers than marks)
+const LAYER_MARKS: [char; 8] = [' ', '\'', '"', '^', '*', '+', '#', '%'];
+
+// Turns colors on or off for everything drawn in the terminal
+pub fn set_color(enabled: bool) {
+    COLOR.store(enabled, atomic::Ordering::Relaxed);
+    control::set_override(enabled);
+}
+
+// Shading for the height of a cell in pretty_print_elevation, from the
+// ground up (staying solid for anything higher)
+const SHADES: [char; 4] = ['\u{2591}', '\u{2592}', '\u{2593}', '\u{2588}'];
+
+// Formats one cell of a tile on the given layer, without colors
+fn plain_cell(index: usize, z: usize) -> String {
+    format!("{}{}", index, LAYER_MARKS[z % LAYER_MARKS.len()])
+}
+
+// One character cell of an oblique projection (see pretty_print_3d)
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Projected {
+    // The top face of a piece, with the piece's index and layer
+    Top(usize, usize),
+    // The visible edge of a piece, below its top face
+    Side(usize),
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct State {
-    pub pieces: ArrayVec<[Placed; UNIQUE_PIECE_COUNT * 2]>,
+    pub pieces: ArrayVec<[Placed; UNIQUE_PIECE_COUNT * MAX_COPIES]>,
+
+    // Which cells count as neighbors when placing pieces
+    adjacency: Adjacency,
+
+    // If present, the maximum number of layers (including the ground),
+    // past which try_place rejects placements
+    max_layers: Option<usize>,
+
+    // Whether mirrored pieces may be placed, which picks the tables that
+    // cover them (and has the search try them)
+    mirrored: bool,
+
+    // Sum of the levels of each kind of piece, kept up to date as pieces
+    // are added and removed, so that scoring doesn't need to walk the list
+    heights: [usize; UNIQUE_PIECE_COUNT],
+
+    // Number of pieces on each layer.  Pieces are sorted from the top layer
+    // down, so each layer is a contiguous run of self.pieces.
+    layer_sizes: [u8; UNIQUE_PIECE_COUNT * MAX_COPIES],
+
+    // Width and height of the pieces' bounding box, found when they're
+    // normalized, since it's needed at every node
+    size: (i32, i32),
+}
+
+// A state's placements, packed as in Placed::pack.  This takes a fraction
+// of the memory of a State, for storing in seen-sets.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Packed(ArrayVec<[u32; UNIQUE_PIECE_COUNT * MAX_COPIES]>);
+
+// The other fields follow from the placements (and the adjacency rarely
+// differs), so only the packed placements are hashed
+impl Hash for State {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.pack().hash(state);
+    }
 }
 
 impl State {
     pub fn new() -> State {
-        State { pieces: ArrayVec::new() }
+        State::with_adjacency(Adjacency::Orthogonal)
+    }
+
+    pub fn with_adjacency(adjacency: Adjacency) -> State {
+        State { pieces: ArrayVec::new(), adjacency: adjacency,
+                max_layers: None, mirrored: false,
+                heights: [0; UNIQUE_PIECE_COUNT],
+                layer_sizes: [0; UNIQUE_PIECE_COUNT * MAX_COPIES],
+                size: (0, 0) }
+    }
+
+    // Builds an empty state which places pieces under the given rules
+    pub fn with_rules(rules: &Rules) -> State {
+        State::with_adjacency(rules.adjacency).with_max_layers(rules.max_layers)
+            .with_mirrored(rules.mirrored)
+    }
+
+    // Checks whether the state places pieces under the given rules, as one
+    // built by with_rules does
+    pub fn has_rules(&self, rules: &Rules) -> bool {
+        self.adjacency == rules.adjacency &&
+            self.max_layers == rules.max_layers &&
+            self.mirrored == rules.mirrored
+    }
+
+    // Caps the number of layers (including the ground) that pieces can be
+    // placed on, or lifts the cap if given None
+    pub fn with_max_layers(mut self, max_layers: Option<usize>) -> State {
+        self.max_layers = max_layers;
+        self
+    }
+
+    // Allows (or forbids) placing mirrored pieces
+    pub fn with_mirrored(mut self, mirrored: bool) -> State {
+        self.mirrored = mirrored;
+        self
     }
 
     // Inserts a new piece, maintaining sorted order
-    fn insert(&self, p: Placed) -> State {
+    pub(crate) fn insert(&self, p: Placed) -> State {
         let mut out = self.clone();
+        out.heights[p.index()] += p.z;
+        out.layer_sizes[p.z] += 1;
         out.pieces.push(p);
         out.pieces.sort_unstable();
+        out.normalize();
+        return out;
+    }
 
-        let xmin = out.pieces.iter().map(|p| p.x).min().unwrap();
-        let ymin = out.pieces.iter().map(|p| p.y).min().unwrap();
-        for p in &mut out.pieces {
-            p.x -= xmin;
-            p.y -= ymin;
+    // Returns the top layer on its own, on the ground.  (Pieces are added
+    // directly, as in from_layout, so that they keep their positions.)
+    pub(crate) fn top_layer(&self) -> State {
+        let mut out = State::with_adjacency(self.adjacency)
+            .with_mirrored(self.mirrored);
+        for p in self.layer(self.layers()) {
+            out.layer_sizes[0] += 1;
+            out.pieces.push(Placed::new(p.id, p.x, p.y, 0));
         }
+        out.pieces.sort_unstable();
+        out.normalize();
+        return out;
+    }
 
+    // Removes the piece at the given index in self.pieces
+    fn remove(&self, i: usize) -> State {
+        let mut out = self.clone();
+        let p = out.pieces.remove(i);
+        out.heights[p.index()] -= p.z;
+        out.layer_sizes[p.z] -= 1;
+        out.normalize();
         return out;
     }
 
-    pub fn score(&self) -> usize {
-        self.pieces.iter().map(|p| p.index() * p.z).sum()
+    // Shifts pieces so that the minimum x and y coordinates are zero
+    fn normalize(&mut self) {
+        if let Some(xmin) = self.pieces.iter().map(|p| p.x).min() {
+            let ymin = self.pieces.iter().map(|p| p.y).min().unwrap();
+            for p in &mut self.pieces {
+                p.x -= xmin;
+                p.y -= ymin;
+            }
+        }
+        self.size = self.bounding_size();
     }
 
-    pub fn size(&self) -> (i32, i32) {
+    fn bounding_size(&self) -> (i32, i32) {
         (self.pieces.iter().map(|p| p.x + 4).max().unwrap_or(0),
          self.pieces.iter().map(|p| p.y + 4).max().unwrap_or(0))
     }
 
+    pub fn score(&self) -> isize {
+        self.score_with(&PIECE_VALUES)
+    }
+
+    // As score, but with a custom value for each piece
+    pub fn score_with(&self, values: &Values) -> isize {
+        let mut s = 0;
+        for i in 0..UNIQUE_PIECE_COUNT {
+            s += values[i] * self.heights[i] as isize;
+        }
+        debug_assert_eq!(s, self.pieces.iter()
+            .map(|p| values[p.index()] * p.z as isize).sum::<isize>());
+        return s;
+    }
+
+    pub fn size(&self) -> (i32, i32) {
+        debug_assert_eq!(self.size, self.bounding_size());
+        self.size
+    }
+
+    pub fn pack(&self) -> Packed {
+        Packed(self.pieces.iter().map(Placed::pack).collect())
+    }
+
+    pub fn adjacency(&self) -> Adjacency {
+        self.adjacency
+    }
+
+    pub fn max_layers(&self) -> Option<usize> {
+        self.max_layers
+    }
+
+    pub fn mirrored(&self) -> bool {
+        self.mirrored
+    }
+
     pub fn is_empty(&self) -> bool {
         self.pieces.is_empty()
     }
@@ -91,80 +414,428 @@ impl State {
         self.pieces.first().map(|p| p.z).unwrap_or(0)
     }
 
+    // Returns the pieces on the given layer
+    pub fn layer(&self, z: usize) -> &[Placed] {
+        let start: usize = self.layer_sizes[z + 1..].iter()
+            .map(|&n| n as usize).sum();
+        &self.pieces[start..start + self.layer_sizes[z] as usize]
+    }
+
+    // Returns every legal placement of the given piece (with its rotation)
+    // along with the state that it makes.  To try several pieces on one
+    // state, build its Bitboards once and use Bitboards::placements.
+    pub fn placements(&self, piece: usize) -> Placements<'_, Bitboards> {
+        Placements::new(self, Bitboards::new(self), piece)
+    }
+
+    // Returns every legal placement of every piece left in the bag, in
+    // each of its distinct orientations (see PieceSet::orientations)
+    pub fn legal_moves(&self, bag: &Bag) -> Vec<Placed> {
+        self.legal_moves_iter(bag).collect()
+    }
+
+    // Streams the placements of State::legal_moves, a row at a time, so
+    // that a caller can stop early without finding all of them
+    pub fn legal_moves_iter(&self, bag: &Bag)
+        -> impl Iterator<Item=Placed> + '_
+    {
+        let boards = Rc::new(Bitboards::new(self));
+        let ids: Vec<usize> = (0..UNIQUE_PIECE_COUNT)
+            .filter(|&t| bag.digit(t) > 0)
+            .flat_map(|t| pieces().orientations(t, self.mirrored))
+            .collect();
+        ids.into_iter().flat_map(move |b|
+            Placements::new(self, boards.clone(), b).map(|p| p.0))
+    }
+
+    // Places a tile (0-9), turned by rotation quarter turns (or mirrored,
+    // then turned by rotation less 4, if mirrored pieces are allowed), at
+    // the given position.  Unlike try_place, this checks its arguments, so
+    // it's the one to use for input from outside the library.
+    pub fn place(&self, tile: usize, rotation: usize, x: i32, y: i32)
+        -> error::Result<State>
+    {
+        if tile >= UNIQUE_PIECE_COUNT {
+            return Err(Error::InvalidTile(tile));
+        } else if rotation >= if self.mirrored { MAX_ORIENTATIONS }
+                              else { MAX_ROTATIONS } {
+            return Err(Error::InvalidRotation(rotation));
+        } else if self.pieces.is_full() {
+            return Err(Error::Full);
+        } else if self.pieces.iter().filter(|p| p.index() == tile).count()
+                  >= MAX_COPIES
+        {
+            return Err(Error::TooManyCopies(tile));
+        } else if !(-MAX_COORDINATE..=MAX_COORDINATE).contains(&x) ||
+                  !(-MAX_COORDINATE..=MAX_COORDINATE).contains(&y)
+        {
+            return Err(Error::OutOfRange(x, y));
+        }
+        self.try_place(piece_id(tile, rotation), x, y)
+            .ok_or(Error::Illegal)
+    }
+
     // Attempts to place a piece at the given position
     pub fn try_place(&self, piece: usize, x: i32, y: i32) -> Option<State> {
-        // We only allow the first piece to be placed at the origin,
-        // and with zero rotation, to reduce degrees of freedom
+        // The first piece can go anywhere on the table
         if self.is_empty() {
-            if x == 0 && y == 0 {
-                let p = Placed::new(piece, x, y, 0);
-                if p.rot() == 0 {
-                    return Some(self.insert(p));
+            return Some(self.insert(Placed::new(piece, x, y, 0)));
+        }
+
+        let tables = Tables::get(self.adjacency, self.mirrored);
+
+        // Did we see a neighboring piece on the layer above?  Pieces being
+        // placed above the top layer don't need a neighbor, so we initialize
+        // this to true.
+        let mut got_neighbor_above = true;
+
+        // Scan down from the top layer, stopping as soon as we know where
+        // the piece lands (or that it can't be placed).  A piece rests on
+        // the highest layer that it overlaps, so the layers below can't
+        // change the answer and are never looked at.
+        for z in (0..=self.layers()).rev() {
+            // The piece mutates as parts of it are placed over other pieces
+            let mut remaining_piece = piece;
+
+            for p in self.layer(z) {
+                match tables.at(remaining_piece).check(x, y, p) {
+                    // Tables are cleaned when they're built, so this can't
+                    // happen; release builds treat it as a failed placement
+                    Overlap::_Partial(_) => {
+                        debug_assert!(false, "Uncleaned index");
+                        return None;
+                    },
+                    // Neighbors are found with the masks below
+                    Overlap::None | Overlap::Neighbor => (),
+                    // Once the piece rests on this layer, it can only land
+                    // fully on it; without a neighbor on the layer above,
+                    // that would be rejected, so there's no point in
+                    // scanning on.
+                    Overlap::Partial(_) if !got_neighbor_above => return None,
+                    Overlap::Partial(t) => remaining_piece = t,
+                    // Landing on a single piece isn't allowed.  (If it
+                    // landed on several, the partial overlaps above already
+                    // checked for a neighbor on the layer above.)
+                    Overlap::Full =>
+                        return if remaining_piece != piece &&
+                                  self.max_layers.is_none_or(|n| z + 1 < n) {
+                            Some(self.insert(Placed::new(piece, x, y, z + 1)))
+                        } else {
+                            None
+                        },
                 }
             }
+
+            // If some of the piece ended up over pieces on this layer,
+            // then it will be unsupported, so we must return None.
+            if remaining_piece != piece {
+                return None;
+            }
+            got_neighbor_above = tables.touches(piece, x, y, self.layer(z));
+        }
+
+        // Otherwise, the piece lands on the table beside a piece there
+        if got_neighbor_above {
+            return Some(self.insert(Placed::new(piece, x, y, 0)));
+        } else {
             return None;
         }
+    }
 
-        // Here's the Z layer that we start on!
-        let mut current_z = self.pieces.first().map(|p| p.z).unwrap_or(0);
+    // Checks whether this arrangement could have been built by a sequence
+    // of legal placements, by rebuilding it from the bottom layer up.
+    pub fn is_valid(&self) -> bool {
+        // Pieces are sorted from the top layer down, so reverse them
+        let mut todo: Vec<Placed> = self.pieces.iter().rev().cloned().collect();
+        if todo.is_empty() {
+            return true;
+        } else if todo[0].z != 0 {
+            return false;
+        }
 
-        // Have we seen a neighboring piece on this particular layer?
-        let mut got_neighbor_this_layer = false;
+        // We track the offset between our coordinates and the rebuilt
+        // state's coordinates, which are normalized as pieces are added.
+        let first = todo.remove(0);
+        let mut state = State::with_adjacency(self.adjacency)
+            .with_max_layers(self.max_layers)
+            .with_mirrored(self.mirrored)
+            .try_place(first.id, 0, 0).unwrap();
+        let mut offset = (first.x, first.y);
 
-        // Did we see a neighboring piece on the previous layer?
-        // Pieces being placed above the top layer don't need a neighbor,
-        // so we initialize this to true.
-        let mut got_neighbor_prev_layer = true;
+        while !todo.is_empty() {
+            let mut found = None;
+            for (i, p) in todo.iter().enumerate() {
+                let next = match state.try_place(p.id, p.x - offset.0,
+                                                       p.y - offset.1) {
+                    Some(next) => next,
+                    None => continue,
+                };
+                // The piece must also land on the layer where we found it
+                let o = (offset.0.min(p.x), offset.1.min(p.y));
+                let q = Placed::new(p.id, p.x - o.0, p.y - o.1, p.z);
+                if next.pieces.contains(&q) {
+                    found = Some((i, next, o));
+                    break;
+                }
+            }
+            match found {
+                Some((i, next, o)) => {
+                    todo.remove(i);
+                    state = next;
+                    offset = o;
+                },
+                None => return false,
+            }
+        }
+        return true;
+    }
 
-        // The piece mutates as parts of it are placed over other pieces
-        let mut remaining_piece = piece;
+    // Returns every placed piece that could be removed without lowering
+    // the score or leaving an invalid arrangement behind.
+    pub fn removable(&self) -> Vec<Placed> {
+        (0..self.pieces.len())
+            .filter(|&i| {
+                let p = self.pieces[i];
+                p.value() * p.z as isize <= 0
+            })
+            .filter(|&i| self.remove(i).is_valid())
+            .map(|i| self.pieces[i])
+            .collect()
+    }
 
-        for p in self.pieces.iter() {
-            if p.z != current_z {
-                // If some of the piece ended up over pieces on this layer,
-                // then it will be unsupported, so we must return false.
-                if remaining_piece != piece {
-                    return None;
+    // Returns the arrangement turned a quarter turn clockwise as a whole.
+    // Tiles with fewer distinct rotations are turned back to one that the
+    // search would use, shifted so that they cover the same cells.
+    pub fn rotated(&self) -> State {
+        let mut out = State::with_adjacency(self.adjacency)
+            .with_max_layers(self.max_layers)
+            .with_mirrored(self.mirrored);
+        out.heights = self.heights;
+        out.layer_sizes = self.layer_sizes;
+        out.pieces = self.pieces.iter().map(Placed::turned).collect();
+        out.pieces.sort_unstable();
+        out.normalize();
+        return out;
+    }
+
+    // Returns a key which is the same for arrangements that only differ
+    // in where they sit or how the whole table is turned:  the least of
+    // the four turns' packed placements, each sorted as packed values.
+    // This is found at every node of the search, so it turns the pieces
+    // in place rather than building each turned State.
+    pub fn canonical(&self) -> Packed {
+        let mut pieces = self.pieces.clone();
+        let mut best: Option<Packed> = None;
+        for r in 0..MAX_ROTATIONS {
+            if r > 0 {
+                for p in pieces.iter_mut() {
+                    *p = p.turned();
                 }
+            }
+            let xmin = pieces.iter().map(|p| p.x).min().unwrap_or(0);
+            let ymin = pieces.iter().map(|p| p.y).min().unwrap_or(0);
+            let mut packed: ArrayVec<[u32; UNIQUE_PIECE_COUNT * MAX_COPIES]> =
+                pieces.iter()
+                    .map(|p| Placed::new(p.id, p.x - xmin, p.y - ymin, p.z)
+                              .pack())
+                    .collect();
+            packed.sort_unstable();
+            let packed = Packed(packed);
+            if best.as_ref().map(|b| packed < *b).unwrap_or(true) {
+                best = Some(packed);
+            }
+        }
+        return best.unwrap();
+    }
+
+    // Returns the arrangement as text, with each piece as "id,x,y,z" and
+    // pieces separated by semicolons
+    pub fn layout(&self) -> String {
+        let pieces: Vec<String> = self.pieces.iter()
+            .map(|p| format!("{},{},{},{}", p.id, p.x, p.y, p.z))
+            .collect();
+        pieces.join(";")
+    }
 
-                current_z = p.z;
-                got_neighbor_prev_layer = got_neighbor_this_layer;
-                got_neighbor_this_layer = false;
-                remaining_piece = piece;
+    // Parses the output of layout(), returning None unless it's a legal
+    // arrangement.  An arrangement with mirrored pieces allows them.
+    pub fn from_layout(s: &str, adjacency: Adjacency) -> Option<State> {
+        let mut state = State::with_adjacency(adjacency);
+        for p in s.split(';').filter(|p| !p.is_empty()) {
+            let f: Vec<&str> = p.split(',').collect();
+            if f.len() != 4 || state.pieces.is_full() {
+                return None;
             }
+            let id = f[0].parse().ok().filter(|&i| i < PIECE_ID_COUNT)?;
+            let z = f[3].parse().ok()
+                .filter(|&z| z < UNIQUE_PIECE_COUNT * MAX_COPIES)?;
+            // Pieces are added directly, since insert() would renormalize
+            // the coordinates after each one
+            let p = Placed::new(id, f[1].parse().ok()?, f[2].parse().ok()?, z);
+            state.mirrored |= p.mirrored();
+            state.heights[p.index()] += p.z;
+            state.layer_sizes[p.z] += 1;
+            state.pieces.push(p);
+        }
+        state.pieces.sort_unstable();
+        state.normalize();
+        if state.is_valid() { Some(state) } else { None }
+    }
 
-            let r = OVERLAP_TABLES.at(remaining_piece).check(x, y, &p);
-            match r {
-                Overlap::_Partial(_) => panic!("Uncleaned index"),
-                Overlap::None => (),
-                Overlap::Neighbor => got_neighbor_this_layer = true,
-                Overlap::Partial(t) => remaining_piece = t,
-                Overlap::Full =>
-                    if (remaining_piece != piece) && (got_neighbor_prev_layer) {
-                        return Some(self.insert(
-                                Placed::new(piece, x, y, p.z + 1)));
-                    } else {
-                        return None;
+    // Draws the arrangement as an image, with its layers side by side
+    // (bottom layer first) in the same colors as pretty_print
+    pub fn to_image(&self) -> Image {
+        Image::of_state(self)
+    }
+
+    // Draws each layer as its own image, bottom layer first
+    pub fn layer_images(&self) -> Vec<Image> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+        (0..=self.layers()).map(|z| Image::of_layer(self, z)).collect()
+    }
+
+    // Projects the arrangement obliquely, with each piece one unit thick and
+    // each layer drawn one cell up and to the right of the layer below.
+    // Returns the width of the projection and its cells, row by row.
+    fn project(&self) -> (usize, Vec<Option<Projected>>) {
+        let (w, h) = self.size();
+        let (w, h) = (w as usize, h as usize);
+        let top = if self.is_empty() { 0 } else { self.layers() + 1 };
+        let (pw, ph) = (w + top, h + top);
+        let mut out = vec![None; pw * ph];
+
+        // Layers are drawn from the bottom up, with each layer's edges
+        // drawn before its top faces, so that only the edges which face
+        // the viewer are left showing.  Pieces are mirrored in x to match
+        // pretty_print.
+        for z in 0..top {
+            for side in [true, false].iter() {
+                let lift = if *side { z } else { z + 1 };
+                for p in self.layer(z) {
+                    let shape = pieces().shape(p.id);
+                    for (px, py) in shape.pts {
+                        let x = w - (p.x + px) as usize - 1 + lift;
+                        let y = (p.y + py) as usize + top - lift;
+                        out[x + y * pw] = Some(if *side {
+                            Projected::Side(p.index())
+                        } else {
+                            Projected::Top(p.index(), z)
+                        });
                     }
+                }
             }
         }
-        if got_neighbor_this_layer && remaining_piece == piece {
-            debug_assert!(current_z == 0);
-            return Some(self.insert(Placed::new(piece, x, y, 0)));
-        } else {
-            return None;
+        return (pw, out);
+    }
+
+    // Prints an oblique 3D view of the arrangement, so that stacks are
+    // visible at a glance.  Top faces are labelled with their layer.
+    pub fn pretty_print_3d(&self) {
+        print!("{}", self.draw_3d(COLOR.load(atomic::Ordering::Relaxed)));
+    }
+
+    // Draws the view printed by pretty_print_3d.  Without colors, top
+    // faces are drawn as in draw, and edges as "::".
+    fn draw_3d(&self, color: bool) -> String {
+        let (w, cells) = self.project();
+        let mut out = String::new();
+        for row in cells.chunks(w) {
+            for c in row {
+                out += &match (*c, color) {
+                    (Some(Projected::Top(i, z)), true) =>
+                        format!("{:>2}", z).color(theme().text(i))
+                            .on_color(theme().terminal[i])
+                            .to_string(),
+                    (Some(Projected::Top(i, z)), false) => plain_cell(i, z),
+                    (Some(Projected::Side(i)), true) =>
+                        "\u{2592}\u{2592}".color(theme().terminal[i]).to_string(),
+                    (Some(Projected::Side(_)), false) => "::".to_string(),
+                    (None, _) => "  ".to_string(),
+                };
+            }
+            out.push('\n');
         }
+        return out;
+    }
+
+    // Prints every layer in one grid, with each cell shaded and labelled by
+    // the height of the tile on top of it (and colored by that tile),
+    // followed by a legend
+    pub fn pretty_print_elevation(&self) {
+        print!("{}", self.draw_elevation(COLOR.load(atomic::Ordering::Relaxed)));
+    }
+
+    fn draw_elevation(&self, color: bool) -> String {
+        let (w, h) = self.size();
+        let (w, h) = (w as usize, h as usize);
+
+        // Pieces are sorted from the top layer down, so the first piece
+        // to cover a cell is the one on top
+        let mut top: Vec<Option<(usize, usize)>> = vec![None; w * h];
+        for p in self.pieces.iter() {
+            let shape = pieces().shape(p.id);
+            for (px, py) in shape.pts {
+                let c = &mut top[w - (p.x + px) as usize - 1 + (p.y + py) as usize * w];
+                if c.is_none() {
+                    *c = Some((p.index(), p.z));
+                }
+            }
+        }
+
+        let shade = |z: usize| SHADES[z.min(SHADES.len() - 1)];
+        let mut out = String::new();
+        for row in top.chunks(w.max(1)).take(h) {
+            for c in row {
+                out += &match *c {
+                    Some((i, z)) => {
+                        let cell = format!("{}{}", shade(z), z % 10);
+                        if color {
+                            cell.color(theme().terminal[i]).to_string()
+                        } else {
+                            cell
+                        }
+                    },
+                    None => "  ".to_string(),
+                };
+            }
+            out.push('\n');
+        }
+        if !self.is_empty() {
+            let layers: Vec<String> = (0..=self.layers())
+                .map(|z| format!("{}{} layer {}", shade(z), z % 10, z))
+                .collect();
+            out += &format!("Height: {}\n", layers.join(", "));
+        }
+        if color && !self.is_empty() {
+            let mut tiles: Vec<usize> = self.pieces.iter().map(|p| p.index()).collect();
+            tiles.sort_unstable();
+            tiles.dedup();
+            let tiles: Vec<String> = tiles.into_iter()
+                .map(|i| i.to_string().color(theme().terminal[i]).to_string())
+                .collect();
+            out += &format!("Tiles: {}\n", tiles.join(" "));
+        }
+        return out;
     }
 
     pub fn pretty_print(&self) {
+        print!("{}", self.draw(COLOR.load(atomic::Ordering::Relaxed)));
+    }
+
+    // Draws each layer printed by pretty_print.  Without colors, each cell
+    // is its tile's digit followed by the layer's mark.
+    fn draw(&self, color: bool) -> String {
         let (w, h) = self.size();
+        let mut out = String::new();
 
         for z in 0..self.pieces.first().map(|p| p.z + 1).unwrap_or(0) {
             let mut v = vec![-1; (w * h) as usize];
 
-            println!("Layer {}:\n", z);
+            out += &format!("Layer {}:\n\n", z);
             for i in self.pieces.iter().filter(|&p| p.z == z) {
-                let p = Piece::from_u16(PIECES[i.index()]).rotn(i.rot());
+                let p = pieces().shape(i.id);
                 for (px, py) in p.pts {
                     let x = px + i.x;
                     let y = py + i.y;
@@ -175,19 +846,22 @@ impl State {
             for y in 0..h {
                 for x in 0..w {
                     let i = v[(x + y * w) as usize];
-                    if i >= 0 {
-                        print!("{}", "  ".on_color(PIECE_COLORS[i as usize]))
+                    out += &if i < 0 {
+                        "  ".to_string()
+                    } else if color {
+                        "  ".on_color(theme().terminal[i as usize]).to_string()
                     } else {
-                        print!("  ");
-                    }
+                        plain_cell(i as usize, z)
+                    };
                 }
-                print!("\n");
+                out.push('\n');
             }
             for _ in 0..w {
-                print!("--");
+                out += "--";
             }
-            print!("\n");
+            out.push('\n');
         }
+        return out;
     }
 }
 
@@ -196,6 +870,8 @@ impl State {
 #[cfg(test)]
 mod tests {
     use state::{Placed, State};
+    use piece::{Adjacency, Piece, PIECES, MAX_ROTATIONS, MIRRORED_ID, pieces};
+    use bag::Bag;
 
     #[test]
     fn score() {
@@ -205,6 +881,10 @@ mod tests {
 
         let state = state.insert(Placed::new(4, 0, 0, 1));
         assert_eq!(state.score(), 1);
+
+        let state = state.insert(Placed::new(36, 0, 0, 2));
+        assert_eq!(state.score(), 19);
+        assert_eq!(state.remove(0).score(), 1);
     }
 
     #[test]
@@ -214,8 +894,60 @@ mod tests {
         assert_eq!(state.pieces[0], Placed::new(0, 0, 0, 1));
         let state = state.insert(Placed::new(0, -3, -2, 0));
         assert_eq!(state.pieces[0], Placed::new(0, 3, 2, 1));
+        assert_eq!(state.size(), (7, 6));
+        assert_eq!(state.remove(0).size(), (4, 4));
+    }
+
+    #[test]
+    fn place() {
+        use error::Error;
+        let state = State::new().place(0, 0, 0, 0).unwrap();
+        assert_eq!(state.place(10, 0, 3, 0), Err(Error::InvalidTile(10)));
+        assert_eq!(state.place(0, 4, 3, 0), Err(Error::InvalidRotation(4)));
+        assert_eq!(state.place(0, 0, 9, 0), Err(Error::Illegal));
+        assert_eq!(state.place(0, 0, 3, i32::MIN),
+                   Err(Error::OutOfRange(3, i32::MIN)));
+        let state = state.place(0, 0, 3, 0).unwrap();
+        assert_eq!(state.place(0, 0, -3, 0), Err(Error::TooManyCopies(0)));
+        assert_eq!(state.place(1, 0, 2, 0).unwrap().score(), 1);
     }
 
+    #[test]
+    fn rotated() {
+        let state = State::from_layout("4,2,0,1;0,0,0,0;0,3,0,0",
+                                       Adjacency::Orthogonal).unwrap();
+        assert_ne!(state.rotated(), state);
+        let mut r = state.clone();
+        for _ in 0..MAX_ROTATIONS {
+            r = r.rotated();
+            assert!(r.is_valid());
+            assert_eq!(r.score(), state.score());
+            assert_eq!(r.canonical(), state.canonical());
+        }
+        assert_eq!(r, state);
+    }
+
+    #[test]
+    fn pack() {
+        let p = Placed::new(39, 80, 200, 19);
+        assert_eq!(Placed::unpack(p.pack()), p);
+        let p = Placed::new(127, 1023, 700, 31);
+        assert_eq!(Placed::unpack(p.pack()), p);
+
+        // Packed placements sort by layer first
+        assert!(Placed::new(0, 0, 0, 1).pack() >
+                Placed::new(39, 1023, 1023, 0).pack());
+
+        // The same arrangement, built in a different order
+        let a = State::new().insert(Placed::new(0, 0, 0, 0))
+            .insert(Placed::new(5, 3, 1, 1));
+        let b = State::new().insert(Placed::new(5, 3, 1, 1))
+            .insert(Placed::new(0, -3, -1, 0));
+        assert_eq!(a.pack(), b.pack());
+        assert!(a.pack() != State::new().insert(Placed::new(0, 0, 0, 0)).pack());
+        assert!(::std::mem::size_of::<::state::Packed>() * 4 <
+                ::std::mem::size_of::<State>());
+    }
 
     #[test]
     fn ordering() {
@@ -227,6 +959,11 @@ mod tests {
         assert_eq!(state.pieces[0], Placed::new(5, 1, 3, 2));
         let state = state.insert(Placed::new(5, 1, 3, 1));
         assert_eq!(state.pieces[0], Placed::new(5, 1, 3, 2));
+
+        assert_eq!(state.layer(2), &[Placed::new(5, 1, 3, 2)]);
+        assert_eq!(state.layer(1).len(), 2);
+        assert_eq!(state.layer(0), &[Placed::new(0, 0, 0, 0)]);
+        assert_eq!(state.remove(0).layer(2).len(), 0);
     }
 
     #[test]
@@ -241,6 +978,159 @@ mod tests {
         assert_eq!(state.size(), (8, 5));
     }
 
+    #[test]
+    fn is_valid() {
+        let state = State::new()
+            .try_place(0, 0, 0).unwrap()
+            .try_place(0, 3, 0).unwrap()
+            .try_place(4, 2, 0).unwrap();
+        assert!(state.is_valid());
+        assert!(State::new().is_valid());
+
+        // Unsupported after removing the right-hand 0
+        let i = state.pieces.iter().position(|p| p.x == 3).unwrap();
+        assert!(!state.remove(i).is_valid());
+
+        // Floating above the ground
+        let state = State::new().insert(Placed::new(0, 0, 0, 1));
+        assert!(!state.is_valid());
+
+        // Not touching
+        let state = State::new()
+            .insert(Placed::new(0, 0, 0, 0))
+            .insert(Placed::new(0, 5, 0, 0));
+        assert!(!state.is_valid());
+    }
+
+    #[test]
+    fn notation() {
+        let p = Placed::new(7 * MAX_ROTATIONS + 1, 3, -2, 0);
+        assert_eq!(p.to_string(), "7@3,-2 r1 z0");
+        assert_eq!("7@3,-2 r1 z0".parse(), Ok(p));
+        assert_eq!("7@3,-2 z0 r1".parse(), Ok(p));
+        assert_eq!("7@3,-2 z0".parse::<Placed>().map(|p| p.rot()), Ok(0));
+
+        // Mirrored pieces are written with 4 added to their rotation
+        let m = Placed::new(MIRRORED_ID + 7 * MAX_ROTATIONS + 1, 3, -2, 0);
+        assert_eq!(m.to_string(), "7@3,-2 r5 z0");
+        assert_eq!("7@3,-2 r5 z0".parse(), Ok(m));
+        assert!(m.mirrored() && m.rot() == 1 && m.index() == 7);
+
+        for bad in &["7@3,-2 r1", "7@3 z0", "7@3,2 r8 z0", "10@0,0 z0",
+                     "7@3,2 z0 z1", "7@3,2 q1 z0", "", "7 3 2"] {
+            assert!(bad.parse::<Placed>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn removable() {
+        let state = State::new()
+            .try_place(0, 0, 0).unwrap()
+            .try_place(0, 3, 0).unwrap()
+            .try_place(4, 2, 0).unwrap()
+            .try_place(0, 6, 0).unwrap();
+        assert_eq!(state.removable(), vec![Placed::new(0, 6, 0, 0)]);
+    }
+
+    #[test]
+    fn try_place_diagonal() {
+        let state = State::with_adjacency(Adjacency::Diagonal)
+            .try_place(0, 0, 0).unwrap();
+        state.try_place(0, 3, 4).unwrap();
+        assert_eq!(state.try_place(0, 4, 4), None, "no neighbor");
+        assert_eq!(State::new().try_place(0, 0, 0).unwrap()
+                   .try_place(0, 3, 4), None, "no orthogonal neighbor");
+    }
+
+    #[test]
+    fn project() {
+        use super::Projected::{Top, Side};
+
+        // A 1 on two 0s, which is lifted a cell further up and right
+        let state = State::new().try_place(0, 0, 0).unwrap()
+            .try_place(0, 3, 0).unwrap()
+            .try_place(4, 2, 0).unwrap();
+        let (w, cells) = state.project();
+        let (sw, sh) = state.size();
+        assert_eq!(w, sw as usize + 2);
+        assert_eq!(cells.len(), w * (sh as usize + 2));
+
+        let tops = |z| cells.iter().filter(|c| match **c {
+            Some(Top(_, l)) => l == z,
+            _ => false,
+        }).count();
+        assert_eq!(tops(1), Piece::from_u16(PIECES[1]).pts.len());
+        assert!(tops(0) > 0);
+        assert!(cells.contains(&Some(Side(1))));
+
+        // The corner of the bottom-left cell is never covered
+        assert_eq!(cells[(sh as usize + 1) * w + w - 1], None);
+        assert_eq!(State::new().project(), (0, vec![]));
+    }
+
+    #[test]
+    fn draw_elevation() {
+        // A 1 on two 0s:  its cells are at height 1, and the uncovered
+        // parts of the 0s are at height 0
+        let state = State::new().try_place(0, 0, 0).unwrap()
+            .try_place(0, 3, 0).unwrap()
+            .try_place(4, 2, 0).unwrap();
+        let out = state.draw_elevation(false);
+        let (w, h) = state.size();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), h as usize + 1);
+        assert!(lines[..h as usize].iter().all(|l| l.chars().count() == 2 * w as usize));
+        let ones = Piece::from_u16(PIECES[1]).pts.len();
+        let zeros = Piece::from_u16(PIECES[0]).pts.len();
+        assert_eq!(out.matches("\u{2592}1").count(), ones + 1);
+        assert_eq!(out.matches("\u{2591}0").count(), 2 * zeros - ones + 1);
+        assert!(lines[h as usize].starts_with("Height: "));
+        assert_eq!(State::new().draw_elevation(false), "");
+    }
+
+    #[test]
+    fn draw_plain() {
+        let state = State::new().try_place(0, 0, 0).unwrap()
+            .try_place(0, 3, 0).unwrap()
+            .try_place(4, 2, 0).unwrap();
+        let flat = state.draw(false);
+        assert!(!flat.contains('\x1b'));
+        assert_eq!(flat.matches("0 ").count(), 2 * Piece::from_u16(PIECES[0]).pts.len());
+        assert_eq!(flat.matches("1'").count(), Piece::from_u16(PIECES[1]).pts.len());
+        assert!(flat.starts_with("Layer 0:\n\n"));
+
+        let tall = state.draw_3d(false);
+        assert!(!tall.contains('\x1b'));
+        assert_eq!(tall.matches("1'").count(), Piece::from_u16(PIECES[1]).pts.len());
+        assert!(tall.contains("::"));
+        assert_eq!(tall.lines().count(), state.project().1.len() / state.project().0);
+    }
+
+    #[test]
+    fn layout() {
+        let state = State::new().try_place(0, 0, 0).unwrap()
+            .try_place(0, 3, 0).unwrap()
+            .try_place(4, 2, 0).unwrap();
+        let text = state.layout();
+        assert_eq!(State::from_layout(&text, Adjacency::Orthogonal),
+                   Some(state));
+        assert_eq!(State::from_layout("", Adjacency::Orthogonal),
+                   Some(State::new()));
+
+        // Pieces must be legally placed
+        assert_eq!(State::from_layout("0,0,0,0;0,1,0,0", Adjacency::Orthogonal),
+                   None);
+        assert_eq!(State::from_layout("0,0,0", Adjacency::Orthogonal), None);
+    }
+
+    #[test]
+    fn try_place_first() {
+        let a = State::new().try_place(5, 0, 0).unwrap();
+        let b = State::new().try_place(5, -3, 7).unwrap();
+        assert_eq!(a, b);
+        assert_eq!(a.pieces[0], Placed::new(5, 0, 0, 0));
+    }
+
     #[test]
     fn try_place() {
         let state = State::new().try_place(0, 0, 0).unwrap();
@@ -260,4 +1150,45 @@ mod tests {
         let state = state.try_place(4, 2, 0).unwrap();
         assert_eq!(state.score(), 1);
     }
+
+    #[test]
+    fn try_place_capped() {
+        let state = State::new().with_max_layers(Some(1))
+            .try_place(0, 0, 0).unwrap()
+            .try_place(0, 3, 0).unwrap();
+
+        // The 1 would land on layer 1, which is past the cap
+        assert_eq!(state.try_place(4, 2, 0), None, "above the cap");
+        assert!(state.clone().with_max_layers(Some(2))
+                .try_place(4, 2, 0).is_some());
+        assert!(state.try_place(4, 6, 0).is_some());
+
+        // The cap is kept through turning and rebuilding
+        assert_eq!(state.rotated().max_layers(), Some(1));
+        assert!(state.is_valid());
+    }
+
+    #[test]
+    fn legal_moves() {
+        let state = State::new().try_place(0, 0, 0).unwrap();
+        let bag = Bag::from_usize(0).add(0).add(4);
+        let found = state.legal_moves(&bag);
+        assert!(!found.is_empty());
+        for p in &found {
+            assert!(p.index() == 0 || p.index() == 1);
+            assert!(state.try_place(p.id(),
+                                    p.x, p.y).is_some());
+        }
+
+        // Every placement of each tile, in each of its distinct rotations
+        let expected: usize = [0, 1].iter().map(|&i|
+            (0..pieces().distinct_rotations[i])
+                .map(|r| state.placements(i * MAX_ROTATIONS + r).count())
+                .sum::<usize>()).sum();
+        assert_eq!(found.len(), expected);
+
+        assert_eq!(state.legal_moves_iter(&bag).take(3).collect::<Vec<_>>(),
+                   found[..3].to_vec());
+        assert!(state.legal_moves(&Bag::from_usize(0)).is_empty());
+    }
 }
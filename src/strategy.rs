@@ -0,0 +1,206 @@
+// Ways of playing a real game, where each tile must be placed as soon as
+// it's drawn, without knowing what comes next.  Anything which plays a
+// game (the simulator, the engine, and Expectimax::evaluate) takes one.
+
+use std::time::Duration;
+
+use expectimax::placements;
+use hint::{Hint, unseen};
+use mcts::Mcts;
+use rules::Rules;
+use rng::Rng;
+use state::{Placed, State};
+
+pub trait Strategy {
+    // A short description, for reports
+    fn name(&self) -> String;
+
+    // Returns where to place the tile (0-9) which was just drawn, in the
+    // state's coordinates, or None if it can't be placed anywhere
+    fn choose(&mut self, state: &State, piece: usize) -> Option<Placed>;
+}
+
+// Places each tile at a legal position picked at random.  The pick only
+// depends on the seed, the arrangement and the tile.
+pub struct Random {
+    rules: Rules,
+    seed: u64,
+}
+
+impl Random {
+    pub fn new(rules: Rules, seed: u64) -> Random {
+        Random { rules: rules, seed: seed }
+    }
+}
+
+impl Strategy for Random {
+    fn name(&self) -> String {
+        format!("random:{}", self.seed)
+    }
+
+    fn choose(&mut self, state: &State, piece: usize) -> Option<Placed> {
+        let mut moves = placements(state, piece, &self.rules);
+        if moves.is_empty() {
+            return None;
+        }
+        let i = Rng::for_state(&[self.seed, piece as u64], state)
+            .below(moves.len());
+        return Some(moves.swap_remove(i).0);
+    }
+}
+
+// Places each tile where it scores the most right away, then where it
+// leaves the smallest footprint
+pub struct Greedy {
+    rules: Rules,
+}
+
+impl Greedy {
+    pub fn new(rules: Rules) -> Greedy {
+        Greedy { rules: rules }
+    }
+}
+
+impl Strategy for Greedy {
+    fn name(&self) -> String {
+        "greedy".to_string()
+    }
+
+    fn choose(&mut self, state: &State, piece: usize) -> Option<Placed> {
+        placements(state, piece, &self.rules).into_iter()
+            .min_by_key(|(_, s)| {
+                let (w, h) = s.size();
+                (-self.rules.score(s), w * h)
+            })
+            .map(|(p, _)| p)
+    }
+}
+
+// Places each tile as suggested by a Hint with the given budget
+pub struct Lookahead {
+    rules: Rules,
+    budget: Duration,
+}
+
+impl Lookahead {
+    pub fn new(rules: Rules, budget: Duration) -> Lookahead {
+        Lookahead { rules: rules, budget: budget }
+    }
+}
+
+impl Strategy for Lookahead {
+    fn name(&self) -> String {
+        format!("hint:{}", self.budget.as_secs_f64())
+    }
+
+    fn choose(&mut self, state: &State, piece: usize) -> Option<Placed> {
+        Some(Hint::new(self.rules).run(state, piece, self.budget))
+    }
+}
+
+// Places each tile as suggested by a tree search of the rest of the game
+// with the given budget, assuming that every tile not yet drawn is still
+// in the deck
+pub struct Search {
+    rules: Rules,
+    budget: Duration,
+}
+
+impl Search {
+    pub fn new(rules: Rules, budget: Duration) -> Search {
+        Search { rules: rules, budget: budget }
+    }
+}
+
+impl Strategy for Search {
+    fn name(&self) -> String {
+        format!("mcts:{}", self.budget.as_secs_f64())
+    }
+
+    fn choose(&mut self, state: &State, piece: usize) -> Option<Placed> {
+        let remaining = unseen(state, piece, &self.rules);
+        Some(Mcts::new(self.rules)
+            .with_iterations(usize::MAX)
+            .with_budget(self.budget)
+            .choose(state, piece, &remaining))
+    }
+}
+
+// Builds a strategy from its name, as written by Strategy::name
+pub fn parse(s: &str, rules: Rules) -> Result<Box<dyn Strategy + Send>, String> {
+    let mut parts = s.splitn(2, ':');
+    let secs = |arg: Option<&str>| arg
+        .and_then(|a| a.parse::<f64>().ok())
+        .filter(|a| a.is_finite() && *a >= 0.0)
+        .map(Duration::from_secs_f64)
+        .ok_or_else(|| format!("Invalid budget in '{}'", s));
+    match (parts.next().unwrap(), parts.next()) {
+        ("random", seed) => match seed.map(|n| n.parse()) {
+            None => Ok(Box::new(Random::new(rules, 0))),
+            Some(Ok(n)) => Ok(Box::new(Random::new(rules, n))),
+            Some(Err(_)) => Err(format!("Invalid seed in '{}'", s)),
+        },
+        ("greedy", None) => Ok(Box::new(Greedy::new(rules))),
+        ("hint", arg) => Ok(Box::new(Lookahead::new(rules, secs(arg)?))),
+        ("mcts", arg) => Ok(Box::new(Search::new(rules, secs(arg)?))),
+        _ => Err(format!("Unknown strategy '{}' (expected random[:SEED], \
+                          greedy, hint:SECONDS or mcts:SECONDS)", s)),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn greedy() {
+        // Two 0s side by side, which a 1 can bridge
+        let state = State::new().try_place(0, 0, 0).unwrap()
+            .try_place(0, 3, 0).unwrap();
+        let p = Greedy::new(Rules::default()).choose(&state, 1).unwrap();
+        assert_eq!(p.z, 1);
+        let s = state.try_place(p.id(), p.x, p.y)
+            .unwrap();
+        assert_eq!(s.score(), 1);
+    }
+
+    #[test]
+    fn random() {
+        // Every pick is legal, and the same arrangement gets the same pick
+        let rules = Rules::default();
+        let mut r = Random::new(rules, 3);
+        let mut state = State::new();
+        for &t in &[0, 1, 2, 3] {
+            let p = r.choose(&state, t).unwrap();
+            assert_eq!(Some(p), r.choose(&state, t));
+            state = state.try_place(p.id(),
+                                    p.x, p.y).unwrap();
+        }
+        assert_eq!(state.pieces.len(), 4);
+    }
+
+    #[test]
+    fn search() {
+        let state = State::new().try_place(0, 0, 0).unwrap()
+            .try_place(0, 3, 0).unwrap();
+        let mut s = Search::new(Rules::default(), Duration::from_millis(50));
+        let p = s.choose(&state, 1).unwrap();
+        assert_eq!(p.index(), 1);
+    }
+
+    #[test]
+    fn parse() {
+        let rules = Rules::default();
+        assert_eq!(super::parse("greedy", rules).unwrap().name(), "greedy");
+        assert_eq!(super::parse("hint:0.5", rules).unwrap().name(), "hint:0.5");
+        assert_eq!(super::parse("mcts:2", rules).unwrap().name(), "mcts:2");
+        assert_eq!(super::parse("random", rules).unwrap().name(), "random:0");
+        assert_eq!(super::parse("random:7", rules).unwrap().name(), "random:7");
+        assert!(super::parse("hint", rules).is_err());
+        assert!(super::parse("hint:-1", rules).is_err());
+        assert!(super::parse("random:x", rules).is_err());
+        assert!(super::parse("best", rules).is_err());
+    }
+}
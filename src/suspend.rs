@@ -0,0 +1,276 @@
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use bag::Bag;
+use journal::{invalid, read_varint, unzigzag, write_varint, zigzag};
+use piece::{MAX_COPIES, UNIQUE_PIECE_COUNT};
+use rules::Rules;
+use state::{Placed, State};
+use tuning::{MoveOrder, PieceOrder};
+use worker::CancelToken;
+
+// A suspend file is MAGIC and VERSION, the rules' copies, and then each
+// paused search (see Snapshot::write), all as varints except for the
+// seen-sets' hashes, which are 8 bytes each.  Placements and the hashes
+// of states depend on Placed::pack, so VERSION changes along with it.
+const MAGIC: &[u8] = b"NMBR9S";
+const VERSION: u64 = 2;
+
+// A paused search of one bag, which Worker::with_snapshot continues from
+// where it stopped.  The moves are found again on the way back down to
+// each stopping point, so the search must be continued with the same
+// results for the bag's subsets.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    pub bag: Bag,
+
+    // The best arrangement found so far, if it beat the initial score
+    pub best: Option<(isize, State)>,
+
+    // The tuning which sets which moves are tried in which order, and how
+    // the search was split, which the continued search must match
+    pub split_depth: usize,
+    pub order: PieceOrder,
+    pub moves: MoveOrder,
+    pub shared_seen: bool,
+
+    // For each subtree that the search was split into (or the whole
+    // search, if it wasn't split), None if it was finished, or else the
+    // index (in Worker::moves order) of the move taken at each step from
+    // its root down to the node where it stopped
+    pub roots: Vec<Option<Vec<usize>>>,
+
+    // Hashes of the states in each subtree's seen-set (or of the one
+    // shared seen-set), which are left empty if the seen-sets were pruned
+    pub seen: Vec<Vec<u64>>,
+}
+
+impl Snapshot {
+    // Drops the seen-sets, which are most of the snapshot's size.  The
+    // continued search then repeats some work, but finds the same score.
+    pub fn prune(&mut self) {
+        self.seen.clear();
+    }
+
+    fn write<W: Write>(&self, out: &mut W, copies: usize) -> io::Result<()> {
+        let mut fields = vec![
+            self.bag.index(copies) as u64,
+            self.split_depth as u64,
+            match self.order {
+                PieceOrder::Index => 0,
+                PieceOrder::LargestArea => 1,
+                PieceOrder::HighestValue => 2,
+            },
+            match self.moves {
+                MoveOrder::Score => 0,
+                MoveOrder::Bound => 1,
+            },
+            self.shared_seen as u64,
+        ];
+        match self.best {
+            Some((score, ref state)) => {
+                fields.push(1);
+                fields.push(zigzag(score));
+                fields.push(state.pieces.len() as u64);
+                fields.extend(state.pieces.iter().map(|p| p.pack() as u64));
+            },
+            None => fields.push(0),
+        }
+        fields.push(self.roots.len() as u64);
+        for r in &self.roots {
+            match r {
+                Some(path) => {
+                    fields.push(path.len() as u64 + 1);
+                    fields.extend(path.iter().map(|&i| i as u64));
+                },
+                None => fields.push(0),
+            }
+        }
+        fields.push(self.seen.len() as u64);
+        for f in fields {
+            write_varint(out, f)?;
+        }
+        for s in &self.seen {
+            write_varint(out, s.len() as u64)?;
+            for h in s {
+                out.write_all(&h.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read(data: &mut &[u8], rules: &Rules) -> io::Result<Snapshot> {
+        let mut f = || read_varint(data).map(|v| v as usize);
+        let bag = f()?;
+        if bag >= (rules.copies + 1).pow(UNIQUE_PIECE_COUNT as u32) {
+            return Err(invalid("Invalid bag"));
+        }
+        let split_depth = f()?;
+        let order = match f()? {
+            0 => PieceOrder::Index,
+            1 => PieceOrder::LargestArea,
+            2 => PieceOrder::HighestValue,
+            _ => return Err(invalid("Unknown piece order")),
+        };
+        let moves = match f()? {
+            0 => MoveOrder::Score,
+            1 => MoveOrder::Bound,
+            _ => return Err(invalid("Unknown move order")),
+        };
+        let shared_seen = f()? != 0;
+        let best = match f()? {
+            0 => None,
+            _ => {
+                let score = unzigzag(f()? as u64);
+                let n = f()?;
+                if n > UNIQUE_PIECE_COUNT * MAX_COPIES {
+                    return Err(invalid("Too many pieces"));
+                }
+                let mut state = State::with_rules(rules);
+                for _ in 0..n {
+                    state = state.insert(Placed::unpack(f()? as u32));
+                }
+                Some((score, state))
+            },
+        };
+        let mut roots = Vec::new();
+        for _ in 0..f()? {
+            roots.push(match f()? {
+                0 => None,
+                n => Some((1..n).map(|_| f()).collect::<io::Result<_>>()?),
+            });
+        }
+        let mut seen = Vec::new();
+        for _ in 0..f()? {
+            let n = read_varint(data)? as usize;
+            if data.len() < n * 8 {
+                return Err(invalid("Truncated seen-set"));
+            }
+            let (hashes, rest) = data.split_at(n * 8);
+            seen.push(hashes.chunks(8)
+                .map(|c| u64::from_le_bytes(c.try_into().unwrap()))
+                .collect());
+            *data = rest;
+        }
+        Ok(Snapshot { bag: Bag::from_index(bag, rules.copies), best: best,
+                      split_depth: split_depth, order: order, moves: moves,
+                      shared_seen: shared_seen, roots: roots, seen: seen })
+    }
+}
+
+// Writes the paused searches, as made under the given rules
+pub fn write<W: Write>(out: &mut W, snapshots: &[Snapshot], rules: &Rules)
+    -> io::Result<()>
+{
+    out.write_all(MAGIC)?;
+    write_varint(out, VERSION)?;
+    write_varint(out, rules.copies as u64)?;
+    write_varint(out, snapshots.len() as u64)?;
+    for s in snapshots {
+        s.write(out, rules.copies)?;
+    }
+    Ok(())
+}
+
+pub fn read<R: Read>(mut input: R, rules: &Rules) -> io::Result<Vec<Snapshot>> {
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+    let mut rest = &data[..];
+    if !rest.starts_with(MAGIC) {
+        return Err(invalid("Not a suspended search"));
+    }
+    rest = &rest[MAGIC.len()..];
+    if read_varint(&mut rest)? != VERSION {
+        return Err(invalid("Unsupported suspend file version"));
+    }
+    if read_varint(&mut rest)? != rules.copies as u64 {
+        return Err(invalid("Suspended under a different number of copies"));
+    }
+    let n = read_varint(&mut rest)?;
+    (0..n).map(|_| Snapshot::read(&mut rest, rules)).collect()
+}
+
+// Writes the file through a temporary one, as for checkpoints, so that an
+// interrupted write doesn't lose the previous file
+pub fn write_file(path: &Path, snapshots: &[Snapshot], rules: &Rules)
+    -> io::Result<()>
+{
+    let tmp = path.with_extension("tmp");
+    let mut out = BufWriter::new(File::create(&tmp)?);
+    write(&mut out, snapshots, rules)?;
+    out.into_inner()?.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+pub fn read_file(path: &Path, rules: &Rules) -> io::Result<Vec<Snapshot>> {
+    read(File::open(path)?, rules)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn interrupted(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+
+    // A second Ctrl-C stops the program as usual
+    unsafe {
+        libc::signal(libc::SIGINT, libc::SIG_DFL);
+    }
+}
+
+// Cancels the token when the program is interrupted (with Ctrl-C or
+// SIGINT), so that running searches stop and can be suspended
+pub fn cancel_on_interrupt(token: CancelToken) {
+    unsafe {
+        libc::signal(libc::SIGINT,
+                     interrupted as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+    thread::spawn(move || {
+        while !INTERRUPTED.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_millis(100));
+        }
+        status!("Interrupted; suspending running searches");
+        token.cancel();
+    });
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let rules = Rules::default();
+        let state = State::new().try_place(0, 0, 0).unwrap()
+            .try_place(4, 3, 0).unwrap();
+        let snapshot = Snapshot {
+            bag: Bag::from_usize(0).add(0).add(4).add(8),
+            best: Some((3, state)),
+            split_depth: 2,
+            order: PieceOrder::LargestArea,
+            moves: MoveOrder::Bound,
+            shared_seen: false,
+            roots: vec![None, Some(vec![]), Some(vec![3, 0, 12])],
+            seen: vec![vec![], vec![1, u64::MAX], vec![7]],
+        };
+        let mut pruned = snapshot.clone();
+        pruned.prune();
+        pruned.best = None;
+
+        let mut out = Vec::new();
+        write(&mut out, &[snapshot.clone(), pruned.clone()], &rules).unwrap();
+        let found = read(&out[..], &rules).unwrap();
+        assert_eq!(found, vec![snapshot, pruned]);
+
+        assert!(read(&out[..out.len() - 1], &rules).is_err());
+        assert!(read(&b"NMBR9J"[..], &rules).is_err());
+    }
+}
@@ -1,78 +1,243 @@
 use std::collections::{VecDeque, HashMap};
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
-use piece::{UNIQUE_PIECE_COUNT, MAX_ROTATIONS, MAX_EDGE_LENGTH, PIECES};
-use piece::{Piece, Overlap};
+use piece::{UNIQUE_PIECE_COUNT, MAX_EDGE_LENGTH, pieces};
+use piece::{MIRRORED_ID, PIECE_ID_COUNT, shape_of};
+use piece::{Piece, Overlap, Adjacency};
 use state::Placed;
+use numa;
 
 const OVERLAP_SIZE: usize = (2 * MAX_EDGE_LENGTH + 1) as usize;
 lazy_static! {
-    pub static ref OVERLAP_TABLES: Tables = { Tables::build() };
+    pub static ref OVERLAP_TABLES: Tables =
+        Tables::build(Adjacency::Orthogonal, false);
+    pub static ref DIAGONAL_OVERLAP_TABLES: Tables =
+        Tables::build(Adjacency::Diagonal, false);
 }
 
+// Tables which also cover the mirrored pieces.  These take nearly three
+// times as long to build, so they're only built for games which use them.
+static MIRRORED_TABLES: [OnceLock<Tables>; 2] = [OnceLock::new(), OnceLock::new()];
+
+// Optional per-NUMA-node copies of the tables, indexed by node and then
+// as in slot below.  Each copy is built by the first thread on its node
+// to need it, so that its pages are allocated on that node.
+static REPLICAS: OnceLock<Vec<[OnceLock<Tables>; 4]>> = OnceLock::new();
+
+// If set, a directory where built tables are saved, so that later runs
+// with the same pieces and adjacency can load them instead
+static CACHE: OnceLock<PathBuf> = OnceLock::new();
+
+// Marks a saved table file, with the format's version after it
+const CACHE_MAGIC: &[u8] = b"NMBR9TBL";
+const CACHE_VERSION: u32 = 3;
+
+// Size of the entries for one piece id, and of a table for the original
+// 40 pieces; tables with the mirrored pieces are twice as big.
+const ID_SIZE: usize = OVERLAP_SIZE * OVERLAP_SIZE;
+pub const TABLE_SIZE: usize = ID_SIZE * MIRRORED_ID;
+
+// How a table's entries are ordered in memory.  benches/table_layout.rs
+// compares the two on lookup traces from real searches; on the machines
+// tried so far the difference is within noise (the tables for one placed
+// piece fit in cache either way), so x-major stays as the default.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum IndexLayout {
+    XMajor,
+    YMajor,
+}
+
+pub const INDEX_LAYOUT: IndexLayout = IndexLayout::XMajor;
+
+impl IndexLayout {
+    pub fn index(&self, x: i32, y: i32, id: usize) -> usize {
+        debug_assert!(id < PIECE_ID_COUNT);
+        debug_assert!(x <= MAX_EDGE_LENGTH);
+        debug_assert!(x >= -MAX_EDGE_LENGTH);
+        debug_assert!(y <= MAX_EDGE_LENGTH);
+        debug_assert!(y >= -MAX_EDGE_LENGTH);
+
+        let x = (x + MAX_EDGE_LENGTH) as usize;
+        let y = (y + MAX_EDGE_LENGTH) as usize;
+        let (lo, hi) = match *self {
+            IndexLayout::XMajor => (x, y),
+            IndexLayout::YMajor => (y, x),
+        };
+
+        // Each piece id's entries are contiguous, so a search which never
+        // places a mirrored piece only touches the first half of a table
+        // which covers them
+        lo + OVERLAP_SIZE * (hi + OVERLAP_SIZE * id)
+    }
+}
+
+// Each entry is stored as one byte, rather than as a (pointer-sized)
+// Overlap:  0 and 1 for no overlap and a full overlap, and otherwise an
+// index (offset by CODE_PARTIAL) into the table's own list of the partial
+// overlaps that it holds.  One table never holds more than a couple of
+// hundred distinct partial overlaps, so this keeps the hot tables small
+// enough to stay in cache.  Neighbors aren't stored here; they're found
+// with the masks in Tables instead.
+const CODE_NONE: u8 = 0;
+const CODE_FULL: u8 = 1;
+const CODE_PARTIAL: u8 = 2;
+
 pub struct Table {
-    data: [Overlap; OVERLAP_SIZE * OVERLAP_SIZE *
-                    MAX_ROTATIONS * UNIQUE_PIECE_COUNT],
+    codes: Box<[u8]>,
+    partials: Vec<u16>,
 }
 
 impl Table {
-    fn new() -> Table {
-        Table { data: [Overlap::None; OVERLAP_SIZE * OVERLAP_SIZE *
-                                      MAX_ROTATIONS * UNIQUE_PIECE_COUNT] }
+    // Builds an empty table with entries for the given number of piece ids
+    fn new(ids: usize) -> Table {
+        Table { codes: vec![CODE_NONE; ID_SIZE * ids].into_boxed_slice(),
+                partials: Vec::new() }
     }
 
-    fn at(&self, x: i32, y: i32, rot: usize, piece: usize) -> Overlap {
+    fn at(&self, x: i32, y: i32, id: usize) -> Overlap {
         if x > MAX_EDGE_LENGTH || x < -MAX_EDGE_LENGTH ||
            y > MAX_EDGE_LENGTH || y < -MAX_EDGE_LENGTH
         {
-           Overlap::None
-        } else {
-            self.data[Table::index(x, y, rot, piece)]
+           return Overlap::None;
+        }
+        let i = Table::index(x, y, id);
+        match self.codes[i] {
+            CODE_NONE => Overlap::None,
+            CODE_FULL => Overlap::Full,
+            c => Overlap::Partial(
+                self.partials[(c - CODE_PARTIAL) as usize] as usize),
         }
     }
 
-    fn store(&mut self, x: i32, y: i32, rot: usize, piece: usize, d: Overlap) {
-        self.data[Table::index(x, y, rot, piece)] = d;
+    // Stores an entry.  The codes of partial overlaps that the table
+    // already holds are looked up in the given scratch array (indexed by
+    // partial overlap, with CODE_NONE for ones it doesn't hold yet), which
+    // is much faster than searching the table's list while building.
+    fn store(&mut self, x: i32, y: i32, id: usize, d: Overlap,
+             codes: &mut [u8]) {
+        let i = Table::index(x, y, id);
+        self.codes[i] = match d {
+            Overlap::None => CODE_NONE,
+            Overlap::Full => CODE_FULL,
+            Overlap::Partial(p) => {
+                if codes[p] == CODE_NONE {
+                    assert!(self.partials.len() + (CODE_PARTIAL as usize) <=
+                            u8::MAX as usize,
+                            "Too many partial overlaps in one table");
+                    self.partials.push(p as u16);
+                    codes[p] = (self.partials.len() - 1) as u8 + CODE_PARTIAL;
+                }
+                codes[p]
+            },
+            Overlap::Neighbor => CODE_NONE,
+            Overlap::_Partial(_) => panic!("Uncleaned index"),
+        };
     }
 
-    fn index(x: i32, y: i32, rot: usize, piece: usize) -> usize {
-        debug_assert!(piece < UNIQUE_PIECE_COUNT);
-        debug_assert!(rot < MAX_ROTATIONS);
-        debug_assert!(x <= MAX_EDGE_LENGTH);
-        debug_assert!(x >= -MAX_EDGE_LENGTH);
-        debug_assert!(y <= MAX_EDGE_LENGTH);
-        debug_assert!(y >= -MAX_EDGE_LENGTH);
-
-        let x = (x + MAX_EDGE_LENGTH) as usize;
-        let y = (y + MAX_EDGE_LENGTH) as usize;
-
-        x + OVERLAP_SIZE *
-            (y + OVERLAP_SIZE *
-                (rot + MAX_ROTATIONS * piece))
+    fn index(x: i32, y: i32, id: usize) -> usize {
+        INDEX_LAYOUT.index(x, y, id)
     }
 
     pub fn check(&self, x: i32, y: i32, p: &Placed) -> Overlap {
-        self.at(x - p.x, y - p.y, p.rot(), p.index())
+        self.at(x - p.x, y - p.y, p.id())
     }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
-pub struct Tables {
-    // The core 10 pieces, as indices, in their 4 possible rotations
-    pieces: [[usize; MAX_ROTATIONS]; UNIQUE_PIECE_COUNT],
+// Neighbor checks work on a 16-cell-wide window around the candidate piece,
+// packed into a u128 with one row per 16 bits.  The candidate's own cells
+// start at (1, 1), so that its dilated mask fits in the first 6 columns;
+// cells of other pieces that fall off the left edge wrap into the unused
+// columns at the right, so they never match.
+const WINDOW_WIDTH: i32 = 16;
 
-    // Bidirectional mapping from packed bitmaps to indices
+// Packs a 4x4 bitmap into window form, with its corner at the origin
+fn window_shape(bmp: u16) -> u128 {
+    Piece::from_u16(bmp).pts.iter()
+        .fold(0, |m, &(x, y)| m | 1 << (x + y * WINDOW_WIDTH))
+}
+
+// Returns the cells that count as touching the given bitmap, in window form
+fn window_neighbors(bmp: u16, adjacency: Adjacency) -> u128 {
+    let mut out = 0;
+    for &(x, y) in Piece::from_u16(bmp).pts.iter() {
+        for &(nx, ny) in adjacency.offsets() {
+            out |= 1 << (x + nx + 1 + (y + ny + 1) * WINDOW_WIDTH);
+        }
+    }
+    return out;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct Tables {
+    // Bidirectional mapping from packed bitmaps to indices.  A bitmap maps
+    // back to the first index with that shape, which only names partial
+    // overlaps (pure geometry); placed pieces keep their own ids, so two
+    // tiles with one shape are still scored and deduplicated apart.
     bmps: HashMap<usize, u16>,
     ids: HashMap<u16, usize>,
 
-    tables: Vec<Table>
+    tables: Vec<Table>,
+
+    // Shapes and dilated neighbor masks for the pieces which the tables
+    // cover (the original 40, or those and the 40 mirrored ones), used to check whether a piece touches anything on a layer
+    shapes: Vec<u128>,
+    neighbors: Vec<u128>,
 }
 
 impl Tables {
+    // Returns the tables for the given neighbor rules, and with the
+    // mirrored pieces if they're allowed, building them the first time
+    // that they're used
+    pub fn get(adjacency: Adjacency, mirrored: bool) -> &'static Tables {
+        let i = match adjacency {
+            Adjacency::Orthogonal => 0,
+            Adjacency::Diagonal => 1,
+        };
+        if let Some(r) = REPLICAS.get() {
+            return r[numa::current_node()][i + 2 * mirrored as usize]
+                .get_or_init(|| Tables::build(adjacency, mirrored));
+        }
+        if mirrored {
+            return MIRRORED_TABLES[i]
+                .get_or_init(|| Tables::build(adjacency, true));
+        }
+        match adjacency {
+            Adjacency::Orthogonal => &OVERLAP_TABLES,
+            Adjacency::Diagonal => &DIAGONAL_OVERLAP_TABLES,
+        }
+    }
+
+    // Switches to keeping a separate copy of the tables on each of the
+    // given number of NUMA nodes.  This must be called before the tables
+    // are first used.
+    pub fn replicate(nodes: usize) {
+        let r = (0..nodes).map(|_| [OnceLock::new(), OnceLock::new(),
+                                    OnceLock::new(), OnceLock::new()]);
+        if REPLICAS.set(r.collect()).is_err() {
+            panic!("Tables were already replicated");
+        }
+    }
+
+    // Saves built tables in the given directory, and loads them from there
+    // when they've already been saved.  This must be called before the
+    // tables are first used.
+    pub fn cache_in(dir: &Path) {
+        if CACHE.set(dir.to_path_buf()).is_err() {
+            panic!("Table cache was already set");
+        }
+    }
+
     fn store(&mut self, bmp: u16) -> (usize, bool) {
         match self.ids.get(&bmp) {
             None => {
-                let id = self.ids.len();
+                let id = self.bmps.len();
                 self.ids.insert(bmp, id);
                 self.bmps.insert(id, bmp);
                 return (id, true);
@@ -85,97 +250,392 @@ impl Tables {
         &self.tables[piece]
     }
 
-    fn last_table<'a>(&'a mut self) -> &'a mut Table {
+    // Checks whether one of the pieces that the tables cover, placed at
+    // the given position, would touch any of the given pieces
+    pub fn touches(&self, piece: usize, x: i32, y: i32,
+                   layer: &[Placed]) -> bool {
+        let mut occupied = 0;
+        for p in layer {
+            let (dx, dy) = (p.x - x, p.y - y);
+            if dx.abs() > MAX_EDGE_LENGTH || dy.abs() > MAX_EDGE_LENGTH {
+                continue;
+            }
+            let shift = dx + 1 + (dy + 1) * WINDOW_WIDTH;
+            let shape = self.shapes[p.id()];
+            occupied |= if shift >= 0 { shape << shift }
+                        else { shape >> -shift };
+        }
+        return occupied & self.neighbors[piece] != 0;
+    }
+
+    // Returns the packed bitmap for a (possibly partial) piece index
+    pub fn bitmap(&self, piece: usize) -> u16 {
+        self.bmps[&piece]
+    }
+
+    fn last_table(&mut self) -> &mut Table {
         self.tables.last_mut().unwrap()
     }
 
-    fn build() -> Tables {
-        let mut todo = VecDeque::new();
+    // Builds the tables from scratch, ignoring the cache directory and any
+    // tables already built, so that `nmbr9 bench` can time it
+    pub fn build_fresh(adjacency: Adjacency, mirrored: bool) -> Tables {
+        Tables::build_from(&pieces().shapes, adjacency, mirrored)
+    }
 
-        let mut out = Tables {
-            pieces: [[0; MAX_ROTATIONS]; UNIQUE_PIECE_COUNT],
+    fn build(adjacency: Adjacency, mirrored: bool) -> Tables {
+        let shapes = &pieces().shapes;
+        let dir = match CACHE.get() {
+            Some(dir) => dir,
+            None => return Tables::build_from(shapes, adjacency, mirrored),
+        };
+        let path = dir.join(cache_name(shapes, adjacency, mirrored));
+        if let Some(t) = fs::read(&path).ok()
+            .and_then(|b| Tables::from_bytes(&b, shapes, adjacency, mirrored))
+        {
+            debug!("Loaded tables from {}", path.display());
+            return t;
+        }
+        let t = Tables::build_from(shapes, adjacency, mirrored);
+        let tmp = path.with_extension("tmp");
+        match fs::write(&tmp, t.to_bytes(shapes, adjacency, mirrored))
+            .and_then(|_| fs::rename(&tmp, &path))
+        {
+            Ok(()) => debug!("Saved tables to {}", path.display()),
+            Err(e) => warning!("Could not save tables to {}: {}",
+                               path.display(), e),
+        }
+        return t;
+    }
+
+    // Writes the tables as a header (which records the pieces, adjacency,
+    // and mirroring that they were built for), the bitmap of each index,
+    // and then each table's codes and list of partial overlaps
+    fn to_bytes(&self, shapes: &[u16; UNIQUE_PIECE_COUNT],
+                adjacency: Adjacency, mirrored: bool) -> Vec<u8> {
+        let mut out = cache_header(shapes, adjacency, mirrored);
+        out.extend(&(self.tables.len() as u32).to_le_bytes());
+        for i in 0..self.tables.len() {
+            out.extend(&self.bmps[&i].to_le_bytes());
+        }
+        for t in self.tables.iter() {
+            out.extend(&t.codes[..]);
+            out.push(t.partials.len() as u8);
+            for p in t.partials.iter() {
+                out.extend(&p.to_le_bytes());
+            }
+        }
+        return out;
+    }
+
+    // Reads tables written by to_bytes, returning None if they're
+    // malformed or were built for other pieces or other rules
+    fn from_bytes(b: &[u8], shapes: &[u16; UNIQUE_PIECE_COUNT],
+                  adjacency: Adjacency, mirrored: bool) -> Option<Tables> {
+        let header = cache_header(shapes, adjacency, mirrored);
+        if !b.starts_with(&header) {
+            return None;
+        }
+        let mut b = &b[header.len()..];
+        let mut take = |n: usize| -> Option<&[u8]> {
+            if b.len() < n {
+                return None;
+            }
+            let (head, rest) = b.split_at(n);
+            b = rest;
+            return Some(head);
+        };
+        let mut count = [0; 4];
+        count.copy_from_slice(take(4)?);
+        let count = u32::from_le_bytes(count) as usize;
+        let covered = if mirrored { PIECE_ID_COUNT } else { MIRRORED_ID };
+        if count < covered {
+            return None;
+        }
+
+        let mut out = Tables::empty(covered);
+        for (id, c) in take(count * 2)?.chunks(2).enumerate() {
+            let bmp = u16::from_le_bytes([c[0], c[1]]);
+            out.ids.entry(bmp).or_insert(id);
+            out.bmps.insert(id, bmp);
+            if id < covered {
+                out.shapes[id] = window_shape(bmp);
+                out.neighbors[id] = window_neighbors(bmp, adjacency);
+            }
+        }
+        for _ in 0..count {
+            let mut t = Table::new(covered);
+            t.codes.copy_from_slice(take(ID_SIZE * covered)?);
+            let n = take(1)?[0] as usize;
+            t.partials = take(n * 2)?.chunks(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .collect();
+            if t.codes.iter().any(|&c| c as usize >= n + CODE_PARTIAL as usize) ||
+               t.partials.iter().any(|&p| p as usize >= count)
+            {
+                return None;
+            }
+            out.tables.push(t);
+        }
+        if take(1).is_some() {
+            return None;
+        }
+        return Some(out);
+    }
+
+    // Returns tables covering the given number of piece ids, with no
+    // pieces or tables in them yet
+    fn empty(covered: usize) -> Tables {
+        Tables {
             bmps: HashMap::new(),
             ids: HashMap::new(),
             tables: Vec::new(),
-        };
+            shapes: vec![0; covered],
+            neighbors: vec![0; covered],
+        }
+    }
 
-        // Construct the 40 original pieces (10 pieces * 4 rotations)
-        for i in 0..UNIQUE_PIECE_COUNT {
-            let mut p = Piece::from_u16(PIECES[i]);
-            for r in 0..MAX_ROTATIONS {
-                let b = p.to_u16();
-                out.pieces[i][r] = out.store(b).0;
-                todo.push_back(b);
-                p = p.rot();
-            }
+    fn build_from(pieces: &[u16; UNIQUE_PIECE_COUNT],
+                  adjacency: Adjacency, mirrored: bool) -> Tables {
+        let mut todo = VecDeque::new();
+        let covered = if mirrored { PIECE_ID_COUNT } else { MIRRORED_ID };
+        let mut out = Tables::empty(covered);
+
+        // Construct the 40 original pieces (10 pieces * 4 rotations), and
+        // the 40 mirrored ones after them if they're wanted.  Each one gets
+        // its own table, even if a custom set repeats a shape (or a piece
+        // is its own mirror image), so that a piece's id is always its
+        // table's index.
+        for id in 0..covered {
+            let b = shape_of(pieces, id).to_u16();
+            out.ids.entry(b).or_insert(id);
+            out.bmps.insert(id, b);
+            out.shapes[id] = window_shape(b);
+            out.neighbors[id] = window_neighbors(b, adjacency);
+            todo.push_back(b);
         }
-        debug_assert!(todo.len() == MAX_ROTATIONS * UNIQUE_PIECE_COUNT);
 
         // Figure out every pieces that we could put onto one of the original
         // pieces.  In some cases, this produces a new sub-piece, which we add
         // to the queue to be checked in turn.
+        let shapes: Vec<Piece> = (0..covered)
+            .map(|id| shape_of(pieces, id))
+            .collect();
+        let mut codes = vec![CODE_NONE; 1 << 16];
         while let Some(t) = todo.pop_front() {
-            out.tables.push(Table::new());
+            out.tables.push(Table::new(covered));
             let t = Piece::from_u16(t);
 
-            for i in 0..UNIQUE_PIECE_COUNT {
-                let mut p = Piece::from_u16(PIECES[i]);
-                for r in 0..MAX_ROTATIONS {
-                    for x in -MAX_EDGE_LENGTH..=MAX_EDGE_LENGTH {
-                        for y in -MAX_EDGE_LENGTH..=MAX_EDGE_LENGTH {
-                            let mut result = p.check(&t, x, y);
-                            if let Overlap::_Partial(p) = result {
-                                if out.store(p).1 {
-                                    todo.push_back(p);
-                                }
-                                // Tag the result with an index for the overlap,
-                                // rather than the raw bitmap
-                                result = Overlap::Partial(
-                                    *out.ids.get(&p).unwrap());
+            for (id, p) in shapes.iter().enumerate() {
+                for x in -MAX_EDGE_LENGTH..=MAX_EDGE_LENGTH {
+                    for y in -MAX_EDGE_LENGTH..=MAX_EDGE_LENGTH {
+                        let mut result = p.check(&t, x, y, adjacency);
+                        if let Overlap::_Partial(p) = result {
+                            if out.store(p).1 {
+                                todo.push_back(p);
                             }
-
-                            // Then, store it in the table
-                            out.last_table().store(x, y, r, i, result);
+                            // Tag the result with an index for the overlap,
+                            // rather than the raw bitmap
+                            result = Overlap::Partial(
+                                *out.ids.get(&p).unwrap());
                         }
+
+                        // Then, store it in the table
+                        out.last_table().store(x, y, id, result, &mut codes);
                     }
-                    p = p.rot();
                 }
             }
+            for &p in out.tables.last().unwrap().partials.iter() {
+                codes[p as usize] = CODE_NONE;
+            }
         }
+        // Partial overlaps are stored as u16 indices
+        assert!(out.bmps.len() <= 1 << 16, "Too many partial pieces");
         return out;
     }
 }
 
+// Returns the start of a saved table file, which must match exactly for
+// the file to be loaded
+fn cache_header(shapes: &[u16; UNIQUE_PIECE_COUNT],
+                adjacency: Adjacency, mirrored: bool) -> Vec<u8> {
+    let mut out = CACHE_MAGIC.to_vec();
+    out.extend(&CACHE_VERSION.to_le_bytes());
+    out.push(match adjacency {
+        Adjacency::Orthogonal => 0,
+        Adjacency::Diagonal => 1,
+    });
+    out.push(mirrored as u8);
+    for s in shapes.iter() {
+        out.extend(&s.to_le_bytes());
+    }
+    return out;
+}
+
+// Returns the name of the file that tables for the given pieces and
+// rules are saved in.  The name only needs to keep different piece sets
+// apart; the header is checked on loading.
+fn cache_name(shapes: &[u16; UNIQUE_PIECE_COUNT], adjacency: Adjacency,
+              mirrored: bool) -> String
+{
+    let mut hasher = DefaultHasher::new();
+    cache_header(shapes, adjacency, mirrored).hash(&mut hasher);
+    return format!("tables-{:016x}.bin", hasher.finish());
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use piece::Overlap;
+    use piece::{Overlap, PIECES};
 
     #[test]
     fn tables() {
-        assert_eq!(OVERLAP_TABLES.at(0).at(0, 0, 0, 0), Overlap::Full);
-        assert_eq!(OVERLAP_TABLES.at(0).at(3, 0, 0, 0), Overlap::Neighbor);
-        assert_eq!(OVERLAP_TABLES.at(0).at(4, 0, 0, 0), Overlap::None);
-        assert_eq!(OVERLAP_TABLES.at(0).at(-3, 0, 0, 0), Overlap::Neighbor);
-        assert_eq!(OVERLAP_TABLES.at(0).at(-4, 0, 0, 0), Overlap::None);
-        assert_eq!(OVERLAP_TABLES.at(0).at(-5, 0, 0, 0), Overlap::None);
-        assert_eq!(OVERLAP_TABLES.at(0).at(5, 0, 0, 0), Overlap::None);
-        assert_eq!(OVERLAP_TABLES.at(0).at(0, 4, 0, 0), Overlap::Neighbor);
-        assert_eq!(OVERLAP_TABLES.at(0).at(0, -4, 0, 0), Overlap::Neighbor);
-        assert_eq!(OVERLAP_TABLES.at(0).at(0, -3, 0, 0),
+        assert_eq!(OVERLAP_TABLES.at(0).at(0, 0, 0), Overlap::Full);
+        assert_eq!(OVERLAP_TABLES.at(0).at(3, 0, 0), Overlap::None);
+        assert_eq!(OVERLAP_TABLES.at(0).at(4, 0, 0), Overlap::None);
+        assert_eq!(OVERLAP_TABLES.at(0).at(-3, 0, 0), Overlap::None);
+        assert_eq!(OVERLAP_TABLES.at(0).at(-4, 0, 0), Overlap::None);
+        assert_eq!(OVERLAP_TABLES.at(0).at(-5, 0, 0), Overlap::None);
+        assert_eq!(OVERLAP_TABLES.at(0).at(5, 0, 0), Overlap::None);
+        assert_eq!(OVERLAP_TABLES.at(0).at(0, 4, 0), Overlap::None);
+        assert_eq!(OVERLAP_TABLES.at(0).at(0, -4, 0), Overlap::None);
+        assert_eq!(OVERLAP_TABLES.at(0).at(0, -3, 0),
             Overlap::Partial(*OVERLAP_TABLES.ids.get(&0b0000101010101110).unwrap()));
 
         // Overlap a 1 onto a 0 and see that we get the correct pattern out
-        assert_eq!(OVERLAP_TABLES.at(4).at(0, 0, 0, 0),
+        assert_eq!(OVERLAP_TABLES.at(4).at(0, 0, 0),
             Overlap::Partial(*OVERLAP_TABLES.ids.get(&0b0000010001000000).unwrap()));
-        assert_eq!(OVERLAP_TABLES.at(4).at(1, 0, 0, 0), Overlap::Full);
-        assert_eq!(OVERLAP_TABLES.at(4).at(-1, 0, 0, 0),
+        assert_eq!(OVERLAP_TABLES.at(4).at(1, 0, 0), Overlap::Full);
+        assert_eq!(OVERLAP_TABLES.at(4).at(-1, 0, 0),
             Overlap::Partial(*OVERLAP_TABLES.ids.get(&0b1000000000000000).unwrap()));
-        assert_eq!(OVERLAP_TABLES.at(4).at(-1, -1, 0, 0),
+        assert_eq!(OVERLAP_TABLES.at(4).at(-1, -1, 0),
             Overlap::Partial(*OVERLAP_TABLES.ids.get(&0b1000000000000100).unwrap()));
-        assert_eq!(OVERLAP_TABLES.at(4).at(-1, 1, 0, 0),
+        assert_eq!(OVERLAP_TABLES.at(4).at(-1, 1, 0),
             Overlap::Partial(*OVERLAP_TABLES.ids.get(&0b1100000000000000).unwrap()));
     }
+
+    #[test]
+    fn diagonal_tables() {
+        let t = Tables::get(Adjacency::Diagonal, false);
+        assert_eq!(t.at(0).at(0, 0, 0), Overlap::Full);
+        let zero = [Placed::new(0, 0, 0, 0)];
+        assert!(t.touches(0, 3, 0, &zero));
+        assert!(t.touches(0, 3, 4, &zero));
+        assert!(t.touches(0, -3, -4, &zero));
+        assert!(!OVERLAP_TABLES.touches(0, 3, 4, &zero));
+    }
+
+    #[test]
+    fn touches() {
+        // Every piece (mirrored or not) against every other, at every
+        // offset where they don't overlap, should match a direct check of
+        // the bitmaps
+        for &(adjacency, mirrored) in &[(Adjacency::Orthogonal, true),
+                                        (Adjacency::Diagonal, false)] {
+            let t = Tables::get(adjacency, mirrored);
+            let count = t.shapes.len();
+            for a in 0..count {
+                let pa = Piece::from_u16(t.bitmap(a));
+                for b in 0..count {
+                    let pb = Piece::from_u16(t.bitmap(b));
+                    let placed = [Placed::new(b, 2, 3, 0)];
+                    for x in -6..=10 {
+                        for y in -6..=10 {
+                            let expected = pb.check(&pa, x - 2, y - 3,
+                                                    adjacency);
+                            if expected == Overlap::Neighbor ||
+                               expected == Overlap::None
+                            {
+                                assert_eq!(t.touches(a, x, y, &placed),
+                                           expected == Overlap::Neighbor);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn mirrored() {
+        // The mirrored tables agree with the plain ones on the original
+        // pieces, and cover the mirrored pieces after them
+        let t = Tables::get(Adjacency::Orthogonal, true);
+        for a in 0..MIRRORED_ID {
+            assert_eq!(t.bitmap(a), OVERLAP_TABLES.bitmap(a));
+        }
+        for a in 0..PIECE_ID_COUNT {
+            let pa = shape_of(&PIECES, a);
+            assert_eq!(t.bitmap(a), pa.to_u16());
+            for b in 0..PIECE_ID_COUNT {
+                let pb = shape_of(&PIECES, b);
+                for x in -MAX_EDGE_LENGTH..=MAX_EDGE_LENGTH {
+                    for y in -MAX_EDGE_LENGTH..=MAX_EDGE_LENGTH {
+                        let found = match t.at(a).at(x, y, b) {
+                            Overlap::Partial(i) => Overlap::_Partial(t.bitmap(i)),
+                            o => o,
+                        };
+                        match pb.check(&pa, x, y, Adjacency::Orthogonal) {
+                            Overlap::Neighbor => assert_eq!(found, Overlap::None),
+                            o => assert_eq!(found, o),
+                        }
+                    }
+                }
+            }
+        }
+        match t.at(0).at(0, 0, MIRRORED_ID + 4) {
+            Overlap::Partial(i) => assert!(i >= PIECE_ID_COUNT),
+            r => panic!("Unexpected overlap {:?}", r),
+        }
+    }
+
+    #[test]
+    fn cache() {
+        let t = &*OVERLAP_TABLES;
+        let bytes = t.to_bytes(&PIECES, Adjacency::Orthogonal, false);
+        let loaded = Tables::from_bytes(&bytes, &PIECES, Adjacency::Orthogonal,
+                                        false).unwrap();
+        assert_eq!(loaded.to_bytes(&PIECES, Adjacency::Orthogonal, false),
+                   bytes);
+        assert_eq!(loaded.ids, t.ids);
+        assert_eq!(&loaded.shapes[..], &t.shapes[..]);
+        assert_eq!(&loaded.neighbors[..], &t.neighbors[..]);
+
+        // Tables for other rules or pieces, or cut short, aren't loaded
+        assert!(Tables::from_bytes(&bytes, &PIECES, Adjacency::Diagonal, false)
+                .is_none());
+        assert!(Tables::from_bytes(&bytes, &PIECES, Adjacency::Orthogonal, true)
+                .is_none());
+        let mut other = PIECES;
+        other[2] = other[1];
+        assert!(Tables::from_bytes(&bytes, &other, Adjacency::Orthogonal, false)
+                .is_none());
+        assert!(Tables::from_bytes(&bytes[..bytes.len() - 1], &PIECES,
+                                   Adjacency::Orthogonal, false).is_none());
+        assert_ne!(cache_name(&PIECES, Adjacency::Orthogonal, false),
+                   cache_name(&other, Adjacency::Orthogonal, false));
+        assert_ne!(cache_name(&PIECES, Adjacency::Orthogonal, false),
+                   cache_name(&PIECES, Adjacency::Orthogonal, true));
+    }
+
+    #[test]
+    fn duplicate_shapes() {
+        // A custom set where the 1 and the 2 share a shape
+        let mut pieces = PIECES;
+        pieces[2] = pieces[1];
+        let t = Tables::build_from(&pieces, Adjacency::Orthogonal, false);
+        assert_eq!(t.bitmap(4), t.bitmap(8));
+        for &(x, y) in &[(0, 0), (1, 0), (-1, 0), (-1, 1), (3, 0)] {
+            assert_eq!(t.at(4).at(x, y, 0), t.at(8).at(x, y, 0));
+            assert_eq!(t.at(0).at(x, y, 4), t.at(0).at(x, y, 8));
+        }
+
+        // Partial overlaps still get fresh indices past the originals
+        match t.at(4).at(0, 0, 0) {
+            Overlap::Partial(i) => assert!(i >= 40),
+            r => panic!("Unexpected overlap {:?}", r),
+        }
+    }
 }
@@ -0,0 +1,201 @@
+// Goal-directed search for an arrangement which reaches a target score,
+// for when it's enough to know that a score is achievable without proving
+// that it's the best.  Rather than the worker's depth-first search, nodes
+// are expanded best-first by their score plus Results::upper_score_bound
+// for the tiles left in the bag (as in A*), so the search heads for the
+// most promising arrangements and stops at the first one which reaches
+// the target.  Nodes whose bound falls short of the target are dropped,
+// so if the queue runs dry, the target can't be reached.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet};
+use std::time::{Duration, Instant};
+
+use bag::Bag;
+use expectimax::placements;
+use piece::{MAX_ROTATIONS, UNIQUE_PIECE_COUNT};
+use results::Results;
+use rules::Rules;
+use state::{Packed, State};
+
+pub struct TargetSearch<'a> {
+    rules: Rules,
+
+    // If present, solved bags tighten the bound which orders the search
+    results: Option<&'a Results>,
+
+    // If present, a wall-clock budget and the most nodes to expand, after
+    // which the search gives up
+    budget: Option<Duration>,
+    node_limit: Option<usize>,
+
+    // Number of nodes expanded by the last run, and whether it ran out of
+    // time or nodes
+    expanded: usize,
+    stopped: bool,
+}
+
+// A node waiting to be expanded, ordered by the most that it could score,
+// then by its own score, then by the order in which it was found (so that
+// the search is repeatable)
+struct Open {
+    bound: isize,
+    score: isize,
+    order: usize,
+    bag: Bag,
+    state: State,
+}
+
+impl Open {
+    fn key(&self) -> (isize, isize, Reverse<usize>) {
+        (self.bound, self.score, Reverse(self.order))
+    }
+}
+
+impl PartialEq for Open {
+    fn eq(&self, other: &Open) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Open {}
+
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Open) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Open {
+    fn cmp(&self, other: &Open) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+impl<'a> TargetSearch<'a> {
+    pub fn new(rules: Rules) -> TargetSearch<'a> {
+        TargetSearch { rules: rules, results: None, budget: None,
+                       node_limit: None, expanded: 0, stopped: false }
+    }
+
+    pub fn with_results(mut self, results: &'a Results) -> TargetSearch<'a> {
+        assert_eq!(results.rules(), self.rules);
+        self.results = Some(results);
+        self
+    }
+
+    pub fn with_budget(mut self, budget: Duration) -> TargetSearch<'a> {
+        self.budget = Some(budget);
+        self
+    }
+
+    pub fn with_node_limit(mut self, nodes: usize) -> TargetSearch<'a> {
+        self.node_limit = Some(nodes);
+        self
+    }
+
+    // Number of nodes expanded by the last run
+    pub fn expanded(&self) -> usize {
+        self.expanded
+    }
+
+    // Whether the last run gave up, rather than ruling out every
+    // arrangement
+    pub fn stopped(&self) -> bool {
+        self.stopped
+    }
+
+    // Searches for an arrangement of the bag (or of part of it, since the
+    // rest of its tiles could be laid on the table) which scores at least
+    // the target, returning it with its score (which may be higher than
+    // the target).  None means that the target can't be reached, unless
+    // the search was stopped.
+    pub fn run(&mut self, bag: &Bag, target: isize) -> Option<(isize, State)> {
+        let fallback;
+        let results = match self.results {
+            Some(r) => r,
+            None => {
+                fallback = Results::new(self.rules);
+                &fallback
+            },
+        };
+        let start = Instant::now();
+        self.expanded = 0;
+        self.stopped = false;
+
+        let state = State::with_rules(&self.rules);
+        let score = self.rules.score(&state);
+        if score >= target {
+            return Some((score, state));
+        }
+        let mut seen: HashSet<Packed> = HashSet::new();
+        let mut open = BinaryHeap::new();
+        open.push(Open { bound: score + results.upper_score_bound(bag, &state),
+                         score: score, order: 0, bag: bag.clone(),
+                         state: state });
+        let mut order = 1;
+
+        while let Some(node) = open.pop() {
+            if node.bound < target {
+                break;
+            }
+            if self.budget.map(|b| start.elapsed() >= b).unwrap_or(false) ||
+               self.node_limit.map(|n| self.expanded >= n).unwrap_or(false)
+            {
+                self.stopped = true;
+                return None;
+            }
+            self.expanded += 1;
+
+            for tile in (0..UNIQUE_PIECE_COUNT).filter(|&t| node.bag.digit(t) > 0) {
+                let bag = node.bag.take(tile * MAX_ROTATIONS);
+                for (_, s) in placements(&node.state, tile, &self.rules) {
+                    let score = self.rules.score(&s);
+                    if score >= target {
+                        return Some((score, s));
+                    }
+                    if bag.is_empty() || !seen.insert(s.canonical()) {
+                        continue;
+                    }
+                    let bound = score + results.upper_score_bound(&bag, &s);
+                    if bound >= target {
+                        open.push(Open { bound: bound, score: score,
+                                         order: order, bag: bag.clone(),
+                                         state: s });
+                        order += 1;
+                    }
+                }
+            }
+        }
+        return None;
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn target() {
+        // Two 0s can hold up a 1 for a point, but no more
+        let bag = Bag::from_usize(0).add(0).add(0).add(4);
+        let mut search = TargetSearch::new(Rules::default());
+        let (score, state) = search.run(&bag, 1).unwrap();
+        assert_eq!(score, 1);
+        assert_eq!(state.pieces.len(), 3);
+        assert!(state.is_valid());
+        assert!(search.expanded() > 0);
+
+        assert_eq!(search.run(&bag, 2), None);
+        assert!(!search.stopped());
+        assert_eq!(search.run(&bag, 0), Some((0, State::new())));
+
+        // A search which can't expand anything gives up
+        let mut search = TargetSearch::new(Rules::default())
+            .with_node_limit(0);
+        assert_eq!(search.run(&bag, 1), None);
+        assert!(search.stopped());
+    }
+}
@@ -0,0 +1,152 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use bag::Bag;
+use watchdog::Progress;
+
+// Columns of the telemetry file, which is a CSV file with one row for each
+// bag that a solver finishes:
+//
+//   bag         count of each tile, as one digit per tile
+//   pieces      number of tiles in the bag
+//   seconds     wall-clock time of the search
+//   nodes       nodes expanded
+//   bounded     subtrees cut off because their bound couldn't beat the
+//               best score
+//   repeated    states skipped because they were already searched
+//   dominated   states skipped as dominated (see Dominance)
+//   seen_bytes  the most memory used by any one seen-set, in bytes
+//   dominance_bytes
+//               the most memory used by any one dominance table, in bytes
+//               (0 unless skipping dominated states)
+//   score       the best score found
+//   exact       whether the score is exact, rather than a lower bound from
+//               a search which ran out of time or hit a limit
+//
+// Over a sweep, this is a record of what made each bag hard to solve.
+const HEADER: &str = "bag,pieces,seconds,nodes,bounded,repeated,dominated,\
+                      seen_bytes,dominance_bytes,score,exact";
+
+// One finished search, as a row of the telemetry file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Row {
+    pub bag: Bag,
+    pub elapsed: Duration,
+    pub nodes: usize,
+    pub bounded: usize,
+    pub repeated: usize,
+    pub dominated: usize,
+    pub seen_bytes: usize,
+    pub dominance_bytes: usize,
+    pub score: isize,
+    pub exact: bool,
+}
+
+impl Row {
+    // Collects a row from the counters of a finished search
+    pub fn new(bag: &Bag, progress: &Progress, elapsed: Duration,
+               score: isize, exact: bool) -> Row {
+        let (bounded, repeated, dominated) = progress.prunes();
+        Row {
+            bag: bag.clone(),
+            elapsed: elapsed,
+            nodes: progress.nodes(),
+            bounded: bounded,
+            repeated: repeated,
+            dominated: dominated,
+            seen_bytes: progress.seen_bytes(),
+            dominance_bytes: progress.dominance_bytes(),
+            score: score,
+            exact: exact,
+        }
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "{},{},{:.3},{},{},{},{},{},{},{},{}",
+                 self.bag.digits(), self.bag.len(),
+                 self.elapsed.as_secs_f64(), self.nodes,
+                 self.bounded, self.repeated, self.dominated,
+                 self.seen_bytes, self.dominance_bytes, self.score, self.exact)
+    }
+}
+
+// An append-only telemetry file, shared by every thread of a solver
+pub struct Telemetry {
+    file: Mutex<File>,
+}
+
+impl Telemetry {
+    // Opens a telemetry file for appending, creating it (with a header
+    // line) if it doesn't exist
+    pub fn open(path: &Path) -> io::Result<Telemetry> {
+        let mut file = OpenOptions::new().append(true).create(true)
+            .open(path)?;
+        if file.metadata()?.len() == 0 {
+            writeln!(file, "{}", HEADER)?;
+        }
+        return Ok(Telemetry { file: Mutex::new(file) });
+    }
+
+    // Appends one row.  Each is written with a single call, so rows from
+    // different threads don't interleave.
+    pub fn append(&self, row: &Row) -> io::Result<()> {
+        let mut line = Vec::new();
+        row.write(&mut line)?;
+        self.file.lock().unwrap().write_all(&line)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+    use std::sync::RwLock;
+    use results::Results;
+    use rules::Rules;
+    use worker::Worker;
+
+    #[test]
+    fn append() {
+        let path = std::env::temp_dir()
+            .join(format!("nmbr9-telemetry-{}", process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        // Two 0s and a 1, whose smaller bags bound the search
+        let results = RwLock::new(Results::new(Rules::default()));
+        let bag = Bag::from_usize(0).add(0).add(0).add(4);
+        let subsets = results.read().unwrap().bags().into_iter()
+            .filter(|b| bag.contains(b) && *b != bag);
+        for b in subsets {
+            results.write().unwrap().write_score(&b, 0, None);
+        }
+        let mut worker = Worker::new(bag.clone(), &results);
+        let (score, _) = worker.run().unwrap();
+        let row = Row::new(&bag, &worker.progress(),
+                           Duration::from_millis(1500), score, true);
+        assert!(row.nodes > 0);
+        assert!(row.seen_bytes > 0);
+        {
+            let t = Telemetry::open(&path).unwrap();
+            t.append(&row).unwrap();
+        }
+        {
+            // Reopening appends rather than truncating, without repeating
+            // the header
+            let t = Telemetry::open(&path).unwrap();
+            t.append(&row).unwrap();
+        }
+        let text = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], HEADER);
+        assert!(lines[1].starts_with("2100000000,3,1.500,"), "{}", lines[1]);
+        assert!(lines[1].ends_with(",1,true"), "{}", lines[1]);
+        assert_eq!(lines[1], lines[2]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,256 @@
+use std::sync::OnceLock;
+
+use colored::Color;
+
+use image::{Rgb, PIECE_RGB};
+use piece::{UNIQUE_PIECE_COUNT, PIECE_COLORS};
+
+// Colors for drawing tiles, shared by every renderer:  the terminal views
+// in State, PNG images and score cards, the HTML browser, and OpenSCAD
+// exports.  Terminal colors and RGB colors are kept side by side, since
+// the terminal only has its own palette of 16 colors (which depends on the
+// user's terminal settings, and so has no exact RGB equivalent).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Theme {
+    pub terminal: [Color; UNIQUE_PIECE_COUNT],
+    pub rgb: [Rgb; UNIQUE_PIECE_COUNT],
+
+    // Behind and between the layers of an image, and its text
+    pub background: Rgb,
+    pub label: Rgb,
+}
+
+// Names of the built-in themes, for --theme
+pub const NAMES: [&str; 3] = ["default", "colorblind", "high-contrast"];
+
+// Paul Tol's "muted" scheme, whose colors stay distinct under the common
+// forms of color blindness (with pale grey for the 0).  The terminal can't
+// match it, so it gets the closest colors which avoid red-green pairs.
+const COLORBLIND_TERMINAL: [Color; UNIQUE_PIECE_COUNT] = [
+    Color::White,
+    Color::BrightMagenta,
+    Color::Blue,
+    Color::BrightYellow,
+    Color::Green,
+    Color::BrightCyan,
+    Color::Magenta,
+    Color::Cyan,
+    Color::Yellow,
+    Color::BrightBlue,
+];
+const COLORBLIND: [Rgb; UNIQUE_PIECE_COUNT] = [
+    [221, 221, 221],
+    [204, 102, 119],
+    [51, 34, 136],
+    [221, 204, 119],
+    [17, 119, 51],
+    [136, 204, 238],
+    [136, 34, 85],
+    [68, 170, 153],
+    [153, 153, 51],
+    [170, 68, 153],
+];
+
+// Fully saturated colors which alternate between light and dark, drawn on
+// black
+const HIGH_CONTRAST_TERMINAL: [Color; UNIQUE_PIECE_COUNT] = [
+    Color::BrightWhite,
+    Color::BrightYellow,
+    Color::Blue,
+    Color::BrightCyan,
+    Color::BrightRed,
+    Color::BrightGreen,
+    Color::Magenta,
+    Color::Yellow,
+    Color::BrightMagenta,
+    Color::BrightBlack,
+];
+const HIGH_CONTRAST: [Rgb; UNIQUE_PIECE_COUNT] = [
+    [255, 255, 255],
+    [255, 255, 0],
+    [0, 0, 255],
+    [0, 255, 255],
+    [255, 0, 0],
+    [0, 255, 0],
+    [160, 0, 255],
+    [255, 160, 0],
+    [255, 0, 255],
+    [128, 128, 128],
+];
+
+// Terminal colors by name, for --tile-colors, with the RGB used to draw
+// them in images (the same as PIECE_RGB, where they overlap)
+const NAMED: [(&str, Color, Rgb); 16] = [
+    ("black", Color::Black, [0, 0, 0]),
+    ("red", Color::Red, [205, 49, 49]),
+    ("green", Color::Green, [13, 188, 121]),
+    ("yellow", Color::Yellow, [205, 205, 0]),
+    ("blue", Color::Blue, [36, 114, 200]),
+    ("magenta", Color::Magenta, [188, 63, 188]),
+    ("cyan", Color::Cyan, [17, 168, 205]),
+    ("white", Color::White, [229, 229, 229]),
+    ("bright-black", Color::BrightBlack, [102, 102, 102]),
+    ("bright-red", Color::BrightRed, [241, 76, 76]),
+    ("bright-green", Color::BrightGreen, [35, 209, 139]),
+    ("bright-yellow", Color::BrightYellow, [245, 245, 67]),
+    ("bright-blue", Color::BrightBlue, [59, 142, 234]),
+    ("bright-magenta", Color::BrightMagenta, [214, 112, 214]),
+    ("bright-cyan", Color::BrightCyan, [41, 184, 219]),
+    ("bright-white", Color::BrightWhite, [255, 255, 255]),
+];
+
+static THEME: OnceLock<Theme> = OnceLock::new();
+
+pub fn theme() -> &'static Theme {
+    THEME.get_or_init(Theme::standard)
+}
+
+// Switches every later drawing to the given theme.  This must be called
+// before anything is drawn.
+pub fn use_theme(theme: Theme) {
+    if THEME.set(theme).is_err() {
+        panic!("A theme was already in use");
+    }
+}
+
+// Parses a color, as either #rrggbb or one of the terminal colors in
+// NAMED.  An RGB color is drawn in the terminal as the nearest of the
+// terminal's colors.
+fn parse_color(s: &str) -> Result<(Color, Rgb), String> {
+    let s = s.trim();
+    if let Some(hex) = s.strip_prefix('#') {
+        let channel = |i: usize| hex.get(i..i + 2)
+            .and_then(|c| u8::from_str_radix(c, 16).ok());
+        return match (hex.len(), channel(0), channel(2), channel(4)) {
+            (6, Some(r), Some(g), Some(b)) => Ok((nearest([r, g, b]), [r, g, b])),
+            _ => Err(format!("Invalid color '{}' (expected #rrggbb)", s)),
+        };
+    }
+    let name = s.to_lowercase().replace('_', "-");
+    return NAMED.iter().find(|c| c.0 == name)
+        .map(|c| (c.1, c.2))
+        .ok_or_else(|| format!("Unknown color '{}'", s));
+}
+
+// The terminal color whose RGB equivalent (in NAMED) is closest
+fn nearest(c: Rgb) -> Color {
+    let dist = |d: &Rgb| (0..3)
+        .map(|i| (c[i] as i32 - d[i] as i32).pow(2))
+        .sum::<i32>();
+    return NAMED.iter().min_by_key(|n| dist(&n.2)).unwrap().1;
+}
+
+impl Theme {
+    pub fn standard() -> Theme {
+        Theme {
+            terminal: PIECE_COLORS,
+            rgb: PIECE_RGB,
+            background: [24, 24, 32],
+            label: [140, 140, 160],
+        }
+    }
+
+    // Looks up one of the built-in themes (see NAMES)
+    pub fn named(name: &str) -> Option<Theme> {
+        match name {
+            "default" => Some(Theme::standard()),
+            "colorblind" => Some(Theme {
+                terminal: COLORBLIND_TERMINAL,
+                rgb: COLORBLIND,
+                background: [24, 24, 32],
+                label: [170, 170, 190],
+            }),
+            "high-contrast" => Some(Theme {
+                terminal: HIGH_CONTRAST_TERMINAL,
+                rgb: HIGH_CONTRAST,
+                background: [0, 0, 0],
+                label: [255, 255, 255],
+            }),
+            _ => None,
+        }
+    }
+
+    // Replaces the colors of some tiles, given as a comma-separated list
+    // with one color per tile (see parse_color), where an empty entry
+    // keeps the tile's color from the theme, e.g. ",,#ff8000" for tile 2
+    pub fn with_colors(mut self, s: &str) -> Result<Theme, String> {
+        let colors: Vec<&str> = s.split(',').collect();
+        if colors.len() > UNIQUE_PIECE_COUNT {
+            return Err(format!("Expected at most {} colors, got {}",
+                               UNIQUE_PIECE_COUNT, colors.len()));
+        }
+        for (i, c) in colors.into_iter().enumerate() {
+            if !c.trim().is_empty() {
+                let (terminal, rgb) = parse_color(c)?;
+                self.terminal[i] = terminal;
+                self.rgb[i] = rgb;
+            }
+        }
+        return Ok(self);
+    }
+
+    // Black or white, whichever is easier to read on the given tile's color
+    pub fn text(&self, tile: usize) -> Color {
+        let [r, g, b] = self.rgb[tile];
+        let luma = 299 * r as u32 + 587 * g as u32 + 114 * b as u32;
+        if luma > 128 * 1000 {
+            Color::Black
+        } else {
+            Color::White
+        }
+    }
+
+    // Formats a tile's color as #rrggbb, for HTML
+    pub fn hex(&self, tile: usize) -> String {
+        let c = self.rgb[tile];
+        format!("#{:02x}{:02x}{:02x}", c[0], c[1], c[2])
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn named() {
+        for name in NAMES.iter() {
+            let t = Theme::named(name).unwrap();
+            // Each built-in theme gives every tile a different color, in
+            // the terminal and in images
+            for i in 0..UNIQUE_PIECE_COUNT {
+                for j in 0..i {
+                    assert_ne!(t.rgb[i], t.rgb[j], "{} {} {}", name, i, j);
+                    assert_ne!(t.terminal[i], t.terminal[j],
+                               "{} {} {}", name, i, j);
+                }
+            }
+        }
+        assert_eq!(Theme::named("default"), Some(Theme::standard()));
+        assert_eq!(Theme::named("rainbow"), None);
+    }
+
+    #[test]
+    fn with_colors() {
+        let t = Theme::standard().with_colors(",#FF8000, bright-blue").unwrap();
+        assert_eq!(t.terminal[0], PIECE_COLORS[0]);
+        assert_eq!(t.rgb[1], [255, 128, 0]);
+        assert_eq!(t.terminal[1], Color::Yellow);
+        assert_eq!(t.terminal[2], Color::BrightBlue);
+        assert_eq!(t.hex(1), "#ff8000");
+        assert_eq!(t.rgb[3], PIECE_RGB[3]);
+
+        assert!(Theme::standard().with_colors("#12345").is_err());
+        assert!(Theme::standard().with_colors("#gg0000").is_err());
+        assert!(Theme::standard().with_colors("chartreuse").is_err());
+        assert!(Theme::standard().with_colors(",,,,,,,,,,red").is_err());
+    }
+
+    #[test]
+    fn text() {
+        let t = Theme::standard();
+        assert_eq!(t.text(0), Color::Black);
+        assert_eq!(t.text(6), Color::White);
+    }
+}
@@ -0,0 +1,84 @@
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use rayon;
+use serde_json;
+
+// A completed span of work on one thread
+struct Span {
+    name: String,
+    thread: usize,
+    start: u64,
+    duration: u64,
+    args: Vec<(&'static str, isize)>,
+}
+
+// Collects spans from worker threads, to be written out in the Chrome
+// trace-event format (loadable in chrome://tracing or Perfetto) with one
+// track per rayon thread.
+pub struct Trace {
+    origin: Instant,
+    spans: Mutex<Vec<Span>>,
+}
+
+impl Trace {
+    pub fn new() -> Trace {
+        Trace { origin: Instant::now(), spans: Mutex::new(Vec::new()) }
+    }
+
+    // Records a span which began at the given time and ends now, on the
+    // calling thread's track
+    pub fn record(&self, name: String, begin: Instant,
+                  args: Vec<(&'static str, isize)>) {
+        let start = begin.duration_since(self.origin).as_micros() as u64;
+        let duration = begin.elapsed().as_micros() as u64;
+        let span = Span {
+            name: name,
+            thread: rayon::current_thread_index().unwrap_or(0),
+            start: start,
+            duration: duration,
+            args: args,
+        };
+        self.spans.lock().unwrap().push(span);
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let spans = self.spans.lock().unwrap();
+        writeln!(out, "[")?;
+        for (i, s) in spans.iter().enumerate() {
+            let args: Vec<String> = s.args.iter()
+                .map(|&(k, v)| format!("\"{}\": {}", k, v))
+                .collect();
+            write!(out, "{{\"name\": {}, \"ph\": \"X\", \"pid\": 0, \
+                         \"tid\": {}, \"ts\": {}, \"dur\": {}, \
+                         \"args\": {{{}}}}}",
+                   serde_json::to_string(&s.name).unwrap(), s.thread, s.start, s.duration,
+                   args.join(", "))?;
+            writeln!(out, "{}", if i + 1 < spans.len() { "," } else { "" })?;
+        }
+        writeln!(out, "]")
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write() {
+        let trace = Trace::new();
+        trace.record("a \"bag\"\n".to_owned(), Instant::now(),
+                     vec![("pieces", 3), ("score", -1)]);
+        trace.record("b".to_owned(), Instant::now(), vec![]);
+
+        let mut out = Vec::new();
+        trace.write(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("[\n{\"name\": \"a \\\"bag\\\"\\n\", \"ph\": \"X\""));
+        assert!(out.contains("\"args\": {\"pieces\": 3, \"score\": -1}},\n"));
+        assert!(out.contains("\"args\": {}}\n]"));
+    }
+}
@@ -0,0 +1,619 @@
+use std::collections::HashSet;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::mem::{size_of, take};
+use std::sync::{Mutex, MutexGuard, TryLockError};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bag::Bag;
+use piece::{Values, pieces};
+use state::{Packed, State};
+
+// How the worker remembers which states it has already searched
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Dedup {
+    // Store every state in full
+    Exact,
+
+    // Store a 64-bit hash of each state.  This uses a fraction of the
+    // memory, at the cost of a tiny chance of skipping a state whose
+    // hash collides with one that was already searched.
+    Hashed,
+
+    // Store states in a fixed-size Bloom filter, which never grows but
+    // skips more states by mistake as it fills up.  A search which falls
+    // back to one only records its score as a lower bound.
+    Bloom,
+
+    // Store 64-bit hashes, as for Hashed, in two generations.  When the
+    // newer one fills half of the memory budget, the older one is thrown
+    // away, so states may be searched again but aren't skipped by mistake.
+    Evicting,
+}
+
+// Which pieces the worker tries first, among placements which score
+// equally well.  A good incumbent prunes the rest of the search, so it
+// pays to find one early.  This only breaks ties in the order of the
+// moves:  every placement is still tried (or pruned by a bound which
+// holds whatever the order), so it can't change the score found.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PieceOrder {
+    // In order of the piece's index
+    Index,
+
+    // Largest pieces first, since they support more above them
+    LargestArea,
+
+    // Most valuable pieces first, so they reach the high layers of good
+    // solutions sooner
+    HighestValue,
+}
+
+impl PieceOrder {
+    // Returns a sort key for the given piece, with lower values first
+    pub fn rank(&self, piece: usize, values: &Values) -> isize {
+        match *self {
+            PieceOrder::Index => piece as isize,
+            PieceOrder::LargestArea => -(pieces().areas[piece] as isize),
+            PieceOrder::HighestValue => -values[piece],
+        }
+    }
+}
+
+// How the worker orders the placements that it tries from each state
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MoveOrder {
+    // Highest scoring first, then the most compact
+    Score,
+
+    // Highest upper bound on the final score first (from the placement's
+    // score plus Results::upper_score_bound for the rest of the bag), then
+    // as for Score.  Bounds cost more to find, but the most promising
+    // branches are searched first.
+    Bound,
+}
+
+// What to minimize among arrangements of a whole bag which reach the same
+// score, when picking which one to report
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Tiebreak {
+    // The area of the arrangement's bounding box
+    Footprint,
+
+    // The number of layers, including the ground
+    Layers,
+}
+
+impl Tiebreak {
+    pub fn cost(&self, state: &State) -> isize {
+        match *self {
+            Tiebreak::Footprint => {
+                let (w, h) = state.size();
+                (w * h) as isize
+            },
+            Tiebreak::Layers if state.is_empty() => 0,
+            Tiebreak::Layers => state.layers() as isize + 1,
+        }
+    }
+}
+
+// Search parameters for a particular bag
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Tuning {
+    pub dedup: Dedup,
+
+    // How many moves to expand up front before searching the resulting
+    // subtrees in parallel (0 searches the bag on a single thread)
+    pub split_depth: usize,
+
+    // Rough memory budget (in bytes) for each worker's seen-set.  Past
+    // this, the worker falls back to the next cheaper dedup strategy.
+    pub seen_limit: usize,
+
+    // If set, a hashed seen-set which passes its budget starts evicting
+    // old states (Dedup::Evicting), rather than becoming a Bloom filter
+    pub evict: bool,
+
+    pub order: PieceOrder,
+    pub moves: MoveOrder,
+
+    // If set, the subtrees of a split search share one seen-set (see
+    // SharedSeen), rather than each keeping its own
+    pub shared_seen: bool,
+
+    // If set, states which are dominated by one already searched are
+    // skipped too (see Dominance).  The table gets a budget of seen_limit
+    // bytes on top of the seen-set's, so this is off by default.
+    pub dominance: bool,
+}
+
+impl Tuning {
+    // Small bags are cheap enough to solve exactly on one thread; larger
+    // bags branch so heavily that their seen-sets dominate memory, and
+    // they're the stragglers at the end of each batch, so they're split
+    // up across the thread pool.
+    //
+    // Ordering moves by their bounds costs a bound per placement, which
+    // only pays off once there are enough tiles left for a good first
+    // guess to prune much of the tree.  It was a quarter faster over the
+    // 5-tile bags measured, and within a fifth either way on larger ones
+    // (winning more often than not); on smaller bags the two orders tie.
+    pub fn for_bag(bag: &Bag) -> Tuning {
+        Tuning::for_len(bag.len())
+    }
+
+    // As for_bag, for any bag with the given number of pieces
+    pub fn for_len(pieces: usize) -> Tuning {
+        let (dedup, split_depth) = match pieces {
+            0..=9 => (Dedup::Exact, 0),
+            10..=13 => (Dedup::Exact, 1),
+            _ => (Dedup::Hashed, 2),
+        };
+        let moves = if pieces >= 5 { MoveOrder::Bound } else { MoveOrder::Score };
+        Tuning {
+            dedup: dedup,
+            split_depth: split_depth,
+            seen_limit: 1 << 30,
+            evict: false,
+            order: PieceOrder::HighestValue,
+            moves: moves,
+            shared_seen: false,
+            dominance: false,
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Rough memory used by each state in a seen-set:  its size plus an
+// allowance for the hash table's overhead.  Bloom filters have a fixed
+// size, so they don't grow with each state.
+pub fn entry_bytes(dedup: Dedup) -> usize {
+    match dedup {
+        Dedup::Exact => size_of::<Packed>() + 8,
+        Dedup::Hashed | Dedup::Evicting => size_of::<u64>() + 8,
+        Dedup::Bloom => 0,
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Number of bits set for each state in the Bloom filter
+const BLOOM_PROBES: usize = 4;
+
+struct Bloom {
+    bits: Vec<u64>,
+}
+
+impl Bloom {
+    fn new(bytes: usize) -> Bloom {
+        Bloom { bits: vec![0; (bytes / 8).max(1)] }
+    }
+
+    // Double hashing, deriving each probe from two halves of the hash.
+    // This is called for every state, so the probes go in an array rather
+    // than being collected into a Vec.
+    fn probes(&self, h: u64) -> [usize; BLOOM_PROBES] {
+        let n = self.bits.len() as u64 * 64;
+        let step = h.rotate_left(32) | 1;
+        let mut out = [0; BLOOM_PROBES];
+        for (i, b) in out.iter_mut().enumerate() {
+            *b = (h.wrapping_add((i as u64).wrapping_mul(step)) % n) as usize;
+        }
+        return out;
+    }
+
+    fn contains(&self, h: u64) -> bool {
+        self.probes(h).iter().all(|&b| self.bits[b / 64] & (1 << (b % 64)) != 0)
+    }
+
+    fn insert(&mut self, h: u64) {
+        for b in self.probes(h) {
+            self.bits[b / 64] |= 1 << (b % 64);
+        }
+    }
+}
+
+enum SeenSet {
+    Exact(HashSet<Packed>),
+    Hashed(HashSet<u64>),
+    Bloom(Bloom),
+    // The newer generation, then the older one
+    Evicting(HashSet<u64>, HashSet<u64>),
+}
+
+// Records which states have already been searched, downgrading to a
+// cheaper strategy if it grows past its memory budget.
+pub struct Seen {
+    set: SeenSet,
+    limit: usize,
+    evict: bool,
+
+    // Estimated memory in use, kept up to date by insert (see measure),
+    // and the most that the set has used, in bytes
+    bytes: usize,
+    peak: usize,
+}
+
+impl Seen {
+    pub fn new(dedup: Dedup, limit: usize) -> Seen {
+        let set = match dedup {
+            Dedup::Exact => SeenSet::Exact(HashSet::new()),
+            Dedup::Hashed => SeenSet::Hashed(HashSet::new()),
+            Dedup::Bloom => SeenSet::Bloom(Bloom::new(limit / 2)),
+            Dedup::Evicting =>
+                SeenSet::Evicting(HashSet::new(), HashSet::new()),
+        };
+        let mut seen = Seen { set: set, limit: limit, evict: false,
+                              bytes: 0, peak: 0 };
+        seen.bytes = seen.measure();
+        seen
+    }
+
+    // Builds the seen-set for the given search parameters
+    pub fn for_tuning(tuning: &Tuning) -> Seen {
+        let mut seen = Seen::new(tuning.dedup, tuning.seen_limit);
+        seen.evict = tuning.evict;
+        seen
+    }
+
+    pub fn dedup(&self) -> Dedup {
+        match self.set {
+            SeenSet::Exact(_) => Dedup::Exact,
+            SeenSet::Hashed(_) => Dedup::Hashed,
+            SeenSet::Bloom(_) => Dedup::Bloom,
+            SeenSet::Evicting(..) => Dedup::Evicting,
+        }
+    }
+
+    // Hashing a state is the same as hashing its packed form, so exact
+    // sets can be downgraded to hashed ones
+    fn hash<T: Hash>(state: &T) -> u64 {
+        let mut h = DefaultHasher::new();
+        state.hash(&mut h);
+        h.finish()
+    }
+
+    // Estimates memory use from the number of states held (see
+    // entry_bytes), allowing an evicting set's older generation as much as
+    // its newer one.  Only rebuilt sets are measured from scratch; insert
+    // adds each new state's share to self.bytes instead.
+    fn measure(&self) -> usize {
+        match self.set {
+            SeenSet::Exact(ref s) => s.len() * entry_bytes(Dedup::Exact),
+            SeenSet::Hashed(ref s) => s.len() * entry_bytes(Dedup::Hashed),
+            SeenSet::Bloom(ref b) => b.bits.len() * 8,
+            SeenSet::Evicting(ref new, _) =>
+                2 * new.len() * entry_bytes(Dedup::Hashed),
+        }
+    }
+
+    // States are looked up by their canonical form (see State::canonical),
+    // so an arrangement counts as seen if it was searched in any of its
+    // four turns
+    pub fn contains(&self, key: &Packed) -> bool {
+        match self.set {
+            SeenSet::Exact(ref s) => s.contains(key),
+            SeenSet::Hashed(ref s) => s.contains(&Seen::hash(key)),
+            SeenSet::Bloom(ref b) => b.contains(Seen::hash(key)),
+            SeenSet::Evicting(ref new, ref old) => {
+                let h = Seen::hash(key);
+                new.contains(&h) || old.contains(&h)
+            },
+        }
+    }
+
+    pub fn insert(&mut self, key: Packed) {
+        let added = match self.set {
+            SeenSet::Exact(ref mut s) => s.insert(key),
+            SeenSet::Hashed(ref mut s) => s.insert(Seen::hash(&key)),
+            SeenSet::Bloom(ref mut b) => {
+                b.insert(Seen::hash(&key));
+                false
+            },
+            SeenSet::Evicting(ref mut new, _) => new.insert(Seen::hash(&key)),
+        };
+        if added {
+            self.bytes += match self.set {
+                SeenSet::Evicting(..) => 2 * entry_bytes(Dedup::Evicting),
+                _ => entry_bytes(self.dedup()),
+            };
+        }
+        self.peak = self.peak.max(self.bytes);
+        if self.bytes > self.limit {
+            self.downgrade();
+        }
+    }
+
+    // Returns the most memory that the set has used, in bytes, as
+    // estimated when states were inserted
+    pub fn peak_bytes(&self) -> usize {
+        self.peak
+    }
+
+    // Returns the hashes of every state in the set, to be saved with a
+    // suspended search.  A Bloom filter can't list its states, so it gives
+    // none (and the continued search repeats their work).
+    pub fn hashes(&self) -> Vec<u64> {
+        match self.set {
+            SeenSet::Exact(ref s) => s.iter().map(Seen::hash).collect(),
+            SeenSet::Hashed(ref s) => s.iter().cloned().collect(),
+            SeenSet::Bloom(_) => Vec::new(),
+            SeenSet::Evicting(ref new, ref old) =>
+                new.iter().chain(old.iter()).cloned().collect(),
+        }
+    }
+
+    // Adds states by their hashes (as from Seen::hashes).  An exact set
+    // is first downgraded, since it can't hold hashes.
+    pub fn extend(&mut self, hashes: &[u64]) {
+        if let SeenSet::Exact(ref s) = self.set {
+            self.set = SeenSet::Hashed(s.iter().map(Seen::hash).collect());
+        }
+        for &h in hashes {
+            match self.set {
+                SeenSet::Exact(_) => unreachable!(),
+                SeenSet::Hashed(ref mut s) => { s.insert(h); },
+                SeenSet::Bloom(ref mut b) => b.insert(h),
+                SeenSet::Evicting(ref mut new, _) => { new.insert(h); },
+            }
+        }
+        self.bytes = self.measure();
+        self.peak = self.peak.max(self.bytes);
+        if self.bytes > self.limit {
+            self.downgrade();
+        }
+    }
+
+    // Moves everything into the next cheaper kind of set
+    fn downgrade(&mut self) {
+        let next = match self.set {
+            SeenSet::Exact(ref s) =>
+                SeenSet::Hashed(s.iter().map(Seen::hash).collect()),
+            SeenSet::Hashed(ref mut s) if self.evict =>
+                SeenSet::Evicting(HashSet::new(), take(s)),
+            SeenSet::Hashed(ref s) => {
+                let mut b = Bloom::new(self.limit / 2);
+                for &h in s.iter() {
+                    b.insert(h);
+                }
+                SeenSet::Bloom(b)
+            },
+            SeenSet::Bloom(_) => return,
+            SeenSet::Evicting(ref mut new, ref mut old) => {
+                *old = take(new);
+                debug!("Seen-set passed {} bytes; evicted old states",
+                       self.limit);
+                self.bytes = 0;
+                return;
+            },
+        };
+        status!("Seen-set passed {} bytes; switching from {:?}",
+                 self.limit, self.dedup());
+        self.set = next;
+        self.bytes = self.measure();
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Number of separately-locked parts of a SharedSeen, so that threads
+// rarely wait on each other
+const SHARDS: usize = 64;
+
+// A seen-set shared by every worker searching one bag's subtrees.  The
+// workers also share their incumbent score, so a state which one of them
+// has searched never needs to be searched again by another.  States are
+// stored as 64-bit hashes, as for Dedup::Hashed.
+//
+// It isn't shared between bags, and doesn't store scores.  A node of the
+// search is a state along with the tiles left to place, which together
+// make up the bag being solved, so two bags' searches never reach the
+// same node; what they do share is subsets, whose best scores are already
+// in Results (and bound every search through it).  Within one bag, a node
+// which has been searched can't lead to a better score than the shared
+// incumbent when it's reached again, so one bit per state is enough.  A
+// stored best remaining score wouldn't be reliable anyway, since the
+// first visit's subtree may have been cut short by the bounds.
+pub struct SharedSeen {
+    shards: Vec<Mutex<HashSet<u64>>>,
+
+    // How many times a shard was locked, and how many of those had to wait
+    // for another thread to release it
+    locks: AtomicU64,
+    waits: AtomicU64,
+}
+
+impl SharedSeen {
+    pub fn new() -> SharedSeen {
+        SharedSeen { shards: (0..SHARDS).map(|_| Mutex::new(HashSet::new()))
+                                        .collect(),
+                     locks: AtomicU64::new(0),
+                     waits: AtomicU64::new(0) }
+    }
+
+    // Locks the shard which holds the given hash, counting contention
+    fn shard(&self, h: u64) -> MutexGuard<'_, HashSet<u64>> {
+        let shard = &self.shards[(h >> 58) as usize % SHARDS];
+        self.locks.fetch_add(1, Ordering::Relaxed);
+        match shard.try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::WouldBlock) => {
+                self.waits.fetch_add(1, Ordering::Relaxed);
+                shard.lock().unwrap()
+            },
+            Err(TryLockError::Poisoned(e)) => panic!("{}", e),
+        }
+    }
+
+    // As for Seen, states are looked up by their canonical form
+    pub fn contains(&self, key: &Packed) -> bool {
+        let h = Seen::hash(key);
+        self.shard(h).contains(&h)
+    }
+
+    pub fn insert(&self, key: &Packed) {
+        let h = Seen::hash(key);
+        self.shard(h).insert(h);
+    }
+
+    // Returns how many times a shard was locked, and how many of those
+    // waited on another thread
+    pub fn contention(&self) -> (u64, u64) {
+        (self.locks.load(Ordering::Relaxed), self.waits.load(Ordering::Relaxed))
+    }
+
+    // As for Seen::hashes and Seen::extend
+    pub fn hashes(&self) -> Vec<u64> {
+        self.shards.iter()
+            .flat_map(|s| s.lock().unwrap().iter().cloned().collect::<Vec<_>>())
+            .collect()
+    }
+
+    pub fn extend(&self, hashes: &[u64]) {
+        for &h in hashes {
+            self.shard(h).insert(h);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|s| s.lock().unwrap().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use state::Placed;
+    use piece::PIECE_VALUES;
+
+    #[test]
+    fn seen() {
+        let a = State::new().insert(Placed::new(0, 0, 0, 0));
+        let b = State::new().insert(Placed::new(4, 0, 0, 0));
+        for &d in &[Dedup::Exact, Dedup::Hashed, Dedup::Bloom,
+                   Dedup::Evicting] {
+            let mut seen = Seen::new(d, 1 << 20);
+            assert!(!seen.contains(&a.canonical()));
+            seen.insert(a.canonical());
+            assert!(seen.contains(&a.canonical()));
+            assert!(!seen.contains(&b.canonical()));
+
+            // The same arrangement, turned, has already been searched
+            assert!(seen.contains(&a.rotated().canonical()));
+        }
+    }
+
+    #[test]
+    fn shared_seen() {
+        let a = State::new().insert(Placed::new(0, 0, 0, 0));
+        let b = State::new().insert(Placed::new(4, 0, 0, 0));
+        let seen = SharedSeen::new();
+        seen.insert(&a.canonical());
+        seen.insert(&a.canonical());
+        assert!(seen.contains(&a.canonical()));
+        assert!(!seen.contains(&b.canonical()));
+        assert!(seen.contains(&a.rotated().rotated().canonical()));
+        assert_eq!(seen.len(), 1);
+
+        // On one thread, no lock ever waits
+        assert_eq!(seen.contention(), (5, 0));
+
+        let copy = SharedSeen::new();
+        copy.extend(&seen.hashes());
+        assert!(copy.contains(&a.canonical()));
+        assert_eq!(copy.len(), 1);
+    }
+
+    #[test]
+    fn hashes() {
+        // Saved hashes restore the same states, even into an exact set
+        let a = State::new().insert(Placed::new(0, 0, 0, 0));
+        let b = State::new().insert(Placed::new(4, 0, 0, 0));
+        for &d in &[Dedup::Exact, Dedup::Hashed, Dedup::Evicting] {
+            let mut seen = Seen::new(d, 1 << 20);
+            seen.insert(a.canonical());
+
+            let mut copy = Seen::new(d, 1 << 20);
+            copy.extend(&seen.hashes());
+            assert!(copy.contains(&a.canonical()));
+            assert!(!copy.contains(&b.canonical()));
+        }
+        assert!(Seen::new(Dedup::Bloom, 1 << 20).hashes().is_empty());
+    }
+
+    #[test]
+    fn for_bag() {
+        let small = Bag::from_usize(0).add(0).add(4);
+        assert_eq!(Tuning::for_bag(&small).split_depth, 0);
+        assert_eq!(Tuning::for_bag(&small).moves, MoveOrder::Score);
+
+        let mut full = Bag::from_usize(0);
+        for i in 0..10 {
+            full = full.add(i * 4).add(i * 4 + 1);
+        }
+        assert_eq!(Tuning::for_bag(&full).dedup, Dedup::Hashed);
+        assert_eq!(Tuning::for_bag(&full).split_depth, 2);
+        assert_eq!(Tuning::for_bag(&full).moves, MoveOrder::Bound);
+    }
+
+    #[test]
+    fn order() {
+        let mut values = PIECE_VALUES;
+        values[0] = 10;
+        assert!(PieceOrder::Index.rank(0, &values) <
+                PieceOrder::Index.rank(9, &values));
+        assert!(PieceOrder::HighestValue.rank(0, &values) <
+                PieceOrder::HighestValue.rank(9, &values));
+        assert!(PieceOrder::LargestArea.rank(0, &values) <
+                PieceOrder::LargestArea.rank(1, &values));
+    }
+
+    #[test]
+    fn downgrade() {
+        // Build a pile of distinct states, by sliding a piece around
+        let states: Vec<State> = (0..1000)
+            .map(|i| State::new()
+                .insert(Placed::new(0, 0, 0, 0))
+                .insert(Placed::new(4, 3 + i % 30, i / 30, 0)))
+            .collect();
+
+        // 1000 hashed states need about 16 KB, so this budget downgrades
+        // the set twice
+        let mut seen = Seen::new(Dedup::Exact, 1 << 13);
+        for s in states.iter() {
+            seen.insert(s.canonical());
+        }
+        assert_eq!(seen.dedup(), Dedup::Bloom);
+        assert!(states.iter().all(|s| seen.contains(&s.canonical())));
+    }
+
+    #[test]
+    fn evict() {
+        let states: Vec<State> = (0..1000)
+            .map(|i| State::new()
+                .insert(Placed::new(0, 0, 0, 0))
+                .insert(Placed::new(4, 3 + i % 30, i / 30, 0)))
+            .collect();
+
+        // Past its budget, the set forgets its oldest states instead of
+        // growing or guessing
+        let tuning = Tuning { dedup: Dedup::Hashed, seen_limit: 1 << 12,
+                              evict: true, ..Tuning::for_len(0) };
+        let mut seen = Seen::for_tuning(&tuning);
+        for s in states.iter() {
+            seen.insert(s.canonical());
+        }
+        assert_eq!(seen.dedup(), Dedup::Evicting);
+        assert!(seen.bytes <= 1 << 12);
+        assert_eq!(seen.bytes, seen.measure());
+        assert!(seen.contains(&states.last().unwrap().canonical()));
+        assert!(!seen.contains(&states[0].canonical()));
+    }
+}
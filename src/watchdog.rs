@@ -0,0 +1,217 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, AtomicU64, AtomicIsize, Ordering};
+use std::time::{Duration, Instant};
+
+use bag::Bag;
+use piece::{MAX_COPIES, UNIQUE_PIECE_COUNT};
+
+// Counters which a worker updates as it searches, so that a watchdog
+// thread can see how it's getting on
+pub struct Progress {
+    bag: Bag,
+    start: Instant,
+
+    // Nodes expanded, and the moves generated from them
+    nodes: AtomicUsize,
+    moves: AtomicUsize,
+
+    // Nodes expanded at each depth (i.e. number of pieces placed)
+    depths: Vec<AtomicUsize>,
+
+    best: AtomicIsize,
+
+    // When the best score last improved, in microseconds since start
+    improved: AtomicU64,
+
+    // Subtrees cut off by their bound, states skipped because they were
+    // already searched, and states skipped as dominated
+    bounded: AtomicUsize,
+    repeated: AtomicUsize,
+    dominated: AtomicUsize,
+
+    // The most memory used by any one seen-set searching the bag, in bytes
+    seen_bytes: AtomicUsize,
+
+    // The most memory used by any one dominance table, in bytes
+    dominance_bytes: AtomicUsize,
+
+    // Node count as of the watchdog's last check
+    checked: AtomicUsize,
+}
+
+impl Progress {
+    pub fn new(bag: Bag) -> Progress {
+        Progress {
+            bag: bag,
+            start: Instant::now(),
+            nodes: AtomicUsize::new(0),
+            moves: AtomicUsize::new(0),
+            depths: (0..=UNIQUE_PIECE_COUNT * MAX_COPIES)
+                .map(|_| AtomicUsize::new(0)).collect(),
+            best: AtomicIsize::new(0),
+            improved: AtomicU64::new(0),
+            bounded: AtomicUsize::new(0),
+            repeated: AtomicUsize::new(0),
+            dominated: AtomicUsize::new(0),
+            seen_bytes: AtomicUsize::new(0),
+            dominance_bytes: AtomicUsize::new(0),
+            checked: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn expanded(&self, depth: usize, moves: usize) {
+        self.nodes.fetch_add(1, Ordering::Relaxed);
+        self.moves.fetch_add(moves, Ordering::Relaxed);
+        self.depths[depth].fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Returns the number of nodes expanded so far
+    pub fn nodes(&self) -> usize {
+        self.nodes.load(Ordering::Relaxed)
+    }
+
+    pub fn bounded(&self) {
+        self.bounded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn repeated(&self) {
+        self.repeated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dominated(&self) {
+        self.dominated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Returns how many subtrees were cut off by their bound, skipped as
+    // already searched, and skipped as dominated
+    pub fn prunes(&self) -> (usize, usize, usize) {
+        (self.bounded.load(Ordering::Relaxed),
+         self.repeated.load(Ordering::Relaxed),
+         self.dominated.load(Ordering::Relaxed))
+    }
+
+    // Records a seen-set's memory use, keeping the largest
+    pub fn seen(&self, bytes: usize) {
+        self.seen_bytes.fetch_max(bytes, Ordering::Relaxed);
+    }
+
+    pub fn seen_bytes(&self) -> usize {
+        self.seen_bytes.load(Ordering::Relaxed)
+    }
+
+    // Records a dominance table's memory use, keeping the largest
+    pub fn dominance(&self, bytes: usize) {
+        self.dominance_bytes.fetch_max(bytes, Ordering::Relaxed);
+    }
+
+    pub fn dominance_bytes(&self) -> usize {
+        self.dominance_bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn improved(&self, score: isize) {
+        self.best.fetch_max(score, Ordering::Relaxed);
+        let t = self.start.elapsed().as_micros() as u64;
+        self.improved.store(t, Ordering::Relaxed);
+    }
+
+    // Time since the best score last improved
+    fn idle(&self) -> Duration {
+        let t = Duration::from_micros(self.improved.load(Ordering::Relaxed));
+        self.start.elapsed().saturating_sub(t)
+    }
+
+    // Prints the bag, depth profile, and frontier statistics
+    fn dump(&self) {
+        let nodes = self.nodes.load(Ordering::Relaxed);
+        let moves = self.moves.load(Ordering::Relaxed);
+        status!("STALLED: {} pieces in the {:?}", self.bag.len(), self.bag);
+        status!("  best score {}, unchanged for {:?}",
+                 self.best.load(Ordering::Relaxed), self.idle());
+        status!("  {} nodes expanded, none since the last check", nodes);
+        let depths: Vec<String> = self.depths.iter().enumerate()
+            .map(|(d, n)| (d, n.load(Ordering::Relaxed)))
+            .filter(|&(_, n)| n > 0)
+            .map(|(d, n)| format!("{}:{}", d, n))
+            .collect();
+        status!("  nodes by depth: {}", depths.join(" "));
+        if nodes > 0 {
+            status!("  average branching factor {:.1}",
+                     moves as f64 / nodes as f64);
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Keeps track of running workers, and reports any which have neither
+// improved their best score within the given period nor expanded a node
+// since the last check.  A search which is still expanding nodes is making
+// progress, even if it's a long time between better scores.
+pub struct Watchdog {
+    period: Duration,
+    active: Mutex<Vec<Arc<Progress>>>,
+}
+
+impl Watchdog {
+    pub fn new(period: Duration) -> Watchdog {
+        Watchdog { period: period, active: Mutex::new(Vec::new()) }
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    pub fn add(&self, p: Arc<Progress>) {
+        self.active.lock().unwrap().push(p);
+    }
+
+    pub fn remove(&self, p: &Arc<Progress>) {
+        self.active.lock().unwrap().retain(|q| !Arc::ptr_eq(p, q));
+    }
+
+    // Dumps diagnostics for every stalled worker, returning how many
+    // were found
+    pub fn check(&self) -> usize {
+        let mut stalled = 0;
+        for p in self.active.lock().unwrap().iter() {
+            let nodes = p.nodes.load(Ordering::Relaxed);
+            let prev = p.checked.swap(nodes, Ordering::Relaxed);
+            if p.idle() >= self.period && nodes == prev {
+                p.dump();
+                stalled += 1;
+            }
+        }
+        return stalled;
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check() {
+        let dog = Watchdog::new(Duration::from_millis(0));
+        let p = Arc::new(Progress::new(Bag::from_usize(0).add(0)));
+        p.expanded(0, 3);
+        p.improved(2);
+        dog.add(p.clone());
+
+        // Expanding nodes counts as progress, even without a better score
+        assert_eq!(dog.check(), 0);
+        assert_eq!(p.checked.load(Ordering::Relaxed), 1);
+        assert_eq!(dog.check(), 1);
+        p.expanded(1, 2);
+        assert_eq!(dog.check(), 0);
+        assert_eq!(dog.check(), 1);
+
+        dog.remove(&p);
+        assert_eq!(dog.check(), 0);
+
+        let dog = Watchdog::new(Duration::from_secs(3600));
+        dog.add(p.clone());
+        assert_eq!(dog.check(), 0);
+    }
+}
@@ -1,91 +1,1686 @@
+use std::cell::OnceCell;
 use std::collections::{HashSet, BTreeMap};
-use std::sync::RwLock;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicIsize, Ordering};
+use std::time::Instant;
+use rayon::prelude::*;
 
-use results::Results;
+use results::{Bounds, Results};
 use bag::Bag;
-use piece::MAX_EDGE_LENGTH;
-use state::State;
+use piece::{UNIQUE_PIECE_COUNT, MAX_ROTATIONS, pieces, tile};
+use state::{Packed, Placed, State};
+use rules::Rules;
+use rng::Rng;
+use scoring::Scoring;
+use tuning::{Tuning, Seen, SharedSeen, PieceOrder, MoveOrder, Tiebreak,
+             Dedup, entry_bytes};
+use watchdog::Progress;
+use bitboard::Bitboards;
+use overlay::Overlay;
+use profile::{Profile, Step, SAMPLE_EVERY};
+use porcelain;
+use logging::{self, Level};
+use journal::{Journal, Event, Header};
+use dominance::Dominance;
+use footprint::{Footprint, PlacementCache, SupportTable};
+use suspend::Snapshot;
+
+// A shared flag which asks running workers to stop early.  Cloning the
+// token gives another handle to the same flag.
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
 
 pub struct Worker<'a> {
-    target: usize,
-    best_score: usize,
-    best_state: State,
+    target: Bag,
+
+    // The best score found by any worker on this bag, shared between the
+    // workers that split up its subtrees
+    incumbent: Arc<AtomicIsize>,
+
+    // The best arrangement found by this worker, if it beat the incumbent
+    best: Option<(isize, State)>,
+
+    // How many of the highest-scoring distinct arrangements of the whole
+    // bag to keep (if any), and those found so far, best first
+    leaders: usize,
+    leaderboard: Vec<(isize, State)>,
+
+    // Canonical forms of the arrangements which have been on the
+    // leaderboard, so that turned copies are only offered once
+    leader_keys: HashSet<Packed>,
+
+    // If present, what to minimize among arrangements of the whole bag with
+    // the best score, and the best such arrangement found so far
+    tiebreak: Option<Tiebreak>,
+    compact: Option<(isize, State)>,
+
+    // If present, the bag's known best score, in which case the leaderboard
+    // collects every distinct arrangement reaching it (see with_optimum)
+    optimum: Option<isize>,
+
     results: &'a RwLock<Results>,
-    seen: HashSet<State>,
+
+    // The results' bounds, which are read at every node without locking
+    bounds: Arc<Bounds>,
+
+    rules: Rules,
+    tuning: Tuning,
+    seen: Seen,
+
+    // If present, the seen-set shared with the other workers which split
+    // up this bag's subtrees, used instead of the worker's own
+    shared: Option<Arc<SharedSeen>>,
+
+    // States searched so far, by their remaining bag and footprint, if
+    // dominated states are skipped (see with_dominance)
+    dominance: Dominance,
+
+    // If present, placements already found for each footprint, shared
+    // with the other workers in a sweep
+    placement_cache: Option<Arc<PlacementCache>>,
+
+    // If present, the tiles which can rest on each top layer, shared with
+    // the other workers in a sweep, for bounding the last tile of the bag
+    support: Option<Arc<SupportTable>>,
+
+    cancel: CancelToken,
+    progress: Arc<Progress>,
+
+    // If present, cancels the search (as through the token) once this
+    // many nodes have been expanded
+    cancel_after: Option<usize>,
+
+    // If present, when to give up and record a partial result
+    deadline: Option<Instant>,
+
+    // Set if any part of the search was cut short by the deadline
+    timed_out: Arc<AtomicBool>,
+
+    // If present, the most nodes to expand, and the most pieces to place
+    // before giving up on a branch
+    node_limit: Option<usize>,
+    depth_limit: Option<usize>,
+
+    // Set if any part of the search was cut short by either limit
+    limited: Arc<AtomicBool>,
+
+    // If present, where to show each new best arrangement
+    overlay: Option<Arc<Overlay>>,
+
+    // If present, shuffles moves which score the same, so that searches
+    // with different seeds explore the tree in different orders
+    seed: Option<u64>,
+
+    // If present, where to record (or replay) each step of the search
+    journal: Option<Arc<Mutex<Journal>>>,
+
+    // If present, the order in which tiles must be placed, as in the real
+    // game (where they're placed as cards are drawn from a deck)
+    draw: Option<Arc<Vec<usize>>>,
+
+    // If present, only arrangements with exactly this many layers
+    // (including the ground) count towards the best score
+    layers: Option<usize>,
+
+    // If present, a game in progress to search from, in which case the
+    // target bag holds the tiles left to place
+    start: Option<State>,
+
+    // If present, where to record samples of how long each node's steps
+    // take, and the number of nodes visited towards the next sample
+    profile: Option<Arc<Profile>>,
+    ticks: usize,
+
+    // The index of the move taken at each step from the search's root to
+    // the current node, and where the search was when it was cancelled
+    path: Vec<usize>,
+    stopped: Option<Vec<usize>>,
+
+    // If present, a paused search to continue (see with_snapshot), and the
+    // snapshot made when this search was cancelled (see suspended)
+    resume: Option<Snapshot>,
+    suspended: Option<Snapshot>,
 }
 
 impl<'a> Worker<'a> {
-    pub fn new(target: usize, results: &'a RwLock<Results>) -> Worker<'a> {
+    pub fn new(target: Bag, results: &'a RwLock<Results>) -> Worker<'a> {
+        let tuning = Tuning::for_bag(&target);
         Worker {
-            target: target,
-            best_score: 0,
-            best_state: State::new(),
+            incumbent: Arc::new(AtomicIsize::new(0)),
+            best: None,
+            leaders: 0,
+            leaderboard: Vec::new(),
+            leader_keys: HashSet::new(),
+            tiebreak: None,
+            compact: None,
+            optimum: None,
             results: results,
-            seen: HashSet::new(),
+            bounds: results.read().unwrap().bounds(),
+            rules: results.read().unwrap().rules(),
+            tuning: tuning,
+            seen: Seen::for_tuning(&tuning),
+            shared: None,
+            dominance: Dominance::new(tuning.seen_limit),
+            placement_cache: None,
+            support: None,
+            cancel: CancelToken::new(),
+            progress: Arc::new(Progress::new(target.clone())),
+            cancel_after: None,
+            deadline: None,
+            timed_out: Arc::new(AtomicBool::new(false)),
+            node_limit: None,
+            depth_limit: None,
+            limited: Arc::new(AtomicBool::new(false)),
+            overlay: None,
+            seed: None,
+            journal: None,
+            draw: None,
+            layers: None,
+            start: None,
+            profile: None,
+            ticks: 0,
+            path: Vec::new(),
+            stopped: None,
+            resume: None,
+            suspended: None,
+            target: target,
         }
     }
 
-    pub fn run(&mut self) {
-        let bag = Bag::from_usize(self.target);
-        self.best_score = self.results.read().unwrap().upper_subset_score(&bag);
-        println!("Running with {} pieces in the {:?},\nand initial best score {}", bag.len(), bag, self.best_score);
-        self.run_(bag, State::new());
+    // Stops the search at the given time, recording the best score found
+    // so far as a lower bound rather than as the bag's score
+    pub fn with_deadline(mut self, deadline: Instant) -> Worker<'a> {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn timed_out(&self) -> bool {
+        self.timed_out.load(Ordering::Relaxed)
+    }
+
+    // Stops expanding nodes once this many have been expanded (across
+    // every thread), recording the best score found as a lower bound
+    pub fn with_node_limit(mut self, nodes: usize) -> Worker<'a> {
+        self.node_limit = Some(nodes);
+        self
+    }
+
+    // Stops expanding arrangements with this many pieces, recording the
+    // best score found as a lower bound
+    pub fn with_depth_limit(mut self, depth: usize) -> Worker<'a> {
+        self.depth_limit = Some(depth);
+        self
+    }
+
+    pub fn limited(&self) -> bool {
+        self.limited.load(Ordering::Relaxed)
+    }
+
+    // Keeps the n highest-scoring arrangements of the whole bag which are
+    // distinct up to turning the table, recording them in the results.
+    // Subtrees can then only be pruned if they can't reach the lowest of
+    // these, so the search is slower.
+    pub fn with_leaders(mut self, n: usize) -> Worker<'a> {
+        self.leaders = n;
+        self
+    }
+
+    // Collects every arrangement of the whole bag which reaches the given
+    // score (which should be the bag's best), distinct up to turning the
+    // table, as its leaderboard.  Only subtrees which can't reach the score
+    // are pruned, so this is slower than the solve which found it.
+    pub fn with_optimum(mut self, score: isize) -> Worker<'a> {
+        self.optimum = Some(score);
+        self.leaders = usize::MAX;
+        self
+    }
+
+    // Reports the arrangement of the whole bag which has the best score
+    // and the lowest cost by the tiebreak.  Subtrees which can only tie the
+    // best score are then searched too, so this is slower.
+    pub fn with_tiebreak(mut self, tiebreak: Tiebreak) -> Worker<'a> {
+        self.tiebreak = Some(tiebreak);
+        self
+    }
+
+    pub fn leaderboard(&self) -> &[(isize, State)] {
+        &self.leaderboard
+    }
+
+    // Returns the counters which this worker updates as it searches
+    pub fn progress(&self) -> Arc<Progress> {
+        self.progress.clone()
+    }
+
+    // Overrides the order in which pieces are tried
+    pub fn with_order(mut self, order: PieceOrder) -> Worker<'a> {
+        self.tuning.order = order;
+        self
+    }
+
+    // Overrides the order in which placements are tried.  Journaled
+    // searches always use MoveOrder::Score, since replays don't have the
+    // results that bounds come from.
+    pub fn with_move_order(mut self, moves: MoveOrder) -> Worker<'a> {
+        self.tuning.moves = moves;
+        self
+    }
+
+    // Caps each seen-set's memory use (in bytes), and if evict is set,
+    // throws away old states past the cap rather than switching to a
+    // Bloom filter (see Tuning)
+    pub fn with_seen_limit(mut self, bytes: usize, evict: bool)
+        -> Worker<'a>
+    {
+        self.tuning.seen_limit = bytes;
+        self.tuning.evict = evict;
+        self.seen = Seen::for_tuning(&self.tuning);
+        self.dominance = Dominance::new(bytes);
+        self
+    }
+
+    // Times the bound and placements of one node in every SAMPLE_EVERY
+    pub fn with_profile(mut self, profile: Arc<Profile>) -> Worker<'a> {
+        self.profile = Some(profile);
+        self
+    }
+
+    // Overrides how many moves are expanded before the search is split
+    // across threads (0 keeps it on one thread, so that it always takes
+    // the same steps)
+    pub fn with_split_depth(mut self, depth: usize) -> Worker<'a> {
+        self.tuning.split_depth = depth;
+        self
+    }
+
+    // Shares one seen-set between the subtrees of a split search
+    pub fn with_shared_seen(mut self, shared: bool) -> Worker<'a> {
+        self.tuning.shared_seen = shared;
+        self
+    }
+
+    // Skips states which can't beat one with the same tiles left that was
+    // already searched (see Dominance).  This is ignored when keeping a
+    // leaderboard, which needs every distinct arrangement, under scoring
+    // which depends on more than each tile's layer, and in journaled
+    // searches, whose headers don't record it.
+    pub fn with_dominance(mut self, dominance: bool) -> Worker<'a> {
+        self.tuning.dominance = dominance;
+        self
+    }
+
+    // Looks up (and records) each footprint's placements in the given
+    // cache, which must have been built for the same neighbor rules
+    pub fn with_placement_cache(mut self, cache: Arc<PlacementCache>)
+        -> Worker<'a>
+    {
+        self.placement_cache = Some(cache);
+        self
+    }
+
+    // Skips states where the bag's last tile can't beat the best score,
+    // given whether it can rest on the top layer (see SupportTable).  The
+    // table must have been built for the same neighbor rules.
+    pub fn with_support_table(mut self, table: Arc<SupportTable>)
+        -> Worker<'a>
+    {
+        self.support = Some(table);
+        self
+    }
+
+    // Lets the worker be stopped early through the given token
+    pub fn with_cancel(mut self, cancel: CancelToken) -> Worker<'a> {
+        self.cancel = cancel;
+        self
+    }
+
+    // Cancels the search once it has expanded this many nodes (across
+    // every thread), at the same point each time it's run on one thread,
+    // which makes a suspended search reproducible
+    pub fn with_cancel_after(mut self, nodes: usize) -> Worker<'a> {
+        self.cancel_after = Some(nodes);
+        self
+    }
+
+    // Continues a search which was cancelled (see suspended), taking the
+    // snapshot's tuning so that it tries the same moves in the same order
+    pub fn with_snapshot(mut self, snapshot: Snapshot) -> Worker<'a> {
+        assert_eq!(snapshot.bag, self.target, "Snapshot doesn't match the bag");
+        self.tuning.split_depth = snapshot.split_depth;
+        self.tuning.order = snapshot.order;
+        self.tuning.moves = snapshot.moves;
+        self.tuning.shared_seen = snapshot.shared_seen;
+        self.resume = Some(snapshot);
+        self
+    }
+
+    // Returns a snapshot of where the search stopped, if it was cancelled,
+    // which with_snapshot can continue later.  Searches which keep
+    // leaderboards or tiebreaks, follow a draw order or seed, need an
+    // exact number of layers, start from a game in progress, or are
+    // journaled can't be suspended, since their snapshots would need more
+    // than the best arrangement and where each subtree stopped.
+    pub fn suspended(&mut self) -> Option<Snapshot> {
+        self.suspended.take()
+    }
+
+    fn suspendable(&self) -> bool {
+        self.leaders == 0 && self.tiebreak.is_none() && self.draw.is_none() &&
+            self.layers.is_none() && self.start.is_none() &&
+            self.seed.is_none() && self.journal.is_none()
+    }
+
+    // Sends each new best arrangement to the given overlay
+    pub fn with_overlay(mut self, overlay: Arc<Overlay>) -> Worker<'a> {
+        self.overlay = Some(overlay);
+        self
+    }
+
+    // Randomizes the order of moves which score the same.  The order
+    // only depends on the seed, so the search is still repeatable.
+    pub fn with_seed(mut self, seed: u64) -> Worker<'a> {
+        self.seed = Some(seed);
+        self
+    }
+
+    // Overrides the search parameters, e.g. to match a journal's
+    pub fn with_tuning(mut self, tuning: Tuning) -> Worker<'a> {
+        self.tuning = tuning;
+        self.seen = Seen::for_tuning(&tuning);
+        self
+    }
+
+    // Records every step of the search to the given journal, or replays
+    // the steps that it holds.  Journaled searches run on one thread, so
+    // that they always take the same steps.
+    pub fn with_journal(mut self, journal: Journal) -> Worker<'a> {
+        self.journal = Some(Arc::new(Mutex::new(journal)));
+        self
+    }
+
+    // Forces tiles (0-9) to be placed in the given order, which must hold
+    // exactly the target bag's tiles.  The best score under a forced order
+    // is usually lower than the bag's best score, so it's not written to
+    // the results, and subsets' scores aren't used as a starting point;
+    // the results still bound the search, since a forced order can't beat
+    // a free one.
+    pub fn with_draw_order(mut self, order: Vec<usize>) -> Worker<'a> {
+        let mut bag = self.target.clone();
+        for &t in &order {
+            bag = bag.try_take(t * MAX_ROTATIONS)
+                .expect("Draw order doesn't match the bag");
+        }
+        assert!(bag.is_empty(), "Draw order doesn't match the bag");
+        self.draw = Some(Arc::new(order));
+        self
+    }
+
+    // Only counts arrangements whose top tile is on the highest of exactly
+    // n layers, e.g. to find the best of the tallest towers that a bag can
+    // build.  Taller arrangements are ruled out by capping the layers, as
+    // with Rules::max_layers, and shallower ones are pruned once the bag
+    // has too few tiles left to build up to the top layer.  As with a draw
+    // order, the best score is usually lower than the bag's, so it's not
+    // written to the results, and subsets' scores aren't used as a
+    // starting point.
+    pub fn with_exact_layers(mut self, n: usize) -> Worker<'a> {
+        assert!(n > 0, "Arrangements need at least one layer");
+        self.rules.max_layers = Some(self.rules.max_layers.map_or(n, |m| m.min(n)));
+        self.layers = Some(n);
+        self
+    }
+
+    // Searches on from a game in progress, placing the target bag's tiles
+    // on top of the given arrangement (which must use the same rules).
+    // The best score is for the whole arrangement, including the tiles
+    // already on the table.  As with a draw order, it's not written to the
+    // results, and subsets' scores aren't used as a starting point.
+    pub fn with_start(mut self, state: State) -> Worker<'a> {
+        assert!(state.has_rules(&self.rules),
+                "Starting arrangement doesn't match the rules");
+        self.start = Some(state);
+        self
+    }
+
+    // Returns the arrangement which the search starts from
+    fn root(&self) -> State {
+        match self.start {
+            Some(ref s) => s.clone().with_max_layers(self.rules.max_layers),
+            None => State::with_rules(&self.rules),
+        }
+    }
+
+    // Checks whether an arrangement counts towards the best score, which
+    // it always does unless a number of layers is required
+    fn counts(&self, state: &State) -> bool {
+        self.layers.is_none_or(|n| !state.is_empty() && state.layers() + 1 == n)
+    }
+
+    // Checks whether the state could still be built up to the required
+    // number of layers with the tiles left in the bag.  Raising the top by
+    // k layers takes at least 2k - 1 tiles, since every layer but the
+    // highest needs two tiles to hold up each tile above it.
+    fn reachable(&self, bag: &Bag, state: &State) -> bool {
+        let n = match self.layers {
+            Some(n) => n,
+            None => return true,
+        };
+        let k = (n - 1).saturating_sub(state.layers());
+        return k == 0 || bag.len() >= 2 * k - 1;
+    }
+
+    // Returns the settings which a journal needs to repeat this search
+    pub fn header(&self) -> Header {
+        Header {
+            bag: self.target.clone(),
+            rules: self.rules,
+            tuning: Tuning { split_depth: 0, moves: MoveOrder::Score,
+                             shared_seen: false, dominance: false,
+                             ..self.tuning },
+            seed: self.seed,
+        }
+    }
+
+    // Returns the number of steps taken through the journal, and whether
+    // a replay used up every recorded step
+    pub fn journal_steps(&self) -> Option<(u64, bool)> {
+        self.journal.as_ref().map(|j| {
+            let j = j.lock().unwrap();
+            (j.steps(), j.finished())
+        })
+    }
+
+    // Passes a step through the journal, if there is one
+    fn log(&self, event: Event, state: &State) {
+        if let Some(ref j) = self.journal {
+            let mut j = j.lock().unwrap();
+            j.step(event, state);
+            self.check_stop(&j, state);
+        }
+    }
+
+    // Returns a value which the search reads from outside, recording it
+    // in the journal.  When replaying, the recorded value is used instead,
+    // and the value isn't computed at all.
+    fn input<F>(&self, make: fn(isize) -> Event, value: F, state: &State)
+        -> isize where F: FnOnce() -> isize
+    {
+        let j = match self.journal {
+            Some(ref j) => j,
+            None => return value(),
+        };
+        let mut j = j.lock().unwrap();
+        let v = if j.replaying() { 0 } else { value() };
+        let v = j.step(make(v), state).value();
+        self.check_stop(&j, state);
+        return v;
+    }
+
+    fn past_deadline(&self, state: &State) -> bool {
+        let late = self.deadline.map(|d| Instant::now() >= d).unwrap_or(false);
+        let j = match self.journal {
+            Some(ref j) => j,
+            None => return late,
+        };
+        let mut j = j.lock().unwrap();
+        let late = j.deadline(late, state);
+        self.check_stop(&j, state);
+        return late;
+    }
+
+    fn past_limits(&self, state: &State) -> bool {
+        self.node_limit.map(|n| self.progress.nodes() >= n).unwrap_or(false) ||
+            self.depth_limit.map(|d| state.pieces.len() >= d).unwrap_or(false)
+    }
+
+    // Replays stop (through the cancellation token) at their final step
+    fn check_stop(&self, j: &Journal, state: &State) {
+        if j.stopped() && !self.cancel.is_cancelled() {
+            status!("Stopped replay after step {}:", j.steps());
+            state.pretty_print();
+            self.cancel.cancel();
+        }
+    }
+
+    // Returns a worker for one subtree of this worker's search, which
+    // shares its incumbent score and cancellation token
+    fn split(&self) -> Worker<'a> {
+        Worker {
+            target: self.target.clone(),
+            incumbent: self.incumbent.clone(),
+            best: None,
+            leaders: self.leaders,
+            leaderboard: Vec::new(),
+            leader_keys: HashSet::new(),
+            tiebreak: self.tiebreak,
+            compact: None,
+            optimum: self.optimum,
+            results: self.results,
+            bounds: self.bounds.clone(),
+            rules: self.rules,
+            tuning: self.tuning,
+            seen: Seen::for_tuning(&self.tuning),
+            shared: self.shared.clone(),
+            dominance: Dominance::new(self.tuning.seen_limit),
+            placement_cache: self.placement_cache.clone(),
+            support: self.support.clone(),
+            cancel: self.cancel.clone(),
+            progress: self.progress.clone(),
+            cancel_after: self.cancel_after,
+            deadline: self.deadline,
+            timed_out: self.timed_out.clone(),
+            node_limit: self.node_limit,
+            depth_limit: self.depth_limit,
+            limited: self.limited.clone(),
+            overlay: self.overlay.clone(),
+            seed: self.seed,
+            journal: self.journal.clone(),
+            draw: self.draw.clone(),
+            layers: self.layers,
+            start: self.start.clone(),
+            profile: self.profile.clone(),
+            ticks: 0,
+            path: Vec::new(),
+            stopped: None,
+            resume: None,
+            suspended: None,
+        }
+    }
+
+    // Returns the time now if this node's steps should be timed
+    fn sample(&mut self) -> Option<Instant> {
+        self.profile.as_ref()?;
+        self.ticks += 1;
+        if self.ticks.is_multiple_of(SAMPLE_EVERY) {
+            Some(Instant::now())
+        } else {
+            None
+        }
+    }
+
+    fn sampled(&self, step: Step, start: Option<Instant>) {
+        if let (Some(p), Some(t)) = (self.profile.as_ref(), start) {
+            p.sampled(step, t.elapsed());
+        }
+    }
+
+    fn best_score(&self) -> isize {
+        self.incumbent.load(Ordering::Relaxed)
+    }
+
+    // Returns whether a subtree whose scores are bounded by b can be
+    // skipped:  it can't beat the best score, and can't make the
+    // leaderboard (if one is kept)
+    fn prunable(&self, b: isize) -> bool {
+        if let Some(score) = self.optimum {
+            return b < score;
+        }
+        if self.leaders == 0 {
+            return match self.tiebreak {
+                Some(_) => b < self.best_score(),
+                None => b <= self.best_score(),
+            };
+        }
+        return self.leaderboard.len() >= self.leaders &&
+            b <= self.leaderboard[self.leaders - 1].0;
+    }
+
+    // Keeps an arrangement of the whole bag if it has the highest score so
+    // far, or ties it at a lower cost by the tiebreak
+    fn consider(&mut self, score: isize, state: &State) {
+        let t = match self.tiebreak {
+            Some(t) => t,
+            None => return,
+        };
+        let better = match self.compact {
+            Some((s, ref c)) => (score, -t.cost(state)) > (s, -t.cost(c)),
+            None => true,
+        };
+        if better {
+            self.compact = Some((score, state.clone()));
+        }
+    }
+
+    // Returns the arrangement to report for the given score:  the one
+    // picked by the tiebreak, if any, or else the first one found
+    fn best_state(&self, score: isize) -> Option<State> {
+        match (&self.compact, &self.best) {
+            (&Some((s, ref state)), _) if s == score => Some(state.clone()),
+            (_, &Some((s, ref state))) if s == score => Some(state.clone()),
+            _ => None,
+        }
+    }
+
+    // Adds an arrangement of the whole bag to the leaderboard, unless it's
+    // a turned copy of one that's already there or scores too low
+    fn offer(&mut self, score: isize, state: &State) {
+        if self.leaders == 0 || self.prunable(score) ||
+            self.optimum.map(|s| s != score).unwrap_or(false)
+        {
+            return;
+        }
+        if !self.leader_keys.insert(state.canonical()) {
+            return;
+        }
+        let i = self.leaderboard.iter().position(|l| l.0 < score)
+            .unwrap_or(self.leaderboard.len());
+        self.leaderboard.insert(i, (score, state.clone()));
+        self.leaderboard.truncate(self.leaders);
+    }
+
+    // Solves for the target bag, returning the best score and arrangement.
+    // If the worker is cancelled partway through, the result is only a
+    // lower bound, so it's returned as None and not written to the table.
+    // If it hits its deadline (or a node or depth limit), the result is
+    // returned and recorded as a lower bound.  It's also None if a number
+    // of layers is required and no arrangement has that many.
+    pub fn run(&mut self) -> Option<(isize, State)> {
+        let began = Instant::now();
+        let bag = self.target.clone();
+
+        // The search starts from the better of a greedy arrangement and the
+        // best subset, so that it can prune from the start
+        let mut greedy = None;
+        let initial = self.input(Event::Initial, || {
+            let mut g = self.greedy();
+            let subsets = match (&self.draw, &self.start) {
+                (None, None) => self.bounds.upper_subset_score(&bag),
+                _ => 0,
+            };
+            // With a number of layers required, only an arrangement of
+            // that height is a starting point:  the greedy one, or the
+            // bag's best arrangement if it happens to be that tall (in
+            // which case nothing can beat it)
+            let initial = match self.layers {
+                Some(_) => {
+                    let known = self.results.read().unwrap().state(&bag)
+                        .filter(|s| self.start.is_none() && self.counts(s))
+                        .map(|s| (self.rules.score(s), s.clone()));
+                    if let Some(k) = known {
+                        if !self.counts(&g.1) || k.0 > g.0 {
+                            g = k;
+                        }
+                    }
+                    if self.counts(&g.1) { g.0 } else { isize::MIN }
+                },
+                None => g.0.max(subsets),
+            };
+            greedy = Some(g);
+            initial
+        }, &State::new());
+        if let Some((score, ref state)) = greedy {
+            if state.pieces.len() == bag.len() && self.counts(state) {
+                self.offer(score, state);
+                self.consider(score, state);
+            }
+        }
+        if let Some((score, state)) = greedy.filter(|g| g.0 == initial) {
+            self.best = Some((score, state));
+        }
+        self.incumbent.store(initial, Ordering::Relaxed);
+        self.progress.improved(initial);
+        let resume = self.resume.take();
+        let resumed = resume.as_ref().and_then(|r| r.best.clone());
+        if let Some((score, state)) = resumed.filter(|b| b.0 > initial) {
+            self.incumbent.store(score, Ordering::Relaxed);
+            self.progress.improved(score);
+            self.best = Some((score, state));
+        }
+        match self.layers {
+            Some(n) if initial == isize::MIN =>
+                status!("Running with {} pieces in the {:?},\nand no initial arrangement with {} layers", bag.len(), bag, n),
+            _ => status!("Running with {} pieces in the {:?},\nand initial best score {}", bag.len(), bag, initial),
+        }
+        if resume.is_some() {
+            status!("Continuing a suspended search from score {}",
+                    self.best_score());
+        }
+        debug!("Using {:?}", self.tuning);
+        logging::event("start", Some(&bag), Some(initial), None);
+
+        let start = self.root();
+        let depth = self.tuning.split_depth;
+        if depth > 0 && self.journal.is_none() {
+            self.run_split(bag, start, depth, resume);
+        } else {
+            let resume = resume.filter(|r| self.matches(r, 1));
+            let (path, seen) = match resume {
+                Some(r) => (r.roots[0].clone(),
+                            r.seen.into_iter().next().unwrap_or_default()),
+                None => (Some(Vec::new()), Vec::new()),
+            };
+            self.resume_root(bag, start, path, &seen);
+            if self.cancel.is_cancelled() && self.suspendable() {
+                let seen = self.seen.hashes();
+                self.suspended = Some(self.snapshot(vec![self.stopped.clone()],
+                                                    vec![seen]));
+            }
+        }
+
+        if let Some(ref j) = self.journal {
+            if let Err(e) = j.lock().unwrap().flush() {
+                warning!("Failed to write journal: {}", e);
+            }
+        }
+
+        let score = self.best_score();
+        debug!("Expanded {} nodes", self.progress.nodes());
+        if self.cancel.is_cancelled() {
+            status!("Cancelled with best score {}\n", score);
+            logging::event("cancelled", Some(&self.target), Some(score),
+                           Some(began.elapsed()));
+            return None;
+        }
+        if self.leaders > 0 && self.draw.is_none() && self.layers.is_none() &&
+            self.start.is_none()
+        {
+            self.results.write().unwrap()
+                .write_leaders(&self.target, self.leaderboard.clone());
+        }
+        if let Some(n) = self.layers {
+            return match self.best_state(score) {
+                Some(state) => {
+                    status!("Got result {} with {} layers\n", score, n);
+                    Some((score, state))
+                },
+                None => {
+                    status!("No arrangement has {} layers\n", n);
+                    None
+                },
+            };
+        }
+        if self.draw.is_some() {
+            status!("Got result {} in draw order\n", score);
+            let state = self.best_state(score).unwrap_or_else(State::new);
+            return Some((score, state));
+        }
+        if self.start.is_some() {
+            status!("Got result {} from the starting arrangement\n", score);
+            let state = self.best_state(score).unwrap_or_else(|| self.root());
+            return Some((score, state));
+        }
 
-        println!("Got result {}\n", self.best_score);
+        // If nothing beat the initial score, then the best arrangement
+        // is from a subset of the bag, if we have it on hand.
         let mut writer = self.results.write().unwrap();
-        writer.write_score(self.target, self.best_score);
+        let state = self.best_state(score)
+            .or_else(|| writer.subset_state(&self.target, score));
+        if self.timed_out() {
+            status!("Timed out with best score {}\n", score);
+            logging::event("timeout", Some(&self.target), Some(score),
+                           Some(began.elapsed()));
+            writer.write_lower_bound(&self.target, score);
+        } else if self.limited() {
+            status!("Hit a search limit with best score {}\n", score);
+            logging::event("limited", Some(&self.target), Some(score),
+                           Some(began.elapsed()));
+            writer.write_lower_bound(&self.target, score);
+        } else {
+            status!("Got result {}\n", score);
+            logging::event("solved", Some(&self.target), Some(score),
+                           Some(began.elapsed()));
+            writer.write_score(&self.target, score, state.as_ref());
+        }
+        let state = state.unwrap_or_else(State::new);
+        return Some((score, state));
+    }
+
+    // Expands the given number of moves, then searches the resulting
+    // subtrees in parallel.  These are spread across the rayon thread pool,
+    // so threads which have finished their own bags will pick them up.
+    //
+    // The subtrees share one incumbent, which only ever rises (fetch_max)
+    // to the score of an arrangement that some thread has found.  Pruning
+    // a branch against another thread's score is as safe as pruning it
+    // against our own, and reading a stale (lower) score only prunes
+    // less.  Every subtree is searched before the best is taken, and roots
+    // with the same canonical state have the same tiles left, so skipping
+    // the repeats loses nothing either.
+    fn run_split(&mut self, bag: Bag, state: State, depth: usize,
+                 resume: Option<Snapshot>)
+    {
+        let mut roots = vec![(bag, state)];
+        for _ in 0..depth {
+            let mut seen = HashSet::new();
+            let mut next = Vec::new();
+            for (b, s) in roots {
+                for (p, t) in self.moves(&b, &s) {
+                    if seen.insert(t.canonical()) {
+                        next.push((b.take(p), t));
+                    }
+                }
+            }
+            roots = next;
+        }
+        let resume = resume.filter(|r| self.matches(r, roots.len()));
+        let resumed = resume.is_some();
+        let (paths, mut seen) = match resume {
+            Some(r) => (r.roots, r.seen),
+            None => (vec![Some(Vec::new()); roots.len()], Vec::new()),
+        };
+        if self.tuning.shared_seen {
+            let shared = SharedSeen::new();
+            for s in seen.drain(..) {
+                shared.extend(&s);
+            }
+            self.shared = Some(Arc::new(shared));
+        }
+
+        let found: Vec<_> = roots.into_par_iter().zip(paths).enumerate()
+            .map(|(i, ((b, s), path))| {
+                let mut w = self.split();
+                w.resume_root(b, s, path, seen.get(i).map_or(&[], |s| &s[..]));
+                let hashes = if w.cancel.is_cancelled() && w.shared.is_none() {
+                    w.seen.hashes()
+                } else {
+                    Vec::new()
+                };
+                (w.best, w.leaderboard, w.compact, w.stopped, hashes)
+            }).collect();
+        // A continued search keeps the best arrangement from before
+        let mut best: Vec<_> = self.best.take().filter(|_| resumed)
+            .into_iter().collect();
+        let mut stopped = Vec::new();
+        let mut hashes = Vec::new();
+        for (b, leaders, compact, s, h) in found {
+            best.extend(b);
+            if let Some((score, state)) = compact {
+                self.consider(score, &state);
+            }
+            for (score, state) in leaders {
+                self.offer(score, &state);
+            }
+            stopped.push(s);
+            hashes.push(h);
+        }
+        self.best = best.into_iter().max_by_key(|f| f.0);
+        if self.cancel.is_cancelled() && self.suspendable() {
+            if let Some(ref shared) = self.shared {
+                hashes = vec![shared.hashes()];
+            }
+            self.suspended = Some(self.snapshot(stopped, hashes));
+        }
+        if let Some(shared) = self.shared.take() {
+            self.progress.seen(shared.len() * entry_bytes(Dedup::Hashed));
+            let (locks, waits) = shared.contention();
+            debug!("Shared seen-set held {} states; {} of {} locks waited",
+                   shared.len(), waits, locks);
+            if let Some(ref p) = self.profile {
+                p.shared_seen(locks, waits);
+            }
+        }
+    }
+
+    // Places the most promising move at each step (the highest scoring,
+    // then the most compact, as ordered by moves), returning the final
+    // score and arrangement.  A tile can always go on the ground beside
+    // the others, so this places the whole bag, and an incumbent seeded
+    // with its score only prunes branches which can't beat an
+    // arrangement we already hold.
+    fn greedy(&self) -> (isize, State) {
+        let mut bag = self.target.clone();
+        let mut state = self.root();
+        while !bag.is_empty() {
+            match self.moves(&bag, &state).into_iter().next() {
+                Some((p, s)) => {
+                    bag = bag.take(p);
+                    state = s;
+                },
+                None => break,
+            }
+        }
+        return (self.rules.score(&state), state);
+    }
+
+    // Checks that a snapshot was made by a search which split into the
+    // given number of subtrees, as this one does
+    fn matches(&self, snapshot: &Snapshot, roots: usize) -> bool {
+        let seen = if self.tuning.shared_seen { 1 } else { roots };
+        if snapshot.roots.len() == roots &&
+           (snapshot.seen.is_empty() || snapshot.seen.len() == seen)
+        {
+            return true;
+        }
+        warning!("Snapshot of {:?} doesn't match its search; starting over",
+                 self.target);
+        return false;
+    }
+
+    fn snapshot(&self, roots: Vec<Option<Vec<usize>>>, seen: Vec<Vec<u64>>)
+        -> Snapshot
+    {
+        Snapshot {
+            bag: self.target.clone(),
+            best: self.best.clone(),
+            split_depth: self.tuning.split_depth,
+            order: self.tuning.order,
+            moves: self.tuning.moves,
+            shared_seen: self.tuning.shared_seen,
+            roots: roots,
+            seen: seen,
+        }
+    }
+
+    // Starts (or continues) searching one subtree:  fills in the seen-set
+    // saved from before, then continues down the path where the search
+    // stopped, if it wasn't finished
+    fn resume_root(&mut self, bag: Bag, state: State,
+                   path: Option<Vec<usize>>, seen: &[u64])
+    {
+        match self.shared {
+            Some(ref shared) => shared.extend(seen),
+            None => self.seen.extend(seen),
+        }
+        if let Some(path) = path {
+            self.resume_(bag, state, &path);
+        }
+        if self.shared.is_none() {
+            self.progress.seen(self.seen.peak_bytes());
+        }
+        self.progress.dominance(self.dominance.peak_bytes());
+
+        // A Bloom filter may have skipped states which were never searched,
+        // so the best score found is only a lower bound, as for a search
+        // which hit a limit
+        if self.shared.is_none() && self.seen.dedup() == Dedup::Bloom &&
+            !self.limited.swap(true, Ordering::Relaxed)
+        {
+            warning!("Seen-set for {:?} passed its budget and became a Bloom \
+                      filter, so its score is only a lower bound", self.target);
+        }
+    }
+
+    fn uses_dominance(&self) -> bool {
+        self.tuning.dominance && self.leaders == 0 && self.journal.is_none() &&
+            self.layers.is_none() && self.rules.scoring != Scoring::TopBonus
+    }
+
+    // Returns an upper bound on the score once the bag's last tile is
+    // placed on the state:  it can only go above the top layer if it can
+    // rest there, and otherwise it can be no higher than that layer.
+    // Scores only depend on each tile's layer, and are highest with the
+    // tile either on the ground or as high as it can go.
+    //
+    // This only applies to the last tile.  With more tiles left, each one
+    // placed on the top layer changes what the next can rest on (two
+    // tiles which can't be lifted alone may hold up a third between
+    // them), so one lookup says nothing about how high the rest can go.
+    fn last_tile_bound(&self, table: &SupportTable, bag: &Bag, state: &State)
+        -> isize
+    {
+        let tile = (0..UNIQUE_PIECE_COUNT).find(|&t| bag.digit(t) > 0)
+            .unwrap();
+        let z = state.layers();
+        let top = if table.supported(state) & 1 << tile != 0 &&
+                     state.max_layers().is_none_or(|n| z + 1 < n) {
+            z + 1
+        } else {
+            z
+        };
+        return [0, top].iter()
+            .map(|&l| self.rules.score(
+                &state.insert(Placed::new(tile * MAX_ROTATIONS, 0, 0, l))))
+            .max().unwrap();
+    }
+
+    // Returns every legal placement of a piece from the bag onto the state,
+    // ordered with the most promising first
+    fn moves(&self, bag: &Bag, state: &State) -> Vec<(usize, State)> {
+        // Try placing every piece in the bag onto every possible position,
+        // skipping rotations which repeat an earlier one.  Moves which make
+        // the same arrangement turned (such as each rotation of the first
+        // piece) are caught by the seen-set, which stores canonical forms.
+        let mut todo = BTreeMap::new();
+        let boards = OnceCell::new();
+        let key = self.placement_cache.as_ref()
+            .map(|_| Footprint::new(state).key());
+        let placed = self.start.as_ref().map_or(0, |s| s.pieces.len());
+        let next = self.draw.as_ref().map(|d| d[state.pieces.len() - placed]);
+        let bounds = match self.tuning.moves {
+            MoveOrder::Bound if self.journal.is_none() => Some(&self.bounds),
+            _ => None,
+        };
+        for b in (0..UNIQUE_PIECE_COUNT)
+            .filter(|&t| bag.digit(t) > 0)
+            .filter(|&t| next.map(|n| t == n).unwrap_or(true))
+            .flat_map(|t| pieces().orientations(t, state.mirrored()))
+        {
+            let rank = self.tuning.order.rank(tile(b), &self.rules.values);
+            let states: Vec<State> = match (&self.placement_cache, &key) {
+                (Some(cache), Some(key)) => match cache.get(key, b) {
+                    Some(placed) => placed.iter()
+                        .map(|&p| state.insert(p))
+                        .collect(),
+                    None => {
+                        let (placed, states) = boards
+                            .get_or_init(|| Bitboards::new(state))
+                            .placements(state, b)
+                            .unzip();
+                        cache.insert(key, b, placed);
+                        states
+                    }
+                },
+                _ => boards.get_or_init(|| Bitboards::new(state))
+                    .placements(state, b)
+                    .map(|p| p.1)
+                    .collect(),
+            };
+            for s in states {
+                let (w, h) = s.size();
+                let tie = match self.seed {
+                    Some(seed) => Rng::for_state(&[seed], &s).next_u64(),
+                    None => (w + h) as u64,
+                };
+                let score = self.rules.score(&s);
+                let bound = match bounds {
+                    Some(r) if bag.len() > 1 =>
+                        score + r.upper_score_bound(&bag.take(b), &s),
+                    _ => score,
+                };
+                let k = (-bound, -score, tie, rank);
+                todo.entry(k).or_insert_with(Vec::new).push((b, s));
+            }
+        }
+        return todo.into_values().flatten().collect();
+    }
+
+    // Follows the path where a cancelled search stopped.  The nodes along
+    // it were already expanded, and the moves before each step searched,
+    // so each is expanded again without checking the seen-set, then the
+    // rest of its moves are searched as usual.
+    fn resume_(&mut self, bag: Bag, state: State, path: &[usize]) {
+        let (&next, rest) = match path.split_first() {
+            Some(p) => p,
+            None => return self.run_(bag, state),
+        };
+        let key = state.canonical();
+        match self.shared {
+            Some(ref shared) => shared.insert(&key),
+            None => self.seen.insert(key),
+        }
+        let todo = self.moves(&bag, &state);
+        for (i, (p, s)) in todo.into_iter().enumerate().skip(next) {
+            self.path.push(i);
+            if i == next {
+                self.resume_(bag.take(p), s, rest);
+            } else {
+                self.run_(bag.take(p), s);
+            }
+            self.path.pop();
+        }
     }
 
     fn run_(&mut self, bag: Bag, state: State) {
-        if bag.is_empty() {
+        if self.cancel_after.map(|n| self.progress.nodes() >= n).unwrap_or(false) {
+            self.cancel.cancel();
+        }
+        if self.cancel.is_cancelled() {
+            if self.stopped.is_none() {
+                self.stopped = Some(self.path.clone());
+            }
             return;
         }
-        if self.seen.contains(&state) {
+        let key = state.canonical();
+        let seen = match self.shared {
+            Some(ref shared) => shared.contains(&key),
+            None => self.seen.contains(&key),
+        };
+        if seen {
+            self.log(Event::Seen, &state);
+            self.progress.repeated();
+            return;
+        }
+        if self.past_deadline(&state) {
+            self.timed_out.store(true, Ordering::Relaxed);
             return;
         }
 
-        let score = state.score();
-        if score > self.best_score {
-            println!("Got new best score: {}", state.score());
-            state.pretty_print();
-            self.best_score = score;
-            self.best_state = state.clone();
+        let score = self.rules.score(&state);
+        if score > self.best_score() && self.counts(&state) {
+            status!("Got new best score: {}", score);
+            logging::event("improved", Some(&self.target), Some(score), None);
+            if !porcelain::enabled() && logging::enabled(Level::Info) {
+                state.pretty_print();
+            }
+            self.log(Event::Improved(score), &state);
+            self.incumbent.fetch_max(score, Ordering::Relaxed);
+            self.progress.improved(score);
+            if let Some(ref overlay) = self.overlay {
+                overlay.improved(&self.target, score, &state);
+            }
+            self.best = Some((score, state.clone()));
+        }
+
+        // Every tile has been placed, so there's nothing left to search
+        if bag.is_empty() {
+            if self.counts(&state) {
+                self.offer(score, &state);
+                self.consider(score, &state);
+            }
+            return;
+        }
+        if !self.reachable(&bag, &state) {
+            return;
+        }
+        if self.uses_dominance() && self.dominance.visit(&bag, &state, score) {
+            self.progress.dominated();
+            return;
         }
 
         // Check to see whether we could possibly beat our current
-        // best score; otherwise, return immediately.
-        if bag.as_usize() != self.target {
-            let b = self.results.read().unwrap().upper_score_bound(&bag, &state);
-            if b <= self.best_score {
+        // best score; otherwise, return immediately.  The bound only
+        // covers the tiles left in the bag, so the tiles already placed
+        // add their own score to it.
+        let timer = self.sample();
+        if bag != self.target {
+            let b = score + self.input(Event::Bound, || {
+                self.bounds.upper_score_bound(&bag, &state)
+            }, &state);
+            self.sampled(Step::Bound, timer);
+            if self.prunable(b) {
+                self.progress.bounded();
                 return;
             }
         }
-
-        // Try placing every piece in the bag onto every possible position
-        let mut todo = BTreeMap::new();
-        let size = state.size();
-        for b in bag.into_iter() {
-            for x in -MAX_EDGE_LENGTH..=size.0 + MAX_EDGE_LENGTH {
-                for y in -MAX_EDGE_LENGTH..=size.1 + MAX_EDGE_LENGTH {
-                    if let Some(s) = state.try_place(b, x, y) {
-                        let (w, h) = s.size();
-                        let k = (-(s.score() as i32), w + h);
-                        if !todo.contains_key(&k) {
-                            todo.insert(k, Vec::new());
-                        }
-                        todo.get_mut(&k).unwrap().push((b, s));
-                    }
-                }
+        if let (Some(table), 1, false, None) = (&self.support, bag.len(),
+                                                state.is_empty(), &self.journal)
+        {
+            if self.prunable(self.last_tile_bound(table, &bag, &state)) {
+                self.progress.bounded();
+                return;
             }
         }
+        if self.past_limits(&state) {
+            self.limited.store(true, Ordering::Relaxed);
+            return;
+        }
 
-        self.seen.insert(state);
+        let timer = timer.map(|_| Instant::now());
+        let todo = self.moves(&bag, &state);
+        self.sampled(Step::Moves, timer);
+        self.log(Event::Expanded(todo.len()), &state);
+        self.progress.expanded(state.pieces.len(), todo.len());
+        match self.shared {
+            Some(ref shared) => shared.insert(&key),
+            None => self.seen.insert(key),
+        }
 
         // Then, recurse and continue running with the placements
-        for (_, vec) in todo {
-            for (p, s) in vec {
-                self.run_(bag.take(p), s);
+        for (i, (p, s)) in todo.into_iter().enumerate() {
+            self.path.push(i);
+            self.run_(bag.take(p), s);
+            self.path.pop();
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use census::Census;
+    use expectimax::placements;
+
+    // Returns results with every proper subset of the bag solved, smallest
+    // first, so that they bound the search of the bag itself
+    fn with_subsets(bag: &Bag, rules: Rules) -> RwLock<Results> {
+        let results = RwLock::new(Results::new(rules));
+        let mut subsets: Vec<Bag> = results.read().unwrap().bags().into_iter()
+            .filter(|b| bag.contains(b) && b != bag)
+            .collect();
+        subsets.sort_by_key(|b| b.len());
+        for b in subsets {
+            Worker::new(b, &results).run();
+        }
+        results
+    }
+
+    #[test]
+    fn cancel() {
+        let results = RwLock::new(Results::new(Rules::default()));
+        let bag = Bag::from_usize(0).add(0).add(4);
+        for b in [bag.take(0).take(4), bag.take(0), bag.take(4)].iter() {
+            results.write().unwrap().write_score(b, 0, None);
+        }
+
+        let token = CancelToken::new();
+        token.cancel();
+        let out = Worker::new(bag.clone(), &results)
+            .with_cancel(token.clone())
+            .run();
+        assert!(out.is_none());
+        assert_eq!(results.read().unwrap().score(&bag), None);
+
+        let (score, _) = Worker::new(bag.clone(), &results).run().unwrap();
+        assert_eq!(score, 0);
+        assert_eq!(results.read().unwrap().score(&bag), Some(0));
+    }
+
+    #[test]
+    fn deadline() {
+        let results = RwLock::new(Results::new(Rules::default()));
+        let bag = Bag::from_usize(0).add(0).add(4);
+        for b in [bag.take(0).take(4), bag.take(0), bag.take(4)].iter() {
+            results.write().unwrap().write_score(b, 0, None);
+        }
+
+        let mut w = Worker::new(bag.clone(), &results)
+            .with_deadline(Instant::now());
+        assert_eq!(w.run().map(|r| r.0), Some(0));
+        assert!(w.timed_out());
+        assert_eq!(results.read().unwrap().score(&bag), None);
+        assert_eq!(results.read().unwrap().lower_bound(&bag), Some(0));
+    }
+
+    #[test]
+    fn limits() {
+        let bag = Bag::from_usize(0).add(0).add(0).add(4);
+        let results = with_subsets(&bag, Rules::default());
+
+        // Every arrangement of each bag, by brute force:  no proper subset
+        // can score, and the best of the whole bag lays the 1 across both
+        // 0s, for a point
+        let brute = |b: &Bag| {
+            let mut census = Census::new(Rules::default());
+            census.count(b);
+            *census.histogram().keys().max().unwrap()
+        };
+        for &p in &[0, 4] {
+            assert_eq!(brute(&bag.take(p)), 0);
+            assert_eq!(results.read().unwrap().score(&bag.take(p)), Some(0));
+        }
+        let best = brute(&bag);
+        assert_eq!(best, 1);
+
+        // Stopping after the first piece leaves the bag unsolved, with
+        // whatever was found recorded as a lower bound
+        let mut w = Worker::new(bag.clone(), &results).with_depth_limit(1);
+        let found = w.run().unwrap().0;
+        assert!(found <= best);
+        assert!(w.limited());
+        assert_eq!(results.read().unwrap().score(&bag), None);
+        assert_eq!(results.read().unwrap().lower_bound(&bag), Some(found));
+
+        let mut w = Worker::new(bag.clone(), &results).with_node_limit(1);
+        w.run();
+        assert!(w.limited());
+        assert_eq!(w.progress().nodes(), 1);
+
+        // Limits which aren't reached don't change the result
+        let mut w = Worker::new(bag.clone(), &results)
+            .with_depth_limit(3).with_node_limit(1_000_000);
+        assert_eq!(w.run().map(|r| r.0), Some(best));
+        assert!(!w.limited());
+        assert_eq!(results.read().unwrap().score(&bag), Some(best));
+    }
+
+    #[test]
+    fn seen_limit() {
+        let bag = Bag::from_usize(0).add(0).add(0).add(4);
+        let results = with_subsets(&bag, Rules::default());
+
+        // Past a tiny budget the seen-set becomes a Bloom filter, which may
+        // skip states, so the score is only recorded as a lower bound
+        let mut w = Worker::new(bag.clone(), &results).with_seen_limit(1, false);
+        let found = w.run().unwrap().0;
+        assert!(w.limited());
+        assert_eq!(results.read().unwrap().score(&bag), None);
+        assert_eq!(results.read().unwrap().lower_bound(&bag), Some(found));
+
+        // An evicting set only searches states again, so it stays exact
+        let mut w = Worker::new(bag.clone(), &results).with_seen_limit(1, true);
+        assert_eq!(w.run().map(|r| r.0), Some(1));
+        assert!(!w.limited());
+        assert_eq!(results.read().unwrap().score(&bag), Some(1));
+    }
+
+    #[test]
+    fn greedy() {
+        // The greedy pass places every tile, and the search never reports
+        // less than it found, even when cut short
+        let bag = Bag::from_usize(0).add(0).add(0).add(4).add(8);
+        let results = with_subsets(&bag, Rules::default());
+        let w = Worker::new(bag.clone(), &results);
+        let (score, state) = w.greedy();
+        assert_eq!(state.pieces.len(), 4);
+        assert_eq!(score, state.score());
+
+        let mut w = Worker::new(bag.clone(), &results).with_node_limit(1);
+        let (found, best) = w.run().unwrap();
+        assert!(found >= score);
+        assert_eq!(best.score(), found);
+    }
+
+    #[test]
+    fn move_order() {
+        // Trying the placements with the best bounds first finds the same
+        // score as trying those with the best scores first
+        let bag = Bag::from_usize(0).add(0).add(4).add(8);
+        let mut found = Vec::new();
+        for &order in &[MoveOrder::Score, MoveOrder::Bound] {
+            let results = with_subsets(&bag, Rules::default());
+            let mut w = Worker::new(bag.clone(), &results)
+                .with_move_order(order);
+            found.push(w.run().map(|r| r.0));
+        }
+        assert_eq!(found[0], found[1]);
+    }
+
+    #[test]
+    fn seed() {
+        // Seeded searches still find the best score
+        let results = RwLock::new(Results::new(Rules::default()));
+        let bag = Bag::from_usize(0).add(0).add(4).add(36);
+
+        let mut w = Worker::new(bag.clone(), &results);
+        w.run_(bag.clone(), State::new());
+        for seed in 0..2 {
+            let mut v = Worker::new(bag.clone(), &results).with_seed(seed);
+            v.run_(bag.clone(), State::new());
+            assert_eq!(w.best_score(), v.best_score());
+        }
+
+        // Different seeds try moves in different orders, but the same
+        // seed always gives the same order
+        let state = State::new().try_place(0, 0, 0).unwrap();
+        let order = |seed| Worker::new(bag.clone(), &results).with_seed(seed)
+            .moves(&bag.take(0), &state).into_iter().map(|m| m.1)
+            .collect::<Vec<State>>();
+        assert_eq!(order(1), order(1));
+        assert_ne!(order(1), order(2));
+    }
+
+    #[test]
+    fn draw_order() {
+        // The best score of placing tiles in the given order, by trying
+        // every legal placement of each in turn
+        fn brute(state: &State, order: &[usize]) -> isize {
+            match order.split_first() {
+                None => Rules::default().score(state),
+                Some((&t, rest)) => placements(state, t, &Rules::default())
+                    .iter().map(|(_, s)| brute(s, rest)).max().unwrap(),
             }
         }
+
+        // Drawing two 0s and then a 1 lets the 1 be stacked for a point,
+        // but drawing the 1 first leaves it on the ground
+        let results = RwLock::new(Results::new(Rules::default()));
+        let bag = Bag::from_usize(0).add(0).add(0).add(4);
+        let run = |bag: &Bag, order: Vec<usize>| {
+            let expected = brute(&State::new(), &order);
+            let (score, state) = Worker::new(bag.clone(), &results)
+                .with_draw_order(order).run().unwrap();
+            assert_eq!(score, expected);
+            state
+        };
+        let state = run(&bag, vec![0, 0, 1]);
+        assert_eq!(state.pieces[0].index(), 1);
+        let state = run(&bag, vec![1, 0, 0]);
+        assert!(state.pieces.iter().all(|p| p.index() != 1 || p.z == 0));
+
+        // A 2 drawn after them can still be lifted onto the 0s
+        let state = run(&bag.add(8), vec![1, 0, 0, 2]);
+        assert_eq!(state.pieces[0].index(), 2);
+        assert_eq!(brute(&State::new(), &[1, 0, 0, 2]), 2);
+
+        // None is recorded as the bag's score
+        assert_eq!(results.read().unwrap().lower_bound(&bag), None);
+        assert_eq!(results.read().unwrap().lower_bound(&bag.add(8)), None);
+    }
+
+    #[test]
+    fn exact_layers() {
+        // Two 0s can hold up a 1, for a second layer but not a third
+        let results = RwLock::new(Results::new(Rules::default()));
+        let bag = Bag::from_usize(0).add(0).add(0).add(4);
+        let run = |n| Worker::new(bag.clone(), &results)
+            .with_exact_layers(n).run();
+        let (score, state) = run(1).unwrap();
+        assert_eq!(score, 0);
+        assert_eq!(state.layers(), 0);
+        let (score, state) = run(2).unwrap();
+        assert_eq!(score, 1);
+        assert_eq!(state.layers(), 1);
+        assert_eq!(run(3), None);
+
+        // None is recorded as the bag's score
+        assert_eq!(results.read().unwrap().lower_bound(&bag), None);
+    }
+
+    #[test]
+    fn start() {
+        // A 1 already on the table can help a 0 to hold up another 1,
+        // which a 0 can't do alone
+        let results = RwLock::new(Results::new(Rules::default()));
+        let start = State::new().try_place(4, 0, 0).unwrap();
+        let bag = Bag::from_usize(0).add(0).add(4);
+        let run = |bag: &Bag| Worker::new(bag.clone(), &results)
+            .with_start(start.clone()).run().unwrap();
+        let (score, state) = run(&bag);
+        assert_eq!(score, 1);
+        assert_eq!(state.pieces.len(), 3);
+        assert!(state.is_valid());
+        let (score, state) = run(&bag.take(4));
+        assert_eq!(score, 0);
+        assert_eq!(state.pieces.len(), 2);
+
+        // Neither is recorded as the bag's score
+        assert_eq!(results.read().unwrap().lower_bound(&bag), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Starting arrangement doesn't match the rules")]
+    fn start_rules() {
+        // An arrangement from a game with mirrored tiles can't be searched
+        // on without them
+        let results = RwLock::new(Results::new(Rules::default()));
+        let start = State::new().with_mirrored(true);
+        Worker::new(Bag::from_usize(0).add(0), &results).with_start(start);
+    }
+
+    #[test]
+    fn journal() {
+        use std::fs::{self, File};
+        use std::{env, process};
+
+        let results = RwLock::new(Results::new(Rules::default()));
+        let bag = Bag::from_usize(0).add(0).add(4);
+        for b in [bag.take(0).take(4), bag.take(0), bag.take(4)].iter() {
+            results.write().unwrap().write_score(b, 0, None);
+        }
+
+        let path = env::temp_dir()
+            .join(format!("nmbr9-journal-{}", process::id()));
+        let mut w = Worker::new(bag.clone(), &results).with_seed(3);
+        let j = Journal::record(Box::new(File::create(&path).unwrap()),
+                                w.header()).unwrap();
+        w = w.with_journal(j);
+        let recorded = w.run().map(|r| r.0);
+        let (steps, _) = w.journal_steps().unwrap();
+        assert!(steps > 2);
+
+        // Replaying doesn't need the subsets' scores, since they're read
+        // from the journal
+        let j = Journal::replay(File::open(&path).unwrap()).unwrap();
+        let h = j.header().clone();
+        let empty = RwLock::new(Results::new(h.rules));
+        let mut r = Worker::new(h.bag.clone(), &empty).with_tuning(h.tuning)
+            .with_seed(h.seed.unwrap()).with_journal(j);
+        assert_eq!(r.run().map(|r| r.0), recorded);
+        assert_eq!(r.journal_steps(), Some((steps, true)));
+
+        // Replays can stop partway through
+        let j = Journal::replay(File::open(&path).unwrap()).unwrap()
+            .with_stop(2);
+        let mut r = Worker::new(h.bag, &empty).with_tuning(h.tuning)
+            .with_seed(3).with_journal(j);
+        assert!(r.run().is_none());
+        assert_eq!(r.journal_steps(), Some((2, false)));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn split() {
+        // Splitting the search should find the same score
+        let results = RwLock::new(Results::new(Rules::default()));
+        let bag = Bag::from_usize(0).add(0).add(1).add(4).add(36);
+
+        let mut w = Worker::new(bag.clone(), &results);
+        w.run_(bag.clone(), State::new());
+        let mut v = Worker::new(bag.clone(), &results);
+        v.run_split(bag.clone(), State::new(), 2, None);
+
+        // Even if the subtrees share a seen-set, skipping the states that
+        // they have in common
+        let mut u = Worker::new(bag.clone(), &results).with_shared_seen(true);
+        u.run_split(bag.clone(), State::new(), 2, None);
+        assert_eq!(w.best_score(), u.best_score());
+
+        assert_eq!(w.best_score(), v.best_score());
+        assert_eq!(w.best.map(|b| b.0), v.best.map(|b| b.0));
+    }
+
+    #[test]
+    fn dominance() {
+        // Skipping dominated states finds the same score
+        let results = RwLock::new(Results::new(Rules::default()));
+        let bag = Bag::from_usize(0).add(0).add(1).add(4).add(36);
+
+        let mut w = Worker::new(bag.clone(), &results);
+        w.run_(bag.clone(), State::new());
+        let mut v = Worker::new(bag.clone(), &results).with_dominance(true);
+        v.run_(bag.clone(), State::new());
+        assert_eq!(w.best_score(), v.best_score());
+        assert!(!v.dominance.is_empty());
+        assert!(v.progress().nodes() <= w.progress().nodes());
+    }
+
+    #[test]
+    fn placement_cache() {
+        // Cached placements make the same moves, so the search takes the
+        // same steps, and a second search finds all of them in the cache
+        let results = RwLock::new(Results::new(Rules::default()));
+        let bag = Bag::from_usize(0).add(0).add(1).add(4).add(36);
+        let cache = Arc::new(PlacementCache::new(1 << 26));
+
+        let mut w = Worker::new(bag.clone(), &results).with_split_depth(0);
+        w.run_(bag.clone(), State::new());
+        for _ in 0..2 {
+            let mut v = Worker::new(bag.clone(), &results).with_split_depth(0)
+                .with_placement_cache(cache.clone());
+            v.run_(bag.clone(), State::new());
+            assert_eq!(w.best_score(), v.best_score());
+            assert_eq!(w.progress().nodes(), v.progress().nodes());
+        }
+        let (hits, misses) = cache.stats();
+        assert!(hits >= misses);
+    }
+
+    #[test]
+    fn support_table() {
+        // The last tile is bounded by where it can rest, which skips some
+        // of the states that the plain bound searches, without changing
+        // the best score
+        let results = RwLock::new(Results::new(Rules::default()));
+        let bag = Bag::from_usize(0).add(0).add(1).add(4).add(36);
+        let table = Arc::new(SupportTable::new(1 << 20));
+
+        let mut w = Worker::new(bag.clone(), &results).with_split_depth(0);
+        w.run_(bag.clone(), State::new());
+        let mut v = Worker::new(bag.clone(), &results).with_split_depth(0)
+            .with_support_table(table.clone());
+        v.run_(bag.clone(), State::new());
+        assert_eq!(w.best_score(), v.best_score());
+        assert!(v.progress().nodes() < w.progress().nodes());
+        assert!(v.progress().prunes().0 > w.progress().prunes().0);
+    }
+
+    #[test]
+    fn leaders() {
+        let bag = Bag::from_digits("2100000000", 2).unwrap();
+        let results = with_subsets(&bag, Rules::default());
+
+        // Laying the 1 across both 0s scores a point, which is only found
+        // by scoring arrangements that use every tile
+        let mut w = Worker::new(bag.clone(), &results).with_leaders(3);
+        assert_eq!(w.run().map(|r| r.0), Some(1));
+        let results = results.read().unwrap();
+        let leaders = results.leaders(&bag);
+        assert_eq!(leaders, w.leaderboard());
+        assert_eq!(leaders.len(), 3);
+        assert_eq!(leaders[0].0, 1);
+        for (i, (score, state)) in leaders.iter().enumerate() {
+            assert_eq!(state.pieces.len(), bag.len());
+            assert_eq!(Rules::default().score(state), *score);
+            assert!(leaders[..i].iter()
+                .all(|l| l.0 >= *score && l.1.canonical() != state.canonical()));
+        }
+    }
+
+    #[test]
+    fn suspend() {
+        let bag = Bag::from_digits("2110000000", 2).unwrap();
+        let results = with_subsets(&bag, Rules::default());
+        let mut full = Worker::new(bag.clone(), &results).with_split_depth(0);
+        let expected = full.run().unwrap().0;
+        assert!(full.progress().nodes() > 20);
+
+        // Cancel each search partway through, then continue it from its
+        // snapshot, which finds the same score
+        for &depth in &[0, 1] {
+            let mut w = Worker::new(bag.clone(), &results)
+                .with_split_depth(depth)
+                .with_cancel_after(20);
+            assert_eq!(w.run(), None);
+
+            let snapshot = w.suspended().unwrap();
+            assert_eq!(snapshot.split_depth, depth);
+            assert!(snapshot.roots.iter().any(|r| r.is_some()));
+            let mut v = Worker::new(bag.clone(), &results)
+                .with_snapshot(snapshot);
+            assert_eq!(v.run().map(|r| r.0), Some(expected));
+        }
+    }
+
+    #[test]
+    fn optimum() {
+        let bag = Bag::from_digits("2100000000", 2).unwrap();
+        let results = with_subsets(&bag, Rules::default());
+
+        // Every distinct arrangement that scores the point is found, and
+        // agrees with a census of the bag
+        let mut w = Worker::new(bag.clone(), &results).with_optimum(1);
+        assert_eq!(w.run().map(|r| r.0), Some(1));
+        let mut census = Census::new(Rules::default()).up_to_rotation();
+        census.count(&bag);
+        assert_eq!(w.leaderboard().len(), census.histogram()[&1]);
+        assert!(w.leaderboard().iter().all(|l| l.0 == 1));
+
+        // A tiebreak picks the most compact of them
+        let smallest = w.leaderboard().iter()
+            .map(|l| Tiebreak::Footprint.cost(&l.1)).min().unwrap();
+        let mut t = Worker::new(bag.clone(), &results)
+            .with_tiebreak(Tiebreak::Footprint);
+        let (score, state) = t.run().unwrap();
+        assert_eq!(score, 1);
+        assert_eq!(state.pieces.len(), bag.len());
+        assert_eq!(Tiebreak::Footprint.cost(&state), smallest);
+        assert!(smallest < w.leaderboard().iter()
+            .map(|l| Tiebreak::Footprint.cost(&l.1)).max().unwrap());
     }
 }